@@ -0,0 +1,491 @@
+use crate::utils::key_pressed;
+use eframe::egui::{self, Key};
+use KeyScope::{Anywhere, Grid, Preview};
+
+// Everything the top bar, the menus and the keys can do, in the order the palette lists it
+#[derive(PartialEq, Clone, Copy)]
+pub enum Command {
+    SelectFiles,
+    SelectFolders,
+    Refresh,
+    Clear,
+    MarkDuplicates,
+    ClearMarks,
+    DeleteMarked,
+    Rename,
+    BatchRename,
+    EditTags,
+    Rate(u8),
+    Convert,
+    ReverseOrder,
+    OpenRandom,
+    BrowseFolder,
+    ExportList,
+    ContactSheet,
+    UpdateCollection,
+    ShowCollection,
+    ExportCollection,
+    NewWindow,
+    CloseWindow,
+    NextWindow,
+    Settings,
+    Stats,
+    FilterImages,
+    FilterAnimated,
+    FilterVideos,
+    FilterFailed,
+    FilterMarkedOnly,
+    SortByPath,
+    SortManually,
+    SortDescending,
+    GroupByType,
+    DensityPreset(usize),
+    GridMinimap,
+    Timings,
+    ClosePreview,
+    ShowInGrid,
+    Fullscreen,
+    PreviousPage,
+    NextPage,
+    TogglePlayback,
+    LoopStart,
+    LoopEnd,
+    ClearLoop,
+    CyclePlaybackMode,
+    VideoFit,
+    AddFrame,
+    CopyDataUrl,
+    Print,
+    RotateClockwise,
+    RotateCounterClockwise,
+    Eyedropper,
+    Ruler,
+    Crop,
+}
+
+// Where a key works, the same key can do something else in the grid than in the preview
+#[derive(PartialEq, Clone, Copy)]
+enum KeyScope {
+    Anywhere,
+    Grid,
+    Preview,
+}
+
+struct KeyBinding {
+    command: Command,
+    modifiers: egui::Modifiers,
+    key: Key,
+    scope: KeyScope,
+}
+
+const fn bind(
+    command: Command,
+    modifiers: egui::Modifiers,
+    key: Key,
+    scope: KeyScope,
+) -> KeyBinding {
+    KeyBinding {
+        command,
+        modifiers,
+        key,
+        scope,
+    }
+}
+
+const NONE: egui::Modifiers = egui::Modifiers::NONE;
+const COMMAND: egui::Modifiers = egui::Modifiers::COMMAND;
+const COMMAND_SHIFT: egui::Modifiers = egui::Modifiers {
+    shift: true,
+    ..egui::Modifiers::COMMAND
+};
+
+// The only place keys are assigned, the palette and the menus show the shortcuts from here
+const KEY_BINDINGS: [KeyBinding; 37] = [
+    bind(Command::Refresh, NONE, Key::F5, Anywhere),
+    bind(Command::Timings, NONE, Key::F3, Anywhere),
+    bind(Command::CloseWindow, COMMAND, Key::W, Anywhere),
+    bind(Command::NextWindow, COMMAND, Key::Tab, Anywhere),
+    bind(Command::Rename, NONE, Key::F2, Anywhere),
+    bind(Command::EditTags, NONE, Key::T, Anywhere),
+    bind(Command::Rate(0), NONE, Key::Num0, Anywhere),
+    bind(Command::Rate(1), NONE, Key::Num1, Anywhere),
+    bind(Command::Rate(2), NONE, Key::Num2, Anywhere),
+    bind(Command::Rate(3), NONE, Key::Num3, Anywhere),
+    bind(Command::Rate(4), NONE, Key::Num4, Anywhere),
+    bind(Command::Rate(5), NONE, Key::Num5, Anywhere),
+    bind(Command::OpenRandom, NONE, Key::X, Anywhere),
+    bind(Command::BrowseFolder, NONE, Key::O, Anywhere),
+    bind(Command::ClearMarks, NONE, Key::Escape, Grid),
+    bind(Command::DeleteMarked, NONE, Key::Delete, Grid),
+    bind(Command::ReverseOrder, NONE, Key::R, Grid),
+    bind(Command::UpdateCollection, NONE, Key::Q, Grid),
+    bind(Command::DensityPreset(0), COMMAND, Key::Num1, Grid),
+    bind(Command::DensityPreset(1), COMMAND, Key::Num2, Grid),
+    bind(Command::DensityPreset(2), COMMAND, Key::Num3, Grid),
+    bind(Command::ClosePreview, NONE, Key::Escape, Preview),
+    bind(Command::ShowInGrid, NONE, Key::G, Preview),
+    bind(Command::Fullscreen, NONE, Key::F, Preview),
+    bind(Command::PreviousPage, NONE, Key::ArrowUp, Preview),
+    bind(Command::NextPage, NONE, Key::ArrowDown, Preview),
+    bind(Command::TogglePlayback, NONE, Key::Space, Preview),
+    bind(Command::LoopStart, NONE, Key::A, Preview),
+    bind(Command::LoopEnd, NONE, Key::B, Preview),
+    bind(Command::ClearLoop, NONE, Key::C, Preview),
+    bind(Command::CyclePlaybackMode, NONE, Key::L, Preview),
+    bind(Command::VideoFit, NONE, Key::Z, Preview),
+    bind(Command::AddFrame, NONE, Key::S, Preview),
+    bind(Command::CopyDataUrl, COMMAND_SHIFT, Key::C, Preview),
+    bind(Command::Eyedropper, NONE, Key::E, Preview),
+    bind(Command::Ruler, NONE, Key::M, Preview),
+    bind(Command::Crop, NONE, Key::K, Preview),
+];
+
+impl KeyBinding {
+    // Shift is only looked at when the binding asks for it, Ctrl always has to match,
+    // so Ctrl+1 switches the density preset instead of rating with 1
+    fn pressed(&self, ctx: &egui::Context) -> bool {
+        let modifiers = ctx.input(|i| i.modifiers);
+
+        key_pressed(ctx, self.key)
+            && modifiers.command == self.modifiers.command
+            && (modifiers.shift || !self.modifiers.shift)
+    }
+
+    fn label(&self) -> String {
+        let mut label = String::new();
+
+        if self.modifiers.command {
+            label.push_str("Ctrl+");
+        }
+
+        if self.modifiers.shift {
+            label.push_str("Shift+");
+        }
+
+        label.push_str(self.key.name());
+        label
+    }
+}
+
+// The commands whose keys went down this frame, for the view that is shown
+pub fn pressed_commands(ctx: &egui::Context, in_preview: bool) -> Vec<Command> {
+    KEY_BINDINGS
+        .iter()
+        .filter(|binding| match binding.scope {
+            Anywhere => true,
+            Grid => !in_preview,
+            Preview => in_preview,
+        })
+        .filter(|binding| binding.pressed(ctx))
+        .map(|binding| binding.command)
+        .collect()
+}
+
+impl Command {
+    pub const ALL: [Command; 63] = [
+        Command::SelectFiles,
+        Command::SelectFolders,
+        Command::Refresh,
+        Command::Clear,
+        Command::MarkDuplicates,
+        Command::ClearMarks,
+        Command::DeleteMarked,
+        Command::Rename,
+        Command::BatchRename,
+        Command::EditTags,
+        Command::Rate(0),
+        Command::Rate(1),
+        Command::Rate(2),
+        Command::Rate(3),
+        Command::Rate(4),
+        Command::Rate(5),
+        Command::Convert,
+        Command::ReverseOrder,
+        Command::OpenRandom,
+        Command::BrowseFolder,
+        Command::ExportList,
+        Command::ContactSheet,
+        Command::UpdateCollection,
+        Command::ShowCollection,
+        Command::ExportCollection,
+        Command::NewWindow,
+        Command::CloseWindow,
+        Command::NextWindow,
+        Command::Settings,
+        Command::Stats,
+        Command::FilterImages,
+        Command::FilterAnimated,
+        Command::FilterVideos,
+        Command::FilterFailed,
+        Command::FilterMarkedOnly,
+        Command::SortByPath,
+        Command::SortManually,
+        Command::SortDescending,
+        Command::GroupByType,
+        Command::DensityPreset(0),
+        Command::DensityPreset(1),
+        Command::DensityPreset(2),
+        Command::GridMinimap,
+        Command::Timings,
+        Command::ClosePreview,
+        Command::ShowInGrid,
+        Command::Fullscreen,
+        Command::PreviousPage,
+        Command::NextPage,
+        Command::TogglePlayback,
+        Command::LoopStart,
+        Command::LoopEnd,
+        Command::ClearLoop,
+        Command::CyclePlaybackMode,
+        Command::VideoFit,
+        Command::AddFrame,
+        Command::CopyDataUrl,
+        Command::Print,
+        Command::RotateClockwise,
+        Command::RotateCounterClockwise,
+        Command::Eyedropper,
+        Command::Ruler,
+        Command::Crop,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Command::SelectFiles => "Select files",
+            Command::SelectFolders => "Select folders",
+            Command::Refresh => "Refresh",
+            Command::Clear => "Clear all entries",
+            Command::MarkDuplicates => "Mark duplicates",
+            Command::ClearMarks => "Clear marks",
+            Command::DeleteMarked => "Delete marked",
+            Command::Rename => "Rename",
+            Command::BatchRename => "Batch rename marked",
+            Command::EditTags => "Edit tags",
+            Command::Rate(rating) => [
+                "Rate 0 stars",
+                "Rate 1 star",
+                "Rate 2 stars",
+                "Rate 3 stars",
+                "Rate 4 stars",
+                "Rate 5 stars",
+            ][*rating as usize],
+            Command::Convert => "Convert marked",
+            Command::ReverseOrder => "Reverse order",
+            Command::OpenRandom => "Open a random entry",
+            Command::BrowseFolder => "Browse the folder of the file",
+            Command::ExportList => "Export list",
+            Command::ContactSheet => "Export contact sheet of marked",
+            Command::UpdateCollection => "Add marked to collection, or remove them from it",
+            Command::ShowCollection => "Toggle collection view",
+            Command::ExportCollection => "Export collection",
+            Command::NewWindow => "New window",
+            Command::CloseWindow => "Close the focused window, or clear all entries without one",
+            Command::NextWindow => "Bring the next window to the front",
+            Command::Settings => "Open settings",
+            Command::Stats => "Open stats",
+            Command::FilterImages => "Toggle images filter",
+            Command::FilterAnimated => "Toggle animated filter",
+            Command::FilterVideos => "Toggle videos filter",
+            Command::FilterFailed => "Toggle failed filter",
+            Command::FilterMarkedOnly => "Toggle showing marked only",
+            Command::SortByPath => "Sort by path",
+            Command::SortManually => "Sort manually",
+            Command::SortDescending => "Toggle descending sort",
+            Command::GroupByType => "Toggle grouping by media type",
+            Command::DensityPreset(index) => {
+                ["Density preset 1", "Density preset 2", "Density preset 3"][*index]
+            }
+            Command::GridMinimap => "Toggle grid minimap",
+            Command::Timings => "Toggle frame timings",
+            Command::ClosePreview => "Close preview",
+            Command::ShowInGrid => "Show in grid",
+            Command::Fullscreen => "Toggle fullscreen",
+            Command::PreviousPage => "Previous page",
+            Command::NextPage => "Next page",
+            Command::TogglePlayback => "Play or pause video",
+            Command::LoopStart => "Set loop start",
+            Command::LoopEnd => "Set loop end",
+            Command::ClearLoop => "Clear loop",
+            Command::CyclePlaybackMode => "Cycle playback mode",
+            Command::VideoFit => "Toggle video fit",
+            Command::AddFrame => "Add frame to gallery",
+            Command::CopyDataUrl => "Copy as data URL",
+            Command::Print => "Print",
+            Command::RotateClockwise => "Rotate clockwise and save",
+            Command::RotateCounterClockwise => "Rotate counter-clockwise and save",
+            Command::Eyedropper => "Toggle eyedropper",
+            Command::Ruler => "Toggle ruler",
+            Command::Crop => "Toggle crop",
+        }
+    }
+
+    pub fn shortcut(&self) -> Option<String> {
+        KEY_BINDINGS
+            .iter()
+            .find(|binding| binding.command == *self)
+            .map(|binding| binding.label())
+    }
+
+    // For menu entries and hints, the text followed by the shortcut if there is one
+    pub fn hint(&self, text: &str) -> String {
+        match self.shortcut() {
+            Some(shortcut) => format!("{} ({})", text, shortcut),
+            None => text.to_string(),
+        }
+    }
+
+    // These act on the file the preview shows, so the palette only lists them there
+    fn needs_preview(&self) -> bool {
+        matches!(
+            self,
+            Command::ClosePreview
+                | Command::ShowInGrid
+                | Command::Fullscreen
+                | Command::PreviousPage
+                | Command::NextPage
+                | Command::TogglePlayback
+                | Command::LoopStart
+                | Command::LoopEnd
+                | Command::ClearLoop
+                | Command::CyclePlaybackMode
+                | Command::VideoFit
+                | Command::AddFrame
+                | Command::CopyDataUrl
+                | Command::Print
+                | Command::RotateClockwise
+                | Command::RotateCounterClockwise
+                | Command::Eyedropper
+                | Command::Ruler
+                | Command::Crop
+        )
+    }
+
+    // Every word of the query has to be in the label, in any order
+    fn matches(&self, query: &str) -> bool {
+        let label = self.label().to_lowercase();
+
+        query
+            .to_lowercase()
+            .split_whitespace()
+            .all(|word| label.contains(word))
+    }
+}
+
+#[derive(Default)]
+pub struct CommandPalette {
+    pub open: bool,
+    query: String,
+    selected: usize,
+}
+
+impl CommandPalette {
+    pub fn new() -> Self {
+        Self {
+            open: true,
+            ..Default::default()
+        }
+    }
+}
+
+// Returns the command that was picked, the palette closes along with it or on Escape
+pub fn build_command_palette(
+    palette: &mut CommandPalette,
+    in_preview: bool,
+    ctx: &egui::Context,
+) -> Option<Command> {
+    let commands = Command::ALL
+        .into_iter()
+        .filter(|command| in_preview || !command.needs_preview())
+        .filter(|command| command.matches(&palette.query))
+        .collect::<Vec<Command>>();
+
+    // The query field keeps the focus, so the keys are read directly instead of through key_pressed
+    let (escape, enter, up, down) = ctx.input(|i| {
+        (
+            i.key_pressed(egui::Key::Escape),
+            i.key_pressed(egui::Key::Enter),
+            i.key_pressed(egui::Key::ArrowUp),
+            i.key_pressed(egui::Key::ArrowDown),
+        )
+    });
+
+    if escape {
+        palette.open = false;
+        return None;
+    }
+
+    if down && palette.selected + 1 < commands.len() {
+        palette.selected += 1;
+    }
+
+    if up {
+        palette.selected = palette.selected.saturating_sub(1);
+    }
+
+    palette.selected = palette.selected.min(commands.len().saturating_sub(1));
+
+    let mut picked = None;
+
+    egui::Window::new("Command palette")
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 40.0))
+        .show(ctx, |ui| {
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut palette.query)
+                    .hint_text("Type a command")
+                    .desired_width(360.0),
+            );
+
+            if response.changed() {
+                palette.selected = 0;
+            }
+
+            response.request_focus();
+
+            ui.separator();
+
+            if commands.is_empty() {
+                ui.label("No matching commands");
+            }
+
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .show(ui, |ui| {
+                    for (index, command) in commands.iter().enumerate() {
+                        let is_selected = index == palette.selected;
+
+                        let row = ui.horizontal(|ui| {
+                            let label = ui.selectable_label(is_selected, command.label());
+
+                            if let Some(shortcut) = command.shortcut() {
+                                ui.with_layout(
+                                    egui::Layout::right_to_left(egui::Align::Center),
+                                    |ui| ui.weak(shortcut),
+                                );
+                            }
+
+                            label
+                        });
+
+                        if is_selected && (up || down) {
+                            row.response.scroll_to_me(None);
+                        }
+
+                        if row.inner.clicked() {
+                            picked = Some(*command);
+                        }
+                    }
+                });
+        });
+
+    if enter {
+        picked = commands.get(palette.selected).copied();
+    }
+
+    if picked.is_some() {
+        palette.open = false;
+    }
+
+    picked
+}
@@ -0,0 +1,47 @@
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+pub struct DecodeQueue {
+    // Reversed, so the next file in the original order can be popped off the end
+    order: Vec<PathBuf>,
+    pending: HashSet<PathBuf>,
+    // Set by the grid on the UI thread while the loader threads take files from here
+    visible: Option<Arc<Mutex<Vec<PathBuf>>>>,
+}
+
+impl DecodeQueue {
+    pub fn new(files: Vec<PathBuf>, visible: Option<Arc<Mutex<Vec<PathBuf>>>>) -> Self {
+        let pending = files.iter().cloned().collect();
+        let order = files.into_iter().rev().collect();
+
+        DecodeQueue {
+            order,
+            pending,
+            visible,
+        }
+    }
+
+    // Files that were taken because they were visible stay in the order and are skipped
+    // once they come up there
+    pub fn next(&mut self) -> Option<PathBuf> {
+        if let Some(visible) = &self.visible {
+            let visible = visible.lock().unwrap();
+
+            if let Some(file) = visible.iter().find(|file| self.pending.contains(*file)) {
+                self.pending.remove(file);
+                return Some(file.clone());
+            }
+        }
+
+        while let Some(file) = self.order.pop() {
+            if self.pending.remove(&file) {
+                return Some(file);
+            }
+        }
+
+        None
+    }
+}
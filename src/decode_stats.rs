@@ -0,0 +1,57 @@
+use crate::{image_entry::ImageDecoder, utils::get_extension};
+use std::{collections::BTreeMap, path::PathBuf, sync::Mutex};
+
+#[derive(Default, Clone)]
+pub struct FormatStats {
+    // Decoder label to the number of files it decoded
+    pub decoded: BTreeMap<String, usize>,
+    pub failed: usize,
+}
+
+static DECODE_STATS: Mutex<BTreeMap<String, FormatStats>> = Mutex::new(BTreeMap::new());
+
+fn format_of(file: &PathBuf) -> String {
+    get_extension(file).unwrap_or_else(|| "(none)".to_string())
+}
+
+pub fn record_decoded(file: &PathBuf, decoder: ImageDecoder) {
+    let mut stats = DECODE_STATS.lock().unwrap();
+    let format_stats = stats.entry(format_of(file)).or_default();
+
+    *format_stats
+        .decoded
+        .entry(decoder.label().to_string())
+        .or_default() += 1;
+}
+
+// Only called once every decoder in the chain has given up on the file
+pub fn record_failed(file: &PathBuf) {
+    let mut stats = DECODE_STATS.lock().unwrap();
+
+    stats.entry(format_of(file)).or_default().failed += 1;
+}
+
+pub fn by_format() -> Vec<(String, FormatStats)> {
+    let stats = DECODE_STATS.lock().unwrap();
+
+    stats
+        .iter()
+        .map(|(format, format_stats)| (format.clone(), format_stats.clone()))
+        .collect()
+}
+
+pub fn by_decoder() -> BTreeMap<String, usize> {
+    let mut totals = BTreeMap::new();
+
+    for (_, format_stats) in by_format() {
+        for (decoder, count) in format_stats.decoded {
+            *totals.entry(decoder).or_default() += count;
+        }
+    }
+
+    totals
+}
+
+pub fn reset() {
+    DECODE_STATS.lock().unwrap().clear();
+}
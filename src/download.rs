@@ -0,0 +1,81 @@
+use log::info;
+use std::{
+    collections::hash_map::DefaultHasher,
+    env,
+    hash::{Hash, Hasher},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// A server that stops answering or never stops sending would otherwise hold the load forever
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_DOWNLOAD_SIZE: u64 = 1024 * 1024 * 1024;
+
+pub fn is_url(arg: &str) -> bool {
+    let arg = arg.to_lowercase();
+
+    arg.starts_with("http://") || arg.starts_with("https://")
+}
+
+pub fn downloads_dir() -> PathBuf {
+    env::temp_dir().join("gallery").join("downloads")
+}
+
+// The extension of the url is kept, so the file goes through the same decoders
+// as it would if it was opened from the disk
+fn download_path(url: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+
+    let url_path = url.split(['?', '#']).next().unwrap_or_default();
+    let extension = Path::new(url_path)
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    downloads_dir().join(format!("{:016x}{}", hasher.finish(), extension))
+}
+
+pub fn download(url: &str) -> Result<PathBuf, String> {
+    let path = download_path(url);
+
+    std::fs::create_dir_all(downloads_dir())
+        .map_err(|err| format!("Could not create downloads folder: {}", err))?;
+
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .build();
+
+    let response = agent.get(url).call().map_err(|err| match err {
+        ureq::Error::Status(code, _) => format!("Download failed: server returned {}", code),
+        ureq::Error::Transport(transport) => format!("Download failed: {}", transport),
+    })?;
+
+    let mut file = std::fs::File::create(&path)
+        .map_err(|err| format!("Could not create {:?}: {}", path, err))?;
+
+    // One byte more than the limit is read, so a body of exactly the limit still fits
+    let mut body = response.into_reader().take(MAX_DOWNLOAD_SIZE + 1);
+
+    let error = match io::copy(&mut body, &mut file) {
+        Ok(size) if size > MAX_DOWNLOAD_SIZE => Some(format!(
+            "Download failed: larger than {} MB",
+            MAX_DOWNLOAD_SIZE / 1024 / 1024
+        )),
+        Ok(_) => None,
+        Err(err) => Some(format!("Download failed: {}", err)),
+    };
+
+    if let Some(error) = error {
+        drop(file);
+        let _ = std::fs::remove_file(&path);
+        return Err(error);
+    }
+
+    info!("Downloaded {} to {:?}", url, path);
+
+    Ok(path)
+}
@@ -0,0 +1,1238 @@
+use crate::{
+    image_entry::{ImageAdjustments, ImageDecoder, ImageEntry},
+    thumbnail_cache,
+    utils::{format_time, is_video, thumbnail_decode_size},
+    GalleryEntry, MediaType, Settings,
+};
+use eframe::{
+    egui::{Color32, FontId},
+    epaint::text::{FontDefinitions, Fonts, LayoutJob, TextWrapping},
+};
+use ffmpeg_next::{
+    codec::{self, context::Context as CodecContext},
+    encoder,
+    format::{self, context::Output as OutputContext, pixel::Pixel},
+    media::Type::{Audio, Subtitle, Video},
+    software::scaling::{context::Context as ScalingContext, flag::Flags},
+    util::frame::video::Video as VideoFrame,
+    Packet, Rational, Rescale,
+};
+use image::{
+    codecs::jpeg::JpegEncoder, imageops, DynamicImage, ExtendedColorType, Rgba, RgbaImage,
+};
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+#[derive(Serialize)]
+pub struct ExportRecord {
+    pub path: String,
+    pub media_type: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub file_size: Option<u64>,
+    pub duration_ms: Option<u64>,
+    pub failed: bool,
+    pub corrupt: bool,
+}
+
+impl ExportRecord {
+    pub fn from_entry(entry: &GalleryEntry) -> ExportRecord {
+        let media_type = match entry.media_type {
+            MediaType::ImageStill => "image",
+            MediaType::ImageAnimated => "animated",
+            MediaType::Video => "video",
+        };
+
+        let (width, height, duration_ms) = match entry.media_type {
+            MediaType::Video => read_video_info(&entry.path),
+            _ => match image::image_dimensions(&entry.path) {
+                Ok((width, height)) => (Some(width), Some(height), None),
+                Err(_) => (None, None, None),
+            },
+        };
+
+        ExportRecord {
+            path: entry.path.to_string_lossy().to_string(),
+            media_type: media_type.to_string(),
+            width,
+            height,
+            file_size: std::fs::metadata(&entry.path)
+                .map(|metadata| metadata.len())
+                .ok(),
+            duration_ms,
+            failed: entry.failed,
+            corrupt: entry.corrupt,
+        }
+    }
+}
+
+// Read from the headers, the thumbnails are scaled down and don't know the original size
+// Only the header is read, for thumbnails that came from the cache and didn't see the original.
+// The size is swapped for images that are shown turned by a quarter
+pub fn read_dimensions(
+    path: &PathBuf,
+    media_type: &MediaType,
+    settings: &Settings,
+) -> Option<(u32, u32)> {
+    match media_type {
+        MediaType::Video => {
+            let (width, height, _) = read_video_info(path);
+
+            width.zip(height)
+        }
+        _ => {
+            let (width, height) = image::image_dimensions(path).ok()?;
+            let orientation = settings
+                .apply_exif_orientation
+                .then(|| ImageEntry::read_exif_orientation(path))
+                .flatten();
+
+            match orientation {
+                Some(5..=8) => Some((height, width)),
+                _ => Some((width, height)),
+            }
+        }
+    }
+}
+
+fn read_video_info(path: &PathBuf) -> (Option<u32>, Option<u32>, Option<u64>) {
+    let ictx = match format::input(path) {
+        Ok(ictx) => ictx,
+        Err(_) => return (None, None, None),
+    };
+
+    let duration_ms = match ictx.duration() {
+        duration if duration >= 0 => Some((duration as f64 / 1000.0).round() as u64),
+        _ => None,
+    };
+
+    let dimensions = ictx
+        .streams()
+        .best(Video)
+        .and_then(|stream| CodecContext::from_parameters(stream.parameters()).ok())
+        .and_then(|context| context.decoder().video().ok())
+        .map(|decoder| (decoder.width(), decoder.height()));
+
+    match dimensions {
+        Some((width, height)) => (Some(width), Some(height), duration_ms),
+        None => (None, None, duration_ms),
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn optional_field<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn to_csv(records: &[ExportRecord]) -> String {
+    let mut csv =
+        String::from("path,media_type,width,height,file_size,duration_ms,failed,corrupt\n");
+
+    for record in records {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{}\n",
+            csv_field(&record.path),
+            record.media_type,
+            optional_field(record.width),
+            optional_field(record.height),
+            optional_field(record.file_size),
+            optional_field(record.duration_ms),
+            record.failed,
+            record.corrupt
+        ));
+    }
+
+    csv
+}
+
+// The format is picked from the extension, anything other than .csv is written as JSON
+pub fn export_entries(entries: &[GalleryEntry], path: &PathBuf) -> Result<(), String> {
+    let records = entries
+        .iter()
+        .map(ExportRecord::from_entry)
+        .collect::<Vec<ExportRecord>>();
+
+    let is_csv = path
+        .extension()
+        .map(|extension| extension.eq_ignore_ascii_case("csv"))
+        .unwrap_or(false);
+
+    let contents = if is_csv {
+        to_csv(&records)
+    } else {
+        serde_json::to_string_pretty(&records).map_err(|err| err.to_string())?
+    };
+
+    std::fs::write(path, contents).map_err(|err| err.to_string())?;
+
+    info!("Exported {} entries to {:?}", records.len(), path);
+
+    Ok(())
+}
+
+// Files with the same name from different folders get a number appended instead of
+// overwriting each other
+fn unique_destination(folder: &PathBuf, file: &PathBuf) -> PathBuf {
+    let file_name = file.file_name().unwrap_or_default();
+    let mut destination = folder.join(file_name);
+
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = file
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut number = 1;
+
+    while destination.exists() {
+        destination = folder.join(format!("{} ({}){}", stem, number, extension));
+        number += 1;
+    }
+
+    destination
+}
+
+// The format follows the extension. JPEG has no alpha channel, so it's dropped for it
+pub fn save_image(image: &RgbaImage, path: &PathBuf) -> Result<(), String> {
+    let is_jpeg = path
+        .extension()
+        .map(|extension| {
+            extension.eq_ignore_ascii_case("jpg") || extension.eq_ignore_ascii_case("jpeg")
+        })
+        .unwrap_or(false);
+
+    let result = if is_jpeg {
+        DynamicImage::ImageRgba8(image.clone()).to_rgb8().save(path)
+    } else {
+        image.save(path)
+    };
+
+    result.map_err(|err| format!("Could not write {:?}: {}", path, err))?;
+
+    info!(
+        "Saved {}x{} image to {:?}",
+        image.width(),
+        image.height(),
+        path
+    );
+
+    Ok(())
+}
+
+pub fn copy_files(files: &[PathBuf], folder: &PathBuf) -> Result<(), String> {
+    std::fs::create_dir_all(folder).map_err(|err| err.to_string())?;
+
+    let mut copied = 0;
+
+    for file in files {
+        let destination = unique_destination(folder, file);
+
+        match std::fs::copy(file, &destination) {
+            Ok(_) => copied += 1,
+            Err(err) => warn!("Failed to copy {:?} to {:?}: {}", file, destination, err),
+        }
+    }
+
+    info!("Copied {} of {} files to {:?}", copied, files.len(), folder);
+
+    Ok(())
+}
+
+const CONTACT_SHEET_BACKGROUND: Rgba<u8> = Rgba([32, 32, 32, 255]);
+const CAPTION_FONT_SIZE: f32 = 12.0;
+const CAPTION_HEIGHT: u32 = 18;
+
+// Textures of the grid can't be read back. The cached thumbnail is used when there is one,
+// otherwise the file is decoded again and scaled down the same way the thumbnails are.
+// The cache doesn't know which decoder made a thumbnail, so it's skipped for files that were
+// opened with a specific one
+fn thumbnail_pixels(
+    file: &PathBuf,
+    forced_decoder: Option<ImageDecoder>,
+    size: f32,
+    settings: &Settings,
+) -> Option<RgbaImage> {
+    if settings.thumbnail_cache && forced_decoder.is_none() {
+        if let Some(color_image) = thumbnail_cache::load(file, size, settings) {
+            let [width, height] = color_image.size;
+            let pixels = color_image
+                .pixels
+                .iter()
+                .flat_map(|pixel| pixel.to_srgba_unmultiplied())
+                .collect::<Vec<u8>>();
+
+            return RgbaImage::from_raw(width as u32, height as u32, pixels);
+        }
+    }
+
+    match ImageEntry::decode_image(file, settings, forced_decoder) {
+        Ok(image) => Some(
+            DynamicImage::ImageRgba8(image)
+                .thumbnail(size as u32, size as u32)
+                .into_rgba8(),
+        ),
+        Err(err) => {
+            warn!("Failed to decode {:?} for the contact sheet: {}", file, err);
+            None
+        }
+    }
+}
+
+// Glyphs are copied from the font atlas, names wider than the cell end with an ellipsis
+fn draw_caption(sheet: &mut RgbaImage, fonts: &Fonts, text: &str, left: u32, top: u32, width: u32) {
+    let mut job = LayoutJob::simple_singleline(
+        text.to_string(),
+        FontId::proportional(CAPTION_FONT_SIZE),
+        Color32::WHITE,
+    );
+    job.wrap = TextWrapping {
+        max_width: width as f32,
+        max_rows: 1,
+        break_anywhere: true,
+        overflow_character: Some('…'),
+    };
+
+    let galley = fonts.layout_job(job);
+    let atlas = fonts.image();
+
+    // Centered in the cell like the thumbnail above it
+    let left = left as f32 + ((width as f32 - galley.size().x) / 2.0).max(0.0);
+
+    for row in &galley.rows {
+        for glyph in &row.glyphs {
+            let uv = glyph.uv_rect;
+            let glyph_left = (left + glyph.pos.x + uv.offset.x).round() as i64;
+            let glyph_top = (top as f32 + glyph.pos.y + uv.offset.y).round() as i64;
+
+            for v in uv.min[1]..uv.max[1] {
+                for u in uv.min[0]..uv.max[0] {
+                    let coverage = atlas.pixels[v as usize * atlas.size[0] + u as usize];
+                    let x = glyph_left + (u - uv.min[0]) as i64;
+                    let y = glyph_top + (v - uv.min[1]) as i64;
+
+                    if coverage <= 0.0 || x < 0 || y < 0 {
+                        continue;
+                    }
+
+                    let (x, y) = (x as u32, y as u32);
+
+                    if x >= sheet.width() || y >= sheet.height() {
+                        continue;
+                    }
+
+                    let pixel = sheet.get_pixel_mut(x, y);
+
+                    for channel in 0..3 {
+                        let background = pixel[channel] as f32;
+                        pixel[channel] = (background + (255.0 - background) * coverage) as u8;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Every thumbnail is centered in a square cell as large as the thumbnail size,
+// with its caption below when there is one
+fn compose_contact_sheet(
+    thumbnails: &[(String, RgbaImage)],
+    cell_size: u32,
+    settings: &Settings,
+) -> RgbaImage {
+    let columns = settings
+        .contact_sheet_columns
+        .clamp(1, thumbnails.len().max(1)) as u32;
+    let rows = (thumbnails.len() as u32).div_ceil(columns);
+    let spacing = settings.contact_sheet_spacing;
+    let caption_height = if settings.contact_sheet_captions {
+        CAPTION_HEIGHT
+    } else {
+        0
+    };
+
+    let row_height = cell_size + caption_height;
+    let width = columns * cell_size + (columns + 1) * spacing;
+    let height = rows * row_height + (rows + 1) * spacing;
+
+    let mut sheet = RgbaImage::from_pixel(width, height, CONTACT_SHEET_BACKGROUND);
+    let fonts = settings
+        .contact_sheet_captions
+        .then(|| Fonts::new(1.0, 4096, FontDefinitions::default()));
+
+    for (index, (name, image)) in thumbnails.iter().enumerate() {
+        let column = index as u32 % columns;
+        let row = index as u32 / columns;
+        let cell_left = spacing + column * (cell_size + spacing);
+        let cell_top = spacing + row * (row_height + spacing);
+
+        let left = cell_left + cell_size.saturating_sub(image.width()) / 2;
+        let top = cell_top + cell_size.saturating_sub(image.height()) / 2;
+
+        imageops::overlay(&mut sheet, image, left as i64, top as i64);
+
+        if let Some(fonts) = &fonts {
+            draw_caption(
+                &mut sheet,
+                fonts,
+                name,
+                cell_left,
+                cell_top + cell_size + 2,
+                cell_size,
+            );
+        }
+    }
+
+    sheet
+}
+
+// Shared with the thread that composes the sheet, so the UI can show that it's running and
+// what went wrong
+#[derive(Default)]
+pub struct ContactSheetExport {
+    // Set once a file was picked and the thumbnails are being collected
+    pub started: AtomicBool,
+    pub finished: AtomicBool,
+    // Shown in the progress window until it's closed
+    pub error: Mutex<Option<String>>,
+}
+
+pub fn export_contact_sheet(
+    files: &[(PathBuf, Option<ImageDecoder>)],
+    destination: &PathBuf,
+    settings: &Settings,
+) -> Result<(), String> {
+    let size = thumbnail_decode_size(settings);
+
+    let thumbnails = files
+        .iter()
+        .filter_map(|(file, forced_decoder)| {
+            let pixels = thumbnail_pixels(file, *forced_decoder, size, settings);
+
+            if pixels.is_none() {
+                warn!(
+                    "Leaving {:?} out of the contact sheet, it has no thumbnail",
+                    file
+                );
+            }
+
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+
+            pixels.map(|pixels| (name.to_string(), pixels))
+        })
+        .collect::<Vec<(String, RgbaImage)>>();
+
+    if thumbnails.is_empty() {
+        return Err("None of the marked entries has a thumbnail".to_string());
+    }
+
+    let sheet = compose_contact_sheet(&thumbnails, size as u32, settings);
+
+    save_image(&sheet, destination)
+}
+
+// Shared with the thread that writes the frames, so the UI can show how far it got
+#[derive(Default)]
+pub struct FrameExport {
+    // Set once a folder was picked and the frames are being decoded
+    pub started: AtomicBool,
+    pub written: AtomicUsize,
+    pub total: AtomicUsize,
+    pub finished: AtomicBool,
+    pub cancelled: AtomicBool,
+    // Shown in the progress window until it's closed
+    pub error: Mutex<Option<String>>,
+}
+
+// The indices are zero-padded, so the frames sort the same way by name as they play
+fn frame_path(folder: &PathBuf, file: &PathBuf, index: usize, digits: usize) -> PathBuf {
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+
+    folder.join(format!("{}_{:0digits$}.png", stem, index, digits = digits))
+}
+
+fn frame_digits(total: usize) -> usize {
+    total.max(1).to_string().len().max(4)
+}
+
+fn write_frame(frame: &RgbaImage, path: &PathBuf, progress: &FrameExport) -> Result<(), String> {
+    frame
+        .save(path)
+        .map_err(|err| format!("Could not write {:?}: {}", path, err))?;
+
+    progress.written.fetch_add(1, Ordering::SeqCst);
+
+    Ok(())
+}
+
+// Videos, and animations that needed FFmpeg to open, are decoded one frame at a time,
+// so a long video doesn't have to fit in memory before anything is written
+fn export_video_frames(
+    file: &PathBuf,
+    folder: &PathBuf,
+    progress: &FrameExport,
+) -> Result<usize, String> {
+    let mut ictx = format::input(file).map_err(|err| err.to_string())?;
+    let input = ictx.streams().best(Video).ok_or("No video stream found")?;
+
+    let video_stream_index = input.index();
+    let context =
+        CodecContext::from_parameters(input.parameters()).map_err(|err| err.to_string())?;
+    let mut decoder = context.decoder().video().map_err(|err| err.to_string())?;
+
+    if decoder.width() == 0 || decoder.height() == 0 {
+        return Err("Video stream has no dimensions".to_string());
+    }
+
+    // Not every container stores the number of frames, so it's estimated from the duration
+    let total = match input.frames() {
+        frames if frames > 0 => frames as usize,
+        _ => {
+            let seconds = ictx.duration().max(0) as f64 / 1_000_000.0;
+            (seconds * f64::from(input.avg_frame_rate())).round() as usize
+        }
+    };
+
+    progress.total.store(total, Ordering::SeqCst);
+
+    let digits = frame_digits(total);
+
+    let mut scaler = ScalingContext::get(
+        decoder.format(),
+        decoder.width(),
+        decoder.height(),
+        Pixel::RGBA,
+        decoder.width(),
+        decoder.height(),
+        Flags::BILINEAR,
+    )
+    .map_err(|err| err.to_string())?;
+
+    let mut index = 0;
+
+    let mut write_decoded_frames = |decoder: &mut ffmpeg_next::decoder::Video| {
+        let mut frame = VideoFrame::empty();
+
+        while decoder.receive_frame(&mut frame).is_ok() {
+            if progress.cancelled.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+
+            let mut rgba_frame = VideoFrame::empty();
+            scaler
+                .run(&frame, &mut rgba_frame)
+                .map_err(|err| err.to_string())?;
+
+            let width = rgba_frame.width() as usize;
+            let height = rgba_frame.height() as usize;
+            let stride = rgba_frame.stride(0);
+
+            let mut buffer = Vec::with_capacity(width * height * 4);
+
+            for y in 0..height {
+                let start = y * stride;
+                buffer.extend_from_slice(&rgba_frame.data(0)[start..start + width * 4]);
+            }
+
+            let image = RgbaImage::from_raw(width as u32, height as u32, buffer)
+                .ok_or("Unexpected layout of the scaled video frame")?;
+
+            write_frame(&image, &frame_path(folder, file, index, digits), progress)?;
+            index += 1;
+        }
+
+        Ok::<(), String>(())
+    };
+
+    for (stream, packet) in ictx.packets() {
+        if progress.cancelled.load(Ordering::SeqCst) {
+            break;
+        }
+
+        if stream.index() == video_stream_index {
+            decoder
+                .send_packet(&packet)
+                .map_err(|err| err.to_string())?;
+            write_decoded_frames(&mut decoder)?;
+        }
+    }
+
+    // Frames the decoder still holds on to
+    if !progress.cancelled.load(Ordering::SeqCst) && decoder.send_eof().is_ok() {
+        write_decoded_frames(&mut decoder)?;
+    }
+
+    Ok(index)
+}
+
+// Videos go through FFmpeg, other files through the decoder they were opened with
+pub fn export_frames(
+    file: &PathBuf,
+    decoder: ImageDecoder,
+    settings: &Settings,
+    folder: &PathBuf,
+    progress: &FrameExport,
+) -> Result<(), String> {
+    std::fs::create_dir_all(folder).map_err(|err| err.to_string())?;
+
+    progress.started.store(true, Ordering::SeqCst);
+
+    let written = match decoder {
+        ImageDecoder::Ffmpeg => export_video_frames(file, folder, progress)?,
+        decoder => {
+            let frames =
+                ImageEntry::decode_frames(file, decoder, settings, &ImageAdjustments::default())
+                    .map_err(|err| err.to_string())?;
+
+            progress.total.store(frames.len(), Ordering::SeqCst);
+
+            let digits = frame_digits(frames.len());
+
+            for (index, frame) in frames.iter().enumerate() {
+                if progress.cancelled.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                write_frame(frame, &frame_path(folder, file, index, digits), progress)?;
+            }
+
+            progress.written.load(Ordering::SeqCst)
+        }
+    };
+
+    info!("Exported {} frames of {:?} to {:?}", written, file, folder);
+
+    Ok(())
+}
+
+// Shared with the thread that cuts the clip, like the frame export
+#[derive(Default)]
+pub struct ClipExport {
+    pub started: AtomicBool,
+    // Milliseconds of the clip that were written, out of its length
+    pub written: AtomicU64,
+    pub total: AtomicU64,
+    // The streams couldn't be copied, so the video is being encoded again
+    pub reencoding: AtomicBool,
+    pub finished: AtomicBool,
+    pub cancelled: AtomicBool,
+    // Shown in the progress window until it's closed
+    pub error: Mutex<Option<String>>,
+}
+
+// Timestamps of the clip are counted in milliseconds, like the times of the video player
+const MILLISECONDS: (i32, i32) = (1, 1000);
+
+fn copy_stream(octx: &mut OutputContext, stream: &format::stream::Stream) -> Result<usize, String> {
+    let mut output = octx
+        .add_stream(encoder::find(codec::Id::None))
+        .map_err(|err| err.to_string())?;
+    output.set_parameters(stream.parameters());
+
+    Ok(output.index())
+}
+
+fn write_copied_packet(
+    octx: &mut OutputContext,
+    mut packet: Packet,
+    input_time_base: Rational,
+    output_index: usize,
+    offset: i64,
+) -> Result<(), String> {
+    let output_time_base = octx
+        .stream(output_index)
+        .map(|stream| stream.time_base())
+        .ok_or("Output stream is missing")?;
+    let offset = offset.rescale(MILLISECONDS, input_time_base);
+
+    packet.set_pts(packet.pts().map(|pts| pts - offset));
+    packet.set_dts(packet.dts().map(|dts| dts - offset));
+    packet.rescale_ts(input_time_base, output_time_base);
+    packet.set_position(-1);
+    packet.set_stream(output_index);
+
+    packet
+        .write_interleaved(octx)
+        .map_err(|err| err.to_string())
+}
+
+// Without a keyframe at the start marker the clip starts at the keyframe before it,
+// otherwise the first frames couldn't be decoded
+fn copy_clip(
+    file: &PathBuf,
+    destination: &PathBuf,
+    start: u64,
+    end: u64,
+    progress: &ClipExport,
+) -> Result<(), String> {
+    let mut ictx = format::input(file).map_err(|err| err.to_string())?;
+    let mut octx = format::output(destination).map_err(|err| err.to_string())?;
+
+    let video_stream_index = ictx
+        .streams()
+        .best(Video)
+        .map(|stream| stream.index())
+        .ok_or("No video stream found")?;
+
+    let mut stream_mapping = vec![None; ictx.nb_streams() as usize];
+
+    for stream in ictx.streams() {
+        if [Video, Audio, Subtitle].contains(&stream.parameters().medium()) {
+            stream_mapping[stream.index()] = Some(copy_stream(&mut octx, &stream)?);
+        }
+    }
+
+    if start > 0 {
+        let timestamp = (start as i64).rescale(MILLISECONDS, ffmpeg_next::rescale::TIME_BASE);
+        ictx.seek(timestamp, ..timestamp)
+            .map_err(|err| err.to_string())?;
+    }
+
+    octx.write_header().map_err(|err| err.to_string())?;
+
+    // The clip is shifted to start at zero by the time of the first packet that is written
+    let mut offset = None;
+
+    for (stream, packet) in ictx.packets() {
+        if progress.cancelled.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let output_index = match stream_mapping[stream.index()] {
+            Some(output_index) => output_index,
+            None => continue,
+        };
+
+        let time_base = stream.time_base();
+        let time = match packet.pts().or(packet.dts()) {
+            Some(timestamp) => timestamp.rescale(time_base, MILLISECONDS),
+            None => continue,
+        };
+
+        if time >= end as i64 {
+            if stream.index() == video_stream_index {
+                break;
+            }
+
+            continue;
+        }
+
+        if stream.index() != video_stream_index && time < start as i64 {
+            continue;
+        }
+
+        let offset = *offset.get_or_insert(time);
+        write_copied_packet(&mut octx, packet, time_base, output_index, offset)?;
+
+        progress.written.store(
+            time.saturating_sub(start as i64).max(0) as u64,
+            Ordering::SeqCst,
+        );
+    }
+
+    octx.write_trailer().map_err(|err| err.to_string())
+}
+
+fn write_encoded_packets(
+    encoder: &mut encoder::Video,
+    octx: &mut OutputContext,
+    output_index: usize,
+    output_time_base: Rational,
+) -> Result<(), String> {
+    let mut packet = Packet::empty();
+
+    while encoder.receive_packet(&mut packet).is_ok() {
+        packet.set_stream(output_index);
+        packet.rescale_ts(MILLISECONDS, output_time_base);
+        packet
+            .write_interleaved(octx)
+            .map_err(|err| err.to_string())?;
+    }
+
+    Ok(())
+}
+
+// Only the video is encoded again, with the default encoder of the container. The other
+// streams are still copied, they don't depend on keyframes
+fn reencode_clip(
+    file: &PathBuf,
+    destination: &PathBuf,
+    start: u64,
+    end: u64,
+    progress: &ClipExport,
+) -> Result<(), String> {
+    let mut ictx = format::input(file).map_err(|err| err.to_string())?;
+    let mut octx = format::output(destination).map_err(|err| err.to_string())?;
+
+    let input = ictx.streams().best(Video).ok_or("No video stream found")?;
+    let video_stream_index = input.index();
+    let input_time_base = input.time_base();
+    let frame_rate = input.avg_frame_rate();
+
+    let context =
+        CodecContext::from_parameters(input.parameters()).map_err(|err| err.to_string())?;
+    let mut decoder = context.decoder().video().map_err(|err| err.to_string())?;
+
+    let codec = encoder::find(octx.format().codec(destination, Video))
+        .ok_or("No video encoder found for the container")?;
+    let global_header = octx.format().flags().contains(format::Flags::GLOBAL_HEADER);
+
+    let mut encoder = CodecContext::new_with_codec(codec)
+        .encoder()
+        .video()
+        .map_err(|err| err.to_string())?;
+
+    encoder.set_width(decoder.width());
+    encoder.set_height(decoder.height());
+    encoder.set_aspect_ratio(decoder.aspect_ratio());
+    encoder.set_format(Pixel::YUV420P);
+    encoder.set_frame_rate((frame_rate.numerator() > 0).then_some(frame_rate));
+    encoder.set_time_base(MILLISECONDS);
+
+    if global_header {
+        encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+    }
+
+    let mut encoder = encoder.open_as(codec).map_err(|err| err.to_string())?;
+
+    let mut stream_mapping = vec![None; ictx.nb_streams() as usize];
+
+    for stream in ictx.streams() {
+        let medium = stream.parameters().medium();
+
+        if stream.index() == video_stream_index {
+            let mut output = octx.add_stream(codec).map_err(|err| err.to_string())?;
+            output.set_parameters(&encoder);
+            stream_mapping[stream.index()] = Some(output.index());
+        } else if [Audio, Subtitle].contains(&medium) {
+            stream_mapping[stream.index()] = Some(copy_stream(&mut octx, &stream)?);
+        }
+    }
+
+    let video_output_index =
+        stream_mapping[video_stream_index].ok_or("Video stream wasn't added")?;
+
+    let mut scaler = ScalingContext::get(
+        decoder.format(),
+        decoder.width(),
+        decoder.height(),
+        Pixel::YUV420P,
+        decoder.width(),
+        decoder.height(),
+        Flags::BILINEAR,
+    )
+    .map_err(|err| err.to_string())?;
+
+    if start > 0 {
+        let timestamp = (start as i64).rescale(MILLISECONDS, ffmpeg_next::rescale::TIME_BASE);
+        ictx.seek(timestamp, ..timestamp)
+            .map_err(|err| err.to_string())?;
+    }
+
+    octx.write_header().map_err(|err| err.to_string())?;
+
+    let video_time_base = octx
+        .stream(video_output_index)
+        .map(|stream| stream.time_base())
+        .ok_or("Output stream is missing")?;
+
+    // Frames before the start marker are only decoded, so the clip starts exactly on it.
+    // Returns true once a frame past the end marker was reached
+    let mut encode_decoded_frames = |decoder: &mut ffmpeg_next::decoder::Video,
+                                     octx: &mut OutputContext| {
+        let mut frame = VideoFrame::empty();
+
+        while decoder.receive_frame(&mut frame).is_ok() {
+            let time = match frame.timestamp() {
+                Some(timestamp) => timestamp.rescale(input_time_base, MILLISECONDS),
+                None => continue,
+            };
+
+            if time < start as i64 {
+                continue;
+            }
+
+            if time >= end as i64 {
+                return Ok(true);
+            }
+
+            let mut converted_frame = VideoFrame::empty();
+            scaler
+                .run(&frame, &mut converted_frame)
+                .map_err(|err| err.to_string())?;
+            converted_frame.set_pts(Some(time - start as i64));
+
+            encoder
+                .send_frame(&converted_frame)
+                .map_err(|err| err.to_string())?;
+            write_encoded_packets(&mut encoder, octx, video_output_index, video_time_base)?;
+
+            progress
+                .written
+                .store((time - start as i64) as u64, Ordering::SeqCst);
+        }
+
+        Ok::<bool, String>(false)
+    };
+
+    let mut reached_end = false;
+
+    for (stream, packet) in ictx.packets() {
+        if progress.cancelled.load(Ordering::SeqCst) {
+            break;
+        }
+
+        if stream.index() == video_stream_index {
+            decoder
+                .send_packet(&packet)
+                .map_err(|err| err.to_string())?;
+
+            if encode_decoded_frames(&mut decoder, &mut octx)? {
+                reached_end = true;
+                break;
+            }
+
+            continue;
+        }
+
+        let output_index = match stream_mapping[stream.index()] {
+            Some(output_index) => output_index,
+            None => continue,
+        };
+
+        let time_base = stream.time_base();
+        let time = match packet.pts().or(packet.dts()) {
+            Some(timestamp) => timestamp.rescale(time_base, MILLISECONDS),
+            None => continue,
+        };
+
+        if time < start as i64 || time >= end as i64 {
+            continue;
+        }
+
+        write_copied_packet(&mut octx, packet, time_base, output_index, start as i64)?;
+    }
+
+    // Frames the decoder still holds on to
+    if !reached_end && !progress.cancelled.load(Ordering::SeqCst) && decoder.send_eof().is_ok() {
+        encode_decoded_frames(&mut decoder, &mut octx)?;
+    }
+
+    encoder.send_eof().map_err(|err| err.to_string())?;
+    write_encoded_packets(&mut encoder, &mut octx, video_output_index, video_time_base)?;
+
+    octx.write_trailer().map_err(|err| err.to_string())
+}
+
+// Copying the streams is fast and lossless, but not everything can be copied into the
+// new file. The video of those clips is encoded again instead
+pub fn export_clip(
+    file: &PathBuf,
+    destination: &PathBuf,
+    start: u64,
+    end: u64,
+    progress: &ClipExport,
+) -> Result<(), String> {
+    if end <= start {
+        return Err("The end of the clip has to come after its start".to_string());
+    }
+
+    progress.total.store(end - start, Ordering::SeqCst);
+    progress.started.store(true, Ordering::SeqCst);
+
+    let result = copy_clip(file, destination, start, end, progress).or_else(|err| {
+        warn!(
+            "Could not copy the streams of {:?}, encoding the clip again: {}",
+            file, err
+        );
+
+        progress.written.store(0, Ordering::SeqCst);
+        progress.reencoding.store(true, Ordering::SeqCst);
+
+        reencode_clip(file, destination, start, end, progress)
+    });
+
+    // A cancelled clip is cut short and a failed one is broken, neither is kept
+    if result.is_err() || progress.cancelled.load(Ordering::SeqCst) {
+        let _ = std::fs::remove_file(destination);
+    }
+
+    result?;
+
+    if progress.cancelled.load(Ordering::SeqCst) {
+        info!("Cancelled the export of the clip of {:?}", file);
+
+        return Ok(());
+    }
+
+    info!(
+        "Exported {} - {} of {:?} to {:?}",
+        format_time(start),
+        format_time(end),
+        file,
+        destination
+    );
+
+    Ok(())
+}
+
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum ConversionFormat {
+    #[default]
+    Jpeg,
+    Png,
+    WebP,
+    Tiff,
+    Bmp,
+}
+
+impl ConversionFormat {
+    pub const ALL: [ConversionFormat; 5] = [
+        ConversionFormat::Jpeg,
+        ConversionFormat::Png,
+        ConversionFormat::WebP,
+        ConversionFormat::Tiff,
+        ConversionFormat::Bmp,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ConversionFormat::Jpeg => "JPEG",
+            ConversionFormat::Png => "PNG",
+            ConversionFormat::WebP => "WebP (lossless)",
+            ConversionFormat::Tiff => "TIFF",
+            ConversionFormat::Bmp => "BMP",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ConversionFormat::Jpeg => "jpg",
+            ConversionFormat::Png => "png",
+            ConversionFormat::WebP => "webp",
+            ConversionFormat::Tiff => "tiff",
+            ConversionFormat::Bmp => "bmp",
+        }
+    }
+
+    // Only JPEG is lossy, the encoders of the others have nothing to tune
+    pub fn has_quality(&self) -> bool {
+        *self == ConversionFormat::Jpeg
+    }
+}
+
+// Videos keep their streams where the container allows it, so there is nothing to tune either
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum VideoConversionFormat {
+    #[default]
+    Mp4,
+    Mkv,
+}
+
+impl VideoConversionFormat {
+    pub const ALL: [VideoConversionFormat; 2] =
+        [VideoConversionFormat::Mp4, VideoConversionFormat::Mkv];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            VideoConversionFormat::Mp4 => "MP4",
+            VideoConversionFormat::Mkv => "MKV",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            VideoConversionFormat::Mp4 => "mp4",
+            VideoConversionFormat::Mkv => "mkv",
+        }
+    }
+}
+
+// Shared with the thread that converts the files, like the frame export
+#[derive(Default)]
+pub struct Conversion {
+    pub started: AtomicBool,
+    pub converted: AtomicUsize,
+    pub total: AtomicUsize,
+    pub finished: AtomicBool,
+    pub cancelled: AtomicBool,
+    pub errors: Mutex<Vec<(PathBuf, String)>>,
+    // The video that is being converted right now, it's written like a clip of its full length
+    pub video: ClipExport,
+}
+
+impl Conversion {
+    pub fn processed(&self) -> usize {
+        self.converted.load(Ordering::SeqCst) + self.errors.lock().unwrap().len()
+    }
+
+    // A long video is stopped as well instead of only the files after it
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.video.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+fn write_converted(
+    image: &RgbaImage,
+    path: &PathBuf,
+    format: ConversionFormat,
+    quality: u8,
+) -> Result<(), String> {
+    let result = match format {
+        ConversionFormat::Jpeg => {
+            let rgb_image = DynamicImage::ImageRgba8(image.clone()).to_rgb8();
+            let file = File::create(path).map_err(|err| err.to_string())?;
+
+            JpegEncoder::new_with_quality(BufWriter::new(file), quality).encode(
+                rgb_image.as_raw(),
+                rgb_image.width(),
+                rgb_image.height(),
+                ExtendedColorType::Rgb8,
+            )
+        }
+        ConversionFormat::Png => image.save_with_format(path, image::ImageFormat::Png),
+        ConversionFormat::WebP => image.save_with_format(path, image::ImageFormat::WebP),
+        ConversionFormat::Tiff => image.save_with_format(path, image::ImageFormat::Tiff),
+        ConversionFormat::Bmp => image.save_with_format(path, image::ImageFormat::Bmp),
+    };
+
+    result.map_err(|err| format!("Could not write {:?}: {}", path, err))
+}
+
+// Written like a clip of the full length. Timestamps of the packets don't have to start at
+// zero, so the end includes the start time of the video
+fn convert_video(
+    file: &PathBuf,
+    destination: &PathBuf,
+    progress: &ClipExport,
+) -> Result<(), String> {
+    let ictx = format::input(file).map_err(|err| err.to_string())?;
+
+    if ictx.duration() <= 0 {
+        return Err("Video has no duration".to_string());
+    }
+
+    let start_time = ictx
+        .streams()
+        .best(Video)
+        .map(|stream| {
+            stream
+                .start_time()
+                .max(0)
+                .rescale(stream.time_base(), MILLISECONDS)
+        })
+        .unwrap_or(0);
+    let end = start_time as u64 + (ictx.duration() as f64 / 1000.0).ceil() as u64;
+
+    progress.written.store(0, Ordering::SeqCst);
+    progress.reencoding.store(false, Ordering::SeqCst);
+
+    let result = export_clip(file, destination, 0, end, progress);
+    progress.reencoding.store(false, Ordering::SeqCst);
+    result?;
+
+    if progress.cancelled.load(Ordering::SeqCst) {
+        return Err("The conversion was cancelled".to_string());
+    }
+
+    Ok(())
+}
+
+// Without a folder the converted file goes next to the original. The original is never
+// overwritten, a file of the same name gets a number appended like copies do
+fn convert_file(
+    file: &PathBuf,
+    forced_decoder: Option<ImageDecoder>,
+    options: &ConversionOptions,
+    settings: &Settings,
+    progress: &Conversion,
+) -> Result<PathBuf, String> {
+    let folder = match &options.folder {
+        Some(folder) => folder.clone(),
+        None => file.parent().map(PathBuf::from).unwrap_or_default(),
+    };
+
+    if is_video(file) {
+        let extension = options.video_format.extension();
+        let destination = unique_destination(&folder, &file.with_extension(extension));
+        convert_video(file, &destination, &progress.video)?;
+
+        return Ok(destination);
+    }
+
+    let image =
+        ImageEntry::decode_image(file, settings, forced_decoder).map_err(|err| err.to_string())?;
+
+    let destination = unique_destination(&folder, &file.with_extension(options.format.extension()));
+    write_converted(&image, &destination, options.format, options.quality)?;
+
+    Ok(destination)
+}
+
+pub struct ConversionOptions {
+    pub format: ConversionFormat,
+    pub video_format: VideoConversionFormat,
+    pub quality: u8,
+    pub folder: Option<PathBuf>,
+}
+
+// Files that fail are reported and skipped, the rest are still converted
+pub fn convert_files(
+    files: &[(PathBuf, Option<ImageDecoder>)],
+    options: &ConversionOptions,
+    settings: &Settings,
+    progress: &Conversion,
+) {
+    if let Some(folder) = &options.folder {
+        if let Err(err) = std::fs::create_dir_all(folder) {
+            progress
+                .errors
+                .lock()
+                .unwrap()
+                .push((folder.clone(), err.to_string()));
+            return;
+        }
+    }
+
+    progress.total.store(files.len(), Ordering::SeqCst);
+    progress.started.store(true, Ordering::SeqCst);
+
+    for (file, forced_decoder) in files {
+        if progress.cancelled.load(Ordering::SeqCst) {
+            break;
+        }
+
+        match convert_file(file, *forced_decoder, options, settings, progress) {
+            Ok(destination) => {
+                info!("Converted {:?} to {:?}", file, destination);
+                progress.converted.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(err) => {
+                warn!("Failed to convert {:?}: {}", file, err);
+                progress.errors.lock().unwrap().push((file.clone(), err));
+            }
+        }
+    }
+
+    info!(
+        "Converted {} of {} files",
+        progress.converted.load(Ordering::SeqCst),
+        files.len()
+    );
+}
@@ -0,0 +1,313 @@
+// Malformed sample tables could otherwise ask for billions of frames
+const MAX_FRAMES: usize = 100_000;
+
+fn read_u24_le(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(3)?)?;
+
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64_be(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(8)?)?;
+
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+// Frame durations in milliseconds, zero where the container doesn't have one either.
+// Every ANMF chunk is one frame, its duration is a 24 bit value after the offsets and the size
+pub fn webp_frame_delays(data: &[u8]) -> Option<Vec<u32>> {
+    if data.get(0..4)? != b"RIFF" || data.get(8..12)? != b"WEBP" {
+        return None;
+    }
+
+    let mut delays = Vec::new();
+    let mut position = 12;
+
+    while position + 8 <= data.len() {
+        let fourcc = &data[position..position + 4];
+        let size = u32::from_le_bytes(data[position + 4..position + 8].try_into().ok()?) as usize;
+        let payload = position + 8;
+
+        // A chunk running past the end of a truncated file ends the list
+        match payload.checked_add(size) {
+            Some(chunk_end) if chunk_end <= data.len() => {}
+            _ => break,
+        }
+
+        if fourcc == b"ANMF" {
+            delays.push(read_u24_le(data, payload + 12)?);
+        }
+
+        // Chunks are padded to an even size
+        position = payload + size + (size & 1);
+    }
+
+    Some(delays)
+}
+
+// Returns the type, the start of the content and the end of every box in the range
+fn iso_boxes(data: &[u8], start: usize, end: usize) -> Vec<([u8; 4], usize, usize)> {
+    let mut boxes = Vec::new();
+    let mut position = start;
+
+    while position + 8 <= end {
+        let size = match read_u32_be(data, position) {
+            Some(size) => size as u64,
+            None => break,
+        };
+        let box_type = [
+            data[position + 4],
+            data[position + 5],
+            data[position + 6],
+            data[position + 7],
+        ];
+
+        // A size of one means the real one follows as 64 bits, zero that the box runs to the end
+        let (header_size, size) = match size {
+            0 => (8, (end - position) as u64),
+            1 => match read_u64_be(data, position + 8) {
+                Some(size) => (16, size),
+                None => break,
+            },
+            size => (8, size),
+        };
+
+        // Every box has to move the position forward, or the walk would never end
+        let box_end = match usize::try_from(size)
+            .ok()
+            .and_then(|size| position.checked_add(size))
+        {
+            Some(box_end) if box_end >= position + header_size && box_end <= end => box_end,
+            _ => break,
+        };
+
+        boxes.push((box_type, position + header_size, box_end));
+        position = box_end;
+    }
+
+    boxes
+}
+
+fn find_box(data: &[u8], start: usize, end: usize, box_type: &[u8; 4]) -> Option<(usize, usize)> {
+    iso_boxes(data, start, end)
+        .into_iter()
+        .find(|(found_type, _, _)| found_type == box_type)
+        .map(|(_, start, end)| (start, end))
+}
+
+// The image sequence is the track with the pict handler, the alpha plane has a track of its own
+fn avif_sequence_track(data: &[u8], start: usize, end: usize) -> Option<(usize, usize)> {
+    iso_boxes(data, start, end)
+        .into_iter()
+        .filter(|(box_type, _, _)| box_type == b"trak")
+        .filter_map(|(_, start, end)| find_box(data, start, end, b"mdia"))
+        .find(|(start, end)| {
+            find_box(data, *start, *end, b"hdlr")
+                .and_then(|(hdlr_start, _)| data.get(hdlr_start + 8..hdlr_start + 12))
+                .map(|handler| handler == b"pict")
+                .unwrap_or(false)
+        })
+}
+
+// Durations come from the sample deltas of the stts box, in units of the timescale of mdhd
+pub fn avif_frame_delays(data: &[u8]) -> Option<Vec<u32>> {
+    let (moov_start, moov_end) = find_box(data, 0, data.len(), b"moov")?;
+    let (mdia_start, mdia_end) = avif_sequence_track(data, moov_start, moov_end)?;
+
+    let (mdhd_start, _) = find_box(data, mdia_start, mdia_end, b"mdhd")?;
+    let timescale = match data.get(mdhd_start)? {
+        1 => read_u32_be(data, mdhd_start + 20)?,
+        _ => read_u32_be(data, mdhd_start + 12)?,
+    };
+
+    if timescale == 0 {
+        return None;
+    }
+
+    let (minf_start, minf_end) = find_box(data, mdia_start, mdia_end, b"minf")?;
+    let (stbl_start, stbl_end) = find_box(data, minf_start, minf_end, b"stbl")?;
+    let (stts_start, _) = find_box(data, stbl_start, stbl_end, b"stts")?;
+
+    let entry_count = read_u32_be(data, stts_start + 4)? as usize;
+    let mut delays = Vec::new();
+
+    for entry in 0..entry_count {
+        let offset = stts_start + 8 + entry * 8;
+        let sample_count = read_u32_be(data, offset)?;
+        let sample_delta = read_u32_be(data, offset + 4)?;
+        let delay = (sample_delta as u64 * 1000 / timescale as u64) as u32;
+
+        let sample_count = (sample_count as usize).min(MAX_FRAMES - delays.len());
+
+        delays.extend(std::iter::repeat(delay).take(sample_count));
+
+        if delays.len() >= MAX_FRAMES {
+            break;
+        }
+    }
+
+    Some(delays)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webp_chunk(fourcc: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = fourcc.to_vec();
+        chunk.extend((payload.len() as u32).to_le_bytes());
+        chunk.extend(payload);
+
+        if payload.len() % 2 == 1 {
+            chunk.push(0);
+        }
+
+        chunk
+    }
+
+    fn anmf(duration: u32) -> Vec<u8> {
+        let mut payload = vec![0; 12];
+        payload.extend(&duration.to_le_bytes()[..3]);
+        payload.push(0);
+
+        webp_chunk(b"ANMF", &payload)
+    }
+
+    fn webp(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+
+        let mut data = b"RIFF".to_vec();
+        data.extend((body.len() as u32 + 4).to_le_bytes());
+        data.extend(b"WEBP");
+        data.extend(body);
+
+        data
+    }
+
+    fn iso_box(box_type: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut data = (content.len() as u32 + 8).to_be_bytes().to_vec();
+        data.extend(box_type);
+        data.extend(content);
+
+        data
+    }
+
+    fn mdhd(version: u8, timescale: u32) -> Vec<u8> {
+        let mut content = vec![version, 0, 0, 0];
+
+        // Creation and modification times come before the timescale, 64 bits each in version 1
+        let times_size = if version == 1 { 16 } else { 8 };
+        content.extend(vec![0; times_size]);
+        content.extend(timescale.to_be_bytes());
+        content.extend(vec![0; 8]);
+
+        iso_box(b"mdhd", &content)
+    }
+
+    fn stts(entries: &[(u32, u32)]) -> Vec<u8> {
+        let mut content = vec![0; 4];
+        content.extend((entries.len() as u32).to_be_bytes());
+
+        for (sample_count, sample_delta) in entries {
+            content.extend(sample_count.to_be_bytes());
+            content.extend(sample_delta.to_be_bytes());
+        }
+
+        iso_box(b"stts", &content)
+    }
+
+    fn avif(mdhd: Vec<u8>, stts: Vec<u8>) -> Vec<u8> {
+        let mut hdlr = vec![0; 8];
+        hdlr.extend(b"pict");
+        hdlr.extend(vec![0; 12]);
+
+        let stbl = iso_box(b"stbl", &stts);
+        let minf = iso_box(b"minf", &stbl);
+        let mdia = iso_box(b"mdia", &[mdhd, iso_box(b"hdlr", &hdlr), minf].concat());
+        let trak = iso_box(b"trak", &mdia);
+
+        [iso_box(b"ftyp", b"avis"), iso_box(b"moov", &trak)].concat()
+    }
+
+    #[test]
+    fn webp_durations_come_from_anmf_chunks() {
+        let data = webp(&[
+            webp_chunk(b"VP8X", &[0; 10]),
+            webp_chunk(b"ANIM", &[0; 6]),
+            anmf(100),
+            webp_chunk(b"ALPH", &[0; 3]),
+            anmf(0x01_0203),
+        ]);
+
+        assert_eq!(webp_frame_delays(&data), Some(vec![100, 0x01_0203]));
+    }
+
+    #[test]
+    fn webp_needs_a_riff_header() {
+        assert_eq!(webp_frame_delays(b"RIFF\0\0\0\0WEBX"), None);
+        assert_eq!(webp_frame_delays(b"RIFF"), None);
+    }
+
+    #[test]
+    fn webp_chunk_size_past_the_end() {
+        let mut data = webp(&[anmf(40)]);
+        data.extend(b"ANMF");
+        data.extend(u32::MAX.to_le_bytes());
+
+        assert_eq!(webp_frame_delays(&data), Some(vec![40]));
+    }
+
+    #[test]
+    fn avif_durations_with_mdhd_version_0() {
+        let data = avif(mdhd(0, 1000), stts(&[(2, 50), (1, 120)]));
+
+        assert_eq!(avif_frame_delays(&data), Some(vec![50, 50, 120]));
+    }
+
+    #[test]
+    fn avif_durations_with_mdhd_version_1() {
+        let data = avif(mdhd(1, 90_000), stts(&[(3, 9000)]));
+
+        assert_eq!(avif_frame_delays(&data), Some(vec![100, 100, 100]));
+    }
+
+    #[test]
+    fn avif_frame_count_is_capped() {
+        let data = avif(mdhd(0, 1000), stts(&[(u32::MAX, 10)]));
+
+        assert_eq!(
+            avif_frame_delays(&data).map(|delays| delays.len()),
+            Some(MAX_FRAMES)
+        );
+    }
+
+    #[test]
+    fn avif_zero_timescale() {
+        let data = avif(mdhd(0, 0), stts(&[(1, 10)]));
+
+        assert_eq!(avif_frame_delays(&data), None);
+    }
+
+    #[test]
+    fn iso_box_sizes_that_overflow_or_stay_in_place() {
+        // 64 bit size that wraps around the address space
+        let mut data = 1u32.to_be_bytes().to_vec();
+        data.extend(b"moov");
+        data.extend(u64::MAX.to_be_bytes());
+
+        assert!(iso_boxes(&data, 0, data.len()).is_empty());
+
+        // 64 bit size smaller than its own header
+        let mut data = 1u32.to_be_bytes().to_vec();
+        data.extend(b"moov");
+        data.extend(8u64.to_be_bytes());
+
+        assert!(iso_boxes(&data, 0, data.len()).is_empty());
+    }
+}
@@ -1,10 +1,20 @@
 use crate::{
-    measure_time,
-    utils::{calculate_contain_size, is_image, load_texture, SharedTextureManager},
-    MediaType,
+    decode_stats, frame_delays, isolated_decoder, measure_time, thumbnail_cache,
+    utils::{
+        calculate_average_color, calculate_contain_size, calculate_perceptual_hash,
+        catch_decoder_panic, get_extension, get_texture_memory, is_image_content, linear_to_srgb,
+        load_still_texture, load_texture, print_file, with_preview_resolution,
+        with_texture_options, SharedTextureManager,
+    },
+    video_entry::video_frame_to_color_image,
+    widgets::image_viewer::ViewTransform,
+    MediaType, Settings,
 };
+use base64::{engine::general_purpose::STANDARD, Engine as _};
 use dicom::pixeldata::PixelDecoder;
-use eframe::egui::{ColorImage, Context as EguiContext, TextureHandle};
+use eframe::egui::{
+    Color32, ColorImage, Context as EguiContext, TextureFilter, TextureHandle, TextureOptions, Vec2,
+};
 use ffmpeg_next::{
     codec::context::Context as CodecContext,
     format::{self, pixel::Pixel},
@@ -12,12 +22,18 @@ use ffmpeg_next::{
     software::scaling::{context::Context as ScalingContext, flag::Flags},
     util::frame::video::Video as VideoFrame,
 };
-use image::{codecs, AnimationDecoder, Delay, FlatSamples, ImageBuffer, Rgb};
-use imagepipe::{ImageSource, Pipeline};
+use image::{
+    codecs, AnimationDecoder, Delay, DynamicImage, ExtendedColorType, FlatSamples, ImageBuffer,
+    ImageEncoder, Pixel as _, Rgb, RgbaImage,
+};
+use imagepipe::{ImageSource, Pipeline, SRGBImage};
+use lcms2::{ColorSpaceSignature, Intent, PixelFormat, Profile, Transform};
+use log::{error, warn};
 use std::{
     fs::File,
-    io::{BufReader, Read},
+    io::{BufReader, ErrorKind, Read, Seek, SeekFrom},
     path::PathBuf,
+    sync::mpsc,
 };
 
 pub struct ImageFrame {
@@ -30,15 +46,15 @@ impl ImageFrame {
         frame: RawImageFrame,
         size: [usize; 2],
         texture_manager: &SharedTextureManager,
-    ) -> Self {
+    ) -> Result<Self, String> {
         let color_image = ColorImage::from_rgba_unmultiplied(size, frame.pixels.as_slice());
 
-        let texture = load_texture(texture_manager.clone(), color_image);
+        let texture = load_texture(texture_manager.clone(), color_image)?;
 
-        ImageFrame {
+        Ok(ImageFrame {
             texture,
             delay: frame.delay,
-        }
+        })
     }
 }
 pub struct RawImageFrame {
@@ -61,8 +77,63 @@ impl RawImageFrame {
     }
 }
 
+// APNG frames can be smaller than the image itself and placed at an offset,
+// so they are drawn onto a canvas that keeps whatever the previous frames left behind
+pub struct FrameCanvas {
+    canvas: RgbaImage,
+}
+
+impl FrameCanvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        FrameCanvas {
+            canvas: RgbaImage::new(width, height),
+        }
+    }
+
+    pub fn draw(&mut self, source: &RgbaImage, left: u32, top: u32, blend: bool) {
+        for (x, y, pixel) in source.enumerate_pixels() {
+            let (x, y) = (x + left, y + top);
+
+            if x >= self.canvas.width() || y >= self.canvas.height() {
+                continue;
+            }
+
+            if blend {
+                self.canvas.get_pixel_mut(x, y).blend(pixel);
+            } else {
+                self.canvas.put_pixel(x, y, *pixel);
+            }
+        }
+    }
+
+    pub fn clear(&mut self, left: u32, top: u32, width: u32, height: u32) {
+        for y in top..(top + height).min(self.canvas.height()) {
+            for x in left..(left + width).min(self.canvas.width()) {
+                self.canvas.put_pixel(x, y, image::Rgba([0, 0, 0, 0]));
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> RgbaImage {
+        self.canvas.clone()
+    }
+
+    pub fn restore(&mut self, snapshot: RgbaImage) {
+        self.canvas = snapshot;
+    }
+
+    pub fn to_raw_frame(&self, delay: Delay) -> RawImageFrame {
+        RawImageFrame {
+            pixels: self.canvas.clone().into_flat_samples(),
+            delay,
+        }
+    }
+}
+
 pub struct StillImage {
     pub texture: TextureHandle,
+    pub hash: u64,
+    pub average_color: Color32,
 }
 
 impl StillImage {
@@ -70,27 +141,41 @@ impl StillImage {
         frame: RawImageFrame,
         size: [usize; 2],
         texture_manager: &SharedTextureManager,
-    ) -> Self {
+    ) -> Result<Self, String> {
         let color_image = ColorImage::from_rgba_unmultiplied(size, frame.pixels.as_slice());
-        let texture = load_texture(texture_manager.clone(), color_image);
 
-        StillImage { texture }
+        StillImage::from_color_image(color_image, texture_manager)
     }
 
-    pub fn from_pixels(
-        pixels: Vec<u8>,
-        size: [usize; 2],
+    pub fn from_dynamic_image(
+        image: DynamicImage,
         texture_manager: &SharedTextureManager,
-    ) -> Self {
-        let color_image = ColorImage::from_rgba_unmultiplied(size, pixels.as_slice());
-        let texture = load_texture(texture_manager.clone(), color_image);
+    ) -> Result<Self, String> {
+        let size = [image.width() as usize, image.height() as usize];
+
+        StillImage::from_raw_frame(RawImageFrame::from_image(image), size, texture_manager)
+    }
+
+    pub fn from_color_image(
+        color_image: ColorImage,
+        texture_manager: &SharedTextureManager,
+    ) -> Result<Self, String> {
+        let hash = calculate_perceptual_hash(&color_image);
+        let average_color = calculate_average_color(&color_image);
+        let texture = load_still_texture(texture_manager.clone(), color_image)?;
 
-        StillImage { texture }
+        Ok(StillImage {
+            texture,
+            hash,
+            average_color,
+        })
     }
 }
 
 pub struct AnimatedImage {
     pub frames: Vec<ImageFrame>,
+    pub hash: u64,
+    pub average_color: Color32,
 }
 
 impl AnimatedImage {
@@ -98,19 +183,41 @@ impl AnimatedImage {
         frames: Vec<RawImageFrame>,
         size: [usize; 2],
         texture_manager: &SharedTextureManager,
-    ) -> Self {
+    ) -> Result<Self, String> {
+        let (hash, average_color) = match frames.first() {
+            Some(frame) => {
+                let color_image = ColorImage::from_rgba_unmultiplied(size, frame.pixels.as_slice());
+
+                (
+                    calculate_perceptual_hash(&color_image),
+                    calculate_average_color(&color_image),
+                )
+            }
+            None => (0, Color32::TRANSPARENT),
+        };
+
         let frames = frames
             .into_iter()
             .map(|frame| ImageFrame::from_raw_frame(frame, size, texture_manager))
-            .collect();
+            .collect::<Result<Vec<ImageFrame>, String>>()?;
 
-        AnimatedImage { frames }
+        Ok(AnimatedImage {
+            frames,
+            hash,
+            average_color,
+        })
     }
 }
 
+// Documents with several pages, which are stepped through by hand instead of played
+pub struct PagedImage {
+    pub pages: Vec<StillImage>,
+}
+
 pub enum Image {
     Still(StillImage),
     Animated(AnimatedImage),
+    Paged(PagedImage),
 }
 
 impl Image {
@@ -125,6 +232,31 @@ impl Image {
 
                 Some(frame.texture.clone())
             }
+            Image::Paged(paged_image) => paged_image.pages.first().map(|page| page.texture.clone()),
+        }
+    }
+
+    pub fn get_hash(&self) -> u64 {
+        match self {
+            Image::Still(still_image) => still_image.hash,
+            Image::Animated(animated_image) => animated_image.hash,
+            Image::Paged(paged_image) => paged_image
+                .pages
+                .first()
+                .map(|page| page.hash)
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn get_average_color(&self) -> Color32 {
+        match self {
+            Image::Still(still_image) => still_image.average_color,
+            Image::Animated(animated_image) => animated_image.average_color,
+            Image::Paged(paged_image) => paged_image
+                .pages
+                .first()
+                .map(|page| page.average_color)
+                .unwrap_or(Color32::TRANSPARENT),
         }
     }
 }
@@ -139,27 +271,285 @@ pub enum ImageFormat {
     Unknown,
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImageDecoder {
+    Native,
+    Dicom,
+    Rpgmv,
+    JpegLs,
+    JBig,
+    Raw,
+    Ffmpeg,
+}
+
+impl ImageDecoder {
+    // Also the default order in which the decoders are tried
+    pub const ALL: [ImageDecoder; 7] = [
+        ImageDecoder::Native,
+        ImageDecoder::Dicom,
+        ImageDecoder::Rpgmv,
+        ImageDecoder::JpegLs,
+        ImageDecoder::JBig,
+        ImageDecoder::Raw,
+        ImageDecoder::Ffmpeg,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ImageDecoder::Native => "Native",
+            ImageDecoder::Dicom => "DICOM",
+            ImageDecoder::Rpgmv => "RPG Maker MV",
+            ImageDecoder::JpegLs => "JPEG-LS",
+            ImageDecoder::JBig => "JBIG",
+            ImageDecoder::Raw => "RAW",
+            ImageDecoder::Ffmpeg => "FFmpeg",
+        }
+    }
+
+    // Decoders made for a single format are only tried on files of that format
+    pub fn supports(&self, format: &ImageFormat) -> bool {
+        match self {
+            ImageDecoder::Dicom => matches!(format, ImageFormat::Dicom),
+            ImageDecoder::Rpgmv => matches!(format, ImageFormat::Rpgmv),
+            ImageDecoder::JpegLs => matches!(format, ImageFormat::JpegLs),
+            ImageDecoder::JBig => matches!(format, ImageFormat::JBig1 | ImageFormat::JBig2),
+            ImageDecoder::Native | ImageDecoder::Raw | ImageDecoder::Ffmpeg => true,
+        }
+    }
+
+    // The others decode without looking at the ImageAdjustments
+    pub fn applies_adjustments(&self) -> bool {
+        matches!(self, ImageDecoder::Native | ImageDecoder::Raw)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Default, Hash)]
+pub enum RawColorSpace {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+impl RawColorSpace {
+    pub fn label(&self) -> &str {
+        match self {
+            RawColorSpace::Srgb => "sRGB",
+            RawColorSpace::Linear => "Linear",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum PlaybackMode {
+    #[default]
+    Loop,
+    // Stops on the last frame
+    Once,
+    // Plays backwards once the last frame is reached, then forwards again
+    PingPong,
+}
+
+impl PlaybackMode {
+    pub fn label(&self) -> &str {
+        match self {
+            PlaybackMode::Loop => "loop",
+            PlaybackMode::Once => "once",
+            PlaybackMode::PingPong => "ping-pong",
+        }
+    }
+
+    pub fn next(&self) -> PlaybackMode {
+        match self {
+            PlaybackMode::Loop => PlaybackMode::Once,
+            PlaybackMode::Once => PlaybackMode::PingPong,
+            PlaybackMode::PingPong => PlaybackMode::Loop,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct ImageAdjustments {
+    pub exposure: f32,
+    pub gamma: f32,
+    // RAW only, temperature in kelvin and tint. None keeps the white balance the camera recorded
+    pub white_balance: Option<(f32, f32)>,
+    // RAW only, None uses the color space from settings
+    pub color_space: Option<RawColorSpace>,
+}
+
+impl Default for ImageAdjustments {
+    fn default() -> Self {
+        ImageAdjustments {
+            exposure: 0.0,
+            gamma: 1.0,
+            white_balance: None,
+            color_space: None,
+        }
+    }
+}
+
+impl ImageAdjustments {
+    pub fn is_default(&self) -> bool {
+        *self == ImageAdjustments::default()
+    }
+
+    pub fn changes_pixels(&self) -> bool {
+        self.exposure != 0.0 || self.gamma != 1.0
+    }
+
+    // Exposure is in stops, so every +1.0 doubles the brightness
+    pub fn apply_to_pixels(&self, pixels: &mut [u8], channels: usize) {
+        if !self.changes_pixels() {
+            return;
+        }
+
+        let multiplier = 2.0_f32.powf(self.exposure);
+        let inverse_gamma = 1.0 / self.gamma.max(0.1);
+
+        let mut lookup_table = [0; 256];
+
+        for (i, value) in lookup_table.iter_mut().enumerate() {
+            let adjusted = (i as f32 / 255.0 * multiplier).min(1.0).powf(inverse_gamma);
+            *value = (adjusted * 255.0).round() as u8;
+        }
+
+        for pixel in pixels.chunks_exact_mut(channels) {
+            for channel in pixel.iter_mut().take(3) {
+                *channel = lookup_table[*channel as usize];
+            }
+        }
+    }
+}
+
 pub struct ImageEntry {
     pub is_animated: bool,
     pub media_type: MediaType,
     pub path: PathBuf,
+    pub adjustments: ImageAdjustments,
+    pub decoder: ImageDecoder,
+    // Skips the automatic decoder chain, for files it picks the wrong decoder for
+    pub forced_decoder: Option<ImageDecoder>,
+    // Nearest keeps pixel art crisp when zoomed in, the image is decoded again to change it
+    pub magnification: TextureFilter,
+    pub view: ViewTransform,
+    // Start and end of the measured line, in the same units as the view uses for positions
+    pub ruler: Option<(Vec2, Vec2)>,
+    // Opposite corners of the crop rectangle, in the same units as the ruler
+    pub crop: Option<(Vec2, Vec2)>,
+    pub min_frame_delay: f64,
+    pub max_animation_fps: f64,
+    // Set while the window is in the background, the animation neither advances nor repaints
+    pub suspended: bool,
+    pub playback_mode: PlaybackMode,
+    // Replaces the delays from the file, for animations with broken timing
+    pub fixed_frame_delay: Option<f64>,
+    // Applied on top of either delay, below 1 slows the animation down
+    pub playback_speed: f64,
+    // Latitude and longitude in degrees, south and west are negative
+    pub gps: Option<(f64, f64)>,
+    playing_backwards: bool,
     image: Image,
+    // Textures can't be read back, so the eyedropper decodes the frames once more on first use
+    pixels: Option<Vec<RgbaImage>>,
+    pending_pixels: Option<mpsc::Receiver<Vec<RgbaImage>>>,
     last_frame_time: std::time::Instant,
     current_frame_index: usize,
+    current_page_index: usize,
 }
 
 impl ImageEntry {
-    pub fn new(image_path: &PathBuf, ctx: &EguiContext) -> Option<Self> {
-        let image = match ImageEntry::load_image(ctx, image_path) {
-            Ok(image) => image,
+    pub fn new(
+        image_path: &PathBuf,
+        ctx: &EguiContext,
+        settings: &Settings,
+        forced_decoder: Option<ImageDecoder>,
+    ) -> Option<Self> {
+        let adjustments = ImageAdjustments::default();
+        let texture_options = ImageEntry::preview_texture_options(settings);
+
+        let (image, decoder) = match measure_time!(
+            "Image decode",
+            with_texture_options(texture_options, || {
+                with_preview_resolution(settings, || {
+                    ImageEntry::load_image(ctx, image_path, settings, &adjustments, forced_decoder)
+                })
+            })
+        ) {
+            Ok(result) => result,
             Err(err) => {
-                println!("Error loading image: {:?}", err);
+                error!("Error loading image: {:?}", err);
 
                 return None;
             }
         };
 
-        Some(ImageEntry {
+        Some(ImageEntry::from_image(
+            image_path,
+            image,
+            decoder,
+            forced_decoder,
+            settings,
+        ))
+    }
+
+    // Frames copied into the gallery only live in memory, so there is no file to decode and
+    // the pixels are kept for the eyedropper right away
+    pub fn from_frame(
+        path: &PathBuf,
+        frame: &RgbaImage,
+        ctx: &EguiContext,
+        settings: &Settings,
+    ) -> Option<Self> {
+        let color_image = ColorImage::from_rgba_unmultiplied(
+            [frame.width() as usize, frame.height() as usize],
+            frame.as_raw(),
+        );
+        let texture_options = ImageEntry::preview_texture_options(settings);
+
+        let still_image = with_texture_options(texture_options, || {
+            with_preview_resolution(settings, || {
+                StillImage::from_color_image(color_image, &ctx.tex_manager())
+            })
+        });
+
+        match still_image {
+            Ok(still_image) => {
+                let image = Image::Still(still_image);
+                let mut entry =
+                    ImageEntry::from_image(path, image, ImageDecoder::Native, None, settings);
+                entry.pixels = Some(vec![frame.clone()]);
+
+                Some(entry)
+            }
+            Err(err) => {
+                error!("Error loading frame {:?}: {}", path, err);
+
+                None
+            }
+        }
+    }
+
+    fn preview_texture_options(settings: &Settings) -> TextureOptions {
+        let magnification = if settings.nearest_preview_filter {
+            TextureFilter::Nearest
+        } else {
+            TextureFilter::Linear
+        };
+
+        TextureOptions {
+            magnification,
+            ..Default::default()
+        }
+    }
+
+    fn from_image(
+        image_path: &PathBuf,
+        image: Image,
+        decoder: ImageDecoder,
+        forced_decoder: Option<ImageDecoder>,
+        settings: &Settings,
+    ) -> Self {
+        ImageEntry {
             is_animated: matches!(image, Image::Animated(_)),
             media_type: if matches!(image, Image::Animated(_)) {
                 MediaType::ImageAnimated
@@ -167,10 +557,70 @@ impl ImageEntry {
                 MediaType::ImageStill
             },
             path: image_path.clone(),
+            adjustments: ImageAdjustments::default(),
+            decoder,
+            forced_decoder,
+            magnification: ImageEntry::preview_texture_options(settings).magnification,
+            view: ViewTransform::default(),
+            ruler: None,
+            crop: None,
+            min_frame_delay: settings.min_frame_delay as f64,
+            max_animation_fps: settings.max_animation_fps as f64,
+            suspended: false,
+            playback_mode: PlaybackMode::default(),
+            fixed_frame_delay: None,
+            playback_speed: 1.0,
+            gps: ImageEntry::read_exif_gps(image_path),
+            playing_backwards: false,
             last_frame_time: std::time::Instant::now(),
             current_frame_index: 0,
+            current_page_index: 0,
             image,
-        })
+            pixels: None,
+            pending_pixels: None,
+        }
+    }
+
+    // Decodes the image again so the adjustments are applied to the source data
+    // instead of the already quantized texture
+    pub fn reload(&mut self, ctx: &EguiContext, settings: &Settings) -> Result<(), String> {
+        let texture_options = TextureOptions {
+            magnification: self.magnification,
+            ..Default::default()
+        };
+
+        match with_texture_options(texture_options, || {
+            with_preview_resolution(settings, || {
+                ImageEntry::load_image(
+                    ctx,
+                    &self.path,
+                    settings,
+                    &self.adjustments,
+                    self.forced_decoder,
+                )
+            })
+        }) {
+            Ok((image, decoder)) => {
+                // A different decoder can turn a still image into an animated one and back
+                self.is_animated = matches!(image, Image::Animated(_));
+                self.media_type = if self.is_animated {
+                    MediaType::ImageAnimated
+                } else {
+                    MediaType::ImageStill
+                };
+                self.image = image;
+                self.pixels = None;
+                self.pending_pixels = None;
+                self.decoder = decoder;
+                self.current_frame_index = 0;
+                self.current_page_index = 0;
+                Ok(())
+            }
+            Err(err) => {
+                error!("Error reloading image: {:?}", err);
+                Err(err.to_string())
+            }
+        }
     }
 
     pub fn try_guess_format(
@@ -179,7 +629,8 @@ impl ImageEntry {
         let mut file = std::fs::File::open(file_path)?;
 
         let mut buffer = [0; 256];
-        file.read_exact(&mut buffer)?;
+        let length = file.read(&mut buffer)?;
+        let buffer = &buffer[..length];
 
         // DICOM
         if buffer.len() >= 132 && &buffer[128..132] == b"DICM" {
@@ -198,199 +649,1278 @@ impl ImageEntry {
             return Ok(ImageFormat::JpegLs);
         }
 
-        Ok(ImageFormat::JBig2)
+        // JBIG2 file header
+        let jbig2_bytes = [0x97, 0x4A, 0x42, 0x32, 0x0D, 0x0A, 0x1A, 0x0A];
+        if buffer.len() >= 8 && &buffer[0..8] == jbig2_bytes {
+            return Ok(ImageFormat::JBig2);
+        }
+
+        if ImageEntry::is_jbig1_header(buffer) {
+            return Ok(ImageFormat::JBig1);
+        }
+
+        // JBIG2 streams taken out of PDFs have no header, the extension is all there is
+        match get_extension(file_path).as_deref() {
+            Some("jb2" | "jbig2") => Ok(ImageFormat::JBig2),
+            Some("jbg" | "jbig") => Ok(ImageFormat::JBig1),
+            _ => Ok(ImageFormat::Unknown),
+        }
     }
 
-    pub fn default_texture(texture_manager: SharedTextureManager) -> TextureHandle {
-        let image_bytes = include_bytes!("assets/images/missing.png");
-        let image_buffer = image::load_from_memory(image_bytes).unwrap();
+    // JBIG1 has no magic bytes, but its 20 byte header leaves little room for other files:
+    // the fourth byte is always zero, the dimensions can't be zero and some bits are reserved.
+    // TIFF based RAW files happen to fit too, so they are ruled out first
+    fn is_jbig1_header(buffer: &[u8]) -> bool {
+        if buffer.len() < 20 || buffer.starts_with(b"II*\0") || buffer.starts_with(b"MM\0*") {
+            return false;
+        }
 
-        let color_image = ColorImage::from_rgb(
-            [256, 256],
-            image_buffer.into_rgb8().into_flat_samples().as_slice(),
-        );
+        let read_u32 = |offset: usize| {
+            u32::from_be_bytes([
+                buffer[offset],
+                buffer[offset + 1],
+                buffer[offset + 2],
+                buffer[offset + 3],
+            ])
+        };
 
-        load_texture(texture_manager, color_image)
+        let (lowest_layer, highest_layer, planes) = (buffer[0], buffer[1], buffer[2]);
+        let (width, height, stripe_rows) = (read_u32(4), read_u32(8), read_u32(12));
+        let (max_x_offset, max_y_offset, order, options) =
+            (buffer[16], buffer[17], buffer[18], buffer[19]);
+
+        buffer[3] == 0
+            && lowest_layer <= highest_layer
+            && planes > 0
+            && width > 0
+            && height > 0
+            && stripe_rows > 0
+            && max_x_offset <= 127
+            && max_y_offset == 0
+            && order & 0xF0 == 0
+            && options & 0x80 == 0
     }
 
-    pub fn get_current_frame(&mut self, ctx: &EguiContext) -> Option<TextureHandle> {
-        let now = std::time::Instant::now();
-        let elapsed = now.duration_since(self.last_frame_time).as_secs_f64();
+    // Decodes at most two frames, so the grid can tell animations apart without loading the
+    // whole file. Formats that merely can hold an animation, like a GIF or an animated WebP
+    // with a single frame, are treated as still
+    pub fn is_animated_file(file: &PathBuf) -> bool {
+        let reader = match image::ImageReader::open(file).and_then(|r| r.with_guessed_format()) {
+            Ok(reader) => reader,
+            Err(_) => return false,
+        };
 
-        match &self.image {
-            Image::Still(still_image) => {
-                if elapsed >= 1.0 {
-                    self.last_frame_time = now;
-                }
+        let input_stream = match File::open(file) {
+            Ok(input_stream) => BufReader::new(input_stream),
+            Err(_) => return false,
+        };
+
+        match reader.format() {
+            Some(image::ImageFormat::Gif) => match codecs::gif::GifDecoder::new(input_stream) {
+                Ok(decoder) => decoder.into_frames().take(2).count() > 1,
+                Err(_) => false,
+            },
 
-                ctx.request_repaint();
+            Some(image::ImageFormat::WebP) => match codecs::webp::WebPDecoder::new(input_stream) {
+                Ok(decoder) if decoder.has_animation() => decoder.into_frames().take(2).count() > 1,
+                _ => false,
+            },
 
-                return Some(still_image.texture.clone());
+            Some(image::ImageFormat::Png) => {
+                let decoder = png::Decoder::new(input_stream);
+
+                match decoder.read_info() {
+                    Ok(reader) => reader
+                        .info()
+                        .animation_control()
+                        .map(|control| control.num_frames > 1)
+                        .unwrap_or(false),
+                    Err(_) => false,
+                }
             }
 
-            Image::Animated(animated_image) => {
-                let delay = match animated_image.frames.get(self.current_frame_index) {
-                    Some(frame) => {
-                        let (num, den) = frame.delay.numer_denom_ms();
+            Some(image::ImageFormat::Avif) => ImageEntry::is_avif_sequence(file),
 
-                        let res = num as f64 / den as f64;
+            _ => false,
+        }
+    }
 
-                        match res {
-                            0.0 => 83.33,
-                            _ => res,
-                        }
-                    }
-                    None => 83.33,
-                };
+    // Animated AVIFs are image sequences, which list "avis" among the ftyp brands
+    fn is_avif_sequence(file: &PathBuf) -> bool {
+        let mut buffer = [0; 64];
+        let length = match File::open(file).and_then(|mut file| file.read(&mut buffer)) {
+            Ok(length) => length,
+            Err(_) => return false,
+        };
 
-                if elapsed >= delay / 1000.0 {
-                    self.last_frame_time = now;
+        if length < 16 || &buffer[4..8] != b"ftyp" {
+            return false;
+        }
 
-                    if self.is_animated {
-                        self.current_frame_index =
-                            (self.current_frame_index + 1) % animated_image.frames.len();
-                    }
-                }
+        let box_size = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+        let end = box_size.min(length);
+
+        // Major brand, then the compatible brands after the minor version
+        &buffer[8..12] == b"avis"
+            || (16..end)
+                .step_by(4)
+                .any(|offset| offset + 4 <= end && &buffer[offset..offset + 4] == b"avis")
+    }
 
-                ctx.request_repaint();
+    pub fn read_exif_orientation(file_path: &PathBuf) -> Option<u32> {
+        let file = File::open(file_path).ok()?;
+        let exif = exif::Reader::new()
+            .read_from_container(&mut BufReader::new(file))
+            .ok()?;
 
-                return Some(
-                    animated_image
-                        .frames
-                        .get(self.current_frame_index)?
-                        .texture
-                        .clone(),
-                );
+        let field = exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?;
+
+        field.value.get_uint(0)
+    }
+
+    // Degrees, minutes and seconds are stored as three rationals, the hemisphere in a separate tag
+    fn read_gps_coordinate(
+        exif: &exif::Exif,
+        tag: exif::Tag,
+        ref_tag: exif::Tag,
+        negative_ref: u8,
+    ) -> Option<f64> {
+        let field = exif.get_field(tag, exif::In::PRIMARY)?;
+
+        let degrees = match &field.value {
+            exif::Value::Rational(parts) if parts.len() >= 3 => {
+                parts[0].to_f64() + parts[1].to_f64() / 60.0 + parts[2].to_f64() / 3600.0
             }
+            _ => return None,
+        };
+
+        let is_negative = match exif.get_field(ref_tag, exif::In::PRIMARY)?.value {
+            exif::Value::Ascii(ref values) => values
+                .first()
+                .and_then(|value| value.first())
+                .map(|hemisphere| hemisphere.eq_ignore_ascii_case(&negative_ref))?,
+            _ => return None,
+        };
+
+        match degrees.is_finite() {
+            true if is_negative => Some(-degrees),
+            true => Some(degrees),
+            false => None,
         }
     }
 
-    pub fn get_number_of_frames(&self) -> usize {
-        match &self.image {
-            Image::Still(_) => 1,
-            Image::Animated(animated_image) => animated_image.frames.len(),
-        }
+    pub fn read_exif_gps(file_path: &PathBuf) -> Option<(f64, f64)> {
+        let file = File::open(file_path).ok()?;
+        let exif = exif::Reader::new()
+            .read_from_container(&mut BufReader::new(file))
+            .ok()?;
+
+        let latitude = ImageEntry::read_gps_coordinate(
+            &exif,
+            exif::Tag::GPSLatitude,
+            exif::Tag::GPSLatitudeRef,
+            b'S',
+        )?;
+        let longitude = ImageEntry::read_gps_coordinate(
+            &exif,
+            exif::Tag::GPSLongitude,
+            exif::Tag::GPSLongitudeRef,
+            b'W',
+        )?;
+
+        Some((latitude, longitude))
     }
 
-    pub fn load_image(
-        ctx: &EguiContext,
-        file: &PathBuf,
-    ) -> Result<Image, Box<dyn std::error::Error>> {
-        let texture_manager = ctx.tex_manager();
+    pub fn apply_exif_orientation(image: DynamicImage, file_path: &PathBuf) -> DynamicImage {
+        ImageEntry::apply_orientation(image, ImageEntry::read_exif_orientation(file_path))
+    }
 
-        match ImageEntry::load_image_native(ctx, file) {
-            Ok(image) => return Ok(image),
-            Err(error) => {
-                println!("Failed to load image using native rust loader, trying other options... Error: {:?}", error);
-            }
+    // Orientation values are described in the EXIF spec, 1 means the image is already upright
+    fn apply_orientation(image: DynamicImage, orientation: Option<u32>) -> DynamicImage {
+        match orientation {
+            Some(2) => image.fliph(),
+            Some(3) => image.rotate180(),
+            Some(4) => image.flipv(),
+            Some(5) => image.rotate90().fliph(),
+            Some(6) => image.rotate90(),
+            Some(7) => image.rotate270().fliph(),
+            Some(8) => image.rotate270(),
+            _ => image,
         }
+    }
 
-        let format = ImageEntry::try_guess_format(file)?;
+    fn read_icc_profile(file_path: &PathBuf) -> Option<Vec<u8>> {
+        let mut decoder = image::ImageReader::open(file_path)
+            .ok()?
+            .with_guessed_format()
+            .ok()?
+            .into_decoder()
+            .ok()?;
 
-        match format {
-            ImageFormat::Dicom => ImageEntry::load_dicom_image(&texture_manager, file),
-            ImageFormat::Rpgmv => ImageEntry::load_rpgmv_image(&texture_manager, file),
-            ImageFormat::JpegLs => ImageEntry::load_jpeg_ls_image(&texture_manager, file),
-            ImageFormat::JBig1 => ImageEntry::load_jbig_image(&texture_manager, file),
-            ImageFormat::JBig2 => ImageEntry::load_jbig_image(&texture_manager, file),
-            ImageFormat::Unknown => match ImageEntry::load_raw_image(&texture_manager, file) {
-                Ok(image) => Ok(image),
-                Err(error) => {
-                    println!(
-                        "Failed to load image using rawloader, trying ffmpeg... Error: {:?}",
-                        error
-                    );
+        image::ImageDecoder::icc_profile(&mut decoder).ok()?
+    }
+
+    // Textures are shown as sRGB, so pixels stored in a wider space like Adobe RGB or Display P3
+    // are converted first. Only RGB profiles are handled, HDR images are left to the tone mapping
+    pub fn apply_icc_profile(image: DynamicImage, file_path: &PathBuf) -> DynamicImage {
+        if matches!(
+            image,
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+        ) {
+            return image;
+        }
 
-                    ImageEntry::load_image_ffmpeg(&texture_manager, file, None, false)
+        let profile = match ImageEntry::read_icc_profile(file_path) {
+            Some(icc_profile) => match Profile::new_icc(&icc_profile) {
+                Ok(profile) => profile,
+                Err(err) => {
+                    warn!("Ignoring invalid ICC profile of {:?}: {}", file_path, err);
+                    return image;
                 }
             },
+            None => return image,
+        };
+
+        if profile.color_space() != ColorSpaceSignature::RgbData {
+            return image;
+        }
+
+        let transform: Transform<[u8; 4], [u8; 4]> = match Transform::new(
+            &profile,
+            PixelFormat::RGBA_8,
+            &Profile::new_srgb(),
+            PixelFormat::RGBA_8,
+            Intent::Perceptual,
+        ) {
+            Ok(transform) => transform,
+            Err(err) => {
+                warn!("Failed to convert the colors of {:?}: {}", file_path, err);
+                return image;
+            }
+        };
+
+        let mut rgba_image = image.into_rgba8();
+        let mut pixels = rgba_image
+            .pixels()
+            .map(|pixel| pixel.0)
+            .collect::<Vec<[u8; 4]>>();
+
+        // Converting in place leaves the alpha channel as it was
+        transform.transform_in_place(&mut pixels);
+
+        for (pixel, converted) in rgba_image.pixels_mut().zip(pixels) {
+            pixel.0 = converted;
+        }
+
+        DynamicImage::ImageRgba8(rgba_image)
+    }
+
+    // HDR formats decode to linear floats, which `into_rgba8` simply clamps, blowing out
+    // everything above 1.0. Instead, compress them into display range with a Reinhard curve
+    pub fn tone_map_hdr(
+        image: DynamicImage,
+        settings: &Settings,
+        adjustments: &ImageAdjustments,
+    ) -> DynamicImage {
+        match image {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {}
+            _ => return image,
+        }
+
+        if !settings.tone_map_hdr {
+            return image;
+        }
+
+        let mut image = image.into_rgba32f();
+        let multiplier = 2.0_f32.powf(settings.hdr_exposure + adjustments.exposure);
+        let inverse_gamma = 1.0 / (settings.hdr_gamma * adjustments.gamma).max(0.1);
+
+        for pixel in image.pixels_mut() {
+            for channel in pixel.0.iter_mut().take(3) {
+                let value = (*channel * multiplier).max(0.0);
+                *channel = (value / (1.0 + value)).powf(inverse_gamma);
+            }
         }
+
+        DynamicImage::ImageRgba32F(image)
+    }
+
+    // Catches files that were cut off while downloading. Some decoders happily return a
+    // partially gray image for those, so the end marker is looked for even if decoding worked
+    pub fn detect_corruption(file: &PathBuf, decoded: bool) -> Option<String> {
+        let complete = match get_extension(file).unwrap_or_default().as_str() {
+            "jpg" | "jpeg" | "jfif" => {
+                ImageEntry::is_complete(file, &[0xFF, 0xD9], ImageEntry::find_jpeg_end)
+            }
+            "png" => {
+                ImageEntry::is_complete(file, b"IEND\xAE\x42\x60\x82", ImageEntry::find_png_end)
+            }
+            _ => Ok(true),
+        };
+
+        if let Ok(false) = complete {
+            return Some("Truncated file".to_string());
+        }
+
+        if decoded {
+            return None;
+        }
+
+        // Files in a format we don't know are unsupported, not corrupt
+        let image_reader =
+            match image::ImageReader::open(file).and_then(|r| r.with_guessed_format()) {
+                Ok(image_reader) if image_reader.format().is_some() => image_reader,
+                _ => return None,
+            };
+
+        match image_reader.decode() {
+            Err(image::ImageError::Decoding(_)) => Some("Corrupt image data".to_string()),
+            Err(image::ImageError::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => {
+                Some("Truncated file".to_string())
+            }
+            _ => None,
+        }
+    }
+
+    // Almost every file simply ends with the marker. Motion Photos and some cameras append data
+    // after it though, those are walked from the start up to the marker instead
+    fn is_complete(
+        file: &PathBuf,
+        end_marker: &[u8],
+        find_end: fn(&mut BufReader<File>) -> std::io::Result<bool>,
+    ) -> std::io::Result<bool> {
+        const TAIL_SIZE: u64 = 1024;
+
+        let mut reader = BufReader::new(File::open(file)?);
+
+        let length = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(length.saturating_sub(TAIL_SIZE)))?;
+
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail)?;
+
+        // Some writers pad the file with zeros after the marker
+        let padding = tail.iter().rev().take_while(|byte| **byte == 0).count();
+        if tail[..tail.len() - padding].ends_with(end_marker) {
+            return Ok(true);
+        }
+
+        reader.seek(SeekFrom::Start(0))?;
+
+        match find_end(&mut reader) {
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            result => result,
+        }
+    }
+
+    // Segments are skipped by their length, the scan data after SOS is read up to the next marker
+    fn find_jpeg_end(reader: &mut BufReader<File>) -> std::io::Result<bool> {
+        let mut byte = [0; 1];
+        let mut read_byte =
+            |reader: &mut BufReader<File>| reader.read_exact(&mut byte).map(|_| byte[0]);
+
+        // Not something that can be followed, the decoder has the last word on it
+        if read_byte(reader)? != 0xFF || read_byte(reader)? != 0xD8 {
+            return Ok(true);
+        }
+
+        loop {
+            if read_byte(reader)? != 0xFF {
+                continue;
+            }
+
+            let mut marker = read_byte(reader)?;
+            while marker == 0xFF {
+                marker = read_byte(reader)?;
+            }
+
+            match marker {
+                0xD9 => return Ok(true),
+                // Escaped 0xFF and restart markers inside the scan data have no length
+                0x00 | 0x01 | 0xD0..=0xD7 => {}
+                _ => {
+                    let length = u16::from_be_bytes([read_byte(reader)?, read_byte(reader)?]);
+
+                    if length < 2 {
+                        return Ok(true);
+                    }
+
+                    reader.seek_relative(length as i64 - 2)?;
+                }
+            }
+        }
+    }
+
+    fn find_png_end(reader: &mut BufReader<File>) -> std::io::Result<bool> {
+        let mut signature = [0; 8];
+        reader.read_exact(&mut signature)?;
+
+        loop {
+            let mut header = [0; 8];
+            reader.read_exact(&mut header)?;
+
+            if &header[4..] == b"IEND" {
+                return Ok(true);
+            }
+
+            // The chunk data is followed by its CRC
+            let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+            reader.seek_relative(length as i64 + 4)?;
+        }
+    }
+
+    // Shown for files that failed to load, the configured placeholder replaces the embedded one
+    pub fn default_texture(
+        texture_manager: SharedTextureManager,
+        settings: &Settings,
+    ) -> TextureHandle {
+        let placeholder_path = settings.placeholder_path.trim();
+
+        if !placeholder_path.is_empty() {
+            match image::open(placeholder_path) {
+                Ok(image) => {
+                    let image_size = [image.width() as usize, image.height() as usize];
+                    let color_image =
+                        ColorImage::from_rgba_unmultiplied(image_size, image.into_rgba8().as_raw());
+
+                    match load_texture(texture_manager.clone(), color_image) {
+                        Ok(texture) => return texture,
+                        Err(err) => warn!(
+                            "Failed to create placeholder texture {:?}, using the default one: {}",
+                            placeholder_path, err
+                        ),
+                    }
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to load placeholder image {:?}, using the default one: {}",
+                        placeholder_path, err
+                    );
+                }
+            }
+        }
+
+        let image_bytes = include_bytes!("assets/images/missing.png");
+
+        // The size comes from the asset itself, so it can be swapped for one of any size
+        let color_image = match image::load_from_memory(image_bytes) {
+            Ok(image_buffer) => {
+                let image_size = [
+                    image_buffer.width() as usize,
+                    image_buffer.height() as usize,
+                ];
+
+                ColorImage::from_rgb(image_size, image_buffer.into_rgb8().as_raw())
+            }
+            Err(err) => {
+                error!("Failed to decode the embedded placeholder image: {}", err);
+
+                ColorImage::new([1, 1], Color32::from_gray(40))
+            }
+        };
+
+        match load_texture(texture_manager.clone(), color_image) {
+            Ok(texture) => texture,
+            Err(err) => {
+                error!("Failed to create the embedded placeholder texture: {}", err);
+
+                ImageEntry::loading_texture(texture_manager)
+            }
+        }
+    }
+
+    // A single pixel, always valid so it can be the last fallback
+    pub fn loading_texture(texture_manager: SharedTextureManager) -> TextureHandle {
+        let color_image = ColorImage::new([1, 1], Color32::from_gray(40));
+
+        load_texture(texture_manager, color_image).expect("A 1x1 texture is always valid")
+    }
+
+    pub fn get_current_frame(&mut self, ctx: &EguiContext) -> Option<TextureHandle> {
+        let now = std::time::Instant::now();
+        let elapsed = now.duration_since(self.last_frame_time).as_secs_f64();
+
+        match &self.image {
+            Image::Still(still_image) => {
+                if elapsed >= 1.0 {
+                    self.last_frame_time = now;
+                }
+
+                // Nothing changes on its own, so the next repaint can wait for input
+                return Some(still_image.texture.clone());
+            }
+
+            Image::Animated(animated_image) if self.suspended => {
+                // Picks up where it stopped instead of skipping ahead once resumed
+                self.last_frame_time = now;
+
+                return Some(
+                    animated_image
+                        .frames
+                        .get(self.current_frame_index)?
+                        .texture
+                        .clone(),
+                );
+            }
+
+            Image::Animated(animated_image) => {
+                let frame = animated_image.frames.get(self.current_frame_index);
+
+                let delay = match (self.fixed_frame_delay, frame) {
+                    (Some(fixed_frame_delay), _) => fixed_frame_delay,
+                    (None, Some(frame)) => {
+                        let (num, den) = frame.delay.numer_denom_ms();
+
+                        let res = num as f64 / den as f64;
+
+                        match res {
+                            0.0 => 83.33,
+                            _ => res,
+                        }
+                    }
+                    (None, None) => 83.33,
+                };
+
+                // Some files ask for a delay of a millisecond or two, which would only spin the CPU
+                let delay = (delay / self.playback_speed.max(0.01))
+                    .max(self.min_frame_delay)
+                    .max(1000.0 / self.max_animation_fps.max(1.0));
+
+                let last_frame_index = animated_image.frames.len().saturating_sub(1);
+
+                if elapsed >= delay / 1000.0 {
+                    self.last_frame_time = now;
+
+                    if self.is_animated {
+                        self.current_frame_index = match self.playback_mode {
+                            PlaybackMode::Loop => {
+                                (self.current_frame_index + 1) % animated_image.frames.len()
+                            }
+                            PlaybackMode::Once => {
+                                (self.current_frame_index + 1).min(last_frame_index)
+                            }
+                            PlaybackMode::PingPong => {
+                                if self.current_frame_index == 0 {
+                                    self.playing_backwards = false;
+                                } else if self.current_frame_index >= last_frame_index {
+                                    self.playing_backwards = true;
+                                }
+
+                                if self.playing_backwards {
+                                    self.current_frame_index.saturating_sub(1)
+                                } else {
+                                    (self.current_frame_index + 1).min(last_frame_index)
+                                }
+                            }
+                        };
+                    }
+                }
+
+                let is_finished = self.playback_mode == PlaybackMode::Once
+                    && self.current_frame_index >= last_frame_index;
+
+                // Paused animations only need a repaint when something else changes
+                if self.is_animated && !is_finished {
+                    let until_next_frame =
+                        (delay / 1000.0 - self.last_frame_time.elapsed().as_secs_f64()).max(0.0);
+
+                    ctx.request_repaint_after(std::time::Duration::from_secs_f64(until_next_frame));
+                }
+
+                return Some(
+                    animated_image
+                        .frames
+                        .get(self.current_frame_index)?
+                        .texture
+                        .clone(),
+                );
+            }
+
+            Image::Paged(paged_image) => Some(
+                paged_image
+                    .pages
+                    .get(self.current_page_index)?
+                    .texture
+                    .clone(),
+            ),
+        }
+    }
+
+    // A mode that plays once starts over, otherwise there would be nothing to see
+    pub fn cycle_playback_mode(&mut self) {
+        self.playback_mode = self.playback_mode.next();
+        self.playing_backwards = false;
+
+        if self.playback_mode == PlaybackMode::Once {
+            self.current_frame_index = 0;
+            self.last_frame_time = std::time::Instant::now();
+        }
+    }
+
+    // The current page and the number of pages, for documents that have them
+    pub fn get_page_info(&self) -> Option<(usize, usize)> {
+        match &self.image {
+            Image::Paged(paged_image) => Some((self.current_page_index, paged_image.pages.len())),
+            _ => None,
+        }
+    }
+
+    pub fn step_page(&mut self, forward: bool) {
+        if let Some((page, pages_count)) = self.get_page_info() {
+            self.current_page_index = if forward {
+                (page + 1).min(pages_count - 1)
+            } else {
+                page.saturating_sub(1)
+            };
+        }
+    }
+
+    pub fn get_texture_memory(&self) -> usize {
+        match &self.image {
+            Image::Still(still_image) => get_texture_memory(&still_image.texture),
+            Image::Animated(animated_image) => animated_image
+                .frames
+                .iter()
+                .map(|frame| get_texture_memory(&frame.texture))
+                .sum(),
+            Image::Paged(paged_image) => paged_image
+                .pages
+                .iter()
+                .map(|page| get_texture_memory(&page.texture))
+                .sum(),
+        }
+    }
+
+    pub fn get_current_frame_index(&self) -> usize {
+        self.current_frame_index
+    }
+
+    pub fn get_number_of_frames(&self) -> usize {
+        match &self.image {
+            Image::Still(_) | Image::Paged(_) => 1,
+            Image::Animated(animated_image) => animated_image.frames.len(),
+        }
+    }
+
+    pub fn load_image(
+        ctx: &EguiContext,
+        file: &PathBuf,
+        settings: &Settings,
+        adjustments: &ImageAdjustments,
+        forced_decoder: Option<ImageDecoder>,
+    ) -> Result<(Image, ImageDecoder), Box<dyn std::error::Error>> {
+        if let Some(decoder) = forced_decoder {
+            return match ImageEntry::load_image_with_decoder(
+                ctx,
+                file,
+                settings,
+                adjustments,
+                decoder,
+            ) {
+                Ok(image) => {
+                    decode_stats::record_decoded(file, decoder);
+                    Ok((image, decoder))
+                }
+                Err(error) => {
+                    decode_stats::record_failed(file);
+                    Err(error)
+                }
+            };
+        }
+
+        let format = ImageEntry::try_guess_format(file).unwrap_or(ImageFormat::Unknown);
+        let mut last_error = None;
+
+        for decoder in settings.decoder_chain(file, &format, false) {
+            match ImageEntry::load_image_with_decoder(ctx, file, settings, adjustments, decoder) {
+                Ok(image) => {
+                    decode_stats::record_decoded(file, decoder);
+                    return Ok((image, decoder));
+                }
+                Err(error) => {
+                    warn!(
+                        "Failed to load image using {} decoder, trying other options... Error: {:?}",
+                        decoder.label(),
+                        error
+                    );
+
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        decode_stats::record_failed(file);
+
+        Err(last_error.unwrap_or_else(|| "No enabled decoder supports this file".into()))
+    }
+
+    pub fn load_image_with_decoder(
+        ctx: &EguiContext,
+        file: &PathBuf,
+        settings: &Settings,
+        adjustments: &ImageAdjustments,
+        decoder: ImageDecoder,
+    ) -> Result<Image, Box<dyn std::error::Error>> {
+        let texture_manager = ctx.tex_manager();
+
+        catch_decoder_panic(decoder.label(), || match decoder {
+            ImageDecoder::Native => ImageEntry::load_image_native(ctx, file, settings, adjustments),
+            ImageDecoder::Dicom => ImageEntry::load_dicom_image(&texture_manager, file),
+            ImageDecoder::Rpgmv => ImageEntry::load_rpgmv_image(&texture_manager, file),
+            ImageDecoder::JpegLs => ImageEntry::load_jpeg_ls_image(&texture_manager, file),
+            ImageDecoder::JBig => ImageEntry::load_jbig_image(&texture_manager, file),
+            ImageDecoder::Raw => {
+                ImageEntry::load_raw_image(&texture_manager, file, settings, adjustments)
+            }
+            ImageDecoder::Ffmpeg => {
+                ImageEntry::load_image_ffmpeg(&texture_manager, file, None, false, settings)
+            }
+        })
+    }
+
+    pub fn load_image_native(
+        ctx: &EguiContext,
+        file: &PathBuf,
+        settings: &Settings,
+        adjustments: &ImageAdjustments,
+    ) -> Result<Image, Box<dyn std::error::Error>> {
+        let texture_manager = ctx.tex_manager();
+
+        if ImageEntry::is_ico_file(file) {
+            return ImageEntry::load_ico_image(&texture_manager, file);
+        }
+
+        let (mut frames, image_size) =
+            ImageEntry::decode_native_frames(file, settings, adjustments)?;
+
+        if frames.len() == 1 {
+            let still_image =
+                StillImage::from_raw_frame(frames.pop().unwrap(), image_size, &texture_manager)?;
+
+            return Ok(Image::Still(still_image));
+        }
+
+        let animated_image = AnimatedImage::from_raw_frames(frames, image_size, &texture_manager)?;
+
+        Ok(Image::Animated(animated_image))
+    }
+
+    fn decode_native_frames(
+        file: &PathBuf,
+        settings: &Settings,
+        adjustments: &ImageAdjustments,
+    ) -> Result<(Vec<RawImageFrame>, [usize; 2]), Box<dyn std::error::Error>> {
+        let reader = image::ImageReader::open(file)?.with_guessed_format()?;
+        let image_format = reader.format();
+        let mut frames = Vec::new();
+
+        match image_format {
+            Some(image::ImageFormat::Gif) => {
+                let input_stream = std::fs::File::open(file)?;
+                let gif_decoder = codecs::gif::GifDecoder::new(BufReader::new(input_stream))?;
+
+                let decoded_frames = gif_decoder.into_frames().collect_frames()?;
+                frames = decoded_frames
+                    .iter()
+                    .map(|frame| RawImageFrame::from_frame(frame.to_owned()))
+                    .collect();
+            }
+
+            Some(image::ImageFormat::WebP) => {
+                let input_stream = std::fs::File::open(file)?;
+                let webp_decoder = codecs::webp::WebPDecoder::new(BufReader::new(input_stream))?;
+
+                // Only read when the decoder reports a frame without a delay
+                let mut container_delays = None;
+
+                // The decoder composes every frame onto the full canvas itself, blending and
+                // disposal included, so unlike APNG its frames are used as they are
+                for (index, frame) in webp_decoder.into_frames().enumerate() {
+                    let frame = frame?;
+
+                    let delay = match frame.delay().numer_denom_ms() {
+                        (0, _) => container_delays
+                            .get_or_insert_with(|| {
+                                std::fs::read(file)
+                                    .ok()
+                                    .and_then(|data| frame_delays::webp_frame_delays(&data))
+                                    .unwrap_or_default()
+                            })
+                            .get(index)
+                            .map(|delay| Delay::from_numer_denom_ms(*delay, 1))
+                            .unwrap_or(frame.delay()),
+                        _ => frame.delay(),
+                    };
+
+                    frames.push(RawImageFrame {
+                        pixels: frame.into_buffer().into_flat_samples(),
+                        delay,
+                    });
+                }
+            }
+
+            Some(image::ImageFormat::Png) => {
+                let input_stream = std::fs::File::open(file)?;
+                let apng_decoder = codecs::png::PngDecoder::new(BufReader::new(input_stream))?;
+
+                let is_apng = apng_decoder.is_apng().unwrap_or(false);
+
+                if is_apng {
+                    frames = ImageEntry::load_apng_frames(file)?;
+                }
+            }
+
+            _ => {}
+        };
+
+        let mut image = match reader.decode() {
+            Ok(image) => image,
+            Err(error) => {
+                return Err(Box::new(error));
+            }
+        };
+
+        if frames.is_empty() && settings.apply_exif_orientation {
+            image = ImageEntry::apply_exif_orientation(image, file);
+        }
+
+        if frames.is_empty() && settings.color_management {
+            image = ImageEntry::apply_icc_profile(image, file);
+        }
+
+        let is_hdr = matches!(
+            image,
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+        );
+
+        image = ImageEntry::tone_map_hdr(image, settings, adjustments);
+
+        // HDR images get the adjustments as part of tone mapping, before they lose precision
+        if frames.is_empty() && !is_hdr && adjustments.changes_pixels() {
+            let mut rgba_image = image.into_rgba8();
+            adjustments.apply_to_pixels(&mut rgba_image, 4);
+
+            image = DynamicImage::ImageRgba8(rgba_image);
+        }
+
+        let image_size = [image.width() as usize, image.height() as usize];
+
+        if frames.is_empty() {
+            frames.push(RawImageFrame::from_image(image));
+        }
+
+        Ok((frames, image_size))
+    }
+
+    // Textures only live on the GPU, so the shown frame is decoded again to get its pixels
+    pub fn current_frame_png(
+        &self,
+        settings: &Settings,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if self.decoder != ImageDecoder::Native {
+            return Err(format!("Exporting {:?} images is not supported", self.decoder).into());
+        }
+
+        let (mut frames, size) =
+            ImageEntry::decode_native_frames(&self.path, settings, &self.adjustments)?;
+
+        let last_index = frames.len().checked_sub(1).ok_or("Image has no frames")?;
+        let frame = frames.swap_remove(self.current_frame_index.min(last_index));
+
+        let mut png_bytes = Vec::new();
+        codecs::png::PngEncoder::new(&mut png_bytes).write_image(
+            frame.pixels.as_slice(),
+            size[0] as u32,
+            size[1] as u32,
+            ExtendedColorType::Rgba8,
+        )?;
+
+        Ok(png_bytes)
+    }
+
+    // Every frame or page at full size, without going through textures
+    pub fn decode_frames(
+        file: &PathBuf,
+        decoder: ImageDecoder,
+        settings: &Settings,
+        adjustments: &ImageAdjustments,
+    ) -> Result<Vec<RgbaImage>, Box<dyn std::error::Error>> {
+        let images = match decoder {
+            ImageDecoder::Native if ImageEntry::is_ico_file(file) => {
+                ImageEntry::decode_ico_images(file)?
+            }
+            ImageDecoder::Native => {
+                let (frames, size) = ImageEntry::decode_native_frames(file, settings, adjustments)?;
+
+                return Ok(frames
+                    .into_iter()
+                    .filter_map(|frame| {
+                        RgbaImage::from_raw(size[0] as u32, size[1] as u32, frame.pixels.samples)
+                    })
+                    .collect());
+            }
+            ImageDecoder::Dicom => ImageEntry::decode_dicom_frames(file, usize::MAX)?,
+            ImageDecoder::JBig => ImageEntry::decode_jbig_pages(file, usize::MAX)?,
+            ImageDecoder::Raw => vec![ImageEntry::decode_raw_image(file, settings, adjustments)?],
+            ImageDecoder::Ffmpeg => {
+                return Err("Reading pixels of images decoded by FFmpeg is not supported".into())
+            }
+            decoder => vec![ImageEntry::decode_first_frame(decoder, file, settings)?],
+        };
+
+        Ok(images.into_iter().map(|image| image.into_rgba8()).collect())
+    }
+
+    // The first frame or page at full size, through the same chain of decoders as the preview
+    pub fn decode_image(
+        file: &PathBuf,
+        settings: &Settings,
+        forced_decoder: Option<ImageDecoder>,
+    ) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+        let decode = |decoder: ImageDecoder| {
+            catch_decoder_panic(decoder.label(), || match decoder {
+                ImageDecoder::Ffmpeg => ImageEntry::decode_ffmpeg_frame(file),
+                decoder => ImageEntry::decode_frames(
+                    file,
+                    decoder,
+                    settings,
+                    &ImageAdjustments::default(),
+                )?
+                .into_iter()
+                .next()
+                .ok_or_else(|| "File has no frames".into()),
+            })
+        };
+
+        if let Some(decoder) = forced_decoder {
+            return decode(decoder);
+        }
+
+        let format = ImageEntry::try_guess_format(file).unwrap_or(ImageFormat::Unknown);
+        let mut last_error = None;
+
+        for decoder in settings.decoder_chain(file, &format, false) {
+            match decode(decoder) {
+                Ok(image) => return Ok(image),
+                Err(error) => last_error = Some(error),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| "No enabled decoder supports this file".into()))
+    }
+
+    fn decode_ffmpeg_frame(file: &PathBuf) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+        let mut ictx = format::input(file)?;
+        let input = ictx
+            .streams()
+            .best(VideoType)
+            .ok_or("No video stream found")?;
+
+        let video_stream_index = input.index();
+        let context = CodecContext::from_parameters(input.parameters())?;
+        let mut decoder = context.decoder().video()?;
+
+        if decoder.width() == 0 || decoder.height() == 0 {
+            return Err("Video stream has no dimensions".into());
+        }
+
+        let mut scaler = ScalingContext::get(
+            decoder.format(),
+            decoder.width(),
+            decoder.height(),
+            Pixel::RGBA,
+            decoder.width(),
+            decoder.height(),
+            Flags::BILINEAR,
+        )?;
+
+        let mut frame = VideoFrame::empty();
+        let mut has_frame = false;
+
+        for (stream, packet) in ictx.packets() {
+            if stream.index() == video_stream_index {
+                decoder.send_packet(&packet)?;
+
+                if decoder.receive_frame(&mut frame).is_ok() {
+                    has_frame = true;
+                    break;
+                }
+            }
+        }
+
+        // Some decoders only give the frame out once they know nothing else is coming
+        if !has_frame {
+            decoder.send_eof()?;
+            decoder.receive_frame(&mut frame)?;
+        }
+
+        let mut rgba_frame = VideoFrame::empty();
+        scaler.run(&frame, &mut rgba_frame)?;
+
+        let color_image = video_frame_to_color_image(&rgba_frame)?;
+        let [width, height] = color_image.size;
+        let pixels = color_image
+            .pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_srgba_unmultiplied())
+            .collect::<Vec<u8>>();
+
+        RgbaImage::from_raw(width as u32, height as u32, pixels)
+            .ok_or_else(|| "Failed to create image buffer".into())
+    }
+
+    // Decoded again on another thread the first time they are needed and kept until
+    // the image is reloaded, nothing is returned until the frames are ready
+    pub fn current_frame_pixels(
+        &mut self,
+        ctx: &EguiContext,
+        settings: &Settings,
+    ) -> Option<&RgbaImage> {
+        if self.pixels.is_none() && self.pending_pixels.is_none() {
+            let (sender, receiver) = mpsc::channel();
+            let path = self.path.clone();
+            let decoder = self.decoder;
+            let adjustments = self.adjustments;
+            let settings = settings.clone();
+            let ctx = ctx.clone();
+
+            std::thread::spawn(move || {
+                let pixels = ImageEntry::decode_frames(&path, decoder, &settings, &adjustments);
+
+                // Failures are remembered as well, so the file isn't decoded again on every frame
+                let pixels = pixels.unwrap_or_else(|err| {
+                    error!("Failed to read pixels of {:?}: {}", path, err);
+                    Vec::new()
+                });
+
+                if sender.send(pixels).is_ok() {
+                    ctx.request_repaint();
+                }
+            });
+
+            self.pending_pixels = Some(receiver);
+        }
+
+        if let Some(receiver) = &self.pending_pixels {
+            match receiver.try_recv() {
+                Ok(pixels) => self.pixels = Some(pixels),
+                Err(mpsc::TryRecvError::Empty) => return None,
+                Err(mpsc::TryRecvError::Disconnected) => self.pixels = Some(Vec::new()),
+            }
+
+            self.pending_pixels = None;
+        }
+
+        let index = match &self.image {
+            Image::Still(_) => 0,
+            Image::Animated(_) => self.current_frame_index,
+            Image::Paged(_) => self.current_page_index,
+        };
+
+        self.pixels.as_ref()?.get(index)
+    }
+
+    // The position goes from 0 to 1 across the image, so it doesn't matter
+    // if the texture ended up smaller than the decoded frame
+    pub fn pixel_at(
+        &mut self,
+        ctx: &EguiContext,
+        settings: &Settings,
+        position: Vec2,
+    ) -> Option<([u32; 2], Color32)> {
+        let frame = self.current_frame_pixels(ctx, settings)?;
+
+        let x = ((position.x * frame.width() as f32) as u32).min(frame.width().saturating_sub(1));
+        let y = ((position.y * frame.height() as f32) as u32).min(frame.height().saturating_sub(1));
+        let [r, g, b, a] = frame.get_pixel_checked(x, y)?.0;
+
+        Some(([x, y], Color32::from_rgba_unmultiplied(r, g, b, a)))
+    }
+
+    // Worked out in pixels of the decoded frame, which can be larger than the texture.
+    // The region is x, y, width and height, empty rectangles give nothing
+    pub fn crop_region(&mut self, ctx: &EguiContext, settings: &Settings) -> Option<[u32; 4]> {
+        let (start, end) = self.crop?;
+        let frame = self.current_frame_pixels(ctx, settings)?;
+        let size = Vec2::new(frame.width() as f32, frame.height() as f32);
+
+        let min = (start.min(end) * size).round();
+        let max = (start.max(end) * size).round();
+
+        let width = (max.x - min.x) as u32;
+        let height = (max.y - min.y) as u32;
+
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        Some([min.x as u32, min.y as u32, width, height])
+    }
+
+    pub fn cropped_frame(&mut self, ctx: &EguiContext, settings: &Settings) -> Option<RgbaImage> {
+        let [x, y, width, height] = self.crop_region(ctx, settings)?;
+        let frame = self.current_frame_pixels(ctx, settings)?;
+
+        Some(image::imageops::crop_imm(frame, x, y, width, height).to_image())
     }
 
-    pub fn load_image_native(
-        ctx: &EguiContext,
-        file: &PathBuf,
-    ) -> Result<Image, Box<dyn std::error::Error>> {
-        let reader = image::ImageReader::open(file)?.with_guessed_format()?;
-        let image_format = reader.format();
-        let texture_manager = ctx.tex_manager();
-        let mut frames = Vec::new();
+    pub fn to_data_url(&self, settings: &Settings) -> Result<String, Box<dyn std::error::Error>> {
+        let png_bytes = self.current_frame_png(settings)?;
 
-        match image_format {
-            Some(image::ImageFormat::Gif) => {
-                let input_stream = std::fs::File::open(file)?;
-                let gif_decoder = codecs::gif::GifDecoder::new(BufReader::new(input_stream))?;
+        Ok(format!(
+            "data:image/png;base64,{}",
+            STANDARD.encode(png_bytes)
+        ))
+    }
 
-                let decoded_frames = gif_decoder.into_frames().collect_frames()?;
-                frames = decoded_frames
-                    .iter()
-                    .map(|frame| RawImageFrame::from_frame(frame.to_owned()))
-                    .collect();
-            }
+    // The frame is printed as a png, so adjustments and the current animation frame are kept
+    pub fn print(&self, settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+        let png_bytes = self.current_frame_png(settings)?;
 
-            Some(image::ImageFormat::WebP) => {
-                let input_stream = std::fs::File::open(file)?;
-                let webp_decoder = codecs::webp::WebPDecoder::new(BufReader::new(input_stream))?;
+        let print_dir = std::env::temp_dir().join("gallery").join("print");
+        std::fs::create_dir_all(&print_dir)?;
 
-                let decoded_frames = webp_decoder.into_frames().collect_frames()?;
-                frames = decoded_frames
-                    .iter()
-                    .map(|frame| RawImageFrame::from_frame(frame.to_owned()))
-                    .collect();
-            }
+        let file_stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let print_path = print_dir.join(format!("{}.png", file_stem));
+        std::fs::write(&print_path, png_bytes)?;
 
-            Some(image::ImageFormat::Png) => {
-                let input_stream = std::fs::File::open(file)?;
-                let apng_decoder = codecs::png::PngDecoder::new(BufReader::new(input_stream))?;
+        print_file(&print_path)?;
 
-                let is_apng = apng_decoder.is_apng().unwrap_or(false);
+        Ok(())
+    }
 
-                if is_apng {
-                    let decoded_frames = apng_decoder.apng()?.into_frames().collect_frames()?;
-                    frames = decoded_frames
-                        .iter()
-                        .map(|frame| RawImageFrame::from_frame(frame.to_owned()))
-                        .collect();
-                }
+    fn png_frame_to_rgba(
+        data: &[u8],
+        width: u32,
+        height: u32,
+        color_type: png::ColorType,
+    ) -> Option<RgbaImage> {
+        let data = data.to_vec();
+
+        match color_type {
+            png::ColorType::Grayscale => {
+                ImageBuffer::<image::Luma<u8>, _>::from_raw(width, height, data)
+                    .map(|image| DynamicImage::ImageLuma8(image).into_rgba8())
             }
+            png::ColorType::GrayscaleAlpha => {
+                ImageBuffer::<image::LumaA<u8>, _>::from_raw(width, height, data)
+                    .map(|image| DynamicImage::ImageLumaA8(image).into_rgba8())
+            }
+            png::ColorType::Rgb => ImageBuffer::<Rgb<u8>, _>::from_raw(width, height, data)
+                .map(|image| DynamicImage::ImageRgb8(image).into_rgba8()),
+            png::ColorType::Rgba => RgbaImage::from_raw(width, height, data),
+            png::ColorType::Indexed => None,
+        }
+    }
 
-            _ => {}
-        };
+    // The image crate clears the whole canvas for the background dispose op, which breaks
+    // APNGs that only update parts of the image, so the frames are composed here instead
+    fn load_apng_frames(file: &PathBuf) -> Result<Vec<RawImageFrame>, Box<dyn std::error::Error>> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(file)?));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+        let mut reader = decoder.read_info()?;
+        let (width, height) = (reader.info().width, reader.info().height);
+        let frames_count = reader
+            .info()
+            .animation_control()
+            .map(|control| control.num_frames)
+            .unwrap_or(0);
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+
+        // Without a fcTL chunk before it, the default image is only a fallback for viewers
+        // that don't support APNG and isn't a part of the animation
+        if reader.info().frame_control().is_none() {
+            reader.next_frame(&mut buffer)?;
+        }
 
-        let image = match reader.decode() {
-            Ok(image) => image,
-            Err(error) => {
-                return Err(Box::new(error));
-            }
-        };
+        let mut canvas = FrameCanvas::new(width, height);
+        let mut frames = Vec::new();
 
-        let image_size = [image.width() as usize, image.height() as usize];
+        for _ in 0..frames_count {
+            let output_info = reader.next_frame(&mut buffer)?;
+            let frame_control = reader
+                .info()
+                .frame_control()
+                .copied()
+                .ok_or("Missing fcTL chunk")?;
+
+            let source = ImageEntry::png_frame_to_rgba(
+                &buffer[..output_info.buffer_size()],
+                output_info.width,
+                output_info.height,
+                output_info.color_type,
+            )
+            .ok_or("Unsupported APNG frame format")?;
+
+            // The first frame has nothing to go back to, so previous works like background there
+            let dispose_op = match frame_control.dispose_op {
+                png::DisposeOp::Previous if frames.is_empty() => png::DisposeOp::Background,
+                dispose_op => dispose_op,
+            };
 
-        if frames.is_empty() {
-            frames.push(RawImageFrame::from_image(image));
-        }
+            let previous = match dispose_op {
+                png::DisposeOp::Previous => Some(canvas.snapshot()),
+                _ => None,
+            };
 
-        if frames.len() == 1 {
-            let still_image =
-                StillImage::from_raw_frame(frames.pop().unwrap(), image_size, &texture_manager);
+            canvas.draw(
+                &source,
+                frame_control.x_offset,
+                frame_control.y_offset,
+                frame_control.blend_op == png::BlendOp::Over,
+            );
 
-            return Ok(Image::Still(still_image));
-        }
+            let delay_denominator = match frame_control.delay_den {
+                0 => 100,
+                denominator => denominator as u32,
+            };
+            let delay = Delay::from_numer_denom_ms(
+                frame_control.delay_num as u32 * 1000,
+                delay_denominator,
+            );
 
-        let animated_image = AnimatedImage::from_raw_frames(frames, image_size, &texture_manager);
+            frames.push(canvas.to_raw_frame(delay));
+
+            match (dispose_op, previous) {
+                (png::DisposeOp::Background, _) => canvas.clear(
+                    frame_control.x_offset,
+                    frame_control.y_offset,
+                    frame_control.width,
+                    frame_control.height,
+                ),
+                (png::DisposeOp::Previous, Some(previous)) => canvas.restore(previous),
+                _ => {}
+            }
+        }
 
-        Ok(Image::Animated(animated_image))
+        Ok(frames)
     }
 
     // TODO ffmpeg crashes and burns without any way to recover on some unsupported files
-    // Ideally, it should run in a separate process. But IPC is painfull and
-    // opening a lot of images will spawn a lot of processes, so this needs to be controlled
+    // Panics on the Rust side are caught by catch_decoder_panic, but aborts inside of ffmpeg
+    // take the whole app down. Ideally, it should run in a separate process. But IPC is painfull
+    // and opening a lot of images will spawn a lot of processes, so this needs to be controlled
     pub fn load_image_ffmpeg(
         texture_manager: &SharedTextureManager,
         file: &PathBuf,
         size: Option<f32>,
         is_thumbnail: bool,
+        settings: &Settings,
     ) -> Result<Image, Box<dyn std::error::Error>> {
+        ImageEntry::load_image_ffmpeg_with_size(texture_manager, file, size, is_thumbnail, settings)
+            .map(|(image, _)| image)
+    }
+
+    // Along with the image comes the size of the video stream, which thumbnails are scaled from
+    fn load_image_ffmpeg_with_size(
+        texture_manager: &SharedTextureManager,
+        file: &PathBuf,
+        size: Option<f32>,
+        is_thumbnail: bool,
+        settings: &Settings,
+    ) -> Result<(Image, (u32, u32)), Box<dyn std::error::Error>> {
         let mut ictx = format::input(file)?;
         let input = ictx
             .streams()
@@ -401,12 +1931,19 @@ impl ImageEntry {
         let context = CodecContext::from_parameters(input.parameters())?;
         let mut decoder = context.decoder().video()?;
 
+        if decoder.width() == 0 || decoder.height() == 0 {
+            return Err("Video stream has no dimensions".into());
+        }
+
+        let source_size = (decoder.width(), decoder.height());
+
         let destination_size = if is_thumbnail {
             let size = size.unwrap_or(256.0);
             let (w, h) =
                 calculate_contain_size(size, size, decoder.width() as f32, decoder.height() as f32);
 
-            (w.trunc() as u32, h.trunc() as u32)
+            // Very narrow videos would otherwise be scaled down to nothing
+            ((w.trunc() as u32).max(1), (h.trunc() as u32).max(1))
         } else {
             (decoder.width(), decoder.height())
         };
@@ -445,6 +1982,15 @@ impl ImageEntry {
                     let stride = rgba_frame.stride(0);
                     let expected_size = width * height * 4;
 
+                    let is_valid = width > 0
+                        && height > 0
+                        && stride >= width * 4
+                        && rgba_frame.data(0).len() >= stride * (height - 1) + width * 4;
+
+                    if !is_valid {
+                        return Err("Unexpected layout of the scaled video frame".into());
+                    }
+
                     let mut buffer = Vec::with_capacity(expected_size);
 
                     for y in 0..height {
@@ -473,23 +2019,66 @@ impl ImageEntry {
                 &buffer,
             );
 
-            return Ok(Image::Still(StillImage {
-                texture: load_texture(texture_manager.clone(), color_image),
-            }));
+            if let Some(size) = size.filter(|_| is_thumbnail && settings.thumbnail_cache) {
+                thumbnail_cache::store(file, size, &color_image, settings);
+            }
+
+            let still_image = StillImage::from_color_image(color_image, texture_manager)?;
+
+            return Ok((Image::Still(still_image), source_size));
+        }
+
+        if buffers.is_empty() {
+            return Err("No frames found".into());
         }
 
+        // Frames without timestamps all come out with a zero delay, AVIF sequences
+        // have the durations in their sample table as well
+        let container_delays = if ImageEntry::is_avif_sequence(file) {
+            std::fs::read(file)
+                .ok()
+                .and_then(|data| frame_delays::avif_frame_delays(&data))
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        let container_delay = |index: usize| container_delays.get(index).copied().unwrap_or(0);
+
         let mut delays = Vec::new();
         for i in 0..pts_values.len() - 1 {
             let delay = (pts_values[i + 1] - pts_values[i]) as f64 * f64::from(time_base);
-            delays.push(Delay::from_numer_denom_ms((delay * 1000.0) as u32, 1));
+            let delay = match (delay * 1000.0) as u32 {
+                0 => container_delay(i),
+                delay => delay,
+            };
+
+            delays.push(Delay::from_numer_denom_ms(delay, 1));
         }
 
-        delays.push(
-            delays
+        let last_delay = match container_delay(pts_values.len() - 1) {
+            0 => delays
                 .last()
                 .unwrap_or(&Delay::from_numer_denom_ms(83, 1))
                 .clone(),
-        );
+            delay => Delay::from_numer_denom_ms(delay, 1),
+        };
+
+        delays.push(last_delay);
+
+        let (hash, average_color) = match buffers.first() {
+            Some(buffer) => {
+                let color_image = ColorImage::from_rgba_unmultiplied(
+                    [image_width as usize, image_height as usize],
+                    buffer.as_slice(),
+                );
+
+                (
+                    calculate_perceptual_hash(&color_image),
+                    calculate_average_color(&color_image),
+                )
+            }
+            None => (0, Color32::TRANSPARENT),
+        };
 
         let frames = buffers
             .into_iter()
@@ -499,91 +2088,222 @@ impl ImageEntry {
                     buffer.as_slice(),
                 );
 
-                let texture = load_texture(texture_manager.clone(), color_image);
+                let texture = load_texture(texture_manager.clone(), color_image)?;
 
-                ImageFrame {
+                Ok(ImageFrame {
                     texture,
                     delay: delays.pop().unwrap(),
-                }
+                })
             })
-            .collect();
+            .collect::<Result<Vec<ImageFrame>, String>>()?;
 
-        let animated_image = AnimatedImage { frames };
+        let animated_image = AnimatedImage {
+            frames,
+            hash,
+            average_color,
+        };
 
-        Ok(Image::Animated(animated_image))
+        Ok((Image::Animated(animated_image), source_size))
     }
 
-    // TODO Split everything thumbnail related to a separate ThumbnailLoader in order to clean up a bit
     pub fn load_thumbnail(
         texture_manager: &SharedTextureManager,
         file: &PathBuf,
         size: f32,
+        settings: &Settings,
     ) -> Option<Image> {
-        let is_image = is_image(&file);
+        ImageEntry::load_thumbnail_with_source(texture_manager, file, size, settings)
+            .map(|(image, _, _)| image)
+    }
+
+    // TODO Split everything thumbnail related to a separate ThumbnailLoader in order to clean up a bit
+    // Along with the thumbnail comes where it came from, the cache or the decoder that made it,
+    // and the size of the original as it's shown. Cached thumbnails don't know that size
+    pub fn load_thumbnail_with_source(
+        texture_manager: &SharedTextureManager,
+        file: &PathBuf,
+        size: f32,
+        settings: &Settings,
+    ) -> Option<(Image, &'static str, Option<(u32, u32)>)> {
+        if settings.thumbnail_cache {
+            if let Some(color_image) = thumbnail_cache::load(file, size, settings) {
+                match StillImage::from_color_image(color_image, texture_manager) {
+                    Ok(still_image) => return Some((Image::Still(still_image), "Cache", None)),
+                    Err(err) => error!(
+                        "Failed to create cached thumbnail texture of {:?}: {}",
+                        file, err
+                    ),
+                }
+            }
+        }
+
+        let is_image = is_image_content(&file);
+
+        if !is_image && settings.isolate_ffmpeg_thumbnails && isolated_decoder::is_available() {
+            return match isolated_decoder::load_thumbnail(file, size, settings) {
+                Ok((color_image, source_size)) => {
+                    if settings.thumbnail_cache {
+                        thumbnail_cache::store(file, size, &color_image, settings);
+                    }
+
+                    StillImage::from_color_image(color_image, texture_manager)
+                        .map(|still_image| {
+                            (
+                                Image::Still(still_image),
+                                "Isolated ffmpeg",
+                                Some(source_size),
+                            )
+                        })
+                        .map_err(|err| {
+                            error!("Failed to create thumbnail texture of {:?}: {}", file, err)
+                        })
+                        .ok()
+                }
+                Err(err) => {
+                    error!("Failed to load thumbnail of {:?}: {}", file, err);
+                    None
+                }
+            };
+        }
 
         if !is_image {
-            match ImageEntry::load_image_ffmpeg(texture_manager, &file, Some(size), true) {
-                Ok(image) => return Some(image),
+            let thumbnail = catch_decoder_panic(ImageDecoder::Ffmpeg.label(), || {
+                ImageEntry::load_image_ffmpeg_with_size(
+                    texture_manager,
+                    &file,
+                    Some(size),
+                    true,
+                    settings,
+                )
+            });
+
+            match thumbnail {
+                Ok((image, source_size)) => {
+                    return Some((image, ImageDecoder::Ffmpeg.label(), Some(source_size)))
+                }
                 Err(err) => {
-                    println!("Failed to load thumbnail using ffmpeg: {:?}", err);
+                    error!("Failed to load thumbnail using ffmpeg: {:?}", err);
                 }
             };
 
             return None;
         }
 
-        match ImageEntry::load_thumbnail_native(texture_manager, &file, size) {
-            Ok(texture) => return Some(texture),
-            Err(err) => {
-                println!(
-                    "Failed to load thumbnail using native loader, trying other options... Error: {:?}",
-                    err
-                );
+        let format = ImageEntry::try_guess_format(file).unwrap_or(ImageFormat::Unknown);
+
+        for decoder in settings.decoder_chain(file, &format, true) {
+            let maybe_image = catch_decoder_panic(decoder.label(), || match decoder {
+                ImageDecoder::Native => {
+                    ImageEntry::load_thumbnail_native(texture_manager, &file, size, settings)
+                }
+                ImageDecoder::Ffmpeg => ImageEntry::load_image_ffmpeg_with_size(
+                    texture_manager,
+                    &file,
+                    Some(size),
+                    true,
+                    settings,
+                ),
+                _ => ImageEntry::decode_first_frame(decoder, file, settings).and_then(|image| {
+                    ImageEntry::thumbnail_from_image(texture_manager, file, image, size, settings)
+                }),
+            });
+
+            match maybe_image {
+                Ok((image, source_size)) => {
+                    decode_stats::record_decoded(file, decoder);
+                    return Some((image, decoder.label(), Some(source_size)));
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to load thumbnail using {} decoder, trying other options... Error: {:?}",
+                        decoder.label(),
+                        err
+                    );
+                }
             }
         }
 
-        // TODO maybe this can be done better, so it's not duplicated
-        let img_format = ImageEntry::try_guess_format(file).unwrap_or(ImageFormat::Unknown);
+        error!(
+            "Failed to load thumbnail using any of the enabled decoders: {:?}",
+            file
+        );
 
-        let maybe_image = match img_format {
-            ImageFormat::Dicom => ImageEntry::load_dicom_image(texture_manager, file),
-            ImageFormat::Rpgmv => ImageEntry::load_rpgmv_image(texture_manager, file),
-            ImageFormat::JpegLs => ImageEntry::load_jpeg_ls_image(texture_manager, file),
-            ImageFormat::JBig1 => ImageEntry::load_jbig_image(texture_manager, file),
-            ImageFormat::JBig2 => ImageEntry::load_jbig_image(texture_manager, file),
-            ImageFormat::Unknown => ImageEntry::load_raw_image(texture_manager, file),
-        };
+        decode_stats::record_failed(file);
 
-        // TODO this returns full image instead of a thumbnail
-        match maybe_image {
-            Ok(image) => return Some(image),
-            Err(err) => {
-                println!("Failed to load thumbnail using other loaders: {:?}", err);
+        None
+    }
 
-                // TODO this returns before ffmpeg attempts to decode an image, since ffmpeg is unstable for now
-                return None;
-            }
+    // The other decoders can only produce the full size image, so it's scaled down here
+    // before it gets uploaded
+    fn thumbnail_from_image(
+        texture_manager: &SharedTextureManager,
+        file: &PathBuf,
+        image: DynamicImage,
+        size: f32,
+        settings: &Settings,
+    ) -> Result<(Image, (u32, u32)), Box<dyn std::error::Error>> {
+        let source_size = (image.width(), image.height());
+        let thumbnail = image.thumbnail(size as u32, size as u32);
+        let thumbnail_size = [thumbnail.width() as usize, thumbnail.height() as usize];
+
+        let color_image =
+            ColorImage::from_rgba_unmultiplied(thumbnail_size, thumbnail.into_rgba8().as_raw());
+
+        if settings.thumbnail_cache {
+            thumbnail_cache::store(file, size, &color_image, settings);
         }
 
-        match ImageEntry::load_image_ffmpeg(texture_manager, &file, Some(size), true) {
-            Ok(texture) => return Some(texture),
-            Err(err) => {
-                println!("Failed to load thumbnail using ffmpeg: {:?}", err);
+        let still_image = StillImage::from_color_image(color_image, texture_manager)?;
+
+        Ok((Image::Still(still_image), source_size))
+    }
+
+    // Only the first frame or page is decoded, that's all a thumbnail shows
+    fn decode_first_frame(
+        decoder: ImageDecoder,
+        file: &PathBuf,
+        settings: &Settings,
+    ) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+        match decoder {
+            ImageDecoder::Dicom => ImageEntry::decode_dicom_frames(file, 1)?
+                .pop()
+                .ok_or_else(|| "DICOM file has no frames".into()),
+            ImageDecoder::Rpgmv => ImageEntry::decode_rpgmv_image(file),
+            ImageDecoder::JpegLs => ImageEntry::decode_jpeg_ls_image(file),
+            ImageDecoder::JBig => ImageEntry::decode_jbig_pages(file, 1)?
+                .pop()
+                .ok_or_else(|| "JBIG document has no pages".into()),
+            ImageDecoder::Raw => {
+                ImageEntry::decode_raw_image(file, settings, &ImageAdjustments::default())
+            }
+            ImageDecoder::Native | ImageDecoder::Ffmpeg => {
+                Err("Decoder has a thumbnail path of its own".into())
             }
         }
-
-        None
     }
 
     fn load_thumbnail_native(
         texture_manager: &SharedTextureManager,
         file: &PathBuf,
         size: f32,
-    ) -> Result<Image, Box<dyn std::error::Error>> {
-        let image_reader = image::ImageReader::open(file)?;
-        let image = image_reader.decode()?;
+        settings: &Settings,
+    ) -> Result<(Image, (u32, u32)), Box<dyn std::error::Error>> {
+        let image_reader = image::ImageReader::open(file)?.with_guessed_format()?;
+        let mut image = image_reader.decode()?;
 
-        let thumbnail = image.thumbnail(size as u32, size as u32);
+        if settings.apply_exif_orientation {
+            image = ImageEntry::apply_exif_orientation(image, file);
+        }
+
+        let source_size = (image.width(), image.height());
+        let mut thumbnail = image.thumbnail(size as u32, size as u32);
+
+        // Converting the colors of the thumbnail is a lot cheaper than of the whole image
+        if settings.color_management {
+            thumbnail = ImageEntry::apply_icc_profile(thumbnail, file);
+        }
+
+        let thumbnail = ImageEntry::tone_map_hdr(thumbnail, settings, &ImageAdjustments::default());
         let thumbnail_size = [thumbnail.width() as usize, thumbnail.height() as usize];
 
         let thumbnail_bytes = thumbnail.into_rgba8();
@@ -592,15 +2312,16 @@ impl ImageEntry {
         let color_image =
             ColorImage::from_rgba_unmultiplied(thumbnail_size, flat_samples.as_slice());
 
-        let texture = load_texture(texture_manager.clone(), color_image);
+        if settings.thumbnail_cache {
+            thumbnail_cache::store(file, size, &color_image, settings);
+        }
+
+        let still_image = StillImage::from_color_image(color_image, texture_manager)?;
 
-        Ok(Image::Still(StillImage { texture }))
+        Ok((Image::Still(still_image), source_size))
     }
 
-    fn load_rpgmv_image(
-        texture_manager: &SharedTextureManager,
-        file: &PathBuf,
-    ) -> Result<Image, Box<dyn std::error::Error>> {
+    fn decode_rpgmv_image(file: &PathBuf) -> Result<DynamicImage, Box<dyn std::error::Error>> {
         // All rpgmv images are just png files with a custom header
         // Simply replacing the header with a valid png header produces a valid png file
         let png_header = [137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82];
@@ -613,42 +2334,51 @@ impl ImageEntry {
 
         buffer.splice(0..header_length * 2, png_header.iter().cloned());
 
-        let image = image::load_from_memory(&buffer)?;
-
-        let image_width = image.width() as usize;
-        let image_height = image.height() as usize;
-
-        return Ok(Image::Still(StillImage::from_pixels(
-            image.into_rgba8().into_flat_samples().as_slice().to_vec(),
-            [image_width, image_height],
-            texture_manager,
-        )));
+        Ok(image::load_from_memory(&buffer)?)
     }
 
-    fn load_dicom_image(
+    fn load_rpgmv_image(
         texture_manager: &SharedTextureManager,
         file: &PathBuf,
     ) -> Result<Image, Box<dyn std::error::Error>> {
+        let image = ImageEntry::decode_rpgmv_image(file)?;
+
+        let still_image = StillImage::from_dynamic_image(image, texture_manager)?;
+
+        Ok(Image::Still(still_image))
+    }
+
+    fn decode_dicom_frames(
+        file: &PathBuf,
+        max_frames: usize,
+    ) -> Result<Vec<DynamicImage>, Box<dyn std::error::Error>> {
         let file = dicom::object::open_file(file)?;
         let pixel_data = file.decode_pixel_data()?;
-        let frames_count = pixel_data.number_of_frames();
+        let frames_count = (pixel_data.number_of_frames() as usize).min(max_frames);
 
         let mut frames = Vec::new();
         for i in 0..frames_count {
-            let img = pixel_data.to_dynamic_image(i)?;
+            let img = pixel_data.to_dynamic_image(i as u32)?;
             frames.push(img);
         }
 
-        let width = pixel_data.columns();
-        let height = pixel_data.rows();
+        Ok(frames)
+    }
+
+    fn load_dicom_image(
+        texture_manager: &SharedTextureManager,
+        file: &PathBuf,
+    ) -> Result<Image, Box<dyn std::error::Error>> {
+        let mut frames = ImageEntry::decode_dicom_frames(file, usize::MAX)?;
 
-        let image_width = width as usize;
-        let image_height = height as usize;
+        let (image_width, image_height) = match frames.first() {
+            Some(frame) => (frame.width() as usize, frame.height() as usize),
+            None => return Err("DICOM file has no frames".into()),
+        };
 
         if frames.len() == 1 {
-            let raw_frame = RawImageFrame::from_image(frames.pop().unwrap());
             let still_image =
-                StillImage::from_raw_frame(raw_frame, [image_width, image_height], texture_manager);
+                StillImage::from_dynamic_image(frames.pop().unwrap(), texture_manager)?;
 
             return Ok(Image::Still(still_image));
         }
@@ -662,26 +2392,74 @@ impl ImageEntry {
             raw_frames,
             [image_width, image_height],
             texture_manager,
-        );
+        )?;
 
         Ok(Image::Animated(animated_image))
     }
 
-    fn load_raw_image(
-        texture_manager: &SharedTextureManager,
+    fn decode_raw_image(
         file: &PathBuf,
-    ) -> Result<Image, Box<dyn std::error::Error>> {
+        settings: &Settings,
+        adjustments: &ImageAdjustments,
+    ) -> Result<DynamicImage, Box<dyn std::error::Error>> {
         // TODO uncool
         // Imagepipe crate is pretty slow and adds a lot to the executable size.
         // It would be great to implement in-house demosaic and remove it from deps
-        let raw_image = rawloader::decode_file(file)?;
+        let mut raw_image = rawloader::decode_file(file)?;
+
+        // rawloader only knows the orientation for some cameras, most other RAWs are TIFF
+        // based and keep it in a regular EXIF tag
+        let orientation = match raw_image.orientation {
+            rawloader::Orientation::Unknown => ImageEntry::read_exif_orientation(file),
+            orientation => Some(orientation.to_u16() as u32),
+        };
+
+        // Rotated below instead of by imagepipe, so the setting covers RAWs like other formats
+        raw_image.orientation = rawloader::Orientation::Normal;
+
         let image_source = ImageSource::Raw(raw_image);
 
         let mut pipeline = Pipeline::new_from_source(image_source)?;
 
+        // Exposure is applied in linear space by the base curve, imagepipe has no gamma
+        // option, so that one is applied to the output
+        pipeline.ops.basecurve.exposure = settings.raw_exposure + adjustments.exposure;
+
+        if let Some((temperature, tint)) = adjustments.white_balance {
+            pipeline.ops.tolab.set_temp(temperature, tint);
+        }
+
         pipeline.run(None);
 
-        let image = pipeline.output_8bit(None)?;
+        let mut image = match adjustments.color_space.unwrap_or(settings.raw_color_space) {
+            RawColorSpace::Srgb => pipeline.output_8bit(None)?,
+            RawColorSpace::Linear => {
+                // 16 bit output is the only one imagepipe leaves linear. The values are encoded
+                // with the sRGB curve before they are cut to 8 bits, the screen expects them so
+                // and the dark tones would lose most of their steps otherwise
+                let image = pipeline.output_16bit(None)?;
+
+                let curve = (0..=u16::MAX)
+                    .map(|value| linear_to_srgb(value as f32 / u16::MAX as f32))
+                    .collect::<Vec<u8>>();
+
+                SRGBImage {
+                    width: image.width,
+                    height: image.height,
+                    data: image
+                        .data
+                        .iter()
+                        .map(|value| curve[*value as usize])
+                        .collect(),
+                }
+            }
+        };
+
+        ImageAdjustments {
+            exposure: 0.0,
+            ..*adjustments
+        }
+        .apply_to_pixels(&mut image.data, 3);
 
         let image = ImageBuffer::<Rgb<u8>, Vec<u8>>::from_raw(
             image.width as u32,
@@ -690,23 +2468,29 @@ impl ImageEntry {
         )
         .ok_or_else(|| "Failed to create image buffer")?;
 
-        let dynamic_image = image::DynamicImage::from(image);
+        let image = image::DynamicImage::from(image);
 
-        let image_width = dynamic_image.width() as usize;
-        let image_height = dynamic_image.height() as usize;
-
-        // TODO add StillImage::from_dynamic_image
-        let raw_frame = RawImageFrame::from_image(dynamic_image);
-        let still_image =
-            StillImage::from_raw_frame(raw_frame, [image_width, image_height], texture_manager);
+        if settings.apply_exif_orientation {
+            return Ok(ImageEntry::apply_orientation(image, orientation));
+        }
 
-        Ok(Image::Still(still_image))
+        Ok(image)
     }
 
-    fn load_jpeg_ls_image(
+    fn load_raw_image(
         texture_manager: &SharedTextureManager,
         file: &PathBuf,
+        settings: &Settings,
+        adjustments: &ImageAdjustments,
     ) -> Result<Image, Box<dyn std::error::Error>> {
+        let image = ImageEntry::decode_raw_image(file, settings, adjustments)?;
+
+        let still_image = StillImage::from_dynamic_image(image, texture_manager)?;
+
+        Ok(Image::Still(still_image))
+    }
+
+    fn decode_jpeg_ls_image(file: &PathBuf) -> Result<DynamicImage, Box<dyn std::error::Error>> {
         let file = File::open(file)?;
 
         let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(file));
@@ -720,40 +2504,149 @@ impl ImageEntry {
         )
         .ok_or_else(|| "Failed to create image buffer")?;
 
-        let dynamic_image = image::DynamicImage::ImageRgb8(image);
+        Ok(image::DynamicImage::ImageRgb8(image))
+    }
 
-        let image_width = dynamic_image.width() as usize;
-        let image_height = dynamic_image.height() as usize;
+    fn load_jpeg_ls_image(
+        texture_manager: &SharedTextureManager,
+        file: &PathBuf,
+    ) -> Result<Image, Box<dyn std::error::Error>> {
+        let image = ImageEntry::decode_jpeg_ls_image(file)?;
 
-        let raw_frame = RawImageFrame::from_image(dynamic_image);
-        let still_image =
-            StillImage::from_raw_frame(raw_frame, [image_width, image_height], texture_manager);
+        let still_image = StillImage::from_dynamic_image(image, texture_manager)?;
 
         Ok(Image::Still(still_image))
     }
 
+    // JBIG pages are one bit per pixel, rows padded to the stride, and a set bit is black
+    fn jbig_page_to_image(page: &jbig2dec::Image) -> Option<DynamicImage> {
+        let (width, height, stride) = (page.width(), page.height(), page.stride());
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let byte = page
+                    .data()
+                    .get((y * stride + x / 8) as usize)
+                    .copied()
+                    .unwrap_or(0);
+                let value = if byte & (0x80 >> (x % 8)) != 0 {
+                    0
+                } else {
+                    255
+                };
+
+                pixels.extend_from_slice(&[value, value, value, 255]);
+            }
+        }
+
+        RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8)
+    }
+
+    fn decode_jbig_pages(
+        file: &PathBuf,
+        max_pages: usize,
+    ) -> Result<Vec<DynamicImage>, Box<dyn std::error::Error>> {
+        // jbig2dec only knows the JBIG2 bitstream, JBIG1 is an unrelated format
+        if let Ok(ImageFormat::JBig1) = ImageEntry::try_guess_format(file) {
+            return Err("JBIG1 files can't be decoded yet, only JBIG2 is supported".into());
+        }
+
+        let doc = jbig2dec::Document::open(file)?;
+
+        doc.images()
+            .iter()
+            .take(max_pages)
+            .map(|page| {
+                ImageEntry::jbig_page_to_image(page).ok_or_else(|| "Invalid JBIG page size".into())
+            })
+            .collect()
+    }
+
     fn load_jbig_image(
         texture_manager: &SharedTextureManager,
         file: &PathBuf,
     ) -> Result<Image, Box<dyn std::error::Error>> {
-        let doc = jbig2dec::Document::open(file)?;
+        let mut pages = ImageEntry::decode_jbig_pages(file, usize::MAX)?
+            .into_iter()
+            .map(|page| StillImage::from_dynamic_image(page, texture_manager))
+            .collect::<Result<Vec<StillImage>, String>>()?;
+
+        match pages.len() {
+            0 => Err("JBIG document has no pages".into()),
+            1 => Ok(Image::Still(pages.pop().unwrap())),
+            _ => Ok(Image::Paged(PagedImage { pages })),
+        }
+    }
+
+    fn is_ico_file(file: &PathBuf) -> bool {
+        image::ImageReader::open(file)
+            .and_then(|reader| reader.with_guessed_format())
+            .map(|reader| reader.format() == Some(image::ImageFormat::Ico))
+            .unwrap_or(false)
+    }
+
+    // The decoder of the image crate only returns the largest image of an icon. To get the
+    // others, every entry of the directory is wrapped into an icon of its own, that way
+    // it still takes care of both the PNG and the BMP entries
+    fn decode_ico_images(file: &PathBuf) -> Result<Vec<DynamicImage>, Box<dyn std::error::Error>> {
+        let data = std::fs::read(file)?;
 
-        let mut images: Vec<Image> = Vec::new();
+        if data.len() < 6 {
+            return Err("Icon file is too short".into());
+        }
+
+        let entries_count = u16::from_le_bytes([data[4], data[5]]) as usize;
+        let mut images = Vec::new();
+
+        for index in 0..entries_count {
+            let entry = data
+                .get(6 + index * 16..6 + (index + 1) * 16)
+                .ok_or("Icon directory is truncated")?;
+
+            let size = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]) as usize;
+            let offset = u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]) as usize;
 
-        for image in doc.images() {
-            let width = image.width();
-            let height = image.height();
-            let data = image.data().to_vec();
+            let image_data = match data.get(offset..offset.saturating_add(size)) {
+                Some(image_data) => image_data,
+                None => {
+                    warn!("Entry {} of {:?} points outside of the file", index, file);
+                    continue;
+                }
+            };
 
-            let image = Image::Still(StillImage::from_pixels(
-                data,
-                [width as usize, height as usize],
-                texture_manager,
-            ));
+            let mut icon = Vec::with_capacity(22 + size);
+            icon.extend_from_slice(&data[0..4]);
+            icon.extend_from_slice(&1u16.to_le_bytes());
+            icon.extend_from_slice(&entry[0..12]);
+            icon.extend_from_slice(&22u32.to_le_bytes());
+            icon.extend_from_slice(image_data);
 
-            images.push(image);
+            match image::load_from_memory_with_format(&icon, image::ImageFormat::Ico) {
+                Ok(image) => images.push(image),
+                Err(err) => warn!("Failed to decode entry {} of {:?}: {}", index, file, err),
+            }
         }
 
-        Ok(images.pop().unwrap())
+        images.sort_by_key(|image| std::cmp::Reverse(image.width() * image.height()));
+
+        Ok(images)
+    }
+
+    // Every resolution becomes a page, the largest one is shown first
+    fn load_ico_image(
+        texture_manager: &SharedTextureManager,
+        file: &PathBuf,
+    ) -> Result<Image, Box<dyn std::error::Error>> {
+        let mut pages = ImageEntry::decode_ico_images(file)?
+            .into_iter()
+            .map(|image| StillImage::from_dynamic_image(image, texture_manager))
+            .collect::<Result<Vec<StillImage>, String>>()?;
+
+        match pages.len() {
+            0 => Err("Icon has no readable images".into()),
+            1 => Ok(Image::Still(pages.pop().unwrap())),
+            _ => Ok(Image::Paged(PagedImage { pages })),
+        }
     }
 }
@@ -0,0 +1,191 @@
+use crate::Settings;
+use eframe::egui::ColorImage;
+use log::{debug, warn};
+use std::{
+    env, fs,
+    io::Read,
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+const DECODER_NAME: &str = "isolated_ffmpeg_decoder";
+// Size of the thumbnail and of the video in front of the pixels, see the decoder for the layout
+const HEADER_SIZE: usize = 16;
+
+// How often a running decoder is checked on, there's nothing to wait on with a timeout in std
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+static NEXT_RESULT: AtomicUsize = AtomicUsize::new(0);
+
+// Built next to the gallery by the build-all alias
+fn decoder_path() -> Option<PathBuf> {
+    let gallery = env::current_exe().ok()?;
+
+    Some(gallery.with_file_name(format!("{}{}", DECODER_NAME, env::consts::EXE_SUFFIX)))
+}
+
+pub fn is_available() -> bool {
+    decoder_path().map(|path| path.exists()).unwrap_or(false)
+}
+
+pub fn results_dir(settings: &Settings) -> PathBuf {
+    match settings.isolated_decoder_dir.trim() {
+        "" => env::temp_dir().join("gallery").join("isolated"),
+        dir => PathBuf::from(dir),
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+// The thumbnail along with the width and the height of the video
+fn read_result(data: &[u8], max_size: usize) -> Result<(ColorImage, (u32, u32)), String> {
+    if data.len() < HEADER_SIZE || data.len() > max_size {
+        return Err(format!("Result of {} bytes is not usable", data.len()));
+    }
+
+    let width = read_u32(data, 0) as usize;
+    let height = read_u32(data, 4) as usize;
+    let source_size = (read_u32(data, 8), read_u32(data, 12));
+    let pixels = &data[HEADER_SIZE..];
+
+    if width == 0 || height == 0 || pixels.len() != width * height * 4 {
+        return Err(format!(
+            "Result of {} bytes doesn't match its size of {}x{}",
+            data.len(),
+            width,
+            height
+        ));
+    }
+
+    Ok((
+        ColorImage::from_rgba_unmultiplied([width, height], pixels),
+        source_size,
+    ))
+}
+
+// Unlike a thread the decoder can be stopped, so one that is stuck inside of ffmpeg is killed
+// once the timeout passes instead of being left to run
+fn wait_with_timeout(child: &mut Child, timeout: Option<Duration>) -> Result<ExitStatus, String> {
+    let started_at = Instant::now();
+
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(status),
+            Ok(None) => {}
+            Err(err) => return Err(format!("Could not wait for the isolated decoder: {}", err)),
+        }
+
+        if let Some(timeout) = timeout.filter(|timeout| started_at.elapsed() >= *timeout) {
+            if let Err(err) = child.kill() {
+                warn!("Failed to kill the isolated decoder: {}", err);
+            }
+
+            let _ = child.wait();
+
+            return Err(format!(
+                "Isolated decoder took longer than {} seconds and was stopped",
+                timeout.as_secs()
+            ));
+        }
+
+        thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+fn run_decoder(command: &mut Command, timeout: Option<Duration>) -> Result<(), String> {
+    let mut child = command
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Could not start the isolated decoder: {}", err))?;
+
+    // Read while the decoder runs, it would block on a full pipe otherwise
+    let stderr = child.stderr.take().map(|mut pipe| {
+        thread::spawn(move || {
+            let mut stderr = String::new();
+            let _ = pipe.read_to_string(&mut stderr);
+
+            stderr
+        })
+    });
+
+    let status = wait_with_timeout(&mut child, timeout)?;
+
+    if !status.success() {
+        let stderr = stderr
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default();
+
+        return Err(format!(
+            "Isolated decoder failed ({}): {}",
+            status,
+            stderr.trim()
+        ));
+    }
+
+    Ok(())
+}
+
+// The decoder runs as a process of its own, so a crash inside of ffmpeg only fails the file.
+// Its result goes through a file in the results folder, which is removed once it was read
+pub fn load_thumbnail(
+    file: &PathBuf,
+    size: f32,
+    settings: &Settings,
+) -> Result<(ColorImage, (u32, u32)), String> {
+    let decoder = decoder_path().ok_or("Could not find the isolated decoder")?;
+    let dir = results_dir(settings);
+
+    fs::create_dir_all(&dir)
+        .map_err(|err| format!("Could not create results folder {:?}: {}", dir, err))?;
+
+    let output = dir.join(format!(
+        "{}-{}.rgba",
+        std::process::id(),
+        NEXT_RESULT.fetch_add(1, Ordering::SeqCst)
+    ));
+    let max_size = settings.isolated_decoder_max_output_mb as usize * 1024 * 1024;
+
+    debug!("Decoding {:?} in {:?}", file, decoder);
+
+    let timeout = match settings.decode_timeout_secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+
+    let result = run_decoder(
+        Command::new(&decoder)
+            .arg(file)
+            .arg(size.to_string())
+            .arg(&output)
+            .arg(max_size.to_string()),
+        timeout,
+    );
+
+    // Reaped whatever happened, a crashed or killed decoder can leave the partial file behind
+    let data = fs::read(&output);
+
+    for path in [&output, &output.with_extension("tmp")] {
+        if path.exists() {
+            if let Err(err) = fs::remove_file(path) {
+                warn!("Failed to remove decoder result {:?}: {}", path, err);
+            }
+        }
+    }
+
+    result?;
+
+    let data = data.map_err(|err| format!("Could not read decoder result: {}", err))?;
+
+    read_result(&data, max_size)
+}
@@ -1,7 +1,4 @@
-mod utils;
-
-use std::env;
-use std::{io::Write, path::PathBuf};
+use std::{env, fs, io::Write, path::PathBuf, process::ExitCode};
 
 use ffmpeg_next::{
     self as ffmpeg,
@@ -11,46 +8,105 @@ use ffmpeg_next::{
     software::scaling::{context::Context as ScalingContext, flag::Flags},
     util::frame::video::Video as VideoFrame,
 };
-use interprocess::local_socket::prelude::LocalSocketListener;
-use interprocess::local_socket::{Listener, ToFsName};
-use interprocess::os::windows::local_socket::NamedPipe;
-use utils::calculate_contain_size;
-
-const SHM_NAME: &str = "my_shared_memory";
-const SEM_NAME: &str = "my_semaphore";
-const BUFFER_SIZE: usize = 1024;
 
-#[cfg(unix)]
-const SOCKET_PATH: &str = "/tmp/rust-ipc.sock";
+// Four little endian u32 in front of the pixels
+const HEADER_SIZE: usize = 16;
 
-#[cfg(windows)]
-const SOCKET_PATH: &str = r"\\.\pipe\rust-ipc";
+// Usage: isolated_ffmpeg_decoder <file> <size> <output> <max output bytes>
+//
+// The thumbnail is written to the output file as its width and height, then the width and the
+// height of the video, all little endian u32, followed by the RGBA pixels. It's written next to
+// the output first and renamed once complete, so the gallery never reads half of it. A crash or
+// an error only leaves the temporary file, which the gallery removes along with the result
+fn main() -> ExitCode {
+    let args = env::args().collect::<Vec<String>>();
 
-fn main() {
-    ffmpeg::init().unwrap();
+    if args.len() != 5 {
+        eprintln!("Usage: isolated_ffmpeg_decoder <file> <size> <output> <max output bytes>");
+        return ExitCode::FAILURE;
+    }
 
-    let args = env::args().collect::<Vec<String>>();
     let path = PathBuf::from(&args[1]);
-    let size = args[2].parse::<f32>().unwrap();
+    let output = PathBuf::from(&args[3]);
 
-    let socket_name = SOCKET_PATH.to_fs_name::<NamedPipe>().unwrap();
-    //let listener = LocalSocketListener::bind(socket_name).unwrap();
+    let (size, max_output_size) = match (args[2].parse::<f32>(), args[4].parse::<usize>()) {
+        (Ok(size), Ok(max_output_size)) if size > 0.0 => (size, max_output_size),
+        _ => {
+            eprintln!("Invalid size or output limit: {} {}", args[2], args[4]);
+            return ExitCode::FAILURE;
+        }
+    };
 
-    // check path and size
     if !path.exists() {
-        return;
+        eprintln!("File does not exist: {:?}", path);
+        return ExitCode::FAILURE;
     }
 
-    if size <= 0.0 {
-        return;
+    if let Err(err) = ffmpeg::init() {
+        eprintln!("Failed to initialize ffmpeg: {}", err);
+        return ExitCode::FAILURE;
     }
 
-    let data = load_thumbnail_ffmpeg(&path, size).unwrap();
+    let result = load_thumbnail_ffmpeg(&path, size).and_then(|(size, source_size, pixels)| {
+        if pixels.len() + HEADER_SIZE > max_output_size {
+            return Err(format!(
+                "Thumbnail of {} bytes is over the limit of {} bytes",
+                pixels.len() + HEADER_SIZE,
+                max_output_size
+            )
+            .into());
+        }
 
-    println!("Thumbnail data size: {}", data.len());
+        write_output(&output, size, source_size, &pixels)
+    });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn load_thumbnail_ffmpeg(file: &PathBuf, size: f32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+fn write_output(
+    output: &PathBuf,
+    size: (u32, u32),
+    source_size: (u32, u32),
+    pixels: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let partial = output.with_extension("tmp");
+    let mut file = fs::File::create(&partial)?;
+
+    for value in [size.0, size.1, source_size.0, source_size.1] {
+        file.write_all(&value.to_le_bytes())?;
+    }
+
+    file.write_all(pixels)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&partial, output)?;
+
+    Ok(())
+}
+
+// Same as calculate_contain_size of the gallery, which can't be shared without pulling in the rest
+fn thumbnail_size(size: f32, width: f32, height: f32) -> (u32, u32) {
+    let (width, height) = if width >= height {
+        (size, size * height / width)
+    } else {
+        (size * width / height, size)
+    };
+
+    (width.max(1.0) as u32, height.max(1.0) as u32)
+}
+
+// The size of the thumbnail and of the video come along with the pixels
+fn load_thumbnail_ffmpeg(
+    file: &PathBuf,
+    size: f32,
+) -> Result<((u32, u32), (u32, u32), Vec<u8>), Box<dyn std::error::Error>> {
     let mut ictx = format::input(file)?;
 
     let input = ictx
@@ -62,44 +118,58 @@ fn load_thumbnail_ffmpeg(file: &PathBuf, size: f32) -> Result<Vec<u8>, Box<dyn s
     let context = CodecContext::from_parameters(input.parameters())?;
     let mut decoder = context.decoder().video()?;
 
-    let thumbnail_size =
-        calculate_contain_size(size, size, decoder.width() as f32, decoder.height() as f32);
+    let (thumbnail_width, thumbnail_height) =
+        thumbnail_size(size, decoder.width() as f32, decoder.height() as f32);
 
     let mut scaler = ScalingContext::get(
         decoder.format(),
         decoder.width(),
         decoder.height(),
         Pixel::RGBA,
-        thumbnail_size.0.trunc() as u32,
-        thumbnail_size.1.trunc() as u32,
+        thumbnail_width,
+        thumbnail_height,
         Flags::BILINEAR,
     )?;
 
     for (stream, packet) in ictx.packets() {
-        if stream.index() == video_stream_index {
-            decoder.send_packet(&packet)?;
+        if stream.index() != video_stream_index {
+            continue;
+        }
+
+        decoder.send_packet(&packet)?;
+
+        // The decoder may need a few packets before it has the first frame
+        let mut frame = VideoFrame::empty();
 
-            let mut frame = VideoFrame::empty();
-            decoder.receive_frame(&mut frame)?;
+        if decoder.receive_frame(&mut frame).is_err() {
+            continue;
+        }
 
-            let mut rgba_frame = VideoFrame::empty();
-            scaler.run(&frame, &mut rgba_frame)?;
+        let mut rgba_frame = VideoFrame::empty();
+        scaler.run(&frame, &mut rgba_frame)?;
 
-            let width = rgba_frame.width() as usize;
-            let height = rgba_frame.height() as usize;
-            let stride = rgba_frame.stride(0);
-            let expected_size = width * height * 4;
+        let width = rgba_frame.width() as usize;
+        let height = rgba_frame.height() as usize;
+        let stride = rgba_frame.stride(0);
+        let data = rgba_frame.data(0);
 
-            let mut buffer = Vec::with_capacity(expected_size);
+        if width == 0 || height == 0 || data.len() < stride * (height - 1) + width * 4 {
+            return Err("Unexpected layout of the scaled video frame".into());
+        }
 
-            for y in 0..height {
-                let start = y * stride;
-                let end = start + width * 4;
-                buffer.extend_from_slice(&rgba_frame.data(0)[start..end]);
-            }
+        let mut buffer = Vec::with_capacity(width * height * 4);
 
-            return Ok(buffer);
+        for y in 0..height {
+            let start = y * stride;
+            let end = start + width * 4;
+            buffer.extend_from_slice(&data[start..end]);
         }
+
+        return Ok((
+            (width as u32, height as u32),
+            (decoder.width(), decoder.height()),
+            buffer,
+        ));
     }
 
     Err("No frames found".into())
@@ -1,40 +1,94 @@
+use crate::commands::Command;
+use crate::export::{self, ClipExport, Conversion, ConversionOptions, FrameExport};
+use crate::rotate;
+use crate::sidecar::Sidecar;
 use crate::utils::{
-    calculate_contain_size, calculate_thumbnail_layout, get_window_size,
+    apply_rename_pattern, calculate_average_color, calculate_contain_size,
+    calculate_justified_rows, calculate_perceptual_hash, calculate_thumbnail_layout, format_bytes,
+    format_time, is_video, key_pressed, load_texture, open_url, random_index,
+    thumbnail_decode_size, validate_file_name, with_thumbnail_resolution,
 };
 use crate::video_entry::VideoEntry;
-use crate::image_entry::ImageEntry;
-use crate::widgets::image_frame::ImageFrame;
-use crate::widgets::video_player::VideoPlayer;
-use crate::{App, CurrentEntry, MediaType};
+use crate::image_entry::{ImageAdjustments, ImageDecoder, ImageEntry, RawColorSpace};
+use crate::widgets::image_frame::{EntryLabel, ImageFrame};
+use crate::widgets::image_viewer::{ImageViewer, ViewTransform};
+use crate::widgets::video_player::{VideoFit, VideoPlayer};
+use crate::{
+    AnimatedThumbnail, App, BatchRenameState, ConversionState, CurrentEntry, GalleryEntry,
+    GridLayout, InteractionModel, MediaFilter, MediaType, OverlayCorner, PreviewTool, PreviewWheel,
+    RenameState, RotationState, Settings, SortMode, TagsState,
+};
 use eframe::egui::{self, FontId};
-use std::path::PathBuf;
+use futures::executor;
+use image::RgbaImage;
+use log::{error, info, warn};
+use rfd::AsyncFileDialog;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    ops::Range,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
 use trash;
 
-pub fn build_grid(app: &mut App, ctx: &egui::Context, ui: &mut egui::Ui) {
-    if ctx.input(|i| i.key_pressed(egui::Key::Delete)) {
-        let mut entries = app.entries.lock().unwrap();
-        let to_delete = entries
-            .iter()
-            .filter(|entry| entry.marked)
-            .map(|entry| entry.path.clone())
-            .collect::<Vec<PathBuf>>();
+const HIGHLIGHT_DURATION: Duration = Duration::from_millis(1500);
+// Same as the window egui uses to detect double clicks
+const DOUBLE_CLICK_DELAY: Duration = Duration::from_millis(300);
+// Moving the pointer across the grid shouldn't decode every animated entry it passes over
+const ANIMATION_HOVER_DELAY: Duration = Duration::from_millis(300);
+// Hovered animations are decoded a couple at a time, the ones hovered meanwhile wait their turn
+const ANIMATION_DECODE_THREADS: usize = 2;
 
-        for file in to_delete {
-            match trash::delete(&file) {
-                Ok(_) => {
-                    entries.retain(|entry| entry.path != file);
-                }
-                Err(err) => {
-                    println!("Error deleting file: {:?}", err);
-                }
-            }
+static ANIMATION_DECODE_POOL: OnceLock<Option<rayon::ThreadPool>> = OnceLock::new();
+// One notch of a mouse wheel, egui-winit scrolls 50 points per line
+const WHEEL_NAVIGATION_STEP: f32 = 50.0;
+// Holding an arrow key repeats its step after the delay, every interval at first
+const KEY_REPEAT_DELAY: Duration = Duration::from_millis(400);
+const KEY_REPEAT_INTERVAL: Duration = Duration::from_millis(150);
+const MINIMAP_WIDTH: f32 = 48.0;
+// Distance of the image menu from the bottom left corner of the preview
+const IMAGE_MENU_MARGIN: f32 = 10.0;
+
+// A row of the grid as the minimap draws it, with the left edge, width and color of every cell
+struct MinimapRow {
+    top: f32,
+    height: f32,
+    cells: Vec<(f32, f32, egui::Color32)>,
+}
+
+impl Hash for MinimapRow {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.top.to_bits().hash(state);
+        self.height.to_bits().hash(state);
+
+        for (left, width, color) in &self.cells {
+            (left.to_bits(), width.to_bits(), color.to_array()).hash(state);
         }
     }
+}
+
+pub fn build_grid(app: &mut App, ctx: &egui::Context, ui: &mut egui::Ui) {
+    if ui.ui_contains_pointer() {
+        update_grid_scrolling(app, ctx);
+    } else {
+        app.scroll_velocity = 0.0;
+    }
 
-    egui::ScrollArea::vertical().show(ui, |ui| {
-        let available_width = ui.available_width();
+    egui::ScrollArea::vertical().show_viewport(ui, |ui, viewport| {
         let gap = 10.0;
-        ui.set_width(available_width);
+        ui.set_width(ui.available_width());
+
+        // The minimap takes a strip between the grid and the scrollbar
+        let available_width = if app.settings.show_grid_minimap {
+            ui.available_width() - MINIMAP_WIDTH - gap * 2.0
+        } else {
+            ui.available_width()
+        };
 
         let min_thumbnail_width = app.settings.min_thumbnail_size;
         let max_columns_count = app.settings.max_columns_count;
@@ -45,67 +99,454 @@ pub fn build_grid(app: &mut App, ctx: &egui::Context, ui: &mut egui::Ui) {
             max_columns_count,
         );
 
-        app.entries
-            .lock()
-            .unwrap()
-            .sort_by(|a, b| a.path.cmp(&b.path));
+        let descending = app.settings.sort_descending != app.reverse_order;
+        let mut entries = app.entries.lock().unwrap();
 
-        let entries_paths = app
-            .entries
-            .lock()
-            .unwrap()
+        match app.settings.sort_mode {
+            SortMode::Path => entries.sort_by(|a, b| {
+                let ordering = a.path.cmp(&b.path);
+
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            }),
+            // The sort is stable, so entries that were never placed keep their order at the end
+            SortMode::Manual => {
+                entries.sort_by_key(|entry| entry.manual_order);
+
+                for (order, entry) in entries.iter_mut().enumerate() {
+                    entry.manual_order = order;
+                }
+            }
+        }
+
+        let is_manual_order = app.settings.sort_mode == SortMode::Manual;
+        let opens_on_double_click =
+            app.settings.interaction_model == InteractionModel::DoubleClickOpens;
+
+        // Dragging an entry into another section couldn't change its type, so the manual
+        // order is never grouped. The sort is stable, inside of a section the order stays
+        let group_by_type = app.settings.group_by_type && !is_manual_order;
+
+        if group_by_type {
+            entries.sort_by_key(|entry| entry.media_type.clone());
+        }
+
+        let visible_indices = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| is_entry_visible(entry, &app.settings, &app.media_filter))
+            .map(|(index, _)| index)
+            .collect::<Vec<usize>>();
+
+        // Numbered by when they were marked, the first one marked is 1
+        let mut selection_numbers = vec![None; entries.len()];
+
+        if app.settings.show_selection_order {
+            let mut marked = entries
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.marked)
+                .map(|(index, entry)| (entry.marked_sequence, index))
+                .collect::<Vec<(u64, usize)>>();
+
+            marked.sort();
+
+            for (number, (_, index)) in marked.into_iter().enumerate() {
+                selection_numbers[index] = Some(number + 1);
+            }
+        }
+
+        // Positions in the visible entries where a new type starts, without grouping the
+        // whole grid is a single section without a header
+        let mut sections: Vec<(Option<MediaType>, Range<usize>)> = Vec::new();
+
+        for (position, index) in visible_indices.iter().enumerate() {
+            let media_type = &entries[*index].media_type;
+
+            match sections.last_mut() {
+                Some((Some(section_type), range)) if section_type == media_type => {
+                    range.end = position + 1;
+                }
+                Some((None, range)) => range.end = position + 1,
+                _ => sections.push((
+                    group_by_type.then(|| media_type.clone()),
+                    position..position + 1,
+                )),
+            }
+        }
+
+        // Only the sizes of the cells are needed to lay out the whole grid, so the rows
+        // that are scrolled out of view don't get any widgets. Rows never cross sections
+        let row_height = thumbnail_width + gap;
+        let mut rows = Vec::new();
+        let mut row_headers = Vec::new();
+        let mut cell_widths = vec![thumbnail_width; visible_indices.len()];
+
+        for (media_type, section) in sections.iter() {
+            let section_rows = match app.settings.grid_layout {
+                GridLayout::Uniform => section
+                    .clone()
+                    .step_by(columns)
+                    .map(|start| (start..(start + columns).min(section.end), thumbnail_width))
+                    .collect::<Vec<_>>(),
+                GridLayout::Justified => {
+                    // Extreme panoramas would take a whole row or end up as a sliver otherwise
+                    let aspect_ratios = visible_indices[section.clone()]
+                        .iter()
+                        .map(|index| {
+                            let [width, height] = entries[*index].thumbnail.size();
+
+                            (width as f32 / height.max(1) as f32).clamp(0.25, 4.0)
+                        })
+                        .collect::<Vec<f32>>();
+
+                    let rows = calculate_justified_rows(
+                        &aspect_ratios,
+                        available_width,
+                        thumbnail_width,
+                        gap,
+                    );
+
+                    rows.into_iter()
+                        .map(|(range, height)| {
+                            for position in range.clone() {
+                                cell_widths[section.start + position] =
+                                    aspect_ratios[position] * height;
+                            }
+
+                            (
+                                section.start + range.start..section.start + range.end,
+                                height,
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                }
+            };
+
+            for row in 0..section_rows.len() {
+                row_headers.push(match media_type {
+                    Some(media_type) if row == 0 => Some(format!(
+                        "{} ({})",
+                        media_type.section_label(),
+                        section.len()
+                    )),
+                    _ => None,
+                });
+            }
+
+            rows.extend(section_rows);
+        }
+
+        let header_height = ui.text_style_height(&egui::TextStyle::Heading) + gap;
+        let rows_count = rows.len();
+        let row_tops = rows
+            .iter()
+            .zip(row_headers.iter())
+            .scan(0.0, |top, ((_, height), header)| {
+                if header.is_some() {
+                    *top += header_height;
+                }
+
+                let row_top = *top;
+                *top += height + gap;
+
+                Some(row_top)
+            })
+            .collect::<Vec<f32>>();
+
+        let grid_origin = ui.cursor().min;
+        let row_rect = |row: usize| match (row_tops.get(row), rows.get(row)) {
+            (Some(top), Some((_, height))) => egui::Rect::from_min_size(
+                grid_origin + egui::vec2(0.0, *top),
+                egui::vec2(available_width, *height),
+            ),
+            _ => egui::Rect::from_min_size(grid_origin, egui::Vec2::ZERO),
+        };
+
+        let grid_height = row_tops.last().copied().unwrap_or(0.0)
+            + rows.last().map(|(_, height)| height + gap).unwrap_or(0.0);
+
+        ui.set_min_height(grid_height);
+
+        // Paging moves by the number of whole rows that fit into the visible part of the grid
+        let page_height = (ui.clip_rect().height() / row_height).floor().max(1.0) * row_height;
+        let is_command_down = ctx.input(|i| i.modifiers.command);
+
+        if key_pressed(ctx, egui::Key::PageDown) {
+            ui.scroll_with_delta(egui::vec2(0.0, -page_height));
+        }
+
+        if key_pressed(ctx, egui::Key::PageUp) {
+            ui.scroll_with_delta(egui::vec2(0.0, page_height));
+        }
+
+        if is_command_down && key_pressed(ctx, egui::Key::Home) {
+            ui.scroll_to_rect(row_rect(0), Some(egui::Align::TOP));
+        }
+
+        if is_command_down && key_pressed(ctx, egui::Key::End) {
+            ui.scroll_to_rect(row_rect(rows_count.max(1) - 1), Some(egui::Align::BOTTOM));
+        }
+
+        let mut clicked_entry = None;
+        let mut hovered_entry = None;
+        let mut hovered_animation = None;
+
+        let highlighted_index = app.highlighted_entry.as_ref().and_then(|(path, _)| {
+            entries
+                .iter()
+                .position(|gallery_entry| &gallery_entry.path == path)
+        });
+
+        // The highlighted row may not have any widgets yet, so it's scrolled to by position.
+        // Hidden entries can't be scrolled to, so this is only tried once
+        if app.scroll_to_highlighted {
+            let position = highlighted_index
+                .and_then(|index| visible_indices.iter().position(|i| *i == index));
+
+            let row = position
+                .and_then(|position| rows.iter().position(|(range, _)| range.contains(&position)));
+
+            if let Some(row) = row {
+                ui.scroll_to_rect(row_rect(row), Some(egui::Align::Center));
+            }
+
+            app.scroll_to_highlighted = false;
+        }
+
+        if app.settings.show_grid_minimap {
+            let placeholder_color = ui.visuals().widgets.inactive.bg_fill;
+
+            let minimap_rows = rows
+                .iter()
+                .zip(row_tops.iter())
+                .map(|((range, height), top)| {
+                    let mut left = 0.0;
+
+                    let cells = range
+                        .clone()
+                        .map(|position| {
+                            let entry = &entries[visible_indices[position]];
+                            let cell = (
+                                left,
+                                cell_widths[position],
+                                entry.average_color.unwrap_or(placeholder_color),
+                            );
+
+                            left += cell_widths[position] + gap;
+
+                            cell
+                        })
+                        .collect();
+
+                    MinimapRow {
+                        top: *top,
+                        height: *height,
+                        cells,
+                    }
+                })
+                .collect::<Vec<MinimapRow>>();
+
+            let clip_rect = ui.clip_rect();
+            let minimap_rect = egui::Rect::from_min_max(
+                egui::pos2(grid_origin.x + available_width + gap, clip_rect.top()),
+                egui::pos2(
+                    grid_origin.x + available_width + gap + MINIMAP_WIDTH,
+                    clip_rect.bottom(),
+                ),
+            );
+
+            let grid_size = egui::vec2(available_width, grid_height);
+            let pixels_per_point = ui.ctx().pixels_per_point();
+            let image_size = [
+                (minimap_rect.width() * pixels_per_point).round().max(1.0) as usize,
+                (minimap_rect.height() * pixels_per_point).round().max(1.0) as usize,
+            ];
+
+            // Drawn into a texture once, and again only when the entries, the filter or the
+            // layout change what it shows
+            let mut hasher = DefaultHasher::new();
+            (image_size, grid_size.x.to_bits(), grid_size.y.to_bits()).hash(&mut hasher);
+            minimap_rows.hash(&mut hasher);
+            let minimap_key = hasher.finish();
+
+            if app.grid_minimap.as_ref().map(|(key, _)| *key) != Some(minimap_key) {
+                let image = draw_grid_minimap(
+                    &minimap_rows,
+                    image_size,
+                    grid_size,
+                    ui.visuals().extreme_bg_color,
+                );
+
+                app.grid_minimap = load_texture(ui.ctx().tex_manager(), image)
+                    .map_err(|err| error!("Failed to create the grid minimap: {}", err))
+                    .ok()
+                    .map(|texture| (minimap_key, texture));
+            }
+
+            let target = app.grid_minimap.as_ref().and_then(|(_, texture)| {
+                build_grid_minimap(ui, minimap_rect, texture, grid_size, viewport)
+            });
+
+            if let Some(target) = target {
+                let target_rect = egui::Rect::from_min_size(
+                    grid_origin + egui::vec2(0.0, target),
+                    egui::vec2(available_width, 0.0),
+                );
+
+                ui.scroll_to_rect(target_rect, Some(egui::Align::Center));
+            }
+        }
+
+        // One extra row on each side, so cells don't pop in at the edges while scrolling
+        let first_row = row_tops
+            .partition_point(|top| *top + row_height < viewport.min.y)
+            .saturating_sub(1);
+        let last_row = (row_tops.partition_point(|top| *top <= viewport.max.y) + 1).min(rows_count);
+
+        let visible_placeholders = rows[first_row..last_row]
             .iter()
+            .flat_map(|(range, _)| range.clone())
+            .map(|position| &entries[visible_indices[position]])
+            .filter(|entry| entry.loading)
             .map(|entry| entry.path.clone())
             .collect::<Vec<PathBuf>>();
 
-        let mut entries_to_toggle = vec![];
+        *app.visible_paths.lock().unwrap() = visible_placeholders;
 
-        for chunk in app.entries.lock().unwrap().chunks_mut(columns) {
-            ui.horizontal(|ui| {
+        for row in first_row..last_row {
+            let (range, height) = rows[row].clone();
+
+            if let Some(header) = &row_headers[row] {
+                let header_rect = egui::Rect::from_min_size(
+                    row_rect(row).min - egui::vec2(0.0, header_height),
+                    egui::vec2(available_width, header_height - gap),
+                );
+
+                ui.allocate_ui_at_rect(header_rect, |ui| {
+                    ui.heading(header);
+                });
+            }
+
+            ui.allocate_ui_at_rect(row_rect(row), |ui| {
                 ui.with_layout(egui::Layout::left_to_right(egui::Align::Center), |ui| {
-                    for entry in chunk.iter_mut() {
+                    if app.settings.grid_layout == GridLayout::Justified {
+                        ui.spacing_mut().item_spacing.x = gap;
+                    }
+
+                    for position in range {
+                        let index = &visible_indices[position];
+                        let entry = &mut entries[*index];
+
+                        let texture = animated_thumbnail_frame(
+                            &app.animated_thumbnails,
+                            &app.settings,
+                            &entry.path,
+                            ctx,
+                        )
+                        .unwrap_or_else(|| entry.thumbnail.clone());
+
                         let i_f = ImageFrame::new(
-                            &entry.thumbnail,
-                            thumbnail_width,
-                            thumbnail_width,
+                            &texture,
+                            cell_widths[position],
+                            height,
                             &entry.path,
                             entry.marked,
-                        );
-                        let image_res = ui.add(i_f);
+                        )
+                        .badge(if entry.missing {
+                            Some("file missing".to_string())
+                        } else if entry.corrupt {
+                            Some("corrupt".to_string())
+                        } else if let Some(group) = entry.duplicate_group {
+                            Some(format!("dup #{}", group))
+                        } else if entry.media_type == MediaType::ImageAnimated {
+                            Some("animated".to_string())
+                        } else {
+                            None
+                        })
+                        .label(entry_label(entry, app.settings.entry_label))
+                        .rating(entry.rating)
+                        .fit(app.settings.thumbnail_fit)
+                        .corner_radius(app.settings.thumbnail_corner_radius)
+                        .selection(
+                            app.settings.selection_style,
+                            app.settings.selection_color,
+                            app.settings.selection_thickness,
+                        )
+                        .checkmark(app.settings.selection_checkmark)
+                        .selection_number(selection_numbers[*index])
+                        .overlay(
+                            app.settings.overlay_font_size,
+                            app.settings.thumbnail_label_color,
+                            app.settings.overlay_color,
+                        )
+                        .loading(entry.loading)
+                        .loading_style(app.settings.loading_style)
+                        .draggable(is_manual_order)
+                        .highlight(highlighted_index == Some(*index));
+                        let mut image_res = ui.add(i_f);
+
+                        if image_res.hovered() {
+                            hovered_entry = Some(entry.path.clone());
+                        }
+
+                        if image_res.hovered() && entry.media_type == MediaType::ImageAnimated {
+                            hovered_animation = Some(entry.path.clone());
+                        }
+
+                        if is_manual_order && image_res.drag_started() {
+                            app.dragged_entry = Some(entry.path.clone());
+                        }
+
+                        if let Some(error) = &entry.error {
+                            image_res = image_res.on_hover_text(error);
+                        }
+
+                        let is_double_click = opens_on_double_click && image_res.double_clicked();
 
                         if image_res.clicked() {
                             let is_shift_down = ctx.input(|i| i.modifiers.shift);
-                            let current_entry_index = entries_paths
-                                .iter()
-                                .position(|path| path == &entry.path)
-                                .unwrap();
-                            let last_marked_entry_index = match app.last_marked_entry_index {
-                                Some(index) => index,
-                                None => current_entry_index,
-                            };
 
-                            if !is_shift_down {
-                                let marked = entry.marked;
-                                entry.marked = !marked;
+                            // The first click of a double click shouldn't mark the entry,
+                            // so it only counts once no second click followed
+                            if opens_on_double_click {
+                                app.pending_click =
+                                    Some((entry.path.clone(), is_shift_down, Instant::now()));
                             } else {
-                                let start =
-                                    std::cmp::min(last_marked_entry_index, current_entry_index);
-                                let end =
-                                    std::cmp::max(last_marked_entry_index, current_entry_index);
-
-                                for i in start..=end {
-                                    entries_to_toggle.push(i);
-                                }
+                                clicked_entry = Some((*index, is_shift_down));
                             }
-                            app.last_marked_entry_index = Some(current_entry_index);
                         }
 
-                        if image_res.secondary_clicked() {
+                        if is_double_click {
+                            app.pending_click = None;
+                        }
+
+                        if image_res.secondary_clicked() || is_double_click {
+                            // Caught here as well, so it doesn't have to wait for the next check
+                            if entry.frame.is_none() && !entry.path.exists() {
+                                error!("File is missing: {:?}", entry.path);
+                                entry.missing = true;
+
+                                return;
+                            }
+
                             if entry.media_type == MediaType::Video {
-                                let video = VideoEntry::new(&entry.path);
+                                let video = VideoEntry::new(
+                                    &entry.path,
+                                    !app.settings.disable_audio,
+                                    app.settings.fallback_frame_rate as f64,
+                                    ctx,
+                                );
 
                                 match video {
-                                    Some(video) => {
+                                    Some(mut video) => {
+                                        if app.settings.autoplay_videos {
+                                            video.play();
+                                        }
+
                                         app.current_entry = Some(CurrentEntry {
                                             media_type: MediaType::Video,
                                             image: None,
@@ -113,7 +554,7 @@ pub fn build_grid(app: &mut App, ctx: &egui::Context, ui: &mut egui::Ui) {
                                         });
                                     }
                                     None => {
-                                        println!("Failed to load video: {:?}", entry.path);
+                                        error!("Failed to load video: {:?}", entry.path);
 
                                         return;
                                     }
@@ -122,12 +563,28 @@ pub fn build_grid(app: &mut App, ctx: &egui::Context, ui: &mut egui::Ui) {
                                 return;
                             }
 
-                            let image = ImageEntry::new(&entry.path, ctx);
+                            let mut image = match &entry.frame {
+                                Some(frame) => {
+                                    ImageEntry::from_frame(&entry.path, frame, ctx, &app.settings)
+                                }
+                                None => ImageEntry::new(
+                                    &entry.path,
+                                    ctx,
+                                    &app.settings,
+                                    app.decoder_overrides.get(&entry.path).copied(),
+                                ),
+                            };
+                            restore_view(&mut image, &app.settings, &app.saved_views);
+
+                            // The full decode knows the real frame count, so the badge follows it
+                            if let Some(image) = &image {
+                                entry.media_type = image.media_type.clone();
+                            }
 
                             app.current_entry = Some(CurrentEntry {
                                 media_type: match &image {
                                     Some(image) => image.media_type.clone(),
-                                    None => MediaType::ImageAnimated,
+                                    None => entry.media_type.clone(),
                                 },
                                 image,
                                 video: None,
@@ -136,269 +593,3163 @@ pub fn build_grid(app: &mut App, ctx: &egui::Context, ui: &mut egui::Ui) {
                     }
                 });
             });
-
-            ui.add_space(gap);
         }
 
-        for i in entries_to_toggle {
-            app.entries.lock().unwrap()[i].marked = true;
-        }
-    });
-}
+        update_hovered_animation(
+            &mut app.animated_thumbnails,
+            &mut app.hovered_animation,
+            &app.settings,
+            &app.decoder_overrides,
+            hovered_animation,
+            ctx,
+        );
+        app.hovered_entry = hovered_entry;
 
-pub fn build_preview(app: &mut App, ctx: &egui::Context, ui: &mut egui::Ui) {
-    let window_size = get_window_size(ctx);
+        if let Some((path, is_shift_down, clicked_at)) = app.pending_click.clone() {
+            match DOUBLE_CLICK_DELAY.checked_sub(clicked_at.elapsed()) {
+                Some(remaining) => ctx.request_repaint_after(remaining),
+                None => {
+                    app.pending_click = None;
+                    clicked_entry = entries
+                        .iter()
+                        .position(|entry| entry.path == path)
+                        .map(|index| (index, is_shift_down));
+                }
+            }
+        }
 
-    if ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
-        app.current_entry = None;
-    }
+        if let Some((index, is_shift_down)) = clicked_entry {
+            // Resolved on every click, the entries may have been sorted since the last one
+            let last_marked_entry_index = app
+                .last_marked_entry
+                .as_ref()
+                .and_then(|path| entries.iter().position(|entry| &entry.path == path))
+                .unwrap_or(index);
 
-    if ctx.input(|i| i.pointer.secondary_pressed()) {
-        app.current_entry = None;
-    }
+            if !is_shift_down {
+                let marked = !entries[index].marked;
+                entries[index].set_marked(marked);
+            } else {
+                let start = std::cmp::min(last_marked_entry_index, index);
+                let end = std::cmp::max(last_marked_entry_index, index);
 
-    if ctx.input(|i| i.key_pressed(egui::Key::Space)) {
-        match &mut app.current_entry {
-            Some(entry) => {
-                match &mut entry.video {
-                    Some(video) => {
-                        video.toggle_playback();
-                        return ();
+                // Hidden entries inside the range stay as they are
+                for i in start..=end {
+                    if visible_indices.binary_search(&i).is_ok() {
+                        entries[i].set_marked(true);
                     }
-                    None => {
-                        return ();
-                    }
-                };
-            }
-            None => {
-                println!("No current entry found");
-                return ();
-            }
-        };
-    }
-
-    if ctx.input(|i| i.key_pressed(egui::Key::ArrowRight) || i.key_pressed(egui::Key::ArrowLeft)) {
-        let current_entry = match &mut app.current_entry {
-            Some(entry) => entry,
-            None => {
-                println!("No current entry found");
-                return ();
+                }
             }
-        };
 
-        match current_entry.video {
-            Some(ref mut video) => {
-                let is_shift_down = ctx.input(|i| i.modifiers.shift);
+            app.last_marked_entry = Some(entries[index].path.clone());
+        }
 
-                if !is_shift_down {
-                    return ();
-                }
+        if let Some(dragged_path) = app.dragged_entry.clone() {
+            // The slot between two cells that is closest to the pointer, counted in visible
+            // entries, along with where the marker for it goes
+            let slot = ctx.input(|i| i.pointer.interact_pos()).and_then(|pointer| {
+                let relative = pointer - grid_origin;
+                let row = row_tops
+                    .partition_point(|top| *top <= relative.y)
+                    .saturating_sub(1);
+                let (range, height) = rows.get(row)?.clone();
 
-                if ctx.input(|i| i.key_pressed(egui::Key::ArrowRight)) {
-                    video.seek_relative(5 * 1000);
-                } else {
-                    video.seek_relative(-5 * 1000);
-                }
+                let mut x = 0.0;
+                let mut target = range.end;
 
-                return ();
-            }
-            None => {}
-        }
+                for position in range {
+                    if relative.x < x + cell_widths[position] / 2.0 {
+                        target = position;
+                        break;
+                    }
 
-        let current_entry_path = match &current_entry.media_type {
-            MediaType::ImageStill | MediaType::ImageAnimated => match &current_entry.image {
-                Some(image) => image.path.clone(),
-                None => {
-                    println!("No image found for current entry");
-                    return ();
-                }
-            },
-            MediaType::Video => match &current_entry.video {
-                Some(video) => video.path.clone(),
-                None => {
-                    println!("No video found for current entry");
-                    return ();
+                    x += cell_widths[position] + gap;
                 }
-            },
-        };
 
-        let current_entry_index = app
-            .entries
-            .lock()
-            .unwrap()
-            .iter()
-            .position(|entry| entry.path == current_entry_path)
-            .unwrap();
+                let marker_top = grid_origin + egui::vec2(x - gap / 2.0, row_tops[row]);
 
-        let previous_entry_index = if current_entry_index == 0 {
-            app.entries.lock().unwrap().len() - 1
-        } else {
-            current_entry_index - 1
-        };
+                Some((target, marker_top, height))
+            });
 
-        let next_entry_index = if current_entry_index + 1 >= app.entries.lock().unwrap().len() {
-            0
-        } else {
-            current_entry_index + 1
-        };
+            if let Some((_, marker_top, height)) = slot {
+                ui.painter().line_segment(
+                    [marker_top, marker_top + egui::vec2(0.0, height)],
+                    egui::Stroke::new(3.0, app.settings.selection_color),
+                );
+                ctx.set_cursor_icon(egui::CursorIcon::Grabbing);
+            }
 
-        let index_to_use = if ctx.input(|i| i.key_pressed(egui::Key::ArrowRight)) {
-            next_entry_index
-        } else {
-            previous_entry_index
-        };
+            let target = slot.map(|(target, _, _)| target);
 
-        let next_entry = &app.entries.lock().unwrap()[index_to_use];
+            if !is_manual_order || ctx.input(|i| i.pointer.any_released()) {
+                let source = entries.iter().position(|entry| entry.path == dragged_path);
 
-        if next_entry.media_type == MediaType::Video {
-            let video = VideoEntry::new(&next_entry.path);
+                if let (true, Some(target), Some(source)) = (is_manual_order, target, source) {
+                    let target_index = visible_indices
+                        .get(target)
+                        .copied()
+                        .unwrap_or(entries.len());
+                    let target_index = if source < target_index {
+                        target_index - 1
+                    } else {
+                        target_index
+                    };
 
-            match video {
-                Some(video) => {
-                    app.current_entry = Some(CurrentEntry {
-                        media_type: MediaType::Video,
-                        image: None,
-                        video: Some(video),
-                    });
-                }
-                None => {
-                    println!("Failed to load video: {:?}", next_entry.path);
+                    let entry = entries.remove(source);
+                    entries.insert(target_index, entry);
+
+                    for (order, entry) in entries.iter_mut().enumerate() {
+                        entry.manual_order = order;
+                    }
                 }
-            }
 
-            return;
+                app.dragged_entry = None;
+            }
         }
 
-        let image = ImageEntry::new(&next_entry.path, ctx);
+        if let Some((_, highlighted_at)) = &app.highlighted_entry {
+            match HIGHLIGHT_DURATION.checked_sub(highlighted_at.elapsed()) {
+                Some(remaining) => ctx.request_repaint_after(remaining),
+                None => app.highlighted_entry = None,
+            }
+        }
+    });
+}
 
-        app.current_entry = Some(CurrentEntry {
-            media_type: match &image {
-                Some(image) => image.media_type.clone(),
-                None => MediaType::ImageAnimated,
-            },
-            image,
-            video: None,
-        });
+fn animated_thumbnail_frame(
+    animated_thumbnails: &[AnimatedThumbnail],
+    settings: &Settings,
+    path: &PathBuf,
+    ctx: &egui::Context,
+) -> Option<egui::TextureHandle> {
+    if !settings.animate_thumbnails {
+        return None;
     }
 
-    let entry = match &mut app.current_entry {
-        Some(entry) => entry,
-        None => {
-            println!("No current entry found");
-            return ();
-        }
-    };
+    let animation = animated_thumbnails
+        .iter()
+        .find(|animation| &animation.path == path)?;
 
-    if entry.media_type == MediaType::Video {
-        let video = match &mut entry.video {
-            Some(video) => video,
-            None => {
-                println!("No video found for current entry");
-                return ();
-            }
-        };
+    let mut image = animation.image.lock().unwrap();
 
-        let video_player = VideoPlayer::new(video, ctx);
+    image.as_mut()?.get_current_frame(ctx)
+}
 
-        ui.centered_and_justified(|ui| {
-            ui.add(video_player);
-        });
+fn spawn_animation_decode(decode: impl FnOnce() + Send + 'static) {
+    let pool = ANIMATION_DECODE_POOL.get_or_init(|| {
+        match rayon::ThreadPoolBuilder::new()
+            .num_threads(ANIMATION_DECODE_THREADS)
+            .build()
+        {
+            Ok(pool) => Some(pool),
+            Err(err) => {
+                warn!("Failed to create animation decode thread pool: {}", err);
+                None
+            }
+        }
+    });
+
+    match pool {
+        Some(pool) => pool.spawn(decode),
+        None => rayon::spawn(decode),
+    }
+}
 
+// The entry has to stay hovered for a moment before it's decoded. Hovering it again only
+// moves it to the back, the ones hovered longest ago stop playing once there are more than
+// the settings allow
+fn update_hovered_animation(
+    animated_thumbnails: &mut Vec<AnimatedThumbnail>,
+    hovered_animation: &mut Option<(PathBuf, Instant)>,
+    settings: &Settings,
+    decoder_overrides: &HashMap<PathBuf, ImageDecoder>,
+    hovered: Option<PathBuf>,
+    ctx: &egui::Context,
+) {
+    if !settings.animate_thumbnails {
+        animated_thumbnails.clear();
+        *hovered_animation = None;
         return;
     }
 
-    let texture = match &entry.media_type {
-        MediaType::ImageStill | MediaType::ImageAnimated => match entry.image.as_mut() {
-            Some(image) => image.get_current_frame(ctx),
-            None => {
-                println!("No image found for current entry");
-                return ();
+    let path = match (hovered_animation.take(), hovered) {
+        (Some((path, hovered_at)), Some(hovered)) if path == hovered => {
+            *hovered_animation = Some((path.clone(), hovered_at));
+
+            match ANIMATION_HOVER_DELAY.checked_sub(hovered_at.elapsed()) {
+                Some(remaining) => {
+                    ctx.request_repaint_after(remaining);
+                    return;
+                }
+                None => path,
             }
-        },
-        MediaType::Video => match entry.video.as_mut() {
-            Some(video) => video.get_current_frame(ctx),
+        }
+        (_, Some(hovered)) => {
+            *hovered_animation = Some((hovered, Instant::now()));
+            ctx.request_repaint_after(ANIMATION_HOVER_DELAY);
+            return;
+        }
+        (_, None) => return,
+    };
+
+    let position = animated_thumbnails
+        .iter()
+        .position(|animation| animation.path == path);
+
+    let animation = match position {
+        Some(position) => animated_thumbnails.remove(position),
+        None => {
+            let image = Arc::new(Mutex::new(None));
+            let cancelled = Arc::new(AtomicBool::new(false));
+            let loaded_image = Arc::clone(&image);
+            let decode_cancelled = Arc::clone(&cancelled);
+            let file = path.clone();
+            let settings = settings.clone();
+            let forced_decoder = decoder_overrides.get(&path).copied();
+            let max_side = thumbnail_decode_size(&settings) as usize;
+            let ctx = ctx.clone();
+
+            spawn_animation_decode(move || {
+                if decode_cancelled.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                // The frames are only shown at the size of a grid cell
+                let image = with_thumbnail_resolution(max_side, || {
+                    ImageEntry::new(&file, &ctx, &settings, forced_decoder)
+                });
+
+                if !decode_cancelled.load(Ordering::SeqCst) {
+                    *loaded_image.lock().unwrap() = image;
+                    ctx.request_repaint();
+                }
+            });
+
+            AnimatedThumbnail {
+                path,
+                image,
+                cancelled,
+            }
+        }
+    };
+
+    animated_thumbnails.push(animation);
+
+    let excess = animated_thumbnails
+        .len()
+        .saturating_sub(settings.max_animated_thumbnails.max(1));
+
+    animated_thumbnails.drain(..excess);
+}
+
+// Extension is what ImageFrame shows by default, the others leave the corner empty until
+// the file is loaded and they are known
+fn entry_label(entry: &GalleryEntry, label: EntryLabel) -> Option<String> {
+    match label {
+        EntryLabel::Extension => entry
+            .path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_string()),
+        EntryLabel::Resolution => entry
+            .dimensions
+            .map(|(width, height)| format!("{}x{}", width, height)),
+        EntryLabel::FileSize => entry.file_size.map(|size| format_bytes(size as usize)),
+        EntryLabel::Hidden => None,
+    }
+}
+
+// The whole grid squeezed into a strip as tall as the view, with the visible part framed.
+// Clicking or dragging on it gives the position in the grid to scroll to
+// Small grids keep their proportions instead of being stretched over the whole strip
+fn minimap_scale(size: egui::Vec2, grid_size: egui::Vec2) -> egui::Vec2 {
+    let scale_x = size.x / grid_size.x.max(1.0);
+    let scale_y = (size.y / grid_size.y.max(1.0)).min(scale_x);
+
+    egui::vec2(scale_x, scale_y)
+}
+
+fn draw_grid_minimap(
+    rows: &[MinimapRow],
+    size: [usize; 2],
+    grid_size: egui::Vec2,
+    background: egui::Color32,
+) -> egui::ColorImage {
+    let mut image = egui::ColorImage::new(size, background);
+    let scale = minimap_scale(egui::vec2(size[0] as f32, size[1] as f32), grid_size);
+
+    // Every cell covers at least a pixel, rows of huge grids would vanish between them otherwise
+    let pixel_range = |start: f32, length: f32, scale: f32, limit: usize| {
+        let first = ((start * scale) as usize).min(limit.saturating_sub(1));
+        let last = (((start + length) * scale).ceil() as usize).clamp(first + 1, limit);
+
+        first..last
+    };
+
+    for row in rows {
+        let y_range = pixel_range(row.top, row.height, scale.y, size[1]);
+
+        for (left, width, color) in &row.cells {
+            let x_range = pixel_range(*left, *width, scale.x, size[0]);
+
+            for y in y_range.clone() {
+                image.pixels[y * size[0] + x_range.start..y * size[0] + x_range.end].fill(*color);
+            }
+        }
+    }
+
+    image
+}
+
+fn build_grid_minimap(
+    ui: &mut egui::Ui,
+    rect: egui::Rect,
+    texture: &egui::TextureHandle,
+    grid_size: egui::Vec2,
+    viewport: egui::Rect,
+) -> Option<f32> {
+    let scale_y = minimap_scale(rect.size(), grid_size).y;
+    let painter = ui.painter_at(rect);
+
+    painter.image(
+        texture.id(),
+        rect,
+        egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+        egui::Color32::WHITE,
+    );
+
+    let visible_rect = egui::Rect::from_x_y_ranges(
+        rect.x_range(),
+        rect.top() + viewport.min.y * scale_y..=rect.top() + viewport.max.y * scale_y,
+    );
+
+    painter.rect_stroke(visible_rect, 2.0, ui.visuals().widgets.active.fg_stroke);
+
+    let response = ui.interact(
+        rect,
+        ui.id().with("grid_minimap"),
+        egui::Sense::click_and_drag(),
+    );
+
+    if !response.clicked() && !response.dragged() {
+        return None;
+    }
+
+    response
+        .interact_pointer_pos()
+        .map(|pointer| (pointer.y - rect.top()) / scale_y)
+}
+
+fn delete_file(file: &PathBuf, permanently: bool) -> Result<(), String> {
+    if permanently {
+        std::fs::remove_file(file).map_err(|err| err.to_string())
+    } else {
+        trash::delete(file).map_err(|err| {
+            format!(
+                "{} (permanent deletion can be turned on in the settings)",
+                err
+            )
+        })
+    }
+}
+
+fn delete_files(entries: &mut Vec<GalleryEntry>, files: &[PathBuf], permanently: bool) {
+    for file in files {
+        match delete_file(file, permanently) {
+            Ok(_) => {
+                entries.retain(|entry| &entry.path != file);
+
+                let sidecar_path = Sidecar::path_for(file);
+
+                if sidecar_path.exists() {
+                    if let Err(err) = delete_file(&sidecar_path, permanently) {
+                        error!("Error deleting sidecar: {}", err);
+                    }
+                }
+            }
+            Err(err) => {
+                error!("Error deleting {:?}: {}", file, err);
+            }
+        }
+    }
+}
+
+pub fn build_delete_confirmation(app: &mut App, ctx: &egui::Context) {
+    let files = match &app.pending_deletion {
+        Some(files) => files.clone(),
+        None => return,
+    };
+
+    let mut open = true;
+    let mut confirmed = false;
+    let mut cancelled = false;
+
+    egui::Window::new("Delete permanently")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "{} files will be deleted permanently. This can't be undone.",
+                files.len()
+            ));
+
+            ui.horizontal(|ui| {
+                if ui.button("Delete").clicked() {
+                    confirmed = true;
+                }
+
+                if ui.button("Cancel").clicked() {
+                    cancelled = true;
+                }
+            });
+        });
+
+    if confirmed {
+        delete_files(&mut app.entries.lock().unwrap(), &files, true);
+        app.last_marked_entry = None;
+    }
+
+    if confirmed || cancelled || !open || key_pressed(ctx, egui::Key::Escape) {
+        app.pending_deletion = None;
+    }
+}
+
+// The new thumbnail is decoded right away, since it's only a single file
+fn update_rotated_entry(app: &mut App, path: &PathBuf, ctx: &egui::Context) {
+    if let Some(CurrentEntry {
+        image: Some(image), ..
+    }) = &mut app.current_entry
+    {
+        if &image.path == path {
+            let _ = image.reload(ctx, &app.settings);
+        }
+    }
+
+    let thumbnail = ImageEntry::load_thumbnail(
+        &ctx.tex_manager(),
+        path,
+        thumbnail_decode_size(&app.settings),
+        &app.settings,
+    );
+
+    let mut entries = app.entries.lock().unwrap();
+
+    if let Some(entry) = entries.iter_mut().find(|entry| &entry.path == path) {
+        if let Some(texture) = thumbnail
+            .as_ref()
+            .and_then(|thumbnail| thumbnail.get_texture())
+        {
+            entry.thumbnail = texture;
+        }
+
+        entry.hash = thumbnail.as_ref().map(|thumbnail| thumbnail.get_hash());
+        entry.average_color = thumbnail.map(|thumbnail| thumbnail.get_average_color());
+        entry.modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        entry.dimensions = match app.settings.entry_label {
+            EntryLabel::Resolution => {
+                export::read_dimensions(path, &entry.media_type, &app.settings)
+            }
+            _ => None,
+        };
+        entry.file_size = std::fs::metadata(path).map(|metadata| metadata.len()).ok();
+    }
+}
+
+pub fn build_rotation_confirmation(app: &mut App, ctx: &egui::Context) {
+    let rotation_state = match &mut app.pending_rotation {
+        Some(rotation_state) => rotation_state,
+        None => return,
+    };
+
+    // The window stays open while rotating, an error is shown in it so it can be tried again
+    let finished = rotation_state
+        .rotation
+        .as_ref()
+        .is_some_and(|rotation| rotation.finished.load(Ordering::SeqCst));
+
+    if finished {
+        let error = rotation_state
+            .rotation
+            .as_ref()
+            .and_then(|rotation| rotation.error.lock().unwrap().take());
+        rotation_state.rotation = None;
+
+        match error {
+            Some(err) => rotation_state.error = Some(err),
             None => {
-                println!("No video found for current entry");
-                return ();
+                let path = rotation_state.path.clone();
+                app.pending_rotation = None;
+                update_rotated_entry(app, &path, ctx);
+                return;
             }
-        },
+        }
+    }
+
+    let rotating = rotation_state.rotation.is_some();
+    let mut open = true;
+    let mut confirmed = false;
+    let mut cancelled = false;
+
+    egui::Window::new("Rotate and save")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            let file_name = rotation_state
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+            let direction = if rotation_state.clockwise {
+                "clockwise"
+            } else {
+                "counter-clockwise"
+            };
+
+            ui.label(format!(
+                "{} will be rotated {} and overwritten.",
+                file_name, direction
+            ));
+
+            if let Some(warning) = &rotation_state.warning {
+                ui.colored_label(egui::Color32::GOLD, warning);
+            }
+
+            if let Some(error) = &rotation_state.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(!rotating, egui::Button::new("Rotate"))
+                    .clicked()
+                {
+                    confirmed = true;
+                }
+
+                if ui
+                    .add_enabled(!rotating, egui::Button::new("Cancel"))
+                    .clicked()
+                {
+                    cancelled = true;
+                }
+
+                if rotating {
+                    ui.spinner();
+                }
+            });
+        });
+
+    if confirmed {
+        let rotation = Arc::new(rotate::Rotation::default());
+        let path = rotation_state.path.clone();
+        let clockwise = rotation_state.clockwise;
+        let settings = app.settings.clone();
+        let ctx = ctx.clone();
+
+        rotation_state.error = None;
+        rotation_state.rotation = Some(Arc::clone(&rotation));
+
+        std::thread::spawn(move || {
+            if let Err(err) = rotate::rotate_and_save(&path, clockwise, &settings) {
+                error!("Error rotating {:?}: {}", path, err);
+                *rotation.error.lock().unwrap() = Some(err);
+            }
+
+            rotation.finished.store(true, Ordering::SeqCst);
+            ctx.request_repaint();
+        });
+
+        return;
+    }
+
+    // The entry is only updated once the rotation finishes, so it can't be closed before that
+    if rotating {
+        return;
+    }
+
+    if cancelled || !open || key_pressed(ctx, egui::Key::Escape) {
+        app.pending_rotation = None;
+    }
+}
+
+// The scroll area only reads the smoothed delta, so it's rewritten here before the area is built
+fn update_grid_scrolling(app: &mut App, ctx: &egui::Context) {
+    // How quickly the momentum dies down, the distance scrolled stays the same as without it
+    let friction = 6.0;
+
+    let direction = if app.settings.invert_scroll {
+        -1.0
+    } else {
+        1.0
     };
 
-    if texture.is_none() {
-        println!("No texture found for current entry");
+    if !app.settings.momentum_scrolling {
+        app.scroll_velocity = 0.0;
+        ctx.input_mut(|i| i.smooth_scroll_delta.y *= direction);
         return;
     }
 
-    let texture = texture.unwrap();
+    let dt = ctx.input(|i| i.stable_dt).min(0.1);
 
-    let texture_size = calculate_contain_size(
-        window_size.x - 10.0,
-        window_size.y - 10.0,
-        texture.size()[0] as f32,
-        texture.size()[1] as f32,
-    );
+    let delta = ctx.input_mut(|i| {
+        app.scroll_velocity += i.raw_scroll_delta.y * friction;
+
+        let delta = app.scroll_velocity * dt;
+        app.scroll_velocity *= (-friction * dt).exp();
+
+        if app.scroll_velocity.abs() < 1.0 {
+            app.scroll_velocity = 0.0;
+        }
 
-    let sized_texture =
-        egui::load::SizedTexture::new(texture.id(), [texture_size.0, texture_size.1]);
+        i.smooth_scroll_delta.y = delta * direction;
 
-    let img = egui::Image::new(sized_texture);
-    ui.centered_and_justified(|ui| {
-        ui.add(img);
+        delta
     });
 
-    let path = match &entry.media_type {
-        MediaType::ImageStill | MediaType::ImageAnimated => match &entry.image {
-            Some(image) => &image.path,
-            None => {
-                println!("No image found for current entry");
-                return ();
+    if delta != 0.0 {
+        ctx.request_repaint();
+    }
+}
+
+fn is_entry_visible(entry: &GalleryEntry, settings: &Settings, media_filter: &MediaFilter) -> bool {
+    let is_type_visible = if entry.failed {
+        media_filter.failed && settings.show_failed_files
+    } else {
+        match entry.media_type {
+            MediaType::ImageStill => media_filter.images,
+            MediaType::ImageAnimated => media_filter.animated,
+            MediaType::Video => media_filter.videos,
+        }
+    };
+
+    is_type_visible
+        && entry.rating >= settings.min_rating
+        && (entry.marked || !media_filter.marked_only)
+}
+
+// A trackpad sends lots of small scrolls, so they are added up until they make a whole step.
+// Returns whether to go to the next entry, scrolling down goes forward
+fn wheel_navigation_step(app: &mut App, ctx: &egui::Context) -> Option<bool> {
+    if app.settings.preview_wheel != PreviewWheel::Navigate || ctx.is_pointer_over_area() {
+        app.wheel_navigation_delta = 0.0;
+        return None;
+    }
+
+    app.wheel_navigation_delta += ctx.input(|i| i.raw_scroll_delta.y);
+
+    if app.wheel_navigation_delta.abs() < WHEEL_NAVIGATION_STEP {
+        return None;
+    }
+
+    let is_forward = app.wheel_navigation_delta < 0.0;
+    app.wheel_navigation_delta = 0.0;
+
+    Some(is_forward)
+}
+
+// The OS key repeat is too slow for scrubbing and differs between systems, so held arrows
+// are repeated here instead. The step happens right away, repeats get faster over two seconds.
+// Returns whether the held arrow points forward whenever it's time for a step
+fn held_arrow_step(app: &mut App, ctx: &egui::Context) -> Option<bool> {
+    let held_key = match ctx.wants_keyboard_input() {
+        true => None,
+        false => ctx.input(|i| {
+            [egui::Key::ArrowRight, egui::Key::ArrowLeft]
+                .into_iter()
+                .find(|key| i.key_down(*key))
+        }),
+    };
+
+    let key = match held_key {
+        Some(key) => key,
+        None => {
+            app.held_arrow = None;
+            return None;
+        }
+    };
+
+    let now = Instant::now();
+
+    match app.held_arrow {
+        Some((held_arrow, held_since, last_step)) if held_arrow == key => {
+            let held_for = now - held_since;
+
+            if held_for < KEY_REPEAT_DELAY {
+                ctx.request_repaint_after(KEY_REPEAT_DELAY - held_for);
+                return None;
+            }
+
+            let acceleration = (held_for.as_secs_f32() / 2.0).min(1.0);
+            let interval = KEY_REPEAT_INTERVAL.mul_f32(1.0 - acceleration * 0.75);
+            let since_last_step = now - last_step;
+
+            if since_last_step < interval {
+                ctx.request_repaint_after(interval - since_last_step);
+                return None;
+            }
+
+            app.held_arrow = Some((key, held_since, now));
+
+            // Nothing else repaints while the key is only held, the next step would wait for
+            // the mouse to move otherwise
+            ctx.request_repaint_after(interval);
+        }
+        _ => {
+            app.held_arrow = Some((key, now, now));
+            ctx.request_repaint_after(KEY_REPEAT_DELAY);
+        }
+    }
+
+    Some(key == egui::Key::ArrowRight)
+}
+
+// Returns false when the entry was a video that failed to open, the current entry is kept then
+fn open_entry(app: &mut App, index: usize, ctx: &egui::Context) -> bool {
+    let (path, media_type, frame) = match app.entries.lock().unwrap().get_mut(index) {
+        Some(entry) if entry.frame.is_none() && !entry.path.exists() => {
+            error!("File is missing: {:?}", entry.path);
+            entry.missing = true;
+
+            return false;
+        }
+        Some(entry) => (
+            entry.path.clone(),
+            entry.media_type.clone(),
+            entry.frame.clone(),
+        ),
+        None => return false,
+    };
+
+    if media_type == MediaType::Video {
+        let video = VideoEntry::new(
+            &path,
+            !app.settings.disable_audio,
+            app.settings.fallback_frame_rate as f64,
+            ctx,
+        );
+
+        return match video {
+            Some(mut video) => {
+                if app.settings.autoplay_videos {
+                    video.play();
+                }
+
+                app.current_entry = Some(CurrentEntry {
+                    media_type: MediaType::Video,
+                    image: None,
+                    video: Some(video),
+                });
+
+                true
             }
-        },
-        MediaType::Video => match &entry.video {
-            Some(video) => &video.path,
             None => {
-                println!("No video found for current entry");
-                return ();
+                error!("Failed to load video: {:?}", path);
+
+                false
             }
-        },
+        };
+    }
+
+    let mut image = match frame {
+        Some(frame) => ImageEntry::from_frame(&path, &frame, ctx, &app.settings),
+        None => ImageEntry::new(
+            &path,
+            ctx,
+            &app.settings,
+            app.decoder_overrides.get(&path).copied(),
+        ),
     };
+    restore_view(&mut image, &app.settings, &app.saved_views);
 
-    let extension = path
-        .extension()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default();
-    let number_of_frames = match &entry.media_type {
-        MediaType::ImageStill | MediaType::ImageAnimated => match &entry.image {
-            Some(image) => image.get_number_of_frames(),
-            None => 0,
+    app.current_entry = Some(CurrentEntry {
+        media_type: match &image {
+            Some(image) => image.media_type.clone(),
+            None => media_type,
         },
-        MediaType::Video => 0,
-    };
+        image,
+        video: None,
+    });
 
-    let resolution = format!("{}x{}", texture.size()[0], texture.size()[1]);
+    true
+}
 
-    ui.painter().text(
-        egui::Pos2::from([5.0, 5.0]),
-        egui::Align2::LEFT_TOP,
-        &extension,
-        FontId::monospace(14.0),
-        egui::Color32::WHITE,
-    );
+fn restore_view(
+    image: &mut Option<ImageEntry>,
+    settings: &Settings,
+    saved_views: &HashMap<PathBuf, ViewTransform>,
+) {
+    if !settings.remember_zoom {
+        return;
+    }
 
-    ui.painter().text(
-        egui::Pos2::from([5.0, 20.0]),
-        egui::Align2::LEFT_TOP,
-        &format!("{} frames", number_of_frames),
-        FontId::monospace(14.0),
-        egui::Color32::WHITE,
-    );
+    if let Some(image) = image {
+        if let Some(view) = saved_views.get(&image.path) {
+            image.view = *view;
+        }
+    }
+}
 
-    ui.painter().text(
-        egui::Pos2::from([5.0, 35.0]),
-        egui::Align2::LEFT_TOP,
-        &resolution,
-        FontId::monospace(14.0),
-        egui::Color32::WHITE,
-    );
+// Picks from the entries the grid currently shows, never the one that is already open
+fn open_random_entry(app: &mut App, ctx: &egui::Context) {
+    let current_path = app.current_entry.as_ref().and_then(|entry| entry.path());
+
+    let candidates = app
+        .entries
+        .lock()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| is_entry_visible(entry, &app.settings, &app.media_filter))
+        .filter(|(_, entry)| Some(&entry.path) != current_path.as_ref())
+        .map(|(index, _)| index)
+        .collect::<Vec<usize>>();
+
+    if candidates.is_empty() {
+        return;
+    }
+
+    open_entry(app, candidates[random_index(candidates.len())], ctx);
+}
+
+fn save_sidecar(entry: &GalleryEntry) {
+    let sidecar = Sidecar {
+        rating: entry.rating,
+        tags: entry.tags.clone(),
+    };
+
+    if let Err(err) = sidecar.save(&entry.path) {
+        error!("Failed to save sidecar for {:?}: {}", entry.path, err);
+    }
+}
+
+fn set_rating(entries: &mut [GalleryEntry], paths: &[PathBuf], rating: u8) {
+    for entry in entries
+        .iter_mut()
+        .filter(|entry| paths.contains(&entry.path))
+    {
+        entry.rating = rating;
+        save_sidecar(entry);
+    }
+}
+
+fn marked_paths(app: &App) -> Vec<PathBuf> {
+    app.entries
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|entry| entry.marked)
+        .map(|entry| entry.path.clone())
+        .collect()
+}
+
+fn leave_preview(app: &mut App, ctx: &egui::Context) {
+    app.current_entry = None;
+
+    // Fullscreen only makes sense for the preview, so leaving it restores the window
+    if ctx.input(|i| i.viewport().fullscreen.unwrap_or(false)) {
+        ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(false));
+    }
+}
+
+// The commands that act on entries. In the preview they apply to the file it shows,
+// in the grid to the marked entries
+pub fn run_entry_command(app: &mut App, command: Command, ctx: &egui::Context) {
+    let shown_path = app.current_entry.as_ref().and_then(|entry| entry.path());
+
+    match command {
+        Command::DeleteMarked => {
+            let to_delete = marked_paths(app);
+
+            // There is no way back from a permanent delete, so it has to be confirmed first
+            if app.settings.delete_permanently {
+                if !to_delete.is_empty() {
+                    app.pending_deletion = Some(to_delete);
+                }
+            } else {
+                delete_files(&mut app.entries.lock().unwrap(), &to_delete, false);
+            }
+        }
+        Command::Rename => {
+            let path = shown_path.or_else(|| {
+                app.last_marked_entry.clone().filter(|path| {
+                    app.entries
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .any(|entry| &entry.path == path)
+                })
+            });
+
+            if let Some(path) = path {
+                open_rename_window(app, path);
+            }
+        }
+        Command::EditTags => {
+            let paths = shown_path
+                .map(|path| vec![path])
+                .unwrap_or_else(|| marked_paths(app));
+
+            if !paths.is_empty() {
+                open_tags_window(app, paths);
+            }
+        }
+        Command::Rate(rating) => {
+            let paths = shown_path
+                .map(|path| vec![path])
+                .unwrap_or_else(|| marked_paths(app));
+
+            set_rating(&mut app.entries.lock().unwrap(), &paths, rating);
+        }
+        Command::DensityPreset(index) => {
+            let (min_thumbnail_size, max_columns_count) = app.settings.density_presets[index];
+
+            app.settings.min_thumbnail_size = min_thumbnail_size;
+            app.settings.max_columns_count = max_columns_count;
+        }
+        Command::OpenRandom => open_random_entry(app, ctx),
+        // In the grid the entry that was jumped to, otherwise the one under the pointer
+        Command::BrowseFolder => {
+            let path = shown_path
+                .or_else(|| app.highlighted_entry.as_ref().map(|(path, _)| path.clone()))
+                .or_else(|| app.hovered_entry.clone());
+
+            if let Some(path) = path {
+                app.browse_folder_of(&path, ctx);
+            }
+        }
+        Command::ClosePreview => leave_preview(app, ctx),
+        // Leaves the preview and brings the entry that was shown into view in the grid
+        Command::ShowInGrid => {
+            if let Some(path) = shown_path {
+                app.highlighted_entry = Some((path, Instant::now()));
+                app.scroll_to_highlighted = true;
+            }
+
+            leave_preview(app, ctx);
+        }
+        Command::Fullscreen => {
+            let is_fullscreen = ctx.input(|i| i.viewport().fullscreen.unwrap_or(false));
+            ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(!is_fullscreen));
+        }
+        Command::PreviousPage | Command::NextPage => {
+            if let Some(CurrentEntry {
+                image: Some(image), ..
+            }) = &mut app.current_entry
+            {
+                image.step_page(command == Command::NextPage);
+            }
+        }
+        Command::TogglePlayback
+        | Command::LoopStart
+        | Command::LoopEnd
+        | Command::ClearLoop
+        | Command::VideoFit => {
+            let video = match &mut app.current_entry {
+                Some(CurrentEntry {
+                    video: Some(video), ..
+                }) => video,
+                _ => return,
+            };
+
+            match command {
+                Command::TogglePlayback => video.toggle_playback(),
+                Command::LoopStart => video.set_loop_start(),
+                Command::LoopEnd => video.set_loop_end(),
+                Command::ClearLoop => video.clear_loop(),
+                _ => {
+                    app.settings.video_fit = match app.settings.video_fit {
+                        VideoFit::Contain => VideoFit::Cover,
+                        VideoFit::Cover => VideoFit::Contain,
+                    };
+                }
+            }
+        }
+        Command::CyclePlaybackMode => {
+            if let Some(CurrentEntry {
+                image: Some(image), ..
+            }) = &mut app.current_entry
+            {
+                if image.media_type == MediaType::ImageAnimated {
+                    image.cycle_playback_mode();
+                }
+            }
+        }
+        Command::AddFrame => match &mut app.current_entry {
+            Some(CurrentEntry {
+                video: Some(video), ..
+            }) => add_video_frame_entry(video, &app.settings, &app.entries, ctx),
+            Some(CurrentEntry {
+                image: Some(image), ..
+            }) if image.media_type == MediaType::ImageAnimated => {
+                add_image_frame_entry(image, &app.settings, &app.entries, ctx)
+            }
+            _ => {}
+        },
+        Command::CopyDataUrl | Command::Print => {
+            let image = match &app.current_entry {
+                Some(CurrentEntry {
+                    image: Some(image), ..
+                }) => image,
+                _ => return,
+            };
+
+            if command == Command::Print {
+                if let Err(err) = image.print(&app.settings) {
+                    error!("Failed to print {:?}: {}", image.path, err);
+                }
+
+                return;
+            }
+
+            match image.to_data_url(&app.settings) {
+                Ok(data_url) => {
+                    ctx.output_mut(|o| o.copied_text = data_url);
+                    info!("Copied {:?} as a data URL", image.path);
+                }
+                Err(err) => {
+                    error!("Failed to copy image as a data URL: {}", err);
+                }
+            }
+        }
+        Command::RotateClockwise | Command::RotateCounterClockwise => {
+            if let Some(CurrentEntry {
+                image: Some(image), ..
+            }) = &app.current_entry
+            {
+                if image.media_type == MediaType::ImageStill {
+                    app.pending_rotation = Some(RotationState {
+                        path: image.path.clone(),
+                        clockwise: command == Command::RotateClockwise,
+                        warning: rotate::reencode_warning(&image.path, &app.settings),
+                        error: None,
+                        rotation: None,
+                    });
+                }
+            }
+        }
+        Command::Eyedropper | Command::Ruler | Command::Crop => {
+            let tool = match command {
+                Command::Eyedropper => PreviewTool::Eyedropper,
+                Command::Ruler => PreviewTool::Ruler,
+                _ => PreviewTool::Crop,
+            };
+
+            app.preview_tool = if app.preview_tool == tool {
+                PreviewTool::None
+            } else {
+                tool
+            };
+        }
+        _ => {}
+    }
+}
+
+pub fn build_preview(app: &mut App, ctx: &egui::Context, ui: &mut egui::Ui) {
+    // Only the moment the focus changes matters, so a video that is started again by hand
+    // while the window is unfocused keeps playing
+    let focused = ctx.input(|i| i.viewport().focused.unwrap_or(true));
+
+    if focused != app.window_focused {
+        app.window_focused = focused;
+
+        if let Some(CurrentEntry {
+            video: Some(video), ..
+        }) = &mut app.current_entry
+        {
+            if !focused && app.settings.pause_video_on_focus_loss {
+                app.resume_video_on_focus = video.is_playing;
+                video.pause();
+            } else if focused && app.resume_video_on_focus {
+                app.resume_video_on_focus = false;
+                video.play();
+            }
+        }
+    }
+
+    // Escape closes the preview through the key table, a right click does the same
+    if ctx.input(|i| i.pointer.secondary_pressed()) {
+        app.run_command(Command::ClosePreview, ctx);
+        return;
+    }
+
+    let wheel_step = wheel_navigation_step(app, ctx);
+    let arrow_step = held_arrow_step(app, ctx);
+
+    if let Some(is_forward) = wheel_step.or(arrow_step) {
+        let current_entry = match &mut app.current_entry {
+            Some(entry) => entry,
+            None => {
+                error!("No current entry found");
+                return ();
+            }
+        };
+
+        match current_entry.video {
+            Some(ref mut video) if wheel_step.is_none() => {
+                let is_shift_down = ctx.input(|i| i.modifiers.shift);
+
+                if !is_shift_down {
+                    return ();
+                }
+
+                if is_forward {
+                    video.seek_relative(5 * 1000);
+                } else {
+                    video.seek_relative(-5 * 1000);
+                }
+
+                return ();
+            }
+            _ => {}
+        }
+
+        let current_entry_path = match &current_entry.media_type {
+            MediaType::ImageStill | MediaType::ImageAnimated => match &current_entry.image {
+                Some(image) => image.path.clone(),
+                None => {
+                    warn!("No image found for current entry");
+                    return ();
+                }
+            },
+            MediaType::Video => match &current_entry.video {
+                Some(video) => video.path.clone(),
+                None => {
+                    warn!("No video found for current entry");
+                    return ();
+                }
+            },
+        };
+
+        let current_entry_index = app
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .position(|entry| entry.path == current_entry_path)
+            .unwrap();
+
+        let previous_entry_index = if current_entry_index == 0 {
+            app.entries.lock().unwrap().len() - 1
+        } else {
+            current_entry_index - 1
+        };
+
+        let next_entry_index = if current_entry_index + 1 >= app.entries.lock().unwrap().len() {
+            0
+        } else {
+            current_entry_index + 1
+        };
+
+        let index_to_use = if is_forward {
+            next_entry_index
+        } else {
+            previous_entry_index
+        };
+
+        if !open_entry(app, index_to_use, ctx) {
+            return;
+        }
+    }
+
+    let entry = match &mut app.current_entry {
+        Some(entry) => entry,
+        None => {
+            error!("No current entry found");
+            return ();
+        }
+    };
+
+    if entry.media_type == MediaType::Video {
+        let video = match &mut entry.video {
+            Some(video) => video,
+            None => {
+                warn!("No video found for current entry");
+                return ();
+            }
+        };
+
+        video.pace_repaints = app.settings.pace_video_repaints;
+
+        // With the debug overlay on, the bars show exactly where the video surface ends
+        let backdrop = if app.show_timings {
+            egui::Color32::RED
+        } else {
+            app.settings.video_backdrop_color
+        };
+
+        let video_player = VideoPlayer::new(video, ctx)
+            .overlay(app.settings.overlay_font_size, app.settings.overlay_color)
+            .backdrop(backdrop)
+            .fit(app.settings.video_fit)
+            .autohide_controls(
+                app.settings
+                    .autohide_video_controls
+                    .then(|| Duration::from_secs_f32(app.settings.video_controls_hide_delay)),
+            );
+
+        ui.centered_and_justified(|ui| {
+            ui.add(video_player);
+        });
+
+        egui::Area::new(egui::Id::new("video_menu"))
+            .anchor(egui::Align2::LEFT_TOP, egui::vec2(10.0, 10.0))
+            .show(ctx, |ui| {
+                ui.menu_button("Menu", |ui| {
+                    if ui
+                        .button(Command::AddFrame.hint(Command::AddFrame.label()))
+                        .clicked()
+                    {
+                        add_video_frame_entry(video, &app.settings, &app.entries, ctx);
+
+                        ui.close_menu();
+                    }
+
+                    if ui
+                        .add_enabled(
+                            app.frame_export.is_none(),
+                            egui::Button::new("Export frames"),
+                        )
+                        .clicked()
+                    {
+                        start_frame_export(
+                            &mut app.frame_export,
+                            video.path.clone(),
+                            ImageDecoder::Ffmpeg,
+                            &app.settings,
+                            ctx,
+                        );
+
+                        ui.close_menu();
+                    }
+
+                    // The A-B loop markers double as the range of the clip,
+                    // without an end marker it runs to the end of the video
+                    let clip_range = video
+                        .loop_start
+                        .map(|start| (start, video.loop_end.unwrap_or(video.video_duration)));
+
+                    if ui
+                        .add_enabled(
+                            app.clip_export.is_none() && clip_range.is_some(),
+                            egui::Button::new("Export A-B clip"),
+                        )
+                        .on_disabled_hover_text(
+                            Command::LoopStart.hint("Set the start of the clip"),
+                        )
+                        .clicked()
+                    {
+                        if let Some((start, end)) = clip_range {
+                            start_clip_export(
+                                &mut app.clip_export,
+                                video.path.clone(),
+                                start,
+                                end,
+                                ctx,
+                            );
+                        }
+
+                        ui.close_menu();
+                    }
+                });
+            });
+
+        return;
+    }
+
+    let texture = match &entry.media_type {
+        MediaType::ImageStill | MediaType::ImageAnimated => match entry.image.as_mut() {
+            Some(image) => {
+                image.min_frame_delay = app.settings.min_frame_delay as f64;
+                image.max_animation_fps = app.settings.max_animation_fps as f64;
+                image.suspended = app.in_standby;
+
+                image.get_current_frame(ctx)
+            }
+            None => {
+                warn!("No image found for current entry");
+                return ();
+            }
+        },
+        MediaType::Video => match entry.video.as_mut() {
+            Some(video) => video.get_current_frame(ctx),
+            None => {
+                warn!("No video found for current entry");
+                return ();
+            }
+        },
+    };
+
+    if texture.is_none() {
+        warn!("No texture found for current entry");
+        return;
+    }
+
+    let texture = texture.unwrap();
+
+    match entry.image.as_mut() {
+        Some(image) => {
+            let viewer_response = ui.add(
+                ImageViewer::new(&texture, &mut image.view)
+                    .pan(![PreviewTool::Ruler, PreviewTool::Crop].contains(&app.preview_tool))
+                    .scroll_zoom(app.settings.preview_wheel == PreviewWheel::Zoom),
+            );
+
+            if app.settings.remember_zoom && app.saved_views.get(&image.path) != Some(&image.view) {
+                app.saved_views.insert(image.path.clone(), image.view);
+            }
+
+            match app.preview_tool {
+                PreviewTool::Eyedropper => build_eyedropper(
+                    image,
+                    &app.settings,
+                    &viewer_response,
+                    texture.size_vec2(),
+                    ctx,
+                ),
+                PreviewTool::Ruler => build_ruler(image, &viewer_response, texture.size_vec2(), ui),
+                PreviewTool::Crop => build_crop(
+                    image,
+                    &app.settings,
+                    &viewer_response,
+                    texture.size_vec2(),
+                    ui,
+                ),
+                PreviewTool::None => {}
+            }
+        }
+        None => {
+            warn!("No image found for current entry");
+            return ();
+        }
+    }
+
+    let path = match &entry.media_type {
+        MediaType::ImageStill | MediaType::ImageAnimated => match &entry.image {
+            Some(image) => &image.path,
+            None => {
+                warn!("No image found for current entry");
+                return ();
+            }
+        },
+        MediaType::Video => match &entry.video {
+            Some(video) => &video.path,
+            None => {
+                warn!("No video found for current entry");
+                return ();
+            }
+        },
+    };
+
+    let extension = path
+        .extension()
+        .unwrap_or_default()
+        .to_str()
+        .unwrap_or_default();
+    let number_of_frames = match &entry.media_type {
+        MediaType::ImageStill | MediaType::ImageAnimated => match &entry.image {
+            Some(image) => image.get_number_of_frames(),
+            None => 0,
+        },
+        MediaType::Video => 0,
+    };
+
+    let frames_text = match entry.image.as_ref() {
+        Some(image) => match image.get_page_info() {
+            Some((page, pages_count)) => format!("page {}/{}", page + 1, pages_count),
+            None if image.media_type == MediaType::ImageAnimated => format!(
+                "{} frames, {}",
+                number_of_frames,
+                image.playback_mode.label()
+            ),
+            None => format!("{} frames", number_of_frames),
+        },
+        None => format!("{} frames", number_of_frames),
+    };
+
+    let resolution = format!("{}x{}", texture.size()[0], texture.size()[1]);
+
+    let (rating, tags, thumbnail) = app
+        .entries
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|gallery_entry| &gallery_entry.path == path)
+        .map(|gallery_entry| {
+            (
+                gallery_entry.rating,
+                gallery_entry.tags.join(", "),
+                Some(gallery_entry.thumbnail.clone()),
+            )
+        })
+        .unwrap_or_default();
+
+    let font_size = app.settings.overlay_font_size;
+    let overlay_color = app.settings.overlay_color;
+    let line_height = font_size + 1.0;
+
+    let mut lines = vec![
+        (
+            extension.to_string(),
+            FontId::monospace(font_size),
+            overlay_color,
+        ),
+        (frames_text, FontId::monospace(font_size), overlay_color),
+        (resolution, FontId::monospace(font_size), overlay_color),
+    ];
+
+    if rating > 0 {
+        lines.push((
+            "★".repeat(rating as usize),
+            FontId::proportional(font_size),
+            egui::Color32::GOLD,
+        ));
+    }
+
+    if !tags.is_empty() {
+        lines.push((tags, FontId::monospace(font_size), overlay_color));
+    }
+
+    if let Some((latitude, longitude)) = entry.image.as_ref().and_then(|image| image.gps) {
+        lines.push((
+            format!("GPS {:.5}, {:.5}", latitude, longitude),
+            FontId::monospace(font_size),
+            overlay_color,
+        ));
+    }
+
+    build_info_overlay(
+        ui,
+        ctx.screen_rect(),
+        app.settings.overlay_corner,
+        &lines,
+        line_height,
+        entry.image.is_some(),
+    );
+
+    let mut menu_command = None;
+
+    if let Some(image) = &mut entry.image {
+        menu_command = build_image_menu(
+            image,
+            &app.settings,
+            &mut app.decoder_overrides,
+            &mut app.show_thumbnail_comparison,
+            &mut app.preview_tool,
+            &mut app.frame_export,
+            ctx,
+        );
+        entry.media_type = image.media_type.clone();
+    }
+
+    if app.show_thumbnail_comparison {
+        if let Some(thumbnail) = &thumbnail {
+            build_thumbnail_comparison(
+                thumbnail,
+                &texture,
+                &mut app.show_thumbnail_comparison,
+                ctx,
+            );
+        }
+    }
+
+    if entry.media_type == MediaType::ImageStill {
+        if let Some(image) = &mut entry.image {
+            if image.decoder.applies_adjustments() {
+                build_adjustments_panel(image, &app.settings, ctx);
+            }
+        }
+    }
+
+    if entry.media_type == MediaType::ImageAnimated {
+        if let Some(image) = &mut entry.image {
+            build_animation_panel(image, ctx);
+        }
+    }
+
+    if let Some(command) = menu_command {
+        app.run_command(command, ctx);
+    }
+}
+
+// The first line is the one closest to the corner, the rest stack away from it
+// The lines are always laid out top-down, in the bottom corners they start high enough
+// for the last one to end at the corner
+fn build_info_overlay(
+    ui: &egui::Ui,
+    rect: egui::Rect,
+    corner: OverlayCorner,
+    lines: &[(String, FontId, egui::Color32)],
+    line_height: f32,
+    image_menu_shown: bool,
+) {
+    let align = corner.align();
+    let mut area = rect.shrink(5.0);
+
+    // The image menu sits in the bottom left corner, so the lines go above it
+    if image_menu_shown && corner == OverlayCorner::BottomLeft {
+        area.max.y -= ui.spacing().interact_size.y + IMAGE_MENU_MARGIN;
+    }
+
+    let anchor = align.pos_in_rect(&area);
+    let top = match align.y() {
+        egui::Align::Max => anchor.y - line_height * lines.len() as f32,
+        _ => anchor.y,
+    };
+    let line_align = egui::Align2([align.x(), egui::Align::Min]);
+
+    for (index, (text, font, color)) in lines.iter().enumerate() {
+        let position = egui::pos2(anchor.x, top + line_height * index as f32);
+
+        ui.painter()
+            .text(position, line_align, text, font.clone(), *color);
+    }
+}
+
+// Returns the command that was picked, it's run once the image isn't borrowed anymore
+fn build_image_menu(
+    image: &mut ImageEntry,
+    settings: &Settings,
+    decoder_overrides: &mut HashMap<PathBuf, ImageDecoder>,
+    show_thumbnail_comparison: &mut bool,
+    preview_tool: &mut PreviewTool,
+    frame_export: &mut Option<Arc<FrameExport>>,
+    ctx: &egui::Context,
+) -> Option<Command> {
+    let mut forced_decoder = image.forced_decoder;
+    let mut nearest_filter = image.magnification == egui::TextureFilter::Nearest;
+    let mut picked = None;
+
+    egui::Area::new(egui::Id::new("image_menu"))
+        .anchor(
+            egui::Align2::LEFT_BOTTOM,
+            egui::vec2(IMAGE_MENU_MARGIN, -IMAGE_MENU_MARGIN),
+        )
+        .show(ctx, |ui| {
+            ui.menu_button("Menu", |ui| {
+                for command in [Command::CopyDataUrl, Command::Print] {
+                    if ui.button(command.hint(command.label())).clicked() {
+                        picked = Some(command);
+                        ui.close_menu();
+                    }
+                }
+
+                if let Some((latitude, longitude)) = image.gps {
+                    if ui.button("Open location in map").clicked() {
+                        let url = format!(
+                            "https://www.openstreetmap.org/?mlat={lat}&mlon={lon}#map=15/{lat}/{lon}",
+                            lat = latitude,
+                            lon = longitude
+                        );
+
+                        if let Err(err) = open_url(&url) {
+                            error!("Failed to open the location of {:?}: {}", image.path, err);
+                        }
+
+                        ui.close_menu();
+                    }
+                }
+
+                if image.media_type == MediaType::ImageStill {
+                    ui.menu_button("Rotate and save", |ui| {
+                        for (command, label) in [
+                            (Command::RotateClockwise, "Clockwise"),
+                            (Command::RotateCounterClockwise, "Counter-clockwise"),
+                        ] {
+                            if ui.button(command.hint(label)).clicked() {
+                                picked = Some(command);
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                }
+
+                if image.media_type == MediaType::ImageAnimated
+                    && ui
+                        .button(Command::AddFrame.hint(Command::AddFrame.label()))
+                        .clicked()
+                {
+                    picked = Some(Command::AddFrame);
+                    ui.close_menu();
+                }
+
+                if image.media_type == MediaType::ImageAnimated
+                    && ui
+                        .add_enabled(frame_export.is_none(), egui::Button::new("Export frames"))
+                        .clicked()
+                {
+                    let path = image.path.clone();
+                    start_frame_export(frame_export, path, image.decoder, settings, ctx);
+
+                    ui.close_menu();
+                }
+
+                ui.menu_button("Open with", |ui| {
+                    if ui
+                        .selectable_label(forced_decoder.is_none(), "Automatic")
+                        .clicked()
+                    {
+                        forced_decoder = None;
+                        ui.close_menu();
+                    }
+
+                    for decoder in ImageDecoder::ALL {
+                        if ui
+                            .selectable_label(forced_decoder == Some(decoder), decoder.label())
+                            .clicked()
+                        {
+                            forced_decoder = Some(decoder);
+                            ui.close_menu();
+                        }
+                    }
+                });
+
+                if ui
+                    .checkbox(&mut nearest_filter, "Nearest-neighbour filtering")
+                    .changed()
+                {
+                    ui.close_menu();
+                }
+
+                if ui
+                    .checkbox(show_thumbnail_comparison, "Compare with thumbnail")
+                    .changed()
+                {
+                    ui.close_menu();
+                }
+
+                ui.menu_button("Tool", |ui| {
+                    for (tool, label) in [
+                        (PreviewTool::None, "None".to_string()),
+                        (PreviewTool::Eyedropper, Command::Eyedropper.hint("Eyedropper")),
+                        (PreviewTool::Ruler, Command::Ruler.hint("Ruler")),
+                        (PreviewTool::Crop, Command::Crop.hint("Crop")),
+                    ] {
+                        if ui.selectable_value(preview_tool, tool, label).clicked() {
+                            ui.close_menu();
+                        }
+                    }
+                });
+            });
+        });
+
+    let magnification = if nearest_filter {
+        egui::TextureFilter::Nearest
+    } else {
+        egui::TextureFilter::Linear
+    };
+
+    if magnification != image.magnification {
+        image.magnification = magnification;
+        let _ = image.reload(ctx, settings);
+    }
+
+    if forced_decoder != image.forced_decoder {
+        let previous_decoder = std::mem::replace(&mut image.forced_decoder, forced_decoder);
+
+        if image.reload(ctx, settings).is_ok() {
+            // Remembered for the file, so the preview keeps using it when the file is opened again
+            match forced_decoder {
+                Some(decoder) => decoder_overrides.insert(image.path.clone(), decoder),
+                None => decoder_overrides.remove(&image.path),
+            };
+        } else {
+            // The decoder couldn't open the file, the previous image is still shown
+            image.forced_decoder = previous_decoder;
+        }
+    }
+
+    picked
+}
+
+// Shows the color of the source pixel under the cursor, clicking copies its hex code
+fn build_eyedropper(
+    image: &mut ImageEntry,
+    settings: &Settings,
+    response: &egui::Response,
+    image_size: egui::Vec2,
+    ctx: &egui::Context,
+) {
+    let pointer = match response.hover_pos() {
+        Some(pointer) => pointer,
+        None => return,
+    };
+
+    let position = match image
+        .view
+        .image_position(response.rect, image_size, pointer)
+    {
+        Some(position) => position,
+        None => return,
+    };
+
+    let ([x, y], color) = match image.pixel_at(ctx, settings, position) {
+        Some(pixel) => pixel,
+        None => return,
+    };
+
+    ctx.set_cursor_icon(egui::CursorIcon::Crosshair);
+
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    let hex = if a == 255 {
+        format!("#{:02X}{:02X}{:02X}", r, g, b)
+    } else {
+        format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a)
+    };
+
+    if response.clicked() {
+        ctx.output_mut(|o| o.copied_text = hex.clone());
+        info!("Copied {} from {:?}", hex, image.path);
+    }
+
+    egui::Area::new(egui::Id::new("eyedropper"))
+        .fixed_pos(pointer + egui::vec2(16.0, 16.0))
+        .order(egui::Order::Foreground)
+        .interactable(false)
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    let (swatch_rect, _) =
+                        ui.allocate_exact_size(egui::vec2(16.0, 16.0), egui::Sense::hover());
+
+                    ui.painter().rect_filled(swatch_rect, 2.0, color);
+                    ui.monospace(&hex);
+                });
+
+                ui.monospace(format!("rgba({}, {}, {}, {})", r, g, b, a));
+                ui.monospace(format!("x {}, y {}", x, y));
+            });
+        });
+}
+
+// Dragging draws a line, its length and bounding box are shown in pixels of the image,
+// a click without a drag removes it
+fn build_ruler(
+    image: &mut ImageEntry,
+    response: &egui::Response,
+    image_size: egui::Vec2,
+    ui: &mut egui::Ui,
+) {
+    let viewport = response.rect;
+
+    if response.hovered() {
+        ui.ctx().set_cursor_icon(egui::CursorIcon::Crosshair);
+    }
+
+    if let Some(pointer) = response.interact_pointer_pos() {
+        let position = image
+            .view
+            .screen_to_image(viewport, image_size, pointer)
+            .clamp(egui::Vec2::ZERO, egui::Vec2::splat(1.0));
+
+        if response.drag_started_by(egui::PointerButton::Primary) {
+            image.ruler = Some((position, position));
+        } else if response.dragged_by(egui::PointerButton::Primary) {
+            if let Some((_, end)) = &mut image.ruler {
+                *end = position;
+            }
+        }
+    }
+
+    if response.clicked() {
+        image.ruler = None;
+    }
+
+    let (start, end) = match image.ruler {
+        Some(ruler) => ruler,
+        None => return,
+    };
+
+    let start_pos = image.view.image_to_screen(viewport, image_size, start);
+    let end_pos = image.view.image_to_screen(viewport, image_size, end);
+
+    let distance = (end - start) * image_size;
+    let label = format!(
+        "{:.1} px, {}x{}",
+        distance.length(),
+        distance.x.abs().round(),
+        distance.y.abs().round()
+    );
+
+    let painter = ui.painter_at(viewport);
+    let shadow = egui::Stroke::new(4.0, egui::Color32::from_black_alpha(160));
+    let stroke = egui::Stroke::new(2.0, egui::Color32::YELLOW);
+
+    painter.line_segment([start_pos, end_pos], shadow);
+    painter.line_segment([start_pos, end_pos], stroke);
+    painter.circle_filled(start_pos, 3.0, egui::Color32::YELLOW);
+    painter.circle_filled(end_pos, 3.0, egui::Color32::YELLOW);
+
+    let label_galley = painter.layout_no_wrap(label, FontId::monospace(14.0), egui::Color32::WHITE);
+    let label_rect = egui::Align2::CENTER_BOTTOM.anchor_size(
+        start_pos.lerp(end_pos, 0.5) - egui::vec2(0.0, 8.0),
+        label_galley.size(),
+    );
+
+    painter.rect_filled(
+        label_rect.expand(3.0),
+        3.0,
+        egui::Color32::from_black_alpha(200),
+    );
+    painter.galley(label_rect.min, label_galley, egui::Color32::WHITE);
+}
+
+// Dragging draws the crop rectangle and a click without a drag removes it, like the ruler.
+// Nothing is written until the region is saved to a new file
+fn build_crop(
+    image: &mut ImageEntry,
+    settings: &Settings,
+    response: &egui::Response,
+    image_size: egui::Vec2,
+    ui: &mut egui::Ui,
+) {
+    let viewport = response.rect;
+
+    if response.hovered() {
+        ui.ctx().set_cursor_icon(egui::CursorIcon::Crosshair);
+    }
+
+    if let Some(pointer) = response.interact_pointer_pos() {
+        let position = image
+            .view
+            .screen_to_image(viewport, image_size, pointer)
+            .clamp(egui::Vec2::ZERO, egui::Vec2::splat(1.0));
+
+        if response.drag_started_by(egui::PointerButton::Primary) {
+            image.crop = Some((position, position));
+        } else if response.dragged_by(egui::PointerButton::Primary) {
+            if let Some((_, end)) = &mut image.crop {
+                *end = position;
+            }
+        }
+    }
+
+    if response.clicked() {
+        image.crop = None;
+    }
+
+    let (start, end) = match image.crop {
+        Some(crop) => crop,
+        None => return,
+    };
+
+    let crop_rect = egui::Rect::from_two_pos(
+        image.view.image_to_screen(viewport, image_size, start),
+        image.view.image_to_screen(viewport, image_size, end),
+    );
+
+    // Everything that gets cut off is darkened
+    let painter = ui.painter_at(viewport);
+    let shade = egui::Color32::from_black_alpha(160);
+
+    for shaded_rect in [
+        egui::Rect::from_min_max(viewport.min, egui::pos2(viewport.max.x, crop_rect.min.y)),
+        egui::Rect::from_min_max(egui::pos2(viewport.min.x, crop_rect.max.y), viewport.max),
+        egui::Rect::from_min_max(
+            egui::pos2(viewport.min.x, crop_rect.min.y),
+            egui::pos2(crop_rect.min.x, crop_rect.max.y),
+        ),
+        egui::Rect::from_min_max(
+            egui::pos2(crop_rect.max.x, crop_rect.min.y),
+            egui::pos2(viewport.max.x, crop_rect.max.y),
+        ),
+    ] {
+        painter.rect_filled(shaded_rect, 0.0, shade);
+    }
+
+    painter.rect_stroke(crop_rect, 0.0, egui::Stroke::new(1.0, egui::Color32::WHITE));
+
+    let region = image.crop_region(ui.ctx(), settings);
+    let label = match region {
+        Some([_, _, width, height]) => format!("{}x{} px", width, height),
+        None => "0x0 px".to_string(),
+    };
+
+    let label_galley = painter.layout_no_wrap(label, FontId::monospace(14.0), egui::Color32::WHITE);
+    let label_rect = egui::Align2::CENTER_BOTTOM.anchor_size(
+        crop_rect.center_top() - egui::vec2(0.0, 8.0),
+        label_galley.size(),
+    );
+
+    painter.rect_filled(
+        label_rect.expand(3.0),
+        3.0,
+        egui::Color32::from_black_alpha(200),
+    );
+    painter.galley(label_rect.min, label_galley, egui::Color32::WHITE);
+
+    if region.is_none() || response.dragged() {
+        return;
+    }
+
+    egui::Area::new(egui::Id::new("crop_actions"))
+        .fixed_pos(crop_rect.center_bottom() + egui::vec2(0.0, 8.0))
+        .pivot(egui::Align2::CENTER_TOP)
+        .order(egui::Order::Foreground)
+        .show(ui.ctx(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Save crop").clicked() {
+                        save_crop(image, settings, ui.ctx());
+                    }
+
+                    if ui.button("Cancel").clicked() {
+                        image.crop = None;
+                    }
+                });
+            });
+        });
+}
+
+// The region is cut out right away, so the image can be changed while the dialog is open
+fn save_crop(image: &mut ImageEntry, settings: &Settings, ctx: &egui::Context) {
+    let cropped = match image.cropped_frame(ctx, settings) {
+        Some(cropped) => cropped,
+        None => {
+            error!("Could not crop {:?}", image.path);
+            return;
+        }
+    };
+
+    let file_stem = image.path.file_stem().unwrap_or_default().to_string_lossy();
+    let task = AsyncFileDialog::new()
+        .set_file_name(format!("{} cropped.png", file_stem))
+        .add_filter("PNG", &["png"])
+        .add_filter("JPEG", &["jpg", "jpeg"])
+        .add_filter("WebP", &["webp"])
+        .save_file();
+
+    std::thread::spawn(move || match executor::block_on(task) {
+        Some(file) => {
+            if let Err(err) = export::save_image(&cropped, &PathBuf::from(file)) {
+                error!("Error saving crop: {}", err);
+            }
+        }
+        None => {
+            info!("No crop file selected");
+        }
+    });
+}
+
+// Helps to tell apart problems of the thumbnail loaders from problems of the full decode,
+// both are scaled to the same size so the difference in detail is easy to spot
+fn build_thumbnail_comparison(
+    thumbnail: &egui::TextureHandle,
+    full_image: &egui::TextureHandle,
+    open: &mut bool,
+    ctx: &egui::Context,
+) {
+    let max_size = egui::vec2(320.0, 320.0);
+
+    egui::Window::new("Thumbnail comparison")
+        .open(open)
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.horizontal_top(|ui| {
+                for (title, texture) in [("Thumbnail", thumbnail), ("Full decode", full_image)] {
+                    ui.vertical(|ui| {
+                        let [width, height] = texture.size();
+                        let texture_size = egui::vec2(width as f32, height as f32);
+                        let scale = (max_size.x / texture_size.x).min(max_size.y / texture_size.y);
+                        let sized_texture =
+                            egui::load::SizedTexture::new(texture.id(), texture_size * scale);
+
+                        ui.label(format!("{}: {}x{}", title, width, height));
+                        ui.add(egui::Image::new(sized_texture));
+                    });
+                }
+            });
+        });
+}
+
+fn build_adjustments_panel(image: &mut ImageEntry, settings: &Settings, ctx: &egui::Context) {
+    let mut should_reload = false;
+
+    egui::Area::new(egui::Id::new("image_adjustments"))
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                let exposure_slider = ui.add(
+                    egui::Slider::new(&mut image.adjustments.exposure, -5.0..=5.0).text("Exposure"),
+                );
+                let gamma_slider = ui
+                    .add(egui::Slider::new(&mut image.adjustments.gamma, 0.2..=3.0).text("Gamma"));
+
+                // Decoding the whole image again is too slow to do on every frame of a drag
+                for slider in [exposure_slider, gamma_slider] {
+                    if slider.drag_stopped() || (slider.changed() && !slider.dragged()) {
+                        should_reload = true;
+                    }
+                }
+
+                if image.decoder == ImageDecoder::Raw {
+                    ui.separator();
+
+                    let mut use_camera_white_balance = image.adjustments.white_balance.is_none();
+
+                    if ui
+                        .checkbox(&mut use_camera_white_balance, "Camera white balance")
+                        .changed()
+                    {
+                        image.adjustments.white_balance = if use_camera_white_balance {
+                            None
+                        } else {
+                            Some((5500.0, 1.0))
+                        };
+                        should_reload = true;
+                    }
+
+                    if let Some((temperature, tint)) = &mut image.adjustments.white_balance {
+                        let temperature_slider = ui.add(
+                            egui::Slider::new(temperature, 2000.0..=12000.0).text("Temperature"),
+                        );
+                        let tint_slider = ui.add(egui::Slider::new(tint, 0.5..=1.5).text("Tint"));
+
+                        for slider in [temperature_slider, tint_slider] {
+                            if slider.drag_stopped() || (slider.changed() && !slider.dragged()) {
+                                should_reload = true;
+                            }
+                        }
+                    }
+
+                    let mut color_space = image
+                        .adjustments
+                        .color_space
+                        .unwrap_or(settings.raw_color_space);
+
+                    egui::ComboBox::from_label("Color space")
+                        .selected_text(color_space.label())
+                        .show_ui(ui, |ui| {
+                            for option in [RawColorSpace::Srgb, RawColorSpace::Linear] {
+                                ui.selectable_value(&mut color_space, option, option.label());
+                            }
+                        });
+
+                    if color_space
+                        != image
+                            .adjustments
+                            .color_space
+                            .unwrap_or(settings.raw_color_space)
+                    {
+                        image.adjustments.color_space = Some(color_space);
+                        should_reload = true;
+                    }
+                }
+
+                let reset_btn =
+                    ui.add_enabled(!image.adjustments.is_default(), egui::Button::new("Reset"));
+
+                if reset_btn.clicked() {
+                    image.adjustments = ImageAdjustments::default();
+                    should_reload = true;
+                }
+            });
+        });
+
+    if should_reload {
+        let _ = image.reload(ctx, settings);
+    }
+}
+
+// Takes the place of the adjustments, which only exist for still images
+fn build_animation_panel(image: &mut ImageEntry, ctx: &egui::Context) {
+    egui::Area::new(egui::Id::new("animation_timing"))
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                let mut use_fixed_delay = image.fixed_frame_delay.is_some();
+
+                if ui
+                    .checkbox(&mut use_fixed_delay, "Same delay for every frame")
+                    .changed()
+                {
+                    image.fixed_frame_delay = if use_fixed_delay { Some(100.0) } else { None };
+                }
+
+                if let Some(fixed_frame_delay) = &mut image.fixed_frame_delay {
+                    ui.add(
+                        egui::Slider::new(fixed_frame_delay, 10.0..=2000.0)
+                            .logarithmic(true)
+                            .suffix(" ms")
+                            .text("Frame delay"),
+                    );
+                }
+
+                ui.add(
+                    egui::Slider::new(&mut image.playback_speed, 0.1..=4.0)
+                        .logarithmic(true)
+                        .suffix("x")
+                        .text("Speed"),
+                );
+
+                let is_default = image.fixed_frame_delay.is_none() && image.playback_speed == 1.0;
+
+                if ui
+                    .add_enabled(!is_default, egui::Button::new("Reset"))
+                    .clicked()
+                {
+                    image.fixed_frame_delay = None;
+                    image.playback_speed = 1.0;
+                }
+            });
+        });
+}
+
+// The new entry goes right after the one the frame was taken from. Nothing is written to the
+// disk, the path only names the frame and is numbered like copies when it's taken already
+fn add_frame_entry(
+    entries: &Mutex<Vec<GalleryEntry>>,
+    source: &PathBuf,
+    label: &str,
+    frame: RgbaImage,
+    settings: &Settings,
+    ctx: &egui::Context,
+) {
+    let size = thumbnail_decode_size(settings);
+    let (width, height) =
+        calculate_contain_size(size, size, frame.width() as f32, frame.height() as f32);
+    let thumbnail = image::imageops::thumbnail(
+        &frame,
+        (width as u32).clamp(1, frame.width()),
+        (height as u32).clamp(1, frame.height()),
+    );
+
+    let color_image = egui::ColorImage::from_rgba_unmultiplied(
+        [thumbnail.width() as usize, thumbnail.height() as usize],
+        thumbnail.as_raw(),
+    );
+    let hash = calculate_perceptual_hash(&color_image);
+    let average_color = calculate_average_color(&color_image);
+
+    let texture = match load_texture(ctx.tex_manager(), color_image) {
+        Ok(texture) => texture,
+        Err(err) => {
+            error!(
+                "Failed to add {} of {:?} to the gallery: {}",
+                label, source, err
+            );
+            return;
+        }
+    };
+
+    let mut entries = entries.lock().unwrap();
+
+    let folder = source.parent().map(PathBuf::from).unwrap_or_default();
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    let mut path = folder.join(format!("{} {}.png", stem, label));
+    let mut number = 1;
+
+    while entries.iter().any(|entry| entry.path == path) {
+        path = folder.join(format!("{} {} ({}).png", stem, label, number));
+        number += 1;
+    }
+
+    let mut entry = GalleryEntry::placeholder(&path, &texture);
+    entry.loading = false;
+    entry.hash = Some(hash);
+    entry.average_color = Some(average_color);
+    entry.dimensions = Some((frame.width(), frame.height()));
+    entry.frame = Some(Arc::new(frame));
+
+    let index = entries
+        .iter()
+        .position(|entry| &entry.path == source)
+        .map(|index| index + 1)
+        .unwrap_or(entries.len());
+
+    entries.insert(index, entry);
+
+    info!(
+        "Added {} of {:?} to the gallery as {:?}",
+        label, source, path
+    );
+}
+
+fn add_image_frame_entry(
+    image: &mut ImageEntry,
+    settings: &Settings,
+    entries: &Mutex<Vec<GalleryEntry>>,
+    ctx: &egui::Context,
+) {
+    let label = format!("frame {}", image.get_current_frame_index() + 1);
+    let path = image.path.clone();
+
+    match image.current_frame_pixels(ctx, settings) {
+        Some(frame) => add_frame_entry(entries, &path, &label, frame.clone(), settings, ctx),
+        None => error!("The current frame of {:?} is not ready yet", path),
+    }
+}
+
+fn add_video_frame_entry(
+    video: &VideoEntry,
+    settings: &Settings,
+    entries: &Mutex<Vec<GalleryEntry>>,
+    ctx: &egui::Context,
+) {
+    let frame = video.current_frame_image().and_then(|color_image| {
+        let [width, height] = color_image.size;
+
+        RgbaImage::from_raw(width as u32, height as u32, color_image.as_raw().to_vec())
+    });
+
+    // Colons and dots don't belong in file names, so the time is in milliseconds
+    let label = format!("at {} ms", video.current_time);
+
+    match frame {
+        Some(frame) => add_frame_entry(entries, &video.path, &label, frame, settings, ctx),
+        None => error!("No frame of {:?} has been shown yet", video.path),
+    }
+}
+
+// The frames are written on a separate thread, build_frame_export_window follows its progress
+fn start_frame_export(
+    frame_export: &mut Option<Arc<FrameExport>>,
+    file: PathBuf,
+    decoder: ImageDecoder,
+    settings: &Settings,
+    ctx: &egui::Context,
+) {
+    let task = AsyncFileDialog::new().pick_folder();
+    let progress = Arc::new(FrameExport::default());
+    let settings = settings.clone();
+    let ctx = ctx.clone();
+
+    *frame_export = Some(Arc::clone(&progress));
+
+    std::thread::spawn(move || {
+        match executor::block_on(task) {
+            Some(folder) => {
+                let folder = PathBuf::from(folder);
+
+                if let Err(err) =
+                    export::export_frames(&file, decoder, &settings, &folder, &progress)
+                {
+                    error!("Error exporting frames of {:?}: {}", file, err);
+                    *progress.error.lock().unwrap() = Some(err);
+                }
+            }
+            None => {
+                info!("No export folder selected");
+            }
+        }
+
+        progress.finished.store(true, Ordering::SeqCst);
+        ctx.request_repaint();
+    });
+}
+
+pub fn build_frame_export_window(app: &mut App, ctx: &egui::Context) {
+    let progress = match &app.frame_export {
+        Some(progress) => Arc::clone(progress),
+        None => return,
+    };
+
+    let error = progress.error.lock().unwrap().clone();
+
+    if progress.finished.load(Ordering::SeqCst) && error.is_none() {
+        app.frame_export = None;
+        return;
+    }
+
+    // Nothing to show while the folder dialog is still open
+    if !progress.started.load(Ordering::SeqCst) && error.is_none() {
+        return;
+    }
+
+    let written = progress.written.load(Ordering::SeqCst);
+    let total = progress.total.load(Ordering::SeqCst);
+
+    egui::Window::new("Exporting frames")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            if let Some(error) = &error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+
+                if ui.button("Close").clicked() {
+                    app.frame_export = None;
+                }
+
+                return;
+            }
+
+            if total == 0 {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Decoding frames...");
+                });
+            } else {
+                // The frame count of some videos is only an estimate, so it can be overtaken
+                let fraction = (written as f32 / total as f32).min(1.0);
+
+                ui.add(
+                    egui::ProgressBar::new(fraction)
+                        .desired_width(240.0)
+                        .text(format!("{} / {} frames", written, total)),
+                );
+            }
+
+            if ui.button("Cancel").clicked() {
+                progress.cancelled.store(true, Ordering::SeqCst);
+            }
+        });
+
+    ctx.request_repaint_after(Duration::from_millis(100));
+}
+
+// Like the frame export, the clip is cut on a separate thread and
+// build_clip_export_window follows its progress
+fn start_clip_export(
+    clip_export: &mut Option<Arc<ClipExport>>,
+    file: PathBuf,
+    start: u64,
+    end: u64,
+    ctx: &egui::Context,
+) {
+    let file_stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = file
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    // The clip keeps the container of the video, so its streams can usually be copied
+    let task = AsyncFileDialog::new()
+        .set_file_name(format!("{} clip.{}", file_stem, extension))
+        .add_filter(extension.to_uppercase(), &[extension.as_str()])
+        .save_file();
+    let progress = Arc::new(ClipExport::default());
+    let ctx = ctx.clone();
+
+    *clip_export = Some(Arc::clone(&progress));
+
+    std::thread::spawn(move || {
+        match executor::block_on(task) {
+            Some(destination) => {
+                let destination = PathBuf::from(destination);
+
+                if let Err(err) = export::export_clip(&file, &destination, start, end, &progress) {
+                    error!("Error exporting a clip of {:?}: {}", file, err);
+                    *progress.error.lock().unwrap() = Some(err);
+                }
+            }
+            None => {
+                info!("No clip file selected");
+            }
+        }
+
+        progress.finished.store(true, Ordering::SeqCst);
+        ctx.request_repaint();
+    });
+}
+
+pub fn build_clip_export_window(app: &mut App, ctx: &egui::Context) {
+    let progress = match &app.clip_export {
+        Some(progress) => Arc::clone(progress),
+        None => return,
+    };
+
+    let error = progress.error.lock().unwrap().clone();
+
+    if progress.finished.load(Ordering::SeqCst) && error.is_none() {
+        app.clip_export = None;
+        return;
+    }
+
+    // Nothing to show while the file dialog is still open
+    if !progress.started.load(Ordering::SeqCst) && error.is_none() {
+        return;
+    }
+
+    let written = progress.written.load(Ordering::SeqCst);
+    let total = progress.total.load(Ordering::SeqCst);
+
+    egui::Window::new("Exporting clip")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            if let Some(error) = &error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+
+                if ui.button("Close").clicked() {
+                    app.clip_export = None;
+                }
+
+                return;
+            }
+
+            if progress.reencoding.load(Ordering::SeqCst) {
+                ui.label("The streams couldn't be copied, encoding the video again");
+            }
+
+            let fraction = (written as f32 / total.max(1) as f32).min(1.0);
+
+            ui.add(
+                egui::ProgressBar::new(fraction)
+                    .desired_width(240.0)
+                    .text(format!("{} / {}", format_time(written), format_time(total))),
+            );
+
+            if ui.button("Cancel").clicked() {
+                progress.cancelled.store(true, Ordering::SeqCst);
+            }
+        });
+
+    ctx.request_repaint_after(Duration::from_millis(100));
+}
+
+pub fn build_contact_sheet_window(app: &mut App, ctx: &egui::Context) {
+    let progress = match &app.contact_sheet_export {
+        Some(progress) => Arc::clone(progress),
+        None => return,
+    };
+
+    let error = progress.error.lock().unwrap().clone();
+
+    if progress.finished.load(Ordering::SeqCst) && error.is_none() {
+        app.contact_sheet_export = None;
+        return;
+    }
+
+    // Nothing to show while the file dialog is still open
+    if !progress.started.load(Ordering::SeqCst) {
+        return;
+    }
+
+    egui::Window::new("Exporting contact sheet")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            if let Some(error) = &error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+
+                if ui.button("Close").clicked() {
+                    app.contact_sheet_export = None;
+                }
+
+                return;
+            }
+
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Collecting the thumbnails of the marked entries");
+            });
+        });
+}
+
+fn start_conversion(app: &mut App, conversion_state: ConversionState, ctx: &egui::Context) {
+    // Files the preview was told to open with a specific decoder are converted with it as well
+    let files = conversion_state
+        .paths
+        .iter()
+        .map(|path| (path.clone(), app.decoder_overrides.get(path).copied()))
+        .collect::<Vec<_>>();
+
+    let task = conversion_state
+        .pick_folder
+        .then(|| AsyncFileDialog::new().pick_folder());
+    let progress = Arc::new(Conversion::default());
+    let settings = app.settings.clone();
+    let ctx = ctx.clone();
+
+    app.conversion = Some(Arc::clone(&progress));
+
+    std::thread::spawn(move || {
+        let folder = match task.map(executor::block_on) {
+            Some(Some(folder)) => Some(PathBuf::from(folder)),
+            Some(None) => {
+                info!("No conversion folder selected");
+                progress.finished.store(true, Ordering::SeqCst);
+                return;
+            }
+            None => None,
+        };
+
+        let options = ConversionOptions {
+            format: conversion_state.format,
+            video_format: conversion_state.video_format,
+            quality: conversion_state.quality,
+            folder,
+        };
+
+        export::convert_files(&files, &options, &settings, &progress);
+
+        progress.finished.store(true, Ordering::SeqCst);
+        ctx.request_repaint();
+    });
+}
+
+// The options first, then the progress. The window stays open after the last file, so the
+// files that couldn't be converted can be looked at
+pub fn build_conversion_window(app: &mut App, ctx: &egui::Context) {
+    if let Some(progress) = app.conversion.as_ref().map(Arc::clone) {
+        build_conversion_progress(app, &progress, ctx);
+        return;
+    }
+
+    let conversion_state = match &mut app.conversion_state {
+        Some(conversion_state) => conversion_state,
+        None => return,
+    };
+
+    let mut open = true;
+    let mut confirmed = false;
+    let mut cancelled = false;
+
+    egui::Window::new("Convert")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            let videos = conversion_state
+                .paths
+                .iter()
+                .filter(|path| is_video(path))
+                .count();
+            let images = conversion_state.paths.len() - videos;
+
+            ui.label(format!("{} marked images, {} videos", images, videos));
+
+            if images > 0 {
+                egui::ComboBox::from_label("Image format")
+                    .selected_text(conversion_state.format.label())
+                    .show_ui(ui, |ui| {
+                        for format in export::ConversionFormat::ALL {
+                            ui.selectable_value(
+                                &mut conversion_state.format,
+                                format,
+                                format.label(),
+                            );
+                        }
+                    });
+
+                if conversion_state.format.has_quality() {
+                    ui.add(
+                        egui::Slider::new(&mut conversion_state.quality, 1..=100).text("Quality"),
+                    );
+                }
+            }
+
+            if videos > 0 {
+                egui::ComboBox::from_label("Video format")
+                    .selected_text(conversion_state.video_format.label())
+                    .show_ui(ui, |ui| {
+                        for format in export::VideoConversionFormat::ALL {
+                            ui.selectable_value(
+                                &mut conversion_state.video_format,
+                                format,
+                                format.label(),
+                            );
+                        }
+                    });
+            }
+
+            ui.checkbox(
+                &mut conversion_state.pick_folder,
+                "Write into a folder instead of next to the originals",
+            );
+
+            ui.horizontal(|ui| {
+                if ui.button("Convert").clicked() {
+                    confirmed = true;
+                }
+
+                if ui.button("Cancel").clicked() {
+                    cancelled = true;
+                }
+            });
+        });
+
+    if !open || cancelled || key_pressed(ctx, egui::Key::Escape) {
+        app.conversion_state = None;
+        return;
+    }
+
+    if confirmed {
+        if let Some(conversion_state) = app.conversion_state.take() {
+            start_conversion(app, conversion_state, ctx);
+        }
+    }
+}
+
+fn build_conversion_progress(app: &mut App, progress: &Conversion, ctx: &egui::Context) {
+    let finished = progress.finished.load(Ordering::SeqCst);
+
+    // Nothing to show while the folder dialog is still open
+    if !finished && !progress.started.load(Ordering::SeqCst) {
+        return;
+    }
+
+    let processed = progress.processed();
+    let total = progress.total.load(Ordering::SeqCst);
+    let errors = progress.errors.lock().unwrap().clone();
+
+    let mut open = true;
+
+    egui::Window::new("Converting")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            if finished {
+                ui.label(format!(
+                    "Converted {} of {} files",
+                    progress.converted.load(Ordering::SeqCst),
+                    total
+                ));
+            } else {
+                let fraction = processed as f32 / total.max(1) as f32;
+
+                ui.add(
+                    egui::ProgressBar::new(fraction)
+                        .desired_width(240.0)
+                        .text(format!("{} / {} files", processed, total)),
+                );
+
+                if progress.video.reencoding.load(Ordering::SeqCst) {
+                    ui.label("The streams couldn't be copied, encoding the video again");
+                }
+            }
+
+            if !errors.is_empty() {
+                ui.separator();
+                ui.colored_label(egui::Color32::LIGHT_RED, format!("{} failed", errors.len()));
+
+                egui::ScrollArea::vertical()
+                    .max_height(200.0)
+                    .show(ui, |ui| {
+                        for (file, error) in errors.iter() {
+                            ui.label(format!(
+                                "{}: {}",
+                                file.file_name().unwrap_or_default().to_string_lossy(),
+                                error
+                            ));
+                        }
+                    });
+            }
+
+            if finished {
+                if ui.button("Close").clicked() {
+                    app.conversion = None;
+                }
+            } else if ui.button("Cancel").clicked() {
+                progress.cancel();
+            }
+        });
+
+    if !open && finished {
+        app.conversion = None;
+    } else if !open {
+        progress.cancel();
+    }
+
+    if !finished {
+        ctx.request_repaint_after(Duration::from_millis(100));
+    }
+}
+
+fn open_rename_window(app: &mut App, path: PathBuf) {
+    let new_name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_str()
+        .unwrap_or_default()
+        .to_string();
+
+    app.rename_state = Some(RenameState {
+        path,
+        new_name,
+        error: None,
+        focus_requested: false,
+    });
+}
+
+fn rename_entry(app: &mut App, path: &PathBuf, new_name: &str) -> Result<(), String> {
+    validate_file_name(new_name)?;
+
+    let new_path = path.with_file_name(new_name);
+
+    if &new_path == path {
+        return Ok(());
+    }
+
+    // Changing only the case of a name on a case-insensitive filesystem points at the same file
+    let is_same_file = match (path.canonicalize(), new_path.canonicalize()) {
+        (Ok(old), Ok(new)) => old == new,
+        _ => false,
+    };
+
+    if new_path.exists() && !is_same_file {
+        return Err(format!("\"{}\" already exists", new_name));
+    }
+
+    std::fs::rename(path, &new_path).map_err(|err| format!("Failed to rename file: {}", err))?;
+    Sidecar::move_with(path, &new_path);
+
+    for entry in app.entries.lock().unwrap().iter_mut() {
+        if &entry.path == path {
+            entry.path = new_path.clone();
+        }
+    }
+
+    if let Some(current_entry) = &mut app.current_entry {
+        if let Some(image) = &mut current_entry.image {
+            if &image.path == path {
+                image.path = new_path.clone();
+            }
+        }
+
+        if let Some(video) = &mut current_entry.video {
+            if &video.path == path {
+                video.path = new_path.clone();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn build_rename_window(app: &mut App, ctx: &egui::Context) {
+    let rename_state = match &mut app.rename_state {
+        Some(rename_state) => rename_state,
+        None => return,
+    };
+
+    let mut open = true;
+    let mut confirmed = false;
+    let mut cancelled = false;
+
+    egui::Window::new("Rename")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            let text_edit =
+                ui.add(egui::TextEdit::singleline(&mut rename_state.new_name).desired_width(300.0));
+
+            if !rename_state.focus_requested {
+                text_edit.request_focus();
+                rename_state.focus_requested = true;
+            }
+
+            if text_edit.changed() {
+                rename_state.error = None;
+            }
+
+            if text_edit.lost_focus() {
+                if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    confirmed = true;
+                }
+
+                if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    cancelled = true;
+                }
+            }
+
+            if let Some(error) = &rename_state.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("Rename").clicked() {
+                    confirmed = true;
+                }
+
+                if ui.button("Cancel").clicked() {
+                    cancelled = true;
+                }
+            });
+        });
+
+    if !open || cancelled {
+        app.rename_state = None;
+        return;
+    }
+
+    if !confirmed {
+        return;
+    }
+
+    let path = rename_state.path.clone();
+    let new_name = rename_state.new_name.clone();
+
+    match rename_entry(app, &path, &new_name) {
+        Ok(_) => {
+            app.rename_state = None;
+        }
+        Err(err) => {
+            error!("Error renaming file: {}", err);
+
+            if let Some(rename_state) = &mut app.rename_state {
+                rename_state.error = Some(err);
+                rename_state.focus_requested = false;
+            }
+        }
+    }
+}
+
+fn get_batch_renames(app: &App, rename_state: &BatchRenameState) -> Vec<(PathBuf, PathBuf)> {
+    app.entries
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|entry| entry.marked)
+        .enumerate()
+        .map(|(i, entry)| {
+            let mut new_name =
+                apply_rename_pattern(&rename_state.pattern, rename_state.start_number + i);
+
+            if let Some(extension) = entry.path.extension() {
+                new_name = format!("{}.{}", new_name, extension.to_str().unwrap_or_default());
+            }
+
+            (entry.path.clone(), entry.path.with_file_name(new_name))
+        })
+        .collect()
+}
+
+fn rollback_renames(renamed: &[(PathBuf, PathBuf)]) {
+    for (old_path, new_path) in renamed.iter().rev() {
+        if let Err(err) = std::fs::rename(new_path, old_path) {
+            error!("Failed to roll back rename of {:?}: {}", old_path, err);
+        }
+    }
+}
+
+fn batch_rename_entries(app: &mut App, renames: &[(PathBuf, PathBuf)]) -> Result<(), String> {
+    if renames.is_empty() {
+        return Err("No marked entries to rename".to_string());
+    }
+
+    for (_, new_path) in renames.iter() {
+        let new_name = new_path
+            .file_name()
+            .unwrap_or_default()
+            .to_str()
+            .unwrap_or_default();
+
+        validate_file_name(new_name)?;
+    }
+
+    let mut renamed = vec![];
+
+    for (old_path, new_path) in renames.iter() {
+        if old_path == new_path {
+            continue;
+        }
+
+        if new_path.exists() {
+            rollback_renames(&renamed);
+
+            return Err(format!(
+                "{:?} already exists, no files were renamed",
+                new_path
+            ));
+        }
+
+        if let Err(err) = std::fs::rename(old_path, new_path) {
+            rollback_renames(&renamed);
+
+            return Err(format!(
+                "Failed to rename {:?}: {}, no files were renamed",
+                old_path, err
+            ));
+        }
+
+        renamed.push((old_path.clone(), new_path.clone()));
+    }
+
+    for (old_path, new_path) in renamed.iter() {
+        Sidecar::move_with(old_path, new_path);
+    }
+
+    for entry in app.entries.lock().unwrap().iter_mut() {
+        if let Some((_, new_path)) = renamed.iter().find(|(old_path, _)| old_path == &entry.path) {
+            entry.path = new_path.clone();
+        }
+    }
+
+    app.last_marked_entry = None;
+
+    Ok(())
+}
+
+pub fn build_batch_rename_window(app: &mut App, ctx: &egui::Context) {
+    let rename_state = match &app.batch_rename_state {
+        Some(rename_state) => rename_state,
+        None => return,
+    };
+
+    let renames = get_batch_renames(app, rename_state);
+
+    let mut open = true;
+    let mut confirmed = false;
+    let mut cancelled = false;
+
+    let rename_state = app.batch_rename_state.as_mut().unwrap();
+
+    egui::Window::new("Batch rename")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Pattern");
+
+                if ui.text_edit_singleline(&mut rename_state.pattern).changed() {
+                    rename_state.error = None;
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Start number");
+
+                if ui
+                    .add(egui::DragValue::new(&mut rename_state.start_number))
+                    .changed()
+                {
+                    rename_state.error = None;
+                }
+            });
+
+            ui.label("Every run of # is replaced with a zero padded number");
+            ui.separator();
+
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .show(ui, |ui| {
+                    for (old_path, new_path) in renames.iter() {
+                        ui.label(format!(
+                            "{} -> {}",
+                            old_path
+                                .file_name()
+                                .unwrap_or_default()
+                                .to_str()
+                                .unwrap_or_default(),
+                            new_path
+                                .file_name()
+                                .unwrap_or_default()
+                                .to_str()
+                                .unwrap_or_default()
+                        ));
+                    }
+                });
+
+            if let Some(error) = &rename_state.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(!renames.is_empty(), egui::Button::new("Rename"))
+                    .clicked()
+                {
+                    confirmed = true;
+                }
+
+                if ui.button("Cancel").clicked() {
+                    cancelled = true;
+                }
+            });
+        });
+
+    if !open || cancelled || key_pressed(ctx, egui::Key::Escape) {
+        app.batch_rename_state = None;
+        return;
+    }
+
+    if !confirmed {
+        return;
+    }
+
+    match batch_rename_entries(app, &renames) {
+        Ok(_) => {
+            app.batch_rename_state = None;
+        }
+        Err(err) => {
+            error!("Error renaming files: {}", err);
+
+            if let Some(rename_state) = &mut app.batch_rename_state {
+                rename_state.error = Some(err);
+            }
+        }
+    }
+}
+
+fn open_tags_window(app: &mut App, paths: Vec<PathBuf>) {
+    // Editing several entries starts from the tags of the first one
+    let text = app
+        .entries
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|entry| paths.contains(&entry.path))
+        .map(|entry| entry.tags.join(", "))
+        .unwrap_or_default();
+
+    app.tags_state = Some(TagsState {
+        paths,
+        text,
+        focus_requested: false,
+    });
+}
+
+pub fn build_tags_window(app: &mut App, ctx: &egui::Context) {
+    let tags_state = match &mut app.tags_state {
+        Some(tags_state) => tags_state,
+        None => return,
+    };
+
+    let mut open = true;
+    let mut confirmed = false;
+    let mut cancelled = false;
+
+    egui::Window::new("Tags")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            if tags_state.paths.len() > 1 {
+                ui.label(format!(
+                    "Editing tags of {} entries",
+                    tags_state.paths.len()
+                ));
+            }
+
+            let text_edit = ui.add(
+                egui::TextEdit::singleline(&mut tags_state.text)
+                    .hint_text("Comma separated tags")
+                    .desired_width(300.0),
+            );
+
+            if !tags_state.focus_requested {
+                text_edit.request_focus();
+                tags_state.focus_requested = true;
+            }
+
+            if text_edit.lost_focus() {
+                if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    confirmed = true;
+                }
+
+                if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    cancelled = true;
+                }
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("Save").clicked() {
+                    confirmed = true;
+                }
+
+                if ui.button("Cancel").clicked() {
+                    cancelled = true;
+                }
+            });
+        });
+
+    if !open || cancelled {
+        app.tags_state = None;
+        return;
+    }
+
+    if !confirmed {
+        return;
+    }
+
+    let mut tags: Vec<String> = vec![];
+
+    for tag in tags_state.text.split(',').map(|tag| tag.trim()) {
+        if !tag.is_empty() && !tags.iter().any(|existing| existing == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+
+    for entry in app.entries.lock().unwrap().iter_mut() {
+        if tags_state.paths.contains(&entry.path) {
+            entry.tags = tags.clone();
+            save_sidecar(entry);
+        }
+    }
+
+    app.tags_state = None;
+}
+
+pub fn build_timings_overlay(app: &mut App, ctx: &egui::Context) {
+    let max_timings = 100;
+
+    // Received even while hidden, so the channel doesn't grow
+    if let Some(receiver) = &app.timing_receiver {
+        app.timings.extend(receiver.try_iter());
+    }
+
+    while app.timings.len() > max_timings {
+        app.timings.pop_front();
+    }
+
+    if !app.show_timings {
+        return;
+    }
+
+    let max_frame_times = 120;
+
+    app.frame_times
+        .push_back(ctx.input(|i| i.unstable_dt) * 1000.0);
+
+    while app.frame_times.len() > max_frame_times {
+        app.frame_times.pop_front();
+    }
+
+    let average_frame_time = app.frame_times.iter().sum::<f32>() / app.frame_times.len() as f32;
+    let max_frame_time = app.frame_times.iter().cloned().fold(0.0, f32::max);
+
+    let buffer_fill = app
+        .current_entry
+        .as_ref()
+        .and_then(|current_entry| current_entry.video.as_ref())
+        .map(|video| video.get_buffer_fill());
+
+    egui::Area::new(egui::Id::new("timings_overlay"))
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 40.0))
+        .order(egui::Order::Foreground)
+        .interactable(false)
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.monospace(format!(
+                    "Frame time: {:.1}ms avg, {:.1}ms max",
+                    average_frame_time, max_frame_time
+                ));
+
+                if let Some((filled, size)) = buffer_fill {
+                    ui.monospace(format!("Video buffer: {}/{} frames", filled, size));
+                }
+
+                ui.separator();
+
+                if app.timings.is_empty() {
+                    ui.monospace("No decode timings yet");
+                }
+
+                for timing in app.timings.iter().rev().take(10) {
+                    ui.monospace(format!(
+                        "{}: {:.1}ms",
+                        timing.label,
+                        timing.duration.as_secs_f64() * 1000.0
+                    ));
+                }
+            });
+        });
 }
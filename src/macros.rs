@@ -1,6 +1,9 @@
 #[macro_export]
 macro_rules! measure_time {
     ($func:expr) => {{
+        $crate::measure_time!(stringify!($func), $func)
+    }};
+    ($label:expr, $func:expr) => {{
         let start = std::time::Instant::now();
         let result = $func;
         let duration = start.elapsed();
@@ -9,12 +12,15 @@ macro_rules! measure_time {
         let micros = duration.subsec_micros() - millis * 1_000;
         let nanos = duration.subsec_nanos() - millis * 1_000_000 - micros * 1_000;
 
-        let func_name = stringify!($func);
-
-        println!(
+        log::debug!(
             "Time elapsed: {}s {}ms {}µs {}ns [{}]",
-            secs, millis, micros, nanos, func_name
+            secs,
+            millis,
+            micros,
+            nanos,
+            $label
         );
+        $crate::timings::record($label, duration);
         result
     }};
 }
@@ -30,10 +36,14 @@ macro_rules! measure_block_time {
         let micros = duration.subsec_micros() - millis * 1_000;
         let nanos = duration.subsec_nanos() - millis * 1_000_000 - micros * 1_000;
 
-        println!(
+        log::debug!(
             "Time elapsed: {}s {}ms {}µs {}ns",
-            secs, millis, micros, nanos
+            secs,
+            millis,
+            micros,
+            nanos
         );
+        $crate::timings::record("block", duration);
         result
     }};
 }
\ No newline at end of file
@@ -1,53 +1,565 @@
+mod commands;
+mod decode_queue;
+mod decode_stats;
+mod download;
+mod export;
+mod frame_delays;
 mod image_entry;
+mod isolated_decoder;
 mod layout;
 mod macros;
+mod rotate;
+mod session;
+mod sidecar;
+mod thumbnail_cache;
+mod timings;
 mod utils;
 mod video_entry;
 mod widgets;
 
+use commands::{build_command_palette, pressed_commands, Command, CommandPalette};
+use decode_queue::DecodeQueue;
 use eframe::egui::{self, text, Visuals};
 use ffmpeg_next as ffmpeg;
 use futures::{executor, FutureExt};
-use image_entry::ImageEntry;
-use layout::{build_grid, build_preview};
-use rayon::prelude::*;
+use image::RgbaImage;
+use image_entry::{ImageDecoder, ImageEntry, ImageFormat, RawColorSpace};
+use layout::{
+    build_batch_rename_window, build_clip_export_window, build_contact_sheet_window,
+    build_conversion_window, build_delete_confirmation, build_frame_export_window, build_grid,
+    build_preview, build_rename_window, build_rotation_confirmation, build_tags_window,
+    build_timings_overlay, run_entry_command,
+};
+use log::{error, info, warn};
+use notify::{event::ModifyKind, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use rfd::AsyncFileDialog;
+use sidecar::Sidecar;
 use std::{
+    collections::{HashMap, HashSet, VecDeque},
     env,
+    io::{self, BufRead},
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::Receiver,
+        Arc, Mutex, OnceLock,
+    },
     thread,
+    time::{Duration, Instant, SystemTime},
 };
+use thumbnail_cache::CacheEncoding;
 use utils::{
-    filter_media_files, filter_valid_paths, is_image, process_entries, SharedTextureManager,
+    check_readable, filter_media_files, filter_valid_paths, format_bytes, get_texture_memory,
+    is_ffmpeg_only_image, is_image_content, is_shutting_down, is_superseded, is_symlink, is_video,
+    process_entries, request_shutdown, run_with_timeout, set_max_texture_side, start_load_epoch,
+    thumbnail_decode_size, SharedTextureManager, IMAGE_EXTENSIONS, VIDEO_EXTENSIONS,
 };
 use video_entry::VideoEntry;
+use widgets::{
+    image_frame::{EntryLabel, LoadingStyle, SelectionStyle, ThumbnailFit},
+    image_viewer::ViewTransform,
+    video_player::VideoFit,
+};
 
-#[derive(PartialEq, Clone)]
+// The order of the variants is the order of the sections when the grid is grouped by type
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum MediaType {
     ImageStill,
     ImageAnimated,
     Video,
 }
 
+impl MediaType {
+    pub fn section_label(&self) -> &'static str {
+        match self {
+            MediaType::ImageStill => "Images",
+            MediaType::ImageAnimated => "Animated",
+            MediaType::Video => "Videos",
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum WindowKind {
+    Settings,
+    Stats,
+}
+
+// Tools that take over clicks and drags in the preview, only one can be active at a time
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum PreviewTool {
+    #[default]
+    None,
+    Eyedropper,
+    Ruler,
+    Crop,
+}
+
 pub struct EguiWindow {
     pub title: String,
     pub open: bool,
+    pub kind: WindowKind,
+}
+
+impl EguiWindow {
+    // Windows are identified by their title, this is the layer egui puts them on
+    pub fn layer_id(&self) -> egui::LayerId {
+        egui::LayerId::new(egui::Order::Middle, egui::Id::new(&self.title))
+    }
+}
+
+pub struct GalleryWindow {
+    pub id: usize,
+    pub app: App,
+    pub open: bool,
+}
+
+pub struct FolderWatcher {
+    pub roots: Vec<PathBuf>,
+    _watcher: RecommendedWatcher,
+}
+
+pub struct RenameState {
+    pub path: PathBuf,
+    pub new_name: String,
+    pub error: Option<String>,
+    pub focus_requested: bool,
+}
+
+#[derive(Clone)]
+pub struct MediaFilter {
+    pub images: bool,
+    pub animated: bool,
+    pub videos: bool,
+    pub failed: bool,
+    // Hides everything that isn't marked, the marks themselves stay as they are
+    pub marked_only: bool,
+}
+
+impl Default for MediaFilter {
+    fn default() -> Self {
+        Self {
+            images: true,
+            animated: true,
+            videos: true,
+            failed: true,
+            marked_only: false,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct MediaCounts {
+    pub images: usize,
+    pub animated: usize,
+    pub videos: usize,
+    pub failed: usize,
+    pub missing: usize,
+}
+
+impl MediaCounts {
+    pub fn from_entries(entries: &[GalleryEntry]) -> Self {
+        let mut counts = MediaCounts::default();
+
+        for entry in entries.iter() {
+            if entry.missing {
+                counts.missing += 1;
+            }
+
+            if entry.failed {
+                counts.failed += 1;
+                continue;
+            }
+
+            match entry.media_type {
+                MediaType::ImageStill => counts.images += 1,
+                MediaType::ImageAnimated => counts.animated += 1,
+                MediaType::Video => counts.videos += 1,
+            }
+        }
+
+        counts
+    }
+}
+
+// An animated entry that plays in its grid cell after it was hovered. It's decoded in the
+// background like the preview would, until then the cell keeps showing the thumbnail
+pub struct AnimatedThumbnail {
+    pub path: PathBuf,
+    pub image: Arc<Mutex<Option<ImageEntry>>>,
+    // Set once it stops playing, a decode that didn't start yet is skipped then
+    pub cancelled: Arc<AtomicBool>,
+}
+
+impl Drop for AnimatedThumbnail {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+pub struct RotationState {
+    pub path: PathBuf,
+    pub clockwise: bool,
+    // Set when the file has to be encoded again instead of only changing its orientation
+    pub warning: Option<String>,
+    pub error: Option<String>,
+    // Set while the file is being rotated on a separate thread
+    pub rotation: Option<Arc<rotate::Rotation>>,
+}
+
+pub struct TagsState {
+    pub paths: Vec<PathBuf>,
+    pub text: String,
+    pub focus_requested: bool,
+}
+
+pub struct ConversionState {
+    pub paths: Vec<PathBuf>,
+    pub format: export::ConversionFormat,
+    pub video_format: export::VideoConversionFormat,
+    pub quality: u8,
+    // The converted files go next to the originals unless a folder is picked
+    pub pick_folder: bool,
+}
+
+pub struct BatchRenameState {
+    pub pattern: String,
+    pub start_number: usize,
+    pub error: Option<String>,
+}
+
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum GridLayout {
+    #[default]
+    Uniform,
+    // Rows of thumbnails with their own aspect ratio, like a photo album
+    Justified,
+}
+
+impl GridLayout {
+    pub fn label(&self) -> &'static str {
+        match self {
+            GridLayout::Uniform => "Uniform cells",
+            GridLayout::Justified => "Justified rows",
+        }
+    }
+}
+
+// Clicking always marks an entry, this decides what opens it in the preview
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum InteractionModel {
+    #[default]
+    RightClickOpens,
+    // Right click keeps working, but single clicks take a moment to mark the entry
+    DoubleClickOpens,
+}
+
+impl InteractionModel {
+    pub fn label(&self) -> &'static str {
+        match self {
+            InteractionModel::RightClickOpens => "Right click opens",
+            InteractionModel::DoubleClickOpens => "Double click opens",
+        }
+    }
+}
+
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum PreviewWheel {
+    #[default]
+    Zoom,
+    // Ctrl+wheel still zooms, egui reports it as a zoom instead of a scroll
+    Navigate,
+}
+
+impl PreviewWheel {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PreviewWheel::Zoom => "Zooms",
+            PreviewWheel::Navigate => "Switches entries, Ctrl zooms",
+        }
+    }
+}
+
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum OverlayCorner {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl OverlayCorner {
+    pub const ALL: [OverlayCorner; 4] = [
+        OverlayCorner::TopLeft,
+        OverlayCorner::TopRight,
+        OverlayCorner::BottomLeft,
+        OverlayCorner::BottomRight,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            OverlayCorner::TopLeft => "Top left",
+            OverlayCorner::TopRight => "Top right",
+            OverlayCorner::BottomLeft => "Bottom left",
+            OverlayCorner::BottomRight => "Bottom right",
+        }
+    }
+
+    pub fn align(&self) -> egui::Align2 {
+        match self {
+            OverlayCorner::TopLeft => egui::Align2::LEFT_TOP,
+            OverlayCorner::TopRight => egui::Align2::RIGHT_TOP,
+            OverlayCorner::BottomLeft => egui::Align2::LEFT_BOTTOM,
+            OverlayCorner::BottomRight => egui::Align2::RIGHT_BOTTOM,
+        }
+    }
+}
+
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum SortMode {
+    #[default]
+    Path,
+    // The order the entries were dragged into in the grid
+    Manual,
+}
+
+impl SortMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SortMode::Path => "Path",
+            SortMode::Manual => "Manual",
+        }
+    }
 }
 
 #[derive(Default, Clone)]
 pub struct Settings {
     pub min_thumbnail_size: usize,
+    pub thumbnail_decode_size: usize,
+    pub decode_threads: usize,
+    pub auto_tune_decode_threads: bool,
+    pub prioritize_visible_decoding: bool,
+    // Thumbnails that take longer are given up on, zero waits forever
+    pub decode_timeout_secs: u64,
+    pub thumbnail_cache: bool,
+    pub thumbnail_cache_encoding: CacheEncoding,
+    // Only used by the JPEG encoding
+    pub thumbnail_cache_quality: u8,
+    pub max_cache_size_mb: u64,
+    // Video thumbnails are made by a separate process, so a crash inside of ffmpeg only fails
+    // the file. Its results go through files in this folder, the temp folder when empty
+    pub isolate_ffmpeg_thumbnails: bool,
+    pub isolated_decoder_dir: String,
+    pub isolated_decoder_max_output_mb: u64,
     pub max_columns_count: usize,
+    // Min thumbnail size and max columns count of the small, medium and large grid,
+    // switched to with Ctrl+1, Ctrl+2 and Ctrl+3
+    pub density_presets: [(usize, usize); 3],
+    pub thumbnail_fit: ThumbnailFit,
+    pub entry_label: EntryLabel,
+    pub grid_layout: GridLayout,
+    pub thumbnail_corner_radius: f32,
+    pub loading_style: LoadingStyle,
+    pub invert_scroll: bool,
+    pub momentum_scrolling: bool,
+    pub interaction_model: InteractionModel,
+    pub preview_wheel: PreviewWheel,
+    // Otherwise every image opens fit to the window
+    pub remember_zoom: bool,
+    pub selection_style: SelectionStyle,
+    pub selection_color: egui::Color32,
+    pub selection_thickness: f32,
+    pub selection_checkmark: bool,
+    pub show_selection_order: bool,
+    pub overlay_font_size: f32,
+    pub overlay_color: egui::Color32,
+    pub overlay_corner: OverlayCorner,
+    pub thumbnail_label_color: egui::Color32,
+    pub video_backdrop_color: egui::Color32,
+    // Toggled with Z while a video is playing
+    pub video_fit: VideoFit,
+    // Only applies to images opened after it is changed
+    pub nearest_preview_filter: bool,
+    // Empty uses the embedded placeholder
+    pub placeholder_path: String,
     pub show_failed_files: bool,
+    // Skips the trash, which isn't available everywhere
+    pub delete_permanently: bool,
+    pub duplicate_threshold: u32,
+    pub apply_exif_orientation: bool,
+    // Off shows the stored values as if they were sRGB, whatever profile the file has
+    pub color_management: bool,
+    pub tone_map_hdr: bool,
+    pub hdr_exposure: f32,
+    pub hdr_gamma: f32,
+    pub raw_exposure: f32,
+    pub raw_color_space: RawColorSpace,
+    pub watch_folders: bool,
+    pub follow_symlinks: bool,
+    pub pace_video_repaints: bool,
+    pub autoplay_videos: bool,
+    pub disable_audio: bool,
+    pub pause_video_on_focus_loss: bool,
+    pub autohide_video_controls: bool,
+    pub cap_preview_resolution: bool,
+    // Longest side of the preview texture in pixels, larger images are scaled down to it
+    pub max_preview_resolution: u32,
+    // Seconds without moving the mouse before the controls of a playing video are hidden
+    pub video_controls_hide_delay: f32,
+    // Used for videos whose container has no usable frame rate
+    pub fallback_frame_rate: f32,
+    pub min_frame_delay: f32,
+    pub max_animation_fps: f32,
+    pub animate_thumbnails: bool,
+    // The most recently hovered animated entries keep playing in the grid, up to this many
+    pub max_animated_thumbnails: usize,
+    pub max_file_count: usize,
+    pub standby: bool,
+    pub standby_when_unfocused: bool,
+    // Tried in this order, the flag tells whether the decoder is used at all
+    pub decoders: Vec<(ImageDecoder, bool)>,
+    // Without it RAW and FFmpeg only get files no other decoder claims, and image thumbnails
+    // only go through FFmpeg for formats nothing else decodes, it crashes on some of them
+    pub full_decoder_chain: bool,
+    // Only files with an enabled extension are loaded, unknown extensions are still sniffed
+    pub image_extensions: Vec<(String, bool)>,
+    pub video_extensions: Vec<(String, bool)>,
+    pub min_rating: u8,
+    pub sort_mode: SortMode,
+    pub sort_descending: bool,
+    pub group_by_type: bool,
+    pub show_grid_minimap: bool,
+    pub contact_sheet_columns: usize,
+    pub contact_sheet_spacing: u32,
+    pub contact_sheet_captions: bool,
+    // Highest pixels per point the thumbnails were decoded for. Follows the screen the window
+    // is on rather than being set by the user, the loader threads have no egui context to ask
+    pub screen_scale: f32,
+}
+
+impl Settings {
+    pub fn enabled_decoders(&self) -> impl Iterator<Item = ImageDecoder> + '_ {
+        self.decoders
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(decoder, _)| *decoder)
+    }
+
+    // The enabled decoders that are tried on a file of this format, in order
+    pub fn decoder_chain(
+        &self,
+        file: &PathBuf,
+        format: &ImageFormat,
+        thumbnail: bool,
+    ) -> Vec<ImageDecoder> {
+        let is_unknown = matches!(format, ImageFormat::Unknown);
+        // Without FFmpeg these would never get a thumbnail at all
+        let ffmpeg_only = is_ffmpeg_only_image(file);
+
+        self.enabled_decoders()
+            .filter(|decoder| decoder.supports(format))
+            .filter(|decoder| {
+                self.full_decoder_chain
+                    || match decoder {
+                        ImageDecoder::Raw => is_unknown,
+                        ImageDecoder::Ffmpeg => is_unknown && (!thumbnail || ffmpeg_only),
+                        _ => true,
+                    }
+            })
+            .collect()
+    }
+
+    pub fn extension_enabled(&self, extension: &str) -> Option<bool> {
+        self.image_extensions
+            .iter()
+            .chain(self.video_extensions.iter())
+            .find(|(known, _)| known == extension)
+            .map(|(_, enabled)| *enabled)
+    }
 }
 
+static MARK_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
 pub struct GalleryEntry {
     path: PathBuf,
     thumbnail: egui::TextureHandle,
     media_type: MediaType,
     marked: bool,
+    // Taken from a counter when the entry gets marked, so the marks can be numbered in order
+    marked_sequence: u64,
     failed: bool,
+    hash: Option<u64>,
+    // Stands in for the thumbnail in the grid minimap
+    average_color: Option<egui::Color32>,
+    duplicate_group: Option<usize>,
+    modified: Option<SystemTime>,
+    // Of the original file, for the corner label of the grid
+    dimensions: Option<(u32, u32)>,
+    file_size: Option<u64>,
+    rating: u8,
+    tags: Vec<String>,
+    error: Option<String>,
+    corrupt: bool,
+    loading: bool,
+    // The file was deleted or its symlink broke after it was loaded
+    missing: bool,
+    // Position in the manual sort mode, entries that were never placed get usize::MAX
+    manual_order: usize,
+    // Frames copied from a video or an animation only live in memory, their path is made up
+    frame: Option<Arc<RgbaImage>>,
+}
+
+impl GalleryEntry {
+    // Shown in the grid right away, so the files keep their place while they are decoded
+    fn placeholder(path: &PathBuf, thumbnail: &egui::TextureHandle) -> Self {
+        Self {
+            path: path.clone(),
+            thumbnail: thumbnail.clone(),
+            media_type: if is_video(path) {
+                MediaType::Video
+            } else {
+                MediaType::ImageStill
+            },
+            marked: false,
+            marked_sequence: 0,
+            failed: false,
+            hash: None,
+            average_color: None,
+            duplicate_group: None,
+            modified: None,
+            dimensions: None,
+            file_size: None,
+            rating: 0,
+            tags: Vec::new(),
+            error: None,
+            corrupt: false,
+            loading: true,
+            missing: false,
+            manual_order: usize::MAX,
+            frame: None,
+        }
+    }
+
+    // Stands for a url that couldn't be loaded, so it can be counted and its error shown
+    fn failed_url(url: &str, thumbnail: &egui::TextureHandle, error: String) -> Self {
+        Self {
+            media_type: MediaType::ImageStill,
+            failed: true,
+            error: Some(error),
+            loading: false,
+            ..GalleryEntry::placeholder(&PathBuf::from(url), thumbnail)
+        }
+    }
+
+    fn set_marked(&mut self, marked: bool) {
+        if marked && !self.marked {
+            self.marked_sequence = MARK_SEQUENCE.fetch_add(1, Ordering::SeqCst) + 1;
+        }
+
+        self.marked = marked;
+    }
 }
 
 pub struct CurrentEntry {
@@ -56,6 +568,17 @@ pub struct CurrentEntry {
     video: Option<VideoEntry>,
 }
 
+impl CurrentEntry {
+    pub fn path(&self) -> Option<PathBuf> {
+        match &self.media_type {
+            MediaType::ImageStill | MediaType::ImageAnimated => {
+                self.image.as_ref().map(|image| image.path.clone())
+            }
+            MediaType::Video => self.video.as_ref().map(|video| video.path.clone()),
+        }
+    }
+}
+
 impl Clone for GalleryEntry {
     fn clone(&self) -> Self {
         Self {
@@ -63,7 +586,22 @@ impl Clone for GalleryEntry {
             thumbnail: self.thumbnail.clone(),
             media_type: self.media_type.clone(),
             marked: self.marked,
+            marked_sequence: self.marked_sequence,
             failed: self.failed,
+            hash: self.hash,
+            average_color: self.average_color,
+            duplicate_group: self.duplicate_group,
+            modified: self.modified,
+            dimensions: self.dimensions,
+            file_size: self.file_size,
+            rating: self.rating,
+            tags: self.tags.clone(),
+            error: self.error.clone(),
+            corrupt: self.corrupt,
+            loading: self.loading,
+            missing: self.missing,
+            manual_order: self.manual_order,
+            frame: self.frame.clone(),
         }
     }
 }
@@ -72,209 +610,2902 @@ impl Clone for GalleryEntry {
 pub struct App {
     entries: Arc<Mutex<Vec<GalleryEntry>>>,
     current_entry: Option<CurrentEntry>,
-    last_marked_entry_index: Option<usize>,
+    // The anchor of shift clicks, kept by path so it survives sorting and filtering
+    last_marked_entry: Option<PathBuf>,
     dropped_files: Vec<PathBuf>,
+    pending_urls: Vec<String>,
     settings: Settings,
     windows: Vec<EguiWindow>,
+    rename_state: Option<RenameState>,
+    batch_rename_state: Option<BatchRenameState>,
+    conversion_state: Option<ConversionState>,
+    conversion: Option<Arc<export::Conversion>>,
+    tags_state: Option<TagsState>,
+    media_filter: MediaFilter,
+    reverse_order: bool,
+    media_counts: MediaCounts,
+    media_counts_updated_at: Option<Instant>,
+    missing_files_checked_at: Option<Instant>,
+    missing_files_check_running: Arc<AtomicBool>,
+    gallery_windows: Vec<GalleryWindow>,
+    next_gallery_window_id: usize,
+    loading_count: Arc<AtomicUsize>,
+    // Bumped whenever the entries are cleared, see start_load_epoch
+    load_epoch: Arc<AtomicUsize>,
+    // Placeholders the grid currently shows, decoded first when prioritize_visible_decoding is on
+    visible_paths: Arc<Mutex<Vec<PathBuf>>>,
+    clear_confirmation_open: bool,
+    pending_files: Arc<Mutex<Option<Vec<PathBuf>>>>,
+    roots: Arc<Mutex<Vec<PathBuf>>>,
+    folder_watcher: Option<FolderWatcher>,
+    collection: Arc<Mutex<Vec<GalleryEntry>>>,
+    show_collection: bool,
+    highlighted_entry: Option<(PathBuf, Instant)>,
+    // Entry under the pointer in the last frame of the grid
+    hovered_entry: Option<PathBuf>,
+    scroll_to_highlighted: bool,
+    decoder_overrides: HashMap<PathBuf, ImageDecoder>,
+    // Zoom and pan the images of this session were left at, restored when they are opened again
+    saved_views: HashMap<PathBuf, ViewTransform>,
+    show_thumbnail_comparison: bool,
+    preview_tool: PreviewTool,
+    // Roots of the previous run, offered while nothing is loaded
+    last_session: Vec<PathBuf>,
+    pending_deletion: Option<Vec<PathBuf>>,
+    // Rotating writes over the file, so it waits for a confirmation
+    pending_rotation: Option<RotationState>,
+    // Points per second the grid keeps scrolling by after the wheel stopped
+    scroll_velocity: f32,
+    // Entry that is being dragged to a new place in the manual sort mode
+    dragged_entry: Option<PathBuf>,
+    // Click in the grid that is waiting to see whether it becomes a double click
+    pending_click: Option<(PathBuf, bool, Instant)>,
+    // The corner label of the last frame, the resolution is only read while it's shown
+    shown_entry_label: EntryLabel,
+    // Arrow key that is held in the preview, when it went down and when it last made a step
+    held_arrow: Option<(egui::Key, Instant, Instant)>,
+    // Scrolled distance in the preview that didn't add up to a whole step between entries yet
+    wheel_navigation_delta: f32,
+    frame_export: Option<Arc<export::FrameExport>>,
+    // Least recently hovered first
+    animated_thumbnails: Vec<AnimatedThumbnail>,
+    // Animated entry under the pointer and since when, it only starts playing after a moment
+    hovered_animation: Option<(PathBuf, Instant)>,
+    clip_export: Option<Arc<export::ClipExport>>,
+    contact_sheet_export: Option<Arc<export::ContactSheetExport>>,
+    in_standby: bool,
+    resume_video_after_standby: bool,
+    window_focused: bool,
+    resume_video_on_focus: bool,
+    show_timings: bool,
+    frame_times: VecDeque<f32>,
+    timings: VecDeque<timings::Timing>,
+    timing_receiver: Option<Receiver<timings::Timing>>,
+    // The texture the grid minimap was last drawn into, with the hash of what it shows
+    grid_minimap: Option<(u64, egui::TextureHandle)>,
+    command_palette: Option<CommandPalette>,
 }
 
 impl App {
-    fn new(_creation_ctx: &eframe::CreationContext<'_>, dropped_files: Vec<PathBuf>) -> Self {
+    fn new(
+        _creation_ctx: &eframe::CreationContext<'_>,
+        dropped_files: Vec<PathBuf>,
+        roots: Vec<PathBuf>,
+        urls: Vec<String>,
+    ) -> Self {
         Self {
             settings: Settings {
                 min_thumbnail_size: 200,
+                thumbnail_decode_size: 512,
+                decode_threads: default_decode_threads(),
+                auto_tune_decode_threads: false,
+                prioritize_visible_decoding: true,
+                decode_timeout_secs: 30,
+                thumbnail_cache: true,
+                thumbnail_cache_encoding: CacheEncoding::Jpeg,
+                thumbnail_cache_quality: 85,
+                max_cache_size_mb: 512,
+                isolate_ffmpeg_thumbnails: false,
+                isolated_decoder_dir: String::new(),
+                isolated_decoder_max_output_mb: 64,
                 max_columns_count: 4,
+                density_presets: [(120, 8), (200, 4), (360, 2)],
+                thumbnail_fit: ThumbnailFit::Contain,
+                entry_label: EntryLabel::Extension,
+                grid_layout: GridLayout::Uniform,
+                thumbnail_corner_radius: 0.0,
+                loading_style: LoadingStyle::Skeleton,
+                invert_scroll: false,
+                momentum_scrolling: false,
+                interaction_model: InteractionModel::RightClickOpens,
+                preview_wheel: PreviewWheel::Zoom,
+                remember_zoom: false,
+                selection_style: SelectionStyle::Border,
+                selection_color: egui::Color32::from_rgb(180, 123, 182),
+                selection_thickness: 3.0,
+                selection_checkmark: true,
+                show_selection_order: false,
+                overlay_font_size: 14.0,
+                overlay_color: egui::Color32::WHITE,
+                overlay_corner: OverlayCorner::TopLeft,
+                thumbnail_label_color: egui::Color32::LIGHT_RED,
+                video_backdrop_color: egui::Color32::BLACK,
+                video_fit: VideoFit::Contain,
+                nearest_preview_filter: false,
+                placeholder_path: String::new(),
                 show_failed_files: true,
+                delete_permanently: false,
+                duplicate_threshold: 6,
+                apply_exif_orientation: true,
+                color_management: true,
+                tone_map_hdr: true,
+                hdr_exposure: 0.0,
+                hdr_gamma: 2.2,
+                raw_exposure: 0.0,
+                raw_color_space: RawColorSpace::Srgb,
+                watch_folders: false,
+                follow_symlinks: false,
+                pace_video_repaints: true,
+                autoplay_videos: false,
+                disable_audio: false,
+                pause_video_on_focus_loss: false,
+                autohide_video_controls: true,
+                cap_preview_resolution: false,
+                max_preview_resolution: 8192,
+                video_controls_hide_delay: 3.0,
+                fallback_frame_rate: 30.0,
+                min_frame_delay: 20.0,
+                max_animation_fps: 60.0,
+                animate_thumbnails: true,
+                max_animated_thumbnails: 1,
+                max_file_count: 10_000,
+                standby: true,
+                standby_when_unfocused: false,
+                decoders: ImageDecoder::ALL
+                    .iter()
+                    .map(|decoder| (*decoder, true))
+                    .collect(),
+                full_decoder_chain: false,
+                image_extensions: IMAGE_EXTENSIONS
+                    .iter()
+                    .map(|extension| (extension.to_string(), true))
+                    .collect(),
+                video_extensions: VIDEO_EXTENSIONS
+                    .iter()
+                    .map(|extension| (extension.to_string(), true))
+                    .collect(),
+                min_rating: 0,
+                sort_mode: SortMode::Path,
+                sort_descending: false,
+                group_by_type: false,
+                show_grid_minimap: false,
+                contact_sheet_columns: 4,
+                contact_sheet_spacing: 8,
+                contact_sheet_captions: true,
+                screen_scale: 1.0,
             },
             dropped_files,
+            pending_urls: urls,
+            roots: Arc::new(Mutex::new(roots)),
+            last_session: session::load(),
+            timing_receiver: Some(timings::connect()),
             ..Default::default()
         }
     }
 }
 
-fn load_files(
+// Unlike load_files the entries stay as they are, only their thumbnails are replaced
+fn redecode_thumbnails(
     files: Vec<PathBuf>,
     texture_manager: SharedTextureManager,
     entries: Arc<Mutex<Vec<GalleryEntry>>>,
-    app_settings: Settings,
+    settings: Settings,
+    loading_count: Arc<AtomicUsize>,
+    load_epoch: Arc<AtomicUsize>,
 ) {
-    thread::spawn(move || {
-        files.into_par_iter().for_each(move |file| {
-            let max_thumbnail_size = 512.0;
-
-            let texture = match ImageEntry::load_thumbnail(
-                &texture_manager,
-                &file,
-                max_thumbnail_size,
-            ) {
-                Some(thumbnail) => thumbnail.get_texture(),
-                None => None,
-            };
+    loading_count.fetch_add(1, Ordering::SeqCst);
 
-            if app_settings.show_failed_files {
-                entries.lock().unwrap().push(GalleryEntry {
-                    path: file.clone(),
-                    failed: texture.is_none(),
-                    thumbnail: match texture {
-                        Some(texture) => texture,
-                        None => ImageEntry::default_texture(texture_manager.clone()),
-                    },
-                    media_type: if is_image(&file) {
-                        MediaType::ImageStill
-                    } else {
-                        MediaType::Video
-                    },
-                    marked: false,
-                });
+    let epoch = load_epoch.load(Ordering::SeqCst);
 
+    // Only searched for again if the entries were sorted or removed while decoding
+    let entry_indices = entries
+        .lock()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (entry.path.clone(), index))
+        .collect::<HashMap<PathBuf, usize>>();
+
+    thread::spawn(move || {
+        let size = thumbnail_decode_size(&settings);
+
+        let redecode_file = |file: &PathBuf| {
+            if is_shutting_down() || is_superseded(&load_epoch, epoch) {
                 return;
             }
 
-            match texture {
-                Some(texture) => {
-                    entries.lock().unwrap().push(GalleryEntry {
-                        path: file.clone(),
-                        thumbnail: texture,
-                        media_type: if is_image(&file) {
-                            MediaType::ImageStill
-                        } else {
-                            MediaType::Video
-                        },
-                        marked: false,
-                        failed: false,
-                    });
+            let thumbnail = ImageEntry::load_thumbnail(&texture_manager, file, size, &settings);
+            let texture = thumbnail
+                .as_ref()
+                .and_then(|thumbnail| thumbnail.get_texture());
+
+            if let (Some(thumbnail), Some(texture)) = (thumbnail, texture) {
+                let mut entries = entries.lock().unwrap();
+
+                if is_superseded(&load_epoch, epoch) {
+                    return;
                 }
-                None => {
-                    println!("Failed to load texture for file: {:?}", file);
+
+                let index = entry_indices
+                    .get(file)
+                    .copied()
+                    .filter(|index| entries.get(*index).is_some_and(|entry| &entry.path == file))
+                    .or_else(|| entries.iter().position(|entry| &entry.path == file));
+
+                if let Some(index) = index {
+                    entries[index].thumbnail = texture;
+                    entries[index].average_color = Some(thumbnail.get_average_color());
                 }
             }
-        });
+        };
+
+        match rayon::ThreadPoolBuilder::new()
+            .num_threads(settings.decode_threads)
+            .build()
+        {
+            Ok(pool) => pool.install(|| files.par_iter().for_each(redecode_file)),
+            Err(err) => {
+                warn!("Failed to create decode thread pool: {}", err);
+                files.iter().for_each(redecode_file);
+            }
+        }
+
+        loading_count.fetch_sub(1, Ordering::SeqCst);
     });
 }
 
-fn handle_selector_button_click(ctx: egui::Context, app: &mut App, select_files: bool) {
-    let file_dialog = AsyncFileDialog::new();
-    let task = if select_files {
-        file_dialog.pick_files().boxed()
-    } else {
-        file_dialog.pick_folders().boxed()
-    };
+// Leaves one core free for the UI thread
+fn default_decode_threads() -> usize {
+    let cores = thread::available_parallelism()
+        .map(|cores| cores.get())
+        .unwrap_or(4);
 
-    app.last_marked_entry_index = None;
-    app.entries.lock().unwrap().clear();
-    let entries = Arc::clone(&app.entries);
-    let texture_manager = ctx.tex_manager();
-    let settings = app.settings.clone();
+    cores.saturating_sub(1).max(1)
+}
 
-    std::thread::spawn(move || {
-        let result = executor::block_on(task);
+fn load_files(
+    files: Vec<PathBuf>,
+    texture_manager: SharedTextureManager,
+    entries: Arc<Mutex<Vec<GalleryEntry>>>,
+    app_settings: Settings,
+    loading_count: Arc<AtomicUsize>,
+    load_epoch: Arc<AtomicUsize>,
+    visible_paths: Arc<Mutex<Vec<PathBuf>>>,
+) {
+    loading_count.fetch_add(1, Ordering::SeqCst);
 
-        match result {
-            Some(files) => {
-                let files = files.iter().map(|file| PathBuf::from(file)).collect();
-                let new_files = filter_media_files(process_entries(files));
+    let placeholder_texture = ImageEntry::loading_texture(texture_manager.clone());
 
-                load_files(new_files, texture_manager, entries, settings);
-            }
-            None => {
-                println!("No files selected");
-            }
-        }
-    });
-}
+    // Where each placeholder was put, so a decoded file doesn't search all entries for its own
+    let (epoch, placeholder_indices) = {
+        let mut entries = entries.lock().unwrap();
+        let placeholder_indices = files
+            .iter()
+            .enumerate()
+            .map(|(offset, file)| (file.clone(), entries.len() + offset))
+            .collect::<HashMap<PathBuf, usize>>();
 
-impl eframe::App for App {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        ctx.set_visuals(Visuals::dark());
+        entries.extend(
+            files
+                .iter()
+                .map(|file| GalleryEntry::placeholder(file, &placeholder_texture)),
+        );
 
-        // Check if we have dropped files that we need to load
-        if !self.dropped_files.is_empty() {
-            let dropped_files = self.dropped_files.clone();
-            let entries = Arc::clone(&self.entries);
-            let texture_manager = ctx.tex_manager();
-            let settings = self.settings.clone();
+        (load_epoch.load(Ordering::SeqCst), placeholder_indices)
+    };
 
-            self.dropped_files.clear();
+    thread::spawn(move || {
+        let decode_threads = app_settings.decode_threads;
+        let decode_timeout = match app_settings.decode_timeout_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        // Decodes of this load that were given up on but are still running
+        let hung_decodes = Arc::new(AtomicUsize::new(0));
+        let thumbnail_cache = app_settings.thumbnail_cache;
+        let max_cache_size = app_settings.max_cache_size_mb * 1024 * 1024;
 
-            load_files(dropped_files, texture_manager, entries, settings);
-        }
+        let visible_paths = app_settings
+            .prioritize_visible_decoding
+            .then_some(visible_paths);
+        let queue = DecodeQueue::new(files, visible_paths);
+        let queue = Mutex::new(queue);
+        let throughput = timings::Throughput::new();
+        // Decoded the first time a file fails, every other failed file shares it
+        let failed_texture = OnceLock::new();
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-            if self.current_entry.is_some() {
-                build_preview(self, &ctx, ui);
+        // Workers above the limit stop for the rest of the load once the UI frames get slow,
+        // at most one every second so a single long frame doesn't stop them all
+        let worker_limit = AtomicUsize::new(decode_threads);
+        let worker_limit_changed_at = Mutex::new(Instant::now());
+
+        let reduce_workers = || {
+            let frame_time = timings::frame_time();
+
+            if !app_settings.auto_tune_decode_threads || frame_time < timings::SLOW_FRAME_TIME {
                 return;
             }
 
-            ui.vertical_centered_justified(|ui| {
-                ui.horizontal(|ui| {
-                    let files_selector_btn = ui.button("Select files");
-                    ui.add_space(10.0);
+            let mut changed_at = worker_limit_changed_at.lock().unwrap();
+            let limit = worker_limit.load(Ordering::SeqCst);
 
-                    let folders_selector_btn = ui.button("Select folders");
-                    ui.add_space(10.0);
+            if changed_at.elapsed() < Duration::from_secs(1) || limit <= 1 {
+                return;
+            }
 
-                    if files_selector_btn.clicked() {
-                        handle_selector_button_click(ctx.clone(), self, true);
-                    }
+            *changed_at = Instant::now();
+            worker_limit.store(limit - 1, Ordering::SeqCst);
 
-                    if folders_selector_btn.clicked() {
-                        handle_selector_button_click(ctx.clone(), self, false);
-                    }
+            info!(
+                "UI frames take {:?}, decoding with {} threads",
+                frame_time,
+                limit - 1
+            );
+        };
 
-                    let settings_btn = ui.button("Settings");
+        let decode_file = |file: PathBuf| {
+            let max_thumbnail_size = thumbnail_decode_size(&app_settings);
+            let is_image = is_image_content(&file);
+            let modified = get_modified_time(&file);
+            let sidecar = Sidecar::load(&file);
 
-                    if settings_btn.clicked() {
-                        self.windows.push(EguiWindow {
-                            title: "Settings".to_string(),
-                            open: true,
-                        });
+            // Unreadable files would only fail inside the decoders with a less useful error
+            let mut error = check_readable(&file).err();
+
+            let (texture, hash, average_color, source_size) = match error {
+                Some(ref err) => {
+                    warn!("Skipping {:?}: {}", file, err);
+                    (None, None, None, None)
+                }
+                None => {
+                    let texture_manager = texture_manager.clone();
+                    let thumbnail_file = file.clone();
+                    let settings = app_settings.clone();
+
+                    let decode_started_at = Instant::now();
+                    let thumbnail = run_with_timeout(decode_timeout, &hung_decodes, move || {
+                        measure_time!(
+                            "load_thumbnail",
+                            ImageEntry::load_thumbnail_with_source(
+                                &texture_manager,
+                                &thumbnail_file,
+                                max_thumbnail_size,
+                                &settings,
+                            )
+                        )
+                    });
+
+                    match thumbnail {
+                        Ok(Some((thumbnail, source, source_size))) => {
+                            throughput.record(source, decode_started_at.elapsed());
+
+                            (
+                                thumbnail.get_texture(),
+                                Some(thumbnail.get_hash()),
+                                Some(thumbnail.get_average_color()),
+                                source_size,
+                            )
+                        }
+                        Ok(None) => (None, None, None, None),
+                        Err(err) => {
+                            warn!("Skipping {:?}: {}", file, err);
+                            error = Some(err);
+
+                            (None, None, None, None)
+                        }
                     }
+                }
+            };
 
-                    ui.add_space(10.0);
+            let corruption = match error {
+                None if is_image => ImageEntry::detect_corruption(&file, texture.is_some()),
+                _ => None,
+            };
 
-                    let number_of_images_label =
-                        format!("Entries: {}", self.entries.lock().unwrap().len());
-                    ui.label(number_of_images_label);
-                });
+            if let Some(reason) = &corruption {
+                warn!("{}: {:?}", reason, file);
+            }
 
-                for window in self.windows.iter_mut() {
-                    egui::Window::new(window.title.clone())
-                        .open(&mut window.open)
-                        .resizable(true)
-                        .max_width(400.0)
-                        .max_height(250.0)
-                        .show(ui.ctx(), |ui| {
-                            ui.add(
-                                egui::Slider::new(&mut self.settings.min_thumbnail_size, 100..=512)
-                                    .text("Min thumbnail size"),
-                            );
-                            ui.add(
-                                egui::Slider::new(&mut self.settings.max_columns_count, 1..=10)
-                                    .text("Max columns count"),
-                            );
+            let corrupt = corruption.is_some();
+            error = error.or(corruption);
 
-                            ui.checkbox(
-                                &mut self.settings.show_failed_files,
-                                "Show images that failed to load",
-                            );
+            let media_type = if !is_image {
+                MediaType::Video
+            } else if texture.is_some() && ImageEntry::is_animated_file(&file) {
+                MediaType::ImageAnimated
+            } else {
+                MediaType::ImageStill
+            };
 
-                            ui.allocate_space(ui.available_size());
-                        });
+            // The decoder saw the original already, only cached thumbnails need the header read.
+            // Files that couldn't be decoded most likely have no readable header either
+            let dimensions = match texture {
+                Some(_) if source_size.is_some() => source_size,
+                Some(_) if app_settings.entry_label == EntryLabel::Resolution => {
+                    export::read_dimensions(&file, &media_type, &app_settings)
                 }
+                _ => None,
+            };
 
-                ui.add_space(10.0);
+            if texture.is_none() && error.is_none() {
+                error!("Failed to load texture for file: {:?}", file);
+            }
 
-                if !self.entries.lock().unwrap().is_empty() {
-                    build_grid(self, &ctx, ui);
+            // Failed files are kept even when they aren't shown, so they can be counted
+            // and brought back without loading everything again
+            let mut loaded_entry = GalleryEntry {
+                path: file.clone(),
+                failed: texture.is_none(),
+                thumbnail: match texture {
+                    Some(texture) => texture,
+                    None => failed_texture
+                        .get_or_init(|| {
+                            ImageEntry::default_texture(texture_manager.clone(), &app_settings)
+                        })
+                        .clone(),
+                },
+                media_type,
+                marked: false,
+                marked_sequence: 0,
+                hash,
+                average_color,
+                duplicate_group: None,
+                modified,
+                dimensions,
+                file_size: std::fs::metadata(&file).map(|metadata| metadata.len()).ok(),
+                rating: sidecar.rating,
+                tags: sidecar.tags,
+                error,
+                corrupt,
+                loading: false,
+                missing: false,
+                manual_order: usize::MAX,
+                frame: None,
+            };
+
+            let mut entries = entries.lock().unwrap();
+
+            if is_superseded(&load_epoch, epoch) {
+                return;
+            }
+
+            // The placeholder only has to be searched for if the entries were sorted or removed
+            // in the meantime, it's gone if they were refreshed
+            let is_placeholder = |entry: &GalleryEntry| entry.loading && entry.path == file;
+            let placeholder_index = placeholder_indices
+                .get(&file)
+                .copied()
+                .filter(|index| entries.get(*index).is_some_and(is_placeholder))
+                .or_else(|| entries.iter().position(is_placeholder));
+
+            if let Some(index) = placeholder_index {
+                loaded_entry.marked = entries[index].marked;
+                loaded_entry.marked_sequence = entries[index].marked_sequence;
+                loaded_entry.manual_order = entries[index].manual_order;
+                entries[index] = loaded_entry;
+            }
+        };
+
+        // Every worker takes the next file from the queue, so the order can change while
+        // the files are loading
+        let decode_files = || {
+            rayon::scope(|scope| {
+                for worker in 0..decode_threads {
+                    let (queue, decode_file, reduce_workers, worker_limit, load_epoch) = (
+                        &queue,
+                        &decode_file,
+                        &reduce_workers,
+                        &worker_limit,
+                        &load_epoch,
+                    );
+
+                    scope.spawn(move |_| loop {
+                        if is_shutting_down() || is_superseded(load_epoch, epoch) {
+                            return;
+                        }
+
+                        reduce_workers();
+
+                        if worker >= worker_limit.load(Ordering::SeqCst) {
+                            return;
+                        }
+
+                        let file = queue.lock().unwrap().next();
+
+                        match file {
+                            Some(file) => decode_file(file),
+                            None => return,
+                        }
+                    });
                 }
-            });
-        });
-    }
+            })
+        };
+
+        // A dedicated pool keeps the loading from taking every core away from the UI
+        match rayon::ThreadPoolBuilder::new()
+            .num_threads(decode_threads)
+            .build()
+        {
+            Ok(pool) => pool.install(decode_files),
+            Err(err) => {
+                warn!(
+                    "Failed to create decode thread pool, using the global one: {}",
+                    err
+                );
+                decode_files();
+            }
+        }
+
+        throughput.log();
+
+        if thumbnail_cache {
+            thumbnail_cache::evict(max_cache_size);
+        }
+
+        loading_count.fetch_sub(1, Ordering::SeqCst);
+    });
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let media_files = filter_media_files(process_entries(filter_valid_paths(args)));
+// Downloads are loaded like local files, the ones that fail still get an entry,
+// so it's visible which urls didn't work and why
+fn load_urls(
+    urls: Vec<String>,
+    texture_manager: SharedTextureManager,
+    entries: Arc<Mutex<Vec<GalleryEntry>>>,
+    app_settings: Settings,
+    loading_count: Arc<AtomicUsize>,
+    load_epoch: Arc<AtomicUsize>,
+    visible_paths: Arc<Mutex<Vec<PathBuf>>>,
+) {
+    // Counted as loading while the downloads run, load_files counts the decoding on its own
+    loading_count.fetch_add(1, Ordering::SeqCst);
+
+    let epoch = load_epoch.load(Ordering::SeqCst);
+
+    thread::spawn(move || {
+        let failed_texture = OnceLock::new();
+
+        let add_failed = |url: &str, error: String| {
+            error!("{}: {}", error, url);
+
+            let thumbnail = failed_texture.get_or_init(|| {
+                ImageEntry::default_texture(texture_manager.clone(), &app_settings)
+            });
+            let mut entries = entries.lock().unwrap();
+
+            if !is_superseded(&load_epoch, epoch) {
+                entries.push(GalleryEntry::failed_url(url, thumbnail, error));
+            }
+        };
+
+        let mut downloads = Vec::new();
+
+        for url in urls {
+            if is_shutting_down() || is_superseded(&load_epoch, epoch) {
+                break;
+            }
+
+            match download::download(&url) {
+                Ok(file) => downloads.push((url, file)),
+                Err(err) => add_failed(&url, err),
+            }
+        }
+
+        // Pages and other files that turned out not to be media would vanish without a word
+        let files = filter_media_files(
+            downloads.iter().map(|(_, file)| file.clone()).collect(),
+            &app_settings,
+        );
+
+        let kept = files.iter().collect::<HashSet<&PathBuf>>();
+
+        for (url, file) in &downloads {
+            if !kept.contains(file) {
+                let error = "Downloaded file is not a supported media file".to_string();
+                add_failed(url, error);
+            }
+        }
+
+        if !is_shutting_down() && !is_superseded(&load_epoch, epoch) {
+            load_files(
+                files,
+                texture_manager.clone(),
+                Arc::clone(&entries),
+                app_settings.clone(),
+                Arc::clone(&loading_count),
+                Arc::clone(&load_epoch),
+                visible_paths,
+            );
+        }
+
+        loading_count.fetch_sub(1, Ordering::SeqCst);
+    });
+}
+
+fn get_modified_time(file: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(file)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// Walks the roots again, keeping the thumbnails of files that didn't change since they were loaded
+fn refresh_files(
+    roots: Vec<PathBuf>,
+    texture_manager: SharedTextureManager,
+    entries: Arc<Mutex<Vec<GalleryEntry>>>,
+    app_settings: Settings,
+    loading_count: Arc<AtomicUsize>,
+    load_epoch: Arc<AtomicUsize>,
+    visible_paths: Arc<Mutex<Vec<PathBuf>>>,
+) {
+    let epoch = load_epoch.load(Ordering::SeqCst);
+
+    thread::spawn(move || {
+        let roots = roots.into_iter().filter(|root| root.exists()).collect();
+        let files = filter_media_files(
+            process_entries(roots, app_settings.follow_symlinks),
+            &app_settings,
+        )
+        .into_iter()
+        .collect::<HashSet<PathBuf>>();
+
+        if is_shutting_down() {
+            return;
+        }
+
+        let new_files = {
+            let mut entries = entries.lock().unwrap();
+
+            if is_superseded(&load_epoch, epoch) {
+                return;
+            }
+
+            entries.retain(|entry| {
+                files.contains(&entry.path) && get_modified_time(&entry.path) == entry.modified
+            });
+
+            let known_files = entries
+                .iter()
+                .map(|entry| entry.path.clone())
+                .collect::<HashSet<PathBuf>>();
+
+            files
+                .into_iter()
+                .filter(|file| !known_files.contains(file))
+                .collect::<Vec<PathBuf>>()
+        };
+
+        info!("Refresh found {} new or changed files", new_files.len());
+
+        load_files(
+            new_files,
+            texture_manager,
+            entries,
+            app_settings,
+            loading_count,
+            load_epoch,
+            visible_paths,
+        );
+    });
+}
+
+// Files that are still being copied would be decoded half written, so a new file is only loaded
+// once its size stayed the same between two checks
+const WATCH_SETTLE_INTERVAL: Duration = Duration::from_millis(500);
+
+// Keeps the entries in sync with the watched folders, new media files are loaded
+// like any other file and entries of removed files are dropped
+fn watch_folders(
+    roots: Vec<PathBuf>,
+    texture_manager: SharedTextureManager,
+    entries: Arc<Mutex<Vec<GalleryEntry>>>,
+    app_settings: Settings,
+    loading_count: Arc<AtomicUsize>,
+    load_epoch: Arc<AtomicUsize>,
+    visible_paths: Arc<Mutex<Vec<PathBuf>>>,
+) -> Option<FolderWatcher> {
+    // New files with the size they had at the last check, None before the first one
+    let settling = Arc::new(Mutex::new(HashMap::<PathBuf, Option<u64>>::new()));
+
+    // Only the watcher holds on to the files, so the thread ends along with it
+    let settling_files = Arc::downgrade(&settling);
+    let watched_entries = Arc::clone(&entries);
+    let watcher_settings = app_settings.clone();
+
+    thread::spawn(move || loop {
+        thread::sleep(WATCH_SETTLE_INTERVAL);
+
+        let settling = match settling_files.upgrade() {
+            Some(settling) if !is_shutting_down() => settling,
+            _ => return,
+        };
+
+        let mut settled_files = Vec::new();
+
+        settling.lock().unwrap().retain(|file, last_size| {
+            let size = match std::fs::metadata(file) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => return false,
+            };
+
+            if *last_size == Some(size) {
+                settled_files.push(file.clone());
+                return false;
+            }
+
+            *last_size = Some(size);
+            true
+        });
+
+        if !settled_files.is_empty() {
+            load_files(
+                settled_files,
+                texture_manager.clone(),
+                Arc::clone(&entries),
+                app_settings.clone(),
+                Arc::clone(&loading_count),
+                Arc::clone(&load_epoch),
+                Arc::clone(&visible_paths),
+            );
+        }
+    });
+
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if is_shutting_down() {
+            return;
+        }
+
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                error!("Folder watcher error: {:?}", err);
+                return;
+            }
+        };
+
+        match event.kind {
+            EventKind::Create(_)
+            | EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Name(_)) => {}
+            _ => return,
+        }
+
+        let mut new_paths = Vec::new();
+
+        for path in event.paths {
+            // Removing a folder only reports the folder itself, not the files inside it
+            if !path.exists() {
+                watched_entries
+                    .lock()
+                    .unwrap()
+                    .retain(|entry| !entry.path.starts_with(&path));
+                settling
+                    .lock()
+                    .unwrap()
+                    .retain(|file, _| !file.starts_with(&path));
+
+                continue;
+            }
+
+            if !watcher_settings.follow_symlinks && is_symlink(&path) {
+                continue;
+            }
+
+            new_paths.push(path);
+        }
+
+        // Folders moved into a watched folder are walked like the ones selected by hand
+        let new_files = filter_media_files(
+            process_entries(new_paths, watcher_settings.follow_symlinks),
+            &watcher_settings,
+        );
+
+        if new_files.is_empty() {
+            return;
+        }
+
+        let known_files = watched_entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect::<HashSet<PathBuf>>();
+
+        let mut settling = settling.lock().unwrap();
+
+        for file in new_files {
+            if !known_files.contains(&file) {
+                settling.entry(file).or_insert(None);
+            }
+        }
+    });
+
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            error!("Failed to create folder watcher: {:?}", err);
+            return None;
+        }
+    };
+
+    for root in roots.iter().filter(|root| root.is_dir()) {
+        if let Err(err) = watcher.watch(root, RecursiveMode::Recursive) {
+            error!("Failed to watch folder {:?}: {:?}", root, err);
+        }
+    }
+
+    Some(FolderWatcher {
+        roots,
+        _watcher: watcher,
+    })
+}
+
+fn find_group(groups: &mut Vec<usize>, index: usize) -> usize {
+    let mut root = index;
+
+    while groups[root] != root {
+        root = groups[root];
+    }
+
+    groups[index] = root;
+
+    root
+}
+
+// Groups entries whose hashes are within `threshold` bits of each other and marks
+// every entry that has at least one likely duplicate
+fn mark_duplicates(entries: &mut [GalleryEntry], threshold: u32) {
+    let mut groups: Vec<usize> = (0..entries.len()).collect();
+
+    for i in 0..entries.len() {
+        let hash = match entries[i].hash {
+            Some(hash) => hash,
+            None => continue,
+        };
+
+        for j in (i + 1)..entries.len() {
+            let other_hash = match entries[j].hash {
+                Some(hash) => hash,
+                None => continue,
+            };
+
+            if (hash ^ other_hash).count_ones() <= threshold {
+                let group = find_group(&mut groups, i);
+                let other_group = find_group(&mut groups, j);
+
+                groups[other_group] = group;
+            }
+        }
+    }
+
+    let roots = (0..entries.len())
+        .map(|i| find_group(&mut groups, i))
+        .collect::<Vec<usize>>();
+
+    let mut group_sizes = vec![0; entries.len()];
+
+    for root in roots.iter() {
+        group_sizes[*root] += 1;
+    }
+
+    let mut group_numbers: Vec<Option<usize>> = vec![None; entries.len()];
+    let mut groups_count = 0;
+
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let root = roots[i];
+
+        if group_sizes[root] < 2 {
+            entry.duplicate_group = None;
+            continue;
+        }
+
+        let group_number = match group_numbers[root] {
+            Some(number) => number,
+            None => {
+                groups_count += 1;
+                group_numbers[root] = Some(groups_count);
+
+                groups_count
+            }
+        };
+
+        entry.duplicate_group = Some(group_number);
+        entry.set_marked(true);
+    }
+
+    info!("Found {} groups of likely duplicates", groups_count);
+}
+
+fn build_extensions_list(ui: &mut egui::Ui, label: &str, extensions: &mut [(String, bool)]) {
+    ui.collapsing(label, |ui| {
+        ui.horizontal(|ui| {
+            if ui.button("Enable all").clicked() {
+                extensions
+                    .iter_mut()
+                    .for_each(|(_, enabled)| *enabled = true);
+            }
+
+            if ui.button("Disable all").clicked() {
+                extensions
+                    .iter_mut()
+                    .for_each(|(_, enabled)| *enabled = false);
+            }
+        });
+
+        ui.horizontal_wrapped(|ui| {
+            for (extension, enabled) in extensions.iter_mut() {
+                ui.checkbox(enabled, extension.as_str());
+            }
+        });
+    });
+}
+
+// Returns the folder the user asked to regenerate the thumbnails for
+fn build_settings_window(
+    settings: &mut Settings,
+    roots: &[PathBuf],
+    ui: &mut egui::Ui,
+) -> Option<PathBuf> {
+    ui.add(
+        egui::Slider::new(&mut settings.min_thumbnail_size, 100..=512).text("Min thumbnail size"),
+    );
+    ui.add(
+        egui::Slider::new(&mut settings.thumbnail_decode_size, 128..=2048)
+            .text("Thumbnail decode size"),
+    );
+    ui.add(
+        egui::Slider::new(
+            &mut settings.decode_threads,
+            1..=thread::available_parallelism()
+                .map(|cores| cores.get())
+                .unwrap_or(4),
+        )
+        .text("Decode threads"),
+    );
+    ui.checkbox(
+        &mut settings.auto_tune_decode_threads,
+        "Use fewer decode threads while the UI is slow",
+    );
+    ui.checkbox(
+        &mut settings.prioritize_visible_decoding,
+        "Decode visible thumbnails first",
+    );
+    ui.add(
+        egui::Slider::new(&mut settings.decode_timeout_secs, 0..=120)
+            .suffix(" s")
+            .text("Thumbnail decode timeout"),
+    )
+    .on_hover_text("0 waits for the decoder however long it takes");
+    ui.add(egui::Slider::new(&mut settings.max_columns_count, 1..=10).text("Max columns count"));
+
+    egui::ComboBox::from_label("Grid layout")
+        .selected_text(settings.grid_layout.label())
+        .show_ui(ui, |ui| {
+            for grid_layout in [GridLayout::Uniform, GridLayout::Justified] {
+                ui.selectable_value(&mut settings.grid_layout, grid_layout, grid_layout.label());
+            }
+        });
+
+    egui::ComboBox::from_label("Thumbnail fit")
+        .selected_text(settings.thumbnail_fit.label())
+        .show_ui(ui, |ui| {
+            for fit in [
+                ThumbnailFit::Contain,
+                ThumbnailFit::Cover,
+                ThumbnailFit::Square,
+            ] {
+                ui.selectable_value(&mut settings.thumbnail_fit, fit, fit.label());
+            }
+        });
+
+    egui::ComboBox::from_label("Corner label")
+        .selected_text(settings.entry_label.label())
+        .show_ui(ui, |ui| {
+            for label in [
+                EntryLabel::Extension,
+                EntryLabel::Resolution,
+                EntryLabel::FileSize,
+                EntryLabel::Hidden,
+            ] {
+                ui.selectable_value(&mut settings.entry_label, label, label.label());
+            }
+        });
+
+    ui.add(
+        egui::Slider::new(&mut settings.thumbnail_corner_radius, 0.0..=24.0)
+            .text("Thumbnail corner radius"),
+    );
+
+    egui::ComboBox::from_label("Loading placeholder")
+        .selected_text(settings.loading_style.label())
+        .show_ui(ui, |ui| {
+            for style in [LoadingStyle::Spinner, LoadingStyle::Skeleton] {
+                ui.selectable_value(&mut settings.loading_style, style, style.label());
+            }
+        });
+
+    ui.checkbox(&mut settings.invert_scroll, "Invert scroll direction");
+    ui.checkbox(&mut settings.momentum_scrolling, "Momentum scrolling");
+    ui.checkbox(
+        &mut settings.show_grid_minimap,
+        "Show a minimap of the grid next to the scrollbar",
+    );
+
+    egui::ComboBox::from_label("Interaction")
+        .selected_text(settings.interaction_model.label())
+        .show_ui(ui, |ui| {
+            for model in [
+                InteractionModel::RightClickOpens,
+                InteractionModel::DoubleClickOpens,
+            ] {
+                ui.selectable_value(&mut settings.interaction_model, model, model.label());
+            }
+        });
+
+    egui::ComboBox::from_label("Mouse wheel in the preview")
+        .selected_text(settings.preview_wheel.label())
+        .show_ui(ui, |ui| {
+            for wheel in [PreviewWheel::Zoom, PreviewWheel::Navigate] {
+                ui.selectable_value(&mut settings.preview_wheel, wheel, wheel.label());
+            }
+        });
+
+    ui.checkbox(
+        &mut settings.remember_zoom,
+        "Remember the zoom of each image instead of fitting it to the window",
+    );
+
+    egui::ComboBox::from_label("Selection style")
+        .selected_text(settings.selection_style.label())
+        .show_ui(ui, |ui| {
+            for style in [
+                SelectionStyle::Border,
+                SelectionStyle::Dim,
+                SelectionStyle::Checkmark,
+            ] {
+                ui.selectable_value(&mut settings.selection_style, style, style.label());
+            }
+        });
+
+    ui.horizontal(|ui| {
+        ui.color_edit_button_srgba(&mut settings.selection_color);
+        ui.label("Selection color");
+    });
+
+    ui.add_enabled(
+        settings.selection_style == SelectionStyle::Border,
+        egui::Slider::new(&mut settings.selection_thickness, 1.0..=8.0).text("Selection thickness"),
+    );
+
+    ui.add_enabled(
+        settings.selection_style != SelectionStyle::Checkmark,
+        egui::Checkbox::new(
+            &mut settings.selection_checkmark,
+            "Show a checkmark on selected thumbnails",
+        ),
+    );
+
+    ui.checkbox(
+        &mut settings.show_selection_order,
+        "Number selected thumbnails in the order they were selected",
+    );
+
+    ui.add(
+        egui::Slider::new(&mut settings.overlay_font_size, 8.0..=32.0).text("Overlay font size"),
+    );
+
+    ui.horizontal(|ui| {
+        ui.color_edit_button_srgba(&mut settings.overlay_color);
+        ui.label("Overlay text color");
+    });
+
+    egui::ComboBox::from_label("Overlay corner")
+        .selected_text(settings.overlay_corner.label())
+        .show_ui(ui, |ui| {
+            for corner in OverlayCorner::ALL {
+                ui.selectable_value(&mut settings.overlay_corner, corner, corner.label());
+            }
+        });
+
+    ui.horizontal(|ui| {
+        ui.color_edit_button_srgba(&mut settings.thumbnail_label_color);
+        ui.label("Thumbnail extension color");
+    });
+
+    ui.horizontal(|ui| {
+        ui.color_edit_button_srgba(&mut settings.video_backdrop_color);
+        ui.label("Video backdrop color");
+    });
+
+    egui::ComboBox::from_label("Video fit")
+        .selected_text(settings.video_fit.label())
+        .show_ui(ui, |ui| {
+            for fit in [VideoFit::Contain, VideoFit::Cover] {
+                ui.selectable_value(&mut settings.video_fit, fit, fit.label());
+            }
+        });
+
+    ui.checkbox(
+        &mut settings.nearest_preview_filter,
+        "Use nearest-neighbour filtering in the preview",
+    );
+
+    ui.checkbox(
+        &mut settings.show_failed_files,
+        "Show images that failed to load",
+    );
+
+    ui.horizontal(|ui| {
+        ui.label("Placeholder image");
+        ui.add(
+            egui::TextEdit::singleline(&mut settings.placeholder_path)
+                .hint_text("Built-in placeholder"),
+        );
+    });
+
+    ui.add(
+        egui::Slider::new(&mut settings.duplicate_threshold, 0..=20).text("Duplicate threshold"),
+    );
+
+    ui.add(egui::Slider::new(&mut settings.min_rating, 0..=5).text("Minimum rating"));
+
+    egui::ComboBox::from_label("Sort by")
+        .selected_text(settings.sort_mode.label())
+        .show_ui(ui, |ui| {
+            for sort_mode in [SortMode::Path, SortMode::Manual] {
+                ui.selectable_value(&mut settings.sort_mode, sort_mode, sort_mode.label());
+            }
+        });
+
+    ui.checkbox(&mut settings.sort_descending, "Sort in descending order");
+    ui.checkbox(&mut settings.group_by_type, "Group by media type");
+
+    ui.checkbox(
+        &mut settings.delete_permanently,
+        "Delete files permanently instead of moving them to the trash",
+    );
+
+    ui.checkbox(
+        &mut settings.apply_exif_orientation,
+        "Apply EXIF orientation",
+    );
+
+    ui.checkbox(
+        &mut settings.color_management,
+        "Convert embedded color profiles to sRGB",
+    );
+
+    ui.checkbox(
+        &mut settings.cap_preview_resolution,
+        "Limit the resolution of the preview",
+    );
+
+    if settings.cap_preview_resolution {
+        ui.add(
+            egui::Slider::new(&mut settings.max_preview_resolution, 1024..=16384)
+                .text("Maximum preview resolution (px)"),
+        );
+    }
+
+    ui.checkbox(
+        &mut settings.watch_folders,
+        "Watch loaded folders for changes",
+    );
+
+    ui.checkbox(
+        &mut settings.follow_symlinks,
+        "Follow symlinks when scanning folders",
+    );
+
+    ui.checkbox(
+        &mut settings.autoplay_videos,
+        "Start playing videos when opened",
+    );
+
+    ui.checkbox(
+        &mut settings.disable_audio,
+        "Disable audio (applies to videos opened afterwards)",
+    );
+
+    ui.checkbox(
+        &mut settings.pause_video_on_focus_loss,
+        "Pause videos when the window loses focus",
+    );
+
+    ui.checkbox(
+        &mut settings.autohide_video_controls,
+        "Hide the video controls while the mouse is still",
+    );
+
+    if settings.autohide_video_controls {
+        ui.add(
+            egui::Slider::new(&mut settings.video_controls_hide_delay, 1.0..=10.0)
+                .text("Hide video controls after (s)"),
+        );
+    }
+
+    ui.add(
+        egui::Slider::new(&mut settings.fallback_frame_rate, 1.0..=120.0)
+            .text("Fallback video frame rate (fps)"),
+    );
+
+    ui.add(
+        egui::Slider::new(&mut settings.min_frame_delay, 0.0..=100.0)
+            .text("Minimum animation frame delay (ms)"),
+    );
+
+    ui.add(
+        egui::Slider::new(&mut settings.max_animation_fps, 1.0..=144.0)
+            .text("Maximum animation FPS"),
+    );
+
+    ui.checkbox(
+        &mut settings.animate_thumbnails,
+        "Play animated images in the grid when hovered",
+    );
+
+    ui.add_enabled(
+        settings.animate_thumbnails,
+        egui::Slider::new(&mut settings.max_animated_thumbnails, 1..=16)
+            .text("Animated thumbnails at once"),
+    );
+
+    ui.checkbox(
+        &mut settings.pace_video_repaints,
+        "Pace video repaints to the frame rate",
+    );
+
+    ui.checkbox(
+        &mut settings.standby,
+        "Pause animations and videos while minimized",
+    );
+
+    ui.add_enabled(
+        settings.standby,
+        egui::Checkbox::new(
+            &mut settings.standby_when_unfocused,
+            "Also pause them while the window is unfocused",
+        ),
+    );
+
+    ui.horizontal(|ui| {
+        ui.label("Ask before loading more than");
+        ui.add(
+            egui::DragValue::new(&mut settings.max_file_count)
+                .speed(100)
+                .clamp_range(1..=1_000_000),
+        );
+        ui.label("files");
+    });
+
+    ui.checkbox(&mut settings.tone_map_hdr, "Tone map HDR images");
+
+    ui.add_enabled(
+        settings.tone_map_hdr,
+        egui::Slider::new(&mut settings.hdr_exposure, -5.0..=5.0).text("HDR exposure"),
+    );
+    ui.add_enabled(
+        settings.tone_map_hdr,
+        egui::Slider::new(&mut settings.hdr_gamma, 1.0..=3.0).text("HDR gamma"),
+    );
+
+    ui.add(egui::Slider::new(&mut settings.raw_exposure, -5.0..=5.0).text("RAW exposure"));
+
+    egui::ComboBox::from_label("RAW color space")
+        .selected_text(settings.raw_color_space.label())
+        .show_ui(ui, |ui| {
+            for color_space in [RawColorSpace::Srgb, RawColorSpace::Linear] {
+                ui.selectable_value(
+                    &mut settings.raw_color_space,
+                    color_space,
+                    color_space.label(),
+                );
+            }
+        });
+
+    ui.collapsing("Grid density presets", |ui| {
+        for ((min_thumbnail_size, max_columns_count), name) in settings
+            .density_presets
+            .iter_mut()
+            .zip(["Small", "Medium", "Large"])
+        {
+            ui.horizontal(|ui| {
+                ui.label(name);
+                ui.add(
+                    egui::DragValue::new(min_thumbnail_size)
+                        .clamp_range(100..=512)
+                        .suffix(" px"),
+                );
+                ui.add(
+                    egui::DragValue::new(max_columns_count)
+                        .clamp_range(1..=10)
+                        .suffix(" columns"),
+                );
+            });
+        }
+    });
+
+    ui.collapsing("Image decoders", |ui| {
+        let decoders_count = settings.decoders.len();
+        let mut swap = None;
+
+        for (index, (decoder, enabled)) in settings.decoders.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                if ui.add_enabled(index > 0, egui::Button::new("⏶")).clicked() {
+                    swap = Some((index, index - 1));
+                }
+
+                if ui
+                    .add_enabled(index + 1 < decoders_count, egui::Button::new("⏷"))
+                    .clicked()
+                {
+                    swap = Some((index, index + 1));
+                }
+
+                ui.checkbox(enabled, decoder.label());
+            });
+        }
+
+        if let Some((from, to)) = swap {
+            settings.decoders.swap(from, to);
+        }
+
+        ui.checkbox(
+            &mut settings.full_decoder_chain,
+            "Try every decoder on every file, including FFmpeg for thumbnails",
+        );
+    });
+
+    build_extensions_list(ui, "Image formats", &mut settings.image_extensions);
+    build_extensions_list(ui, "Video formats", &mut settings.video_extensions);
+
+    ui.collapsing("Contact sheet", |ui| {
+        ui.add(egui::Slider::new(&mut settings.contact_sheet_columns, 1..=16).text("Columns"));
+        ui.add(
+            egui::Slider::new(&mut settings.contact_sheet_spacing, 0..=64)
+                .text("Spacing")
+                .suffix(" px"),
+        );
+        ui.checkbox(&mut settings.contact_sheet_captions, "File name captions");
+    });
+
+    ui.separator();
+
+    ui.checkbox(&mut settings.thumbnail_cache, "Cache thumbnails on disk");
+
+    // Lossy thumbnails take the least space, pixel art looks better cached losslessly
+    egui::ComboBox::from_label("Cache encoding")
+        .selected_text(settings.thumbnail_cache_encoding.label())
+        .show_ui(ui, |ui| {
+            for encoding in [
+                CacheEncoding::Raw,
+                CacheEncoding::Png,
+                CacheEncoding::Jpeg,
+                CacheEncoding::WebP,
+            ] {
+                ui.selectable_value(
+                    &mut settings.thumbnail_cache_encoding,
+                    encoding,
+                    encoding.label(),
+                );
+            }
+        });
+
+    if settings.thumbnail_cache_encoding == CacheEncoding::Jpeg {
+        ui.add(
+            egui::Slider::new(&mut settings.thumbnail_cache_quality, 1..=100)
+                .text("Cache JPEG quality"),
+        );
+    }
+
+    ui.add(
+        egui::Slider::new(&mut settings.max_cache_size_mb, 64..=8192).text("Max cache size (MB)"),
+    );
+
+    ui.horizontal(|ui| {
+        ui.label(format!(
+            "Cache size: {}",
+            format_bytes(thumbnail_cache::cache_size() as usize)
+        ));
+
+        if ui.button("Clear cache").clicked() {
+            thumbnail_cache::clear();
+        }
+    });
+
+    ui.separator();
+
+    ui.add_enabled(
+        isolated_decoder::is_available(),
+        egui::Checkbox::new(
+            &mut settings.isolate_ffmpeg_thumbnails,
+            "Decode video thumbnails in a separate process",
+        ),
+    )
+    .on_disabled_hover_text("The isolated_ffmpeg_decoder executable was not found");
+
+    if settings.isolate_ffmpeg_thumbnails {
+        ui.horizontal(|ui| {
+            ui.label("Decoder results folder");
+            ui.add(
+                egui::TextEdit::singleline(&mut settings.isolated_decoder_dir)
+                    .hint_text("Temp folder"),
+            );
+        });
+
+        ui.add(
+            egui::Slider::new(&mut settings.isolated_decoder_max_output_mb, 1..=512)
+                .text("Max decoder result size (MB)"),
+        );
+    }
+
+    let mut regenerate_folder = None;
+
+    for root in roots.iter().filter(|root| root.is_dir()) {
+        ui.horizontal(|ui| {
+            if ui.button("Regenerate thumbnails").clicked() {
+                regenerate_folder = Some(root.clone());
+            }
+
+            ui.label(root.to_string_lossy());
+        });
+    }
+
+    regenerate_folder
+}
+
+fn build_stats_window(
+    entries: &[GalleryEntry],
+    current_entry: Option<&CurrentEntry>,
+    ui: &mut egui::Ui,
+) {
+    let thumbnails_memory: usize = entries
+        .iter()
+        .map(|entry| get_texture_memory(&entry.thumbnail))
+        .sum();
+
+    let current_entry_memory = match current_entry {
+        Some(current_entry) => {
+            let image_memory = current_entry
+                .image
+                .as_ref()
+                .map(|image| image.get_texture_memory())
+                .unwrap_or(0);
+
+            let video_memory = current_entry
+                .video
+                .as_ref()
+                .map(|video| video.get_texture_memory())
+                .unwrap_or(0);
+
+            image_memory + video_memory
+        }
+        None => 0,
+    };
+
+    // Textures are uploaded as RGBA8, so this is close to what ends up in VRAM
+    ui.label(format!(
+        "Thumbnails: {} ({} textures)",
+        format_bytes(thumbnails_memory),
+        entries.len()
+    ));
+    ui.label(format!(
+        "Current entry: {}",
+        format_bytes(current_entry_memory)
+    ));
+    ui.label(format!(
+        "Total: {}",
+        format_bytes(thumbnails_memory + current_entry_memory)
+    ));
+
+    // Counts both thumbnails and previews, so a file opened in the preview is counted twice
+    ui.separator();
+
+    let decoder_totals = decode_stats::by_decoder();
+
+    if decoder_totals.is_empty() {
+        ui.label("No images decoded yet");
+    }
+
+    for (decoder, count) in decoder_totals {
+        ui.label(format!("{}: {} decoded", decoder, count));
+    }
+
+    for (format, format_stats) in decode_stats::by_format() {
+        let mut parts = format_stats
+            .decoded
+            .iter()
+            .map(|(decoder, count)| format!("{} {}", count, decoder))
+            .collect::<Vec<String>>();
+
+        if format_stats.failed > 0 {
+            parts.push(format!("{} failed", format_stats.failed));
+        }
+
+        ui.monospace(format!("{}: {}", format, parts.join(", ")));
+    }
+
+    if ui.button("Reset decode stats").clicked() {
+        decode_stats::reset();
+    }
+}
+
+fn handle_export_button_click(app: &mut App) {
+    let task = AsyncFileDialog::new()
+        .set_file_name("entries.json")
+        .add_filter("JSON", &["json"])
+        .add_filter("CSV", &["csv"])
+        .save_file();
+
+    let entries = Arc::clone(&app.entries);
+
+    std::thread::spawn(move || match executor::block_on(task) {
+        Some(file) => {
+            // Probing resolutions and durations can take a while, so don't hold the lock for it
+            let entries = entries.lock().unwrap().clone();
+
+            if let Err(err) = export::export_entries(&entries, &PathBuf::from(file)) {
+                error!("Error exporting entries: {}", err);
+            }
+        }
+        None => {
+            info!("No export file selected");
+        }
+    });
+}
+
+// Composes the thumbnails of the marked entries into one image, in the order they were marked.
+// build_contact_sheet_window follows it
+fn handle_contact_sheet_click(app: &mut App, ctx: &egui::Context) {
+    if app.contact_sheet_export.is_some() {
+        return;
+    }
+
+    // Files the preview was told to open with a specific decoder get their thumbnail from it
+    let mut marked = app
+        .entries
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|entry| entry.marked)
+        .map(|entry| {
+            let forced_decoder = app.decoder_overrides.get(&entry.path).copied();
+
+            (entry.marked_sequence, (entry.path.clone(), forced_decoder))
+        })
+        .collect::<Vec<(u64, (PathBuf, Option<ImageDecoder>))>>();
+
+    marked.sort_by_key(|(sequence, _)| *sequence);
+
+    let files = marked
+        .into_iter()
+        .map(|(_, file)| file)
+        .collect::<Vec<(PathBuf, Option<ImageDecoder>)>>();
+
+    if files.is_empty() {
+        info!("No marked entries for the contact sheet");
+        return;
+    }
+
+    let task = AsyncFileDialog::new()
+        .set_file_name("contact_sheet.png")
+        .add_filter("PNG", &["png"])
+        .save_file();
+
+    let settings = app.settings.clone();
+    let progress = Arc::new(export::ContactSheetExport::default());
+    let ctx = ctx.clone();
+
+    app.contact_sheet_export = Some(Arc::clone(&progress));
+
+    std::thread::spawn(move || {
+        match executor::block_on(task) {
+            Some(file) => {
+                let destination = PathBuf::from(file);
+
+                progress.started.store(true, Ordering::SeqCst);
+                ctx.request_repaint();
+
+                if let Err(err) = export::export_contact_sheet(&files, &destination, &settings) {
+                    error!("Error exporting contact sheet: {}", err);
+                    *progress.error.lock().unwrap() = Some(err);
+                }
+            }
+            None => {
+                info!("No contact sheet file selected");
+            }
+        }
+
+        progress.finished.store(true, Ordering::SeqCst);
+        ctx.request_repaint();
+    });
+}
+
+fn handle_collection_export_click(app: &mut App) {
+    let task = AsyncFileDialog::new().pick_folder();
+
+    let collection = if app.show_collection {
+        Arc::clone(&app.entries)
+    } else {
+        Arc::clone(&app.collection)
+    };
+
+    std::thread::spawn(move || match executor::block_on(task) {
+        Some(folder) => {
+            let files = collection
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|entry| entry.path.clone())
+                .collect::<Vec<PathBuf>>();
+
+            if let Err(err) = export::copy_files(&files, &PathBuf::from(folder)) {
+                error!("Error exporting collection: {}", err);
+            }
+        }
+        None => {
+            info!("No export folder selected");
+        }
+    });
+}
+
+fn handle_selector_button_click(ctx: egui::Context, app: &mut App, select_files: bool) {
+    let file_dialog = AsyncFileDialog::new();
+    let task = if select_files {
+        file_dialog.pick_files().boxed()
+    } else {
+        file_dialog.pick_folders().boxed()
+    };
+
+    app.last_marked_entry = None;
+
+    let epoch = {
+        let mut entries = app.entries.lock().unwrap();
+        entries.clear();
+
+        start_load_epoch(&app.load_epoch)
+    };
+
+    let entries = Arc::clone(&app.entries);
+    let texture_manager = ctx.tex_manager();
+    let settings = app.settings.clone();
+    let loading_count = Arc::clone(&app.loading_count);
+    let load_epoch = Arc::clone(&app.load_epoch);
+    let visible_paths = Arc::clone(&app.visible_paths);
+    let roots = Arc::clone(&app.roots);
+    let pending_files = Arc::clone(&app.pending_files);
+
+    std::thread::spawn(move || {
+        let result = executor::block_on(task);
+
+        // The entries were cleared again while the dialog was open
+        if is_shutting_down() || is_superseded(&load_epoch, epoch) {
+            return;
+        }
+
+        match result {
+            Some(files) => {
+                let files: Vec<PathBuf> = files.iter().map(|file| PathBuf::from(file)).collect();
+                *roots.lock().unwrap() = files.clone();
+
+                let new_files =
+                    filter_media_files(process_entries(files, settings.follow_symlinks), &settings);
+
+                // Huge sets wait for the user to decide how much of them to load
+                if new_files.len() > settings.max_file_count {
+                    *pending_files.lock().unwrap() = Some(new_files);
+                    ctx.request_repaint();
+                    return;
+                }
+
+                load_files(
+                    new_files,
+                    texture_manager,
+                    entries,
+                    settings,
+                    loading_count,
+                    load_epoch,
+                    visible_paths,
+                );
+            }
+            None => {
+                info!("No files selected");
+            }
+        }
+    });
+}
+
+impl App {
+    // Loaders that are still running keep pushing into the old entries,
+    // so both the entries and the loading counter are swapped instead of cleared
+    fn clear(&mut self) {
+        self.set_collection_view(false);
+
+        // Lets the loaders know to stop instead of finishing for entries nobody sees
+        start_load_epoch(&self.load_epoch);
+
+        self.entries = Arc::new(Mutex::new(Vec::new()));
+        self.loading_count = Arc::new(AtomicUsize::new(0));
+        self.last_marked_entry = None;
+        self.current_entry = None;
+        self.rename_state = None;
+        self.batch_rename_state = None;
+        self.tags_state = None;
+        self.pending_deletion = None;
+        self.clear_confirmation_open = false;
+        self.pending_files = Arc::new(Mutex::new(None));
+        self.roots = Arc::new(Mutex::new(Vec::new()));
+        self.folder_watcher = None;
+    }
+
+    // Loads the roots of the last session as if they were picked in the file dialog again
+    fn reopen_last_session(&mut self, ctx: &egui::Context) {
+        let roots = std::mem::take(&mut self.last_session);
+
+        self.load_roots(roots, ctx);
+    }
+
+    // The folder of a file that was opened on its own, as a gallery of its own in a new window
+    fn browse_folder_of(&mut self, path: &PathBuf, ctx: &egui::Context) {
+        let folder = match path.parent() {
+            Some(folder) => folder.to_path_buf(),
+            None => {
+                warn!("{:?} has no parent folder", path);
+                return;
+            }
+        };
+
+        let mut app = App {
+            settings: self.settings.clone(),
+            ..Default::default()
+        };
+
+        app.load_roots(vec![folder], ctx);
+        self.open_gallery_window(app);
+    }
+
+    fn open_gallery_window(&mut self, app: App) {
+        self.next_gallery_window_id += 1;
+
+        self.gallery_windows.push(GalleryWindow {
+            id: self.next_gallery_window_id,
+            app,
+            open: true,
+        });
+    }
+
+    fn load_roots(&mut self, roots: Vec<PathBuf>, ctx: &egui::Context) {
+        *self.roots.lock().unwrap() = roots.clone();
+        self.last_marked_entry = None;
+
+        self.load_in_background(ctx, move |settings| {
+            filter_media_files(process_entries(roots, settings.follow_symlinks), settings)
+        });
+    }
+
+    // Walking folders and sniffing the content of files can take a while, so the files are
+    // collected on the loader thread
+    fn load_in_background(
+        &self,
+        ctx: &egui::Context,
+        collect_files: impl FnOnce(&Settings) -> Vec<PathBuf> + Send + 'static,
+    ) {
+        let entries = Arc::clone(&self.entries);
+        let texture_manager = ctx.tex_manager();
+        let settings = self.settings.clone();
+        let loading_count = Arc::clone(&self.loading_count);
+        let load_epoch = Arc::clone(&self.load_epoch);
+        let visible_paths = Arc::clone(&self.visible_paths);
+        let pending_files = Arc::clone(&self.pending_files);
+        let ctx = ctx.clone();
+
+        std::thread::spawn(move || {
+            let new_files = collect_files(&settings);
+
+            if new_files.len() > settings.max_file_count {
+                *pending_files.lock().unwrap() = Some(new_files);
+                ctx.request_repaint();
+                return;
+            }
+
+            load_files(
+                new_files,
+                texture_manager,
+                entries,
+                settings,
+                loading_count,
+                load_epoch,
+                visible_paths,
+            );
+        });
+    }
+
+    // Nothing in a hidden window needs to move, so animations and videos stop asking
+    // for repaints until it comes back
+    fn update_standby(&mut self, ctx: &egui::Context) {
+        let (minimized, focused) = ctx.input(|i| {
+            (
+                i.viewport().minimized.unwrap_or(false),
+                i.viewport().focused.unwrap_or(true),
+            )
+        });
+
+        let in_standby = self.settings.standby
+            && (minimized || (self.settings.standby_when_unfocused && !focused));
+
+        if in_standby == self.in_standby {
+            return;
+        }
+
+        self.in_standby = in_standby;
+
+        let video = match &mut self.current_entry {
+            Some(CurrentEntry {
+                video: Some(video), ..
+            }) => video,
+            _ => {
+                self.resume_video_after_standby = false;
+                return;
+            }
+        };
+
+        if in_standby {
+            self.resume_video_after_standby = video.is_playing;
+            video.pause();
+        } else if self.resume_video_after_standby {
+            self.resume_video_after_standby = false;
+            video.play();
+        }
+    }
+
+    fn update_folder_watcher(&mut self, ctx: &egui::Context) {
+        // The watcher would push new files into the collection while it is shown
+        if self.show_collection {
+            return;
+        }
+
+        let roots = self.roots.lock().unwrap().clone();
+
+        if !self.settings.watch_folders || roots.is_empty() {
+            self.folder_watcher = None;
+            return;
+        }
+
+        if let Some(folder_watcher) = &self.folder_watcher {
+            if folder_watcher.roots == roots {
+                return;
+            }
+        }
+
+        self.folder_watcher = watch_folders(
+            roots,
+            ctx.tex_manager(),
+            Arc::clone(&self.entries),
+            self.settings.clone(),
+            Arc::clone(&self.loading_count),
+            Arc::clone(&self.load_epoch),
+            Arc::clone(&self.visible_paths),
+        );
+    }
+
+    fn request_clear(&mut self) {
+        if self.show_collection {
+            {
+                let mut entries = self.entries.lock().unwrap();
+                entries.clear();
+                start_load_epoch(&self.load_epoch);
+            }
+
+            self.last_marked_entry = None;
+            return;
+        }
+
+        let is_loading = self.loading_count.load(Ordering::SeqCst) > 0;
+        let has_marked_entries = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|entry| entry.marked);
+
+        if is_loading || has_marked_entries {
+            self.clear_confirmation_open = true;
+        } else {
+            self.clear();
+        }
+    }
+
+    fn build_clear_confirmation(&mut self, ctx: &egui::Context) {
+        if !self.clear_confirmation_open {
+            return;
+        }
+
+        let mut open = true;
+
+        egui::Window::new("Clear")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .show(ctx, |ui| {
+                if self.loading_count.load(Ordering::SeqCst) > 0 {
+                    ui.label("Files are still loading.");
+                }
+
+                let marked_count = self
+                    .entries
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|entry| entry.marked)
+                    .count();
+
+                if marked_count > 0 {
+                    ui.label(format!("{} marked entries will be unmarked.", marked_count));
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Clear").clicked() {
+                        self.clear();
+                    }
+
+                    if ui.button("Cancel").clicked() {
+                        self.clear_confirmation_open = false;
+                    }
+                });
+            });
+
+        if !open {
+            self.clear_confirmation_open = false;
+        }
+    }
+
+    // The collection is shown through the same grid, so the two lists simply trade places
+    fn set_collection_view(&mut self, show_collection: bool) {
+        if self.show_collection == show_collection {
+            return;
+        }
+
+        std::mem::swap(&mut self.entries, &mut self.collection);
+        self.show_collection = show_collection;
+        self.last_marked_entry = None;
+    }
+
+    // Marked entries are added to the collection, or taken out of it while it is shown
+    pub fn update_collection(&mut self) {
+        if self.show_collection {
+            self.entries.lock().unwrap().retain(|entry| !entry.marked);
+            self.last_marked_entry = None;
+            return;
+        }
+
+        let entries = self.entries.lock().unwrap();
+        let mut collection = self.collection.lock().unwrap();
+
+        for entry in entries
+            .iter()
+            .filter(|entry| entry.marked && !entry.loading)
+        {
+            if collection
+                .iter()
+                .any(|collected| collected.path == entry.path)
+            {
+                continue;
+            }
+
+            let mut entry = entry.clone();
+            entry.marked = false;
+            collection.push(entry);
+        }
+    }
+
+    fn collection_len(&self) -> usize {
+        if self.show_collection {
+            self.entries.lock().unwrap().len()
+        } else {
+            self.collection.lock().unwrap().len()
+        }
+    }
+
+    fn build_file_limit_prompt(&mut self, ctx: &egui::Context) {
+        let file_count = match self.pending_files.lock().unwrap().as_ref() {
+            Some(files) => files.len(),
+            None => return,
+        };
+
+        let max_file_count = self.settings.max_file_count;
+        let mut open = true;
+        let mut files_to_load = None;
+        let mut cancelled = false;
+
+        egui::Window::new("Load files")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .show(ctx, |ui| {
+                ui.label(format!("About to load {} files.", file_count));
+
+                ui.horizontal(|ui| {
+                    if ui.button("Continue").clicked() {
+                        files_to_load = Some(file_count);
+                    }
+
+                    if ui
+                        .button(format!("Load first {}", max_file_count))
+                        .clicked()
+                    {
+                        files_to_load = Some(max_file_count);
+                    }
+
+                    if ui.button("Cancel").clicked() {
+                        cancelled = true;
+                    }
+                });
+            });
+
+        if let Some(count) = files_to_load {
+            if let Some(mut files) = self.pending_files.lock().unwrap().take() {
+                files.truncate(count);
+
+                let entries = if self.show_collection {
+                    Arc::clone(&self.collection)
+                } else {
+                    Arc::clone(&self.entries)
+                };
+
+                load_files(
+                    files,
+                    ctx.tex_manager(),
+                    entries,
+                    self.settings.clone(),
+                    Arc::clone(&self.loading_count),
+                    Arc::clone(&self.load_epoch),
+                    Arc::clone(&self.visible_paths),
+                );
+            }
+        } else if cancelled || !open {
+            info!("Loading of {} files cancelled", file_count);
+            *self.pending_files.lock().unwrap() = None;
+        }
+    }
+
+    // Counting is cheap, but not cheap enough to do every frame on huge sets
+    fn update_media_counts(&mut self) {
+        let is_outdated = match self.media_counts_updated_at {
+            Some(updated_at) => updated_at.elapsed() >= Duration::from_millis(500),
+            None => true,
+        };
+
+        if !is_outdated {
+            return;
+        }
+
+        self.media_counts = MediaCounts::from_entries(&self.entries.lock().unwrap());
+        self.media_counts_updated_at = Some(Instant::now());
+    }
+
+    // Files can be deleted or lose their symlink target after they were loaded. Every entry
+    // has to be looked up on the disk, so the check runs on its own thread every few seconds
+    fn check_missing_files(&mut self, ctx: &egui::Context) {
+        let is_outdated = match self.missing_files_checked_at {
+            Some(checked_at) => checked_at.elapsed() >= Duration::from_secs(5),
+            None => true,
+        };
+
+        if !is_outdated
+            || self
+                .missing_files_check_running
+                .swap(true, Ordering::SeqCst)
+        {
+            return;
+        }
+
+        self.missing_files_checked_at = Some(Instant::now());
+
+        let entries = Arc::clone(&self.entries);
+        let running = Arc::clone(&self.missing_files_check_running);
+        let ctx = ctx.clone();
+
+        thread::spawn(move || {
+            let paths = entries
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|entry| !entry.loading && entry.frame.is_none())
+                .map(|entry| entry.path.clone())
+                .collect::<Vec<PathBuf>>();
+
+            // Failed downloads keep their url as the path, there is nothing on the disk to find
+            let missing = paths
+                .into_iter()
+                .filter(|path| !download::is_url(&path.to_string_lossy()) && !path.exists())
+                .collect::<HashSet<PathBuf>>();
+
+            let mut changed = false;
+
+            for entry in entries.lock().unwrap().iter_mut() {
+                let is_missing = missing.contains(&entry.path);
+
+                if entry.missing != is_missing {
+                    entry.missing = is_missing;
+                    changed = true;
+                }
+            }
+
+            running.store(false, Ordering::SeqCst);
+
+            if changed {
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    fn open_conversion_window(&mut self) {
+        let paths = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| entry.marked)
+            .map(|entry| entry.path.clone())
+            .collect::<Vec<PathBuf>>();
+
+        if paths.is_empty() {
+            info!("No marked files to convert");
+            return;
+        }
+
+        self.conversion_state = Some(ConversionState {
+            paths,
+            format: export::ConversionFormat::default(),
+            video_format: export::VideoConversionFormat::default(),
+            quality: 90,
+            pick_folder: false,
+        });
+    }
+
+    fn remove_missing_files(&mut self) {
+        self.entries.lock().unwrap().retain(|entry| !entry.missing);
+        self.last_marked_entry = None;
+        self.media_counts_updated_at = None;
+    }
+
+    // Drops the cached thumbnails of everything in the folder and decodes the files again
+    fn regenerate_thumbnails(&mut self, ctx: &egui::Context, folder: PathBuf) {
+        let thumbnail_size = thumbnail_decode_size(&self.settings);
+
+        let files = {
+            let mut entries = self.entries.lock().unwrap();
+            let files = entries
+                .iter()
+                .filter(|entry| entry.path.starts_with(&folder))
+                .map(|entry| entry.path.clone())
+                .collect::<Vec<PathBuf>>();
+
+            entries.retain(|entry| !entry.path.starts_with(&folder));
+
+            files
+        };
+
+        for file in &files {
+            thumbnail_cache::remove(file, thumbnail_size, &self.settings);
+        }
+
+        self.last_marked_entry = None;
+
+        load_files(
+            files,
+            ctx.tex_manager(),
+            Arc::clone(&self.entries),
+            self.settings.clone(),
+            Arc::clone(&self.loading_count),
+            Arc::clone(&self.load_epoch),
+            Arc::clone(&self.visible_paths),
+        );
+    }
+
+    fn refresh(&mut self, ctx: &egui::Context) {
+        if self.show_collection {
+            info!("The collection can't be refreshed");
+            return;
+        }
+
+        let roots = self.roots.lock().unwrap().clone();
+
+        if roots.is_empty() {
+            info!("Nothing to refresh");
+            return;
+        }
+
+        self.last_marked_entry = None;
+
+        refresh_files(
+            roots,
+            ctx.tex_manager(),
+            Arc::clone(&self.entries),
+            self.settings.clone(),
+            Arc::clone(&self.loading_count),
+            Arc::clone(&self.load_epoch),
+            Arc::clone(&self.visible_paths),
+        );
+    }
+
+    // A window of the same kind is brought to the front instead of opening a second one
+    fn open_window(&mut self, title: &str, kind: WindowKind, ctx: &egui::Context) {
+        match self.windows.iter().find(|window| window.kind == kind) {
+            Some(window) => ctx.move_to_top(window.layer_id()),
+            None => self.windows.push(EguiWindow {
+                title: title.to_string(),
+                open: true,
+                kind,
+            }),
+        }
+    }
+
+    // The tool window in front of all the other windows, if there is one. Popups and tooltips
+    // are drawn above the windows, so only the window layers are looked at
+    fn focused_window(&self, ctx: &egui::Context) -> Option<usize> {
+        let top_window_layer = ctx.memory(|memory| {
+            memory
+                .layer_ids()
+                .filter(|layer_id| layer_id.order == egui::Order::Middle)
+                .last()
+        })?;
+
+        self.windows
+            .iter()
+            .position(|window| window.open && window.layer_id() == top_window_layer)
+    }
+
+    // The dialogs close on Escape as well, so it only clears the marks when none of them is open
+    fn is_dialog_open(&self) -> bool {
+        self.pending_deletion.is_some()
+            || self.pending_rotation.is_some()
+            || self.rename_state.is_some()
+            || self.batch_rename_state.is_some()
+            || self.conversion_state.is_some()
+            || self.tags_state.is_some()
+            || self.command_palette.is_some()
+    }
+
+    fn handle_command_keys(&mut self, ctx: &egui::Context) {
+        self.windows.retain(|window| window.open);
+
+        for command in pressed_commands(ctx, self.current_entry.is_some()) {
+            if command == Command::ClearMarks && self.is_dialog_open() {
+                continue;
+            }
+
+            self.run_command(command, ctx);
+        }
+    }
+
+    // Moving the window to a screen with a higher scale decodes the thumbnails again, going back
+    // to a lower one keeps the sharper thumbnails around
+    fn update_screen_scale(&mut self, ctx: &egui::Context) {
+        let pixels_per_point = ctx.pixels_per_point();
+
+        if pixels_per_point <= self.settings.screen_scale {
+            return;
+        }
+
+        self.settings.screen_scale = pixels_per_point;
+
+        let files = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| !entry.failed && !entry.loading)
+            .map(|entry| entry.path.clone())
+            .collect::<Vec<PathBuf>>();
+
+        if files.is_empty() {
+            return;
+        }
+
+        info!(
+            "Screen scale changed to {}, decoding {} thumbnails again",
+            pixels_per_point,
+            files.len()
+        );
+
+        redecode_thumbnails(
+            files,
+            ctx.tex_manager(),
+            Arc::clone(&self.entries),
+            self.settings.clone(),
+            Arc::clone(&self.loading_count),
+            Arc::clone(&self.load_epoch),
+        );
+    }
+
+    // Entries with cached thumbnails that were loaded while another label was shown have no
+    // resolution yet. Videos have to be opened for it, so it's read off the UI thread
+    fn update_entry_label(&mut self, ctx: &egui::Context) {
+        if self.settings.entry_label == self.shown_entry_label {
+            return;
+        }
+
+        self.shown_entry_label = self.settings.entry_label;
+
+        if self.settings.entry_label != EntryLabel::Resolution {
+            return;
+        }
+
+        let files = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| !entry.failed && !entry.loading && entry.dimensions.is_none())
+            .map(|entry| (entry.path.clone(), entry.media_type.clone()))
+            .collect::<Vec<(PathBuf, MediaType)>>();
+
+        if files.is_empty() {
+            return;
+        }
+
+        let entries = Arc::clone(&self.entries);
+        let settings = self.settings.clone();
+        let ctx = ctx.clone();
+
+        thread::spawn(move || {
+            let mut dimensions = HashMap::new();
+
+            for (file, media_type) in files {
+                if is_shutting_down() {
+                    return;
+                }
+
+                if let Some(size) = export::read_dimensions(&file, &media_type, &settings) {
+                    dimensions.insert(file, size);
+                }
+            }
+
+            for entry in entries.lock().unwrap().iter_mut() {
+                if let Some(size) = dimensions.get(&entry.path) {
+                    entry.dimensions = Some(*size);
+                }
+            }
+
+            ctx.request_repaint();
+        });
+    }
+
+    // The buttons of the top bar, the keys and the command palette all go through here
+    fn run_command(&mut self, command: Command, ctx: &egui::Context) {
+        match command {
+            Command::SelectFiles | Command::SelectFolders => {
+                self.set_collection_view(false);
+                handle_selector_button_click(ctx.clone(), self, command == Command::SelectFiles);
+            }
+            Command::Refresh => self.refresh(ctx),
+            Command::Clear => self.request_clear(),
+            Command::MarkDuplicates => mark_duplicates(
+                &mut self.entries.lock().unwrap(),
+                self.settings.duplicate_threshold,
+            ),
+            Command::ClearMarks => {
+                for entry in self.entries.lock().unwrap().iter_mut() {
+                    entry.marked = false;
+                }
+
+                self.last_marked_entry = None;
+            }
+            Command::BatchRename => {
+                self.batch_rename_state = Some(BatchRenameState {
+                    pattern: "image_###".to_string(),
+                    start_number: 1,
+                    error: None,
+                });
+            }
+            Command::Convert => self.open_conversion_window(),
+            Command::ReverseOrder => self.reverse_order = !self.reverse_order,
+            Command::ExportList => handle_export_button_click(self),
+            Command::ContactSheet => handle_contact_sheet_click(self, ctx),
+            Command::UpdateCollection => self.update_collection(),
+            Command::ShowCollection => self.set_collection_view(!self.show_collection),
+            Command::ExportCollection => {
+                if self.collection_len() > 0 {
+                    handle_collection_export_click(self);
+                }
+            }
+            Command::NewWindow => self.open_gallery_window(App {
+                settings: self.settings.clone(),
+                ..Default::default()
+            }),
+            Command::Settings => self.open_window("Settings", WindowKind::Settings, ctx),
+            Command::Stats => self.open_window("Stats", WindowKind::Stats, ctx),
+            Command::FilterImages => self.media_filter.images = !self.media_filter.images,
+            Command::FilterAnimated => self.media_filter.animated = !self.media_filter.animated,
+            Command::FilterVideos => self.media_filter.videos = !self.media_filter.videos,
+            Command::FilterFailed => self.media_filter.failed = !self.media_filter.failed,
+            Command::FilterMarkedOnly => {
+                self.media_filter.marked_only = !self.media_filter.marked_only
+            }
+            Command::SortByPath => self.settings.sort_mode = SortMode::Path,
+            Command::SortManually => self.settings.sort_mode = SortMode::Manual,
+            Command::SortDescending => {
+                self.settings.sort_descending = !self.settings.sort_descending
+            }
+            Command::GroupByType => self.settings.group_by_type = !self.settings.group_by_type,
+            Command::GridMinimap => {
+                self.settings.show_grid_minimap = !self.settings.show_grid_minimap
+            }
+            Command::Timings => {
+                self.show_timings = !self.show_timings;
+                self.frame_times.clear();
+            }
+            // Closes the focused tool window and only clears the entries when there is none
+            Command::CloseWindow => match self.focused_window(ctx) {
+                Some(index) => self.windows[index].open = false,
+                None => self.request_clear(),
+            },
+            Command::NextWindow => {
+                if !self.windows.is_empty() {
+                    let next = match self.focused_window(ctx) {
+                        Some(index) => (index + 1) % self.windows.len(),
+                        None => 0,
+                    };
+
+                    ctx.move_to_top(self.windows[next].layer_id());
+                }
+            }
+            Command::DeleteMarked
+            | Command::Rename
+            | Command::EditTags
+            | Command::Rate(_)
+            | Command::DensityPreset(_)
+            | Command::OpenRandom
+            | Command::BrowseFolder
+            | Command::ClosePreview
+            | Command::ShowInGrid
+            | Command::Fullscreen
+            | Command::PreviousPage
+            | Command::NextPage
+            | Command::TogglePlayback
+            | Command::LoopStart
+            | Command::LoopEnd
+            | Command::ClearLoop
+            | Command::CyclePlaybackMode
+            | Command::VideoFit
+            | Command::AddFrame
+            | Command::CopyDataUrl
+            | Command::Print
+            | Command::RotateClockwise
+            | Command::RotateCounterClockwise
+            | Command::Eyedropper
+            | Command::Ruler
+            | Command::Crop => run_entry_command(self, command, ctx),
+        }
+    }
+
+    // The palette is taken out while it's shown, the command it returns may need all of the app
+    fn build_command_palette(&mut self, ctx: &egui::Context) {
+        let mut palette = match self.command_palette.take() {
+            Some(palette) => palette,
+            None => return,
+        };
+
+        let command = build_command_palette(&mut palette, self.current_entry.is_some(), ctx);
+
+        if palette.open {
+            self.command_palette = Some(palette);
+        }
+
+        if let Some(command) = command {
+            self.run_command(command, ctx);
+        }
+    }
+
+    fn show(&mut self, ctx: &egui::Context) {
+        ctx.set_visuals(Visuals::dark());
+
+        self.update_screen_scale(ctx);
+        self.update_entry_label(ctx);
+        set_max_texture_side(ctx.input(|i| i.max_texture_side));
+
+        self.handle_command_keys(ctx);
+
+        // Read directly, the query field of the open palette has the keyboard focus
+        if ctx.input(|i| i.modifiers.command && i.key_pressed(egui::Key::P)) {
+            self.command_palette = match self.command_palette {
+                Some(_) => None,
+                None => Some(CommandPalette::new()),
+            };
+        }
+
+        self.update_standby(ctx);
+        self.update_folder_watcher(ctx);
+
+        if !self.pending_urls.is_empty() {
+            load_urls(
+                std::mem::take(&mut self.pending_urls),
+                ctx.tex_manager(),
+                Arc::clone(&self.entries),
+                self.settings.clone(),
+                Arc::clone(&self.loading_count),
+                Arc::clone(&self.load_epoch),
+                Arc::clone(&self.visible_paths),
+            );
+        }
+
+        // Check if we have dropped files that we need to load
+        if !self.dropped_files.is_empty() {
+            let dropped_files = std::mem::take(&mut self.dropped_files);
+
+            self.load_in_background(ctx, move |settings| {
+                filter_media_files(dropped_files, settings)
+            });
+        }
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if self.current_entry.is_some() {
+                build_preview(self, &ctx, ui);
+                return;
+            }
+
+            ui.vertical_centered_justified(|ui| {
+                ui.horizontal_wrapped(|ui| {
+                    let files_selector_btn = ui.button("Select files");
+                    ui.add_space(10.0);
+
+                    let folders_selector_btn = ui.button("Select folders");
+                    ui.add_space(10.0);
+
+                    if files_selector_btn.clicked() {
+                        self.run_command(Command::SelectFiles, ctx);
+                    }
+
+                    if folders_selector_btn.clicked() {
+                        self.run_command(Command::SelectFolders, ctx);
+                    }
+
+                    let refresh_btn = ui.button("Refresh");
+                    ui.add_space(10.0);
+
+                    if refresh_btn.clicked() {
+                        self.run_command(Command::Refresh, ctx);
+                    }
+
+                    let clear_btn = ui.button("Clear");
+                    ui.add_space(10.0);
+
+                    if clear_btn.clicked() {
+                        self.run_command(Command::Clear, ctx);
+                    }
+
+                    let duplicates_btn = ui.button("Mark duplicates");
+                    ui.add_space(10.0);
+
+                    if duplicates_btn.clicked() {
+                        self.run_command(Command::MarkDuplicates, ctx);
+                    }
+
+                    let batch_rename_btn = ui.button("Batch rename");
+                    ui.add_space(10.0);
+
+                    if batch_rename_btn.clicked() {
+                        self.run_command(Command::BatchRename, ctx);
+                    }
+
+                    let convert_btn = ui.button("Convert");
+                    ui.add_space(10.0);
+
+                    if convert_btn.clicked() {
+                        self.run_command(Command::Convert, ctx);
+                    }
+
+                    ui.toggle_value(&mut self.reverse_order, "Reverse order");
+                    ui.add_space(10.0);
+
+                    let export_btn = ui.button("Export list");
+                    ui.add_space(10.0);
+
+                    if export_btn.clicked() {
+                        self.run_command(Command::ExportList, ctx);
+                    }
+
+                    let contact_sheet_btn = ui.button("Contact sheet");
+                    ui.add_space(10.0);
+
+                    if contact_sheet_btn.clicked() {
+                        self.run_command(Command::ContactSheet, ctx);
+                    }
+
+                    let collect_btn = ui.button(if self.show_collection {
+                        "Remove from collection"
+                    } else {
+                        "Add to collection"
+                    });
+                    ui.add_space(10.0);
+
+                    if collect_btn.clicked() {
+                        self.run_command(Command::UpdateCollection, ctx);
+                    }
+
+                    let collection_len = self.collection_len();
+                    let mut show_collection = self.show_collection;
+
+                    if ui
+                        .toggle_value(
+                            &mut show_collection,
+                            format!("Collection ({})", collection_len),
+                        )
+                        .changed()
+                    {
+                        self.set_collection_view(show_collection);
+                    }
+                    ui.add_space(10.0);
+
+                    let export_collection_btn =
+                        ui.add_enabled(collection_len > 0, egui::Button::new("Export collection"));
+                    ui.add_space(10.0);
+
+                    if export_collection_btn.clicked() {
+                        self.run_command(Command::ExportCollection, ctx);
+                    }
+
+                    let new_window_btn = ui.button("New window");
+                    ui.add_space(10.0);
+
+                    if new_window_btn.clicked() {
+                        self.run_command(Command::NewWindow, ctx);
+                    }
+
+                    let settings_btn = ui.button("Settings");
+                    ui.add_space(10.0);
+
+                    if settings_btn.clicked() {
+                        self.run_command(Command::Settings, ctx);
+                    }
+
+                    let stats_btn = ui.button("Stats");
+
+                    if stats_btn.clicked() {
+                        self.run_command(Command::Stats, ctx);
+                    }
+
+                    ui.add_space(10.0);
+
+                    ui.toggle_value(&mut self.media_filter.images, "Images");
+                    ui.toggle_value(&mut self.media_filter.animated, "Animated");
+                    ui.toggle_value(&mut self.media_filter.videos, "Videos");
+                    ui.toggle_value(&mut self.media_filter.failed, "Failed");
+                    ui.toggle_value(&mut self.media_filter.marked_only, "Marked only");
+                    ui.add_space(10.0);
+
+                    let number_of_images_label =
+                        format!("Entries: {}", self.entries.lock().unwrap().len());
+                    ui.label(number_of_images_label);
+
+                    self.update_media_counts();
+                    self.check_missing_files(ctx);
+
+                    let media_counts_label = format!(
+                        "{} images, {} videos, {} animated, {} failed",
+                        self.media_counts.images,
+                        self.media_counts.videos,
+                        self.media_counts.animated,
+                        self.media_counts.failed
+                    );
+                    ui.label(media_counts_label);
+
+                    if !self.settings.show_failed_files && self.media_counts.failed > 0 {
+                        ui.add_space(10.0);
+
+                        let show_failed_btn =
+                            ui.button(format!("Show {} skipped", self.media_counts.failed));
+
+                        if show_failed_btn.clicked() {
+                            self.settings.show_failed_files = true;
+                        }
+                    }
+
+                    if self.media_counts.missing > 0 {
+                        ui.add_space(10.0);
+
+                        let remove_missing_btn = ui
+                            .button(format!("Remove {} missing", self.media_counts.missing))
+                            .on_hover_text(
+                                "Files that were deleted or moved since they were loaded",
+                            );
+
+                        if remove_missing_btn.clicked() {
+                            self.remove_missing_files();
+                        }
+                    }
+
+                    if self.loading_count.load(Ordering::SeqCst) > 0 {
+                        ui.add_space(10.0);
+                        ui.spinner();
+                    }
+                });
+
+                let mut regenerate_folder = None;
+
+                for window in self.windows.iter_mut() {
+                    egui::Window::new(window.title.clone())
+                        .open(&mut window.open)
+                        .resizable(true)
+                        .max_width(400.0)
+                        .max_height(250.0)
+                        .show(ui.ctx(), |ui| {
+                            match window.kind {
+                                WindowKind::Settings => {
+                                    let roots = self.roots.lock().unwrap().clone();
+
+                                    if let Some(folder) =
+                                        build_settings_window(&mut self.settings, &roots, ui)
+                                    {
+                                        regenerate_folder = Some(folder);
+                                    }
+                                }
+                                WindowKind::Stats => build_stats_window(
+                                    &self.entries.lock().unwrap(),
+                                    self.current_entry.as_ref(),
+                                    ui,
+                                ),
+                            }
+
+                            ui.allocate_space(ui.available_size());
+                        });
+                }
+
+                if let Some(folder) = regenerate_folder {
+                    self.regenerate_thumbnails(ctx, folder);
+                }
+
+                ui.add_space(10.0);
+
+                if !self.entries.lock().unwrap().is_empty() {
+                    build_grid(self, &ctx, ui);
+                } else if !self.last_session.is_empty()
+                    && self.roots.lock().unwrap().is_empty()
+                    && self.loading_count.load(Ordering::SeqCst) == 0
+                {
+                    let reopen_btn = ui.button(format!(
+                        "Reopen last session ({} folders)",
+                        self.last_session.len()
+                    ));
+                    let last_session = self
+                        .last_session
+                        .iter()
+                        .map(|root| root.to_string_lossy().to_string())
+                        .collect::<Vec<String>>();
+                    let reopen_btn = reopen_btn.on_hover_text(last_session.join("\n"));
+
+                    if reopen_btn.clicked() {
+                        self.reopen_last_session(ctx);
+                    }
+                }
+            });
+        });
+
+        build_rename_window(self, ctx);
+        build_batch_rename_window(self, ctx);
+        build_conversion_window(self, ctx);
+        build_tags_window(self, ctx);
+        build_delete_confirmation(self, ctx);
+        build_frame_export_window(self, ctx);
+        build_clip_export_window(self, ctx);
+        build_contact_sheet_window(self, ctx);
+        build_rotation_confirmation(self, ctx);
+        build_timings_overlay(self, ctx);
+        self.build_clear_confirmation(ctx);
+        self.build_file_limit_prompt(ctx);
+        self.build_command_palette(ctx);
+
+        self.show_gallery_windows(ctx);
+    }
+
+    // Every gallery window is a separate viewport with its own entries and settings,
+    // textures are uploaded through the same context, so the texture manager is shared
+    fn show_gallery_windows(&mut self, ctx: &egui::Context) {
+        for window in self.gallery_windows.iter_mut() {
+            let viewport_id =
+                egui::ViewportId::from_hash_of(("gallery_window", ctx.viewport_id(), window.id));
+
+            let viewport_builder = egui::ViewportBuilder::default()
+                .with_title(format!("Gallery #{}", window.id))
+                .with_min_inner_size(egui::vec2(400.0, 400.0))
+                .with_inner_size(egui::vec2(600.0, 400.0));
+
+            ctx.show_viewport_immediate(viewport_id, viewport_builder, |ctx, _class| {
+                window.app.show(ctx);
+
+                if ctx.input(|i| i.viewport().close_requested()) {
+                    window.open = false;
+                }
+            });
+        }
+
+        self.gallery_windows.retain(|window| window.open);
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let started_at = Instant::now();
+
+        self.show(ctx);
+
+        timings::record_frame_time(started_at.elapsed());
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        request_shutdown();
+
+        // Closing with nothing loaded keeps the previous session around
+        let roots = self.roots.lock().unwrap().clone();
+
+        if !roots.is_empty() {
+            session::save(&roots);
+        }
+
+        // Stops the watcher thread and the audio of the current video
+        self.folder_watcher = None;
+        self.current_entry = None;
+    }
+}
+
+// Reads a newline separated list of paths, e.g. `find . -name "*.png" | gallery -`
+fn read_paths_from_stdin() -> Vec<String> {
+    let mut paths = Vec::new();
+
+    for line in io::stdin().lock().lines() {
+        match line {
+            Ok(line) => {
+                let path = line.trim();
+
+                if !path.is_empty() {
+                    paths.push(path.to_string());
+                }
+            }
+            Err(err) => {
+                error!("Error reading paths from stdin: {:?}", err);
+                break;
+            }
+        }
+    }
+
+    paths
+}
+
+fn main() {
+    // RUST_LOG overrides the level, e.g. RUST_LOG=gallery=debug
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("warn,gallery=info"),
+    )
+    .init();
+
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    let read_stdin = args.iter().any(|arg| arg == "-" || arg == "--stdin");
+
+    if read_stdin {
+        args.retain(|arg| arg != "-" && arg != "--stdin");
+        args.extend(read_paths_from_stdin());
+    }
+
+    let (urls, args): (Vec<String>, Vec<String>) =
+        args.into_iter().partition(|arg| download::is_url(arg));
+
+    let roots = filter_valid_paths(args);
+    // Filtered once the settings exist, so disabled formats are skipped here too
+    let files = process_entries(roots.clone(), false);
 
     ffmpeg::init().unwrap();
 
@@ -291,11 +3522,11 @@ fn main() {
     match eframe::run_native(
         "Gallery",
         native_options,
-        Box::new(|cc| Box::new(App::new(cc, media_files))),
+        Box::new(|cc| Box::new(App::new(cc, files, roots, urls))),
     ) {
         Ok(_) => {}
         Err(err) => {
-            println!("Error: {}", err);
+            error!("Error: {}", err);
         }
     }
 }
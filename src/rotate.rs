@@ -0,0 +1,408 @@
+use crate::{image_entry::ImageEntry, Settings};
+use image::{codecs::jpeg::JpegEncoder, ImageFormat};
+use log::info;
+use std::{
+    io::Cursor,
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Mutex},
+};
+
+const JPEG_QUALITY: u8 = 95;
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const ORIENTATION_TAG: u16 = 0x0112;
+// Big endian header with an empty first IFD, for JPEGs that have no EXIF segment yet
+const EMPTY_TIFF: &[u8] = &[b'M', b'M', 0, 42, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0];
+
+// What the orientation becomes when the displayed image is turned a quarter clockwise,
+// mirrored orientations stay mirrored
+fn rotate_orientation(orientation: u16) -> u16 {
+    match orientation {
+        2 => 7,
+        3 => 8,
+        4 => 5,
+        5 => 2,
+        6 => 3,
+        7 => 4,
+        8 => 1,
+        _ => 6,
+    }
+}
+
+// The marker and the range of every segment before the start of the scan, the range covers the
+// marker and the length as well
+fn jpeg_segments(data: &[u8]) -> Option<Vec<(u8, usize, usize)>> {
+    if data.get(0..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    let mut position = 2;
+
+    while position + 4 <= data.len() {
+        if data[position] != 0xFF {
+            return None;
+        }
+
+        let marker = data[position + 1];
+
+        // Fill bytes before a marker
+        if marker == 0xFF {
+            position += 1;
+            continue;
+        }
+
+        // The metadata segments all come before the start of the scan
+        if marker == 0xDA {
+            return Some(segments);
+        }
+
+        let length = u16::from_be_bytes([data[position + 2], data[position + 3]]) as usize;
+        let end = position + 2 + length;
+
+        if length < 2 || end > data.len() {
+            return None;
+        }
+
+        segments.push((marker, position, end));
+        position = end;
+    }
+
+    None
+}
+
+fn is_exif_segment(data: &[u8], marker: u8, start: usize, end: usize) -> bool {
+    marker == 0xE1 && data[start + 4..end].starts_with(EXIF_HEADER)
+}
+
+// Returns where the orientation value is stored in the file and if it's big endian
+fn find_jpeg_orientation(data: &[u8]) -> Option<(usize, bool)> {
+    let (_, start, _) = jpeg_segments(data)?
+        .into_iter()
+        .find(|(marker, start, end)| is_exif_segment(data, *marker, *start, *end))?;
+
+    find_tiff_orientation(data, start + 4 + EXIF_HEADER.len())
+}
+
+fn read_u16(data: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    let bytes = [*data.get(offset)?, *data.get(offset + 1)?];
+
+    Some(if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+fn u16_bytes(value: u16, big_endian: bool) -> [u8; 2] {
+    if big_endian {
+        value.to_be_bytes()
+    } else {
+        value.to_le_bytes()
+    }
+}
+
+fn u32_bytes(value: u32, big_endian: bool) -> [u8; 4] {
+    if big_endian {
+        value.to_be_bytes()
+    } else {
+        value.to_le_bytes()
+    }
+}
+
+fn tiff_byte_order(tiff: &[u8]) -> Option<bool> {
+    match tiff.get(0..2)? {
+        b"MM" => Some(true),
+        b"II" => Some(false),
+        _ => None,
+    }
+}
+
+fn find_tiff_orientation(data: &[u8], tiff_start: usize) -> Option<(usize, bool)> {
+    let tiff = data.get(tiff_start..)?;
+    let big_endian = tiff_byte_order(tiff)?;
+
+    let ifd = read_u32(tiff, 4, big_endian)? as usize;
+    let entries_count = read_u16(tiff, ifd, big_endian)? as usize;
+
+    for index in 0..entries_count {
+        let entry = ifd + 2 + index * 12;
+
+        // A single SHORT fits into the entry itself, so the value is right after the count
+        if read_u16(tiff, entry, big_endian)? == ORIENTATION_TAG
+            && read_u16(tiff, entry + 2, big_endian)? == 3
+        {
+            return Some((tiff_start + entry + 8, big_endian));
+        }
+    }
+
+    None
+}
+
+// The first IFD is copied to the end along with the new entry. Everything else stays where it
+// is, moving it would mean fixing up every offset that points into the block
+fn tiff_with_orientation(tiff: &[u8], orientation: u16) -> Option<Vec<u8>> {
+    let big_endian = tiff_byte_order(tiff)?;
+
+    let ifd = read_u32(tiff, 4, big_endian)? as usize;
+    let entries_count = read_u16(tiff, ifd, big_endian)? as usize;
+    let entries_end = ifd + 2 + entries_count * 12;
+    let next_ifd = tiff.get(entries_end..entries_end + 4)?;
+
+    let mut entries = tiff
+        .get(ifd + 2..entries_end)?
+        .chunks_exact(12)
+        .map(|entry| entry.to_vec())
+        .collect::<Vec<Vec<u8>>>();
+
+    let mut orientation_entry = Vec::with_capacity(12);
+    orientation_entry.extend_from_slice(&u16_bytes(ORIENTATION_TAG, big_endian));
+    orientation_entry.extend_from_slice(&u16_bytes(3, big_endian));
+    orientation_entry.extend_from_slice(&u32_bytes(1, big_endian));
+    orientation_entry.extend_from_slice(&u16_bytes(orientation, big_endian));
+    orientation_entry.extend_from_slice(&[0, 0]);
+
+    // Entries are sorted by their tag
+    let index = entries
+        .iter()
+        .position(|entry| read_u16(entry, 0, big_endian).unwrap_or(0) > ORIENTATION_TAG)
+        .unwrap_or(entries.len());
+    entries.insert(index, orientation_entry);
+
+    let mut result = tiff.to_vec();
+
+    // IFDs start on a word boundary
+    if result.len() % 2 == 1 {
+        result.push(0);
+    }
+
+    let new_ifd = u32::try_from(result.len()).ok()?;
+
+    result.extend_from_slice(&u16_bytes(entries.len() as u16, big_endian));
+
+    for entry in &entries {
+        result.extend_from_slice(entry);
+    }
+
+    result.extend_from_slice(next_ifd);
+    result[4..8].copy_from_slice(&u32_bytes(new_ifd, big_endian));
+
+    Some(result)
+}
+
+// Replaces the existing EXIF segment or adds one after the JFIF segment, which has to stay first
+fn insert_jpeg_orientation(data: &[u8], orientation: u16) -> Option<Vec<u8>> {
+    let segments = jpeg_segments(data)?;
+    let exif = segments
+        .iter()
+        .find(|(marker, start, end)| is_exif_segment(data, *marker, *start, *end));
+
+    let (tiff, replaced) = match exif {
+        Some(&(_, start, end)) => (&data[start + 4 + EXIF_HEADER.len()..end], start..end),
+        None => {
+            let position = match segments.first() {
+                Some(&(0xE0, _, end)) => end,
+                _ => 2,
+            };
+
+            (EMPTY_TIFF, position..position)
+        }
+    };
+
+    let tiff = tiff_with_orientation(tiff, orientation)?;
+    let length = u16::try_from(2 + EXIF_HEADER.len() + tiff.len()).ok()?;
+
+    let mut result = Vec::with_capacity(data.len() + tiff.len() + 10);
+    result.extend_from_slice(&data[..replaced.start]);
+    result.extend_from_slice(&[0xFF, 0xE1]);
+    result.extend_from_slice(&length.to_be_bytes());
+    result.extend_from_slice(EXIF_HEADER);
+    result.extend_from_slice(&tiff);
+    result.extend_from_slice(&data[replaced.end..]);
+
+    Some(result)
+}
+
+// Encoding drops the metadata, so the EXIF, XMP and ICC segments of the original are copied
+// behind the segments of the encoder. The pixels now look the way they were shown, so the
+// orientation in the copy is reset
+fn copy_jpeg_metadata(original: &[u8], encoded: &[u8]) -> Vec<u8> {
+    let (metadata, position) = match (jpeg_segments(original), jpeg_segments(encoded)) {
+        (Some(original_segments), Some(encoded_segments)) => {
+            let metadata = original_segments
+                .into_iter()
+                .filter(|(marker, _, _)| *marker == 0xE1 || *marker == 0xE2)
+                .flat_map(|(_, start, end)| original[start..end].iter().copied())
+                .collect::<Vec<u8>>();
+
+            let position = match encoded_segments.first() {
+                Some(&(0xE0, _, end)) => end,
+                _ => 2,
+            };
+
+            (metadata, position)
+        }
+        _ => return encoded.to_vec(),
+    };
+
+    let mut result = Vec::with_capacity(encoded.len() + metadata.len());
+    result.extend_from_slice(&encoded[..position]);
+    result.extend_from_slice(&metadata);
+    result.extend_from_slice(&encoded[position..]);
+
+    if let Some((offset, big_endian)) = find_jpeg_orientation(&result) {
+        result[offset..offset + 2].copy_from_slice(&u16_bytes(1, big_endian));
+    }
+
+    result
+}
+
+// The new contents go to a temporary file first, so a failed write can't leave half an image.
+// The temporary file is a new one, so it gets the permissions of the original before replacing it
+fn write_file(file: &PathBuf, contents: &[u8]) -> Result<(), String> {
+    let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+    let temporary = file.with_file_name(format!(".{}.rotating", file_name));
+    let permissions = std::fs::metadata(file)
+        .map_err(|err| format!("Could not read {:?}: {}", file, err))?
+        .permissions();
+
+    std::fs::write(&temporary, contents)
+        .and_then(|_| std::fs::set_permissions(&temporary, permissions))
+        .and_then(|_| std::fs::rename(&temporary, file))
+        .map_err(|err| {
+            let _ = std::fs::remove_file(&temporary);
+            format!("Could not replace {:?}: {}", file, err)
+        })
+}
+
+// Changes the orientation of a JPEG, or adds one if it has none
+fn rotate_losslessly(data: &[u8], quarter_turns: usize) -> Option<Vec<u8>> {
+    let found = find_jpeg_orientation(data);
+
+    let mut orientation = match found {
+        Some((offset, big_endian)) => read_u16(data, offset, big_endian)?,
+        None => 1,
+    };
+
+    for _ in 0..quarter_turns {
+        orientation = rotate_orientation(orientation);
+    }
+
+    let rotated = match found {
+        Some((offset, big_endian)) => {
+            let mut rotated = data.to_vec();
+            rotated[offset..offset + 2].copy_from_slice(&u16_bytes(orientation, big_endian));
+            rotated
+        }
+        None => insert_jpeg_orientation(data, orientation)?,
+    };
+
+    Some(rotated)
+}
+
+// Shown before the file is overwritten, there is none when only the orientation changes
+pub fn reencode_warning(file: &PathBuf, settings: &Settings) -> Option<String> {
+    let data = std::fs::read(file).ok()?;
+
+    match image::guess_format(&data).ok()? {
+        // Without applying the orientation, changing it wouldn't show up in the gallery itself
+        ImageFormat::Jpeg
+            if settings.apply_exif_orientation && rotate_losslessly(&data, 1).is_some() =>
+        {
+            None
+        }
+        ImageFormat::Jpeg => Some(format!(
+            "The image will be encoded again at quality {}, which loses some quality. \
+             Its metadata is kept.",
+            JPEG_QUALITY
+        )),
+        _ => Some(
+            "The image will be encoded again, metadata other than the pixels may be lost."
+                .to_string(),
+        ),
+    }
+}
+
+#[derive(Default)]
+pub struct Rotation {
+    pub finished: AtomicBool,
+    // Shown in the confirmation window, so it can be tried again
+    pub error: Mutex<Option<String>>,
+}
+
+// JPEGs only get their orientation changed or added, which is lossless.
+// Everything else is decoded, rotated and encoded again in the same format.
+// Returns whether the rotation was lossless
+pub fn rotate_and_save(
+    file: &PathBuf,
+    clockwise: bool,
+    settings: &Settings,
+) -> Result<bool, String> {
+    let metadata = std::fs::metadata(file).map_err(|err| err.to_string())?;
+
+    if metadata.permissions().readonly() {
+        return Err(format!("{:?} is read-only", file));
+    }
+
+    let data = std::fs::read(file).map_err(|err| err.to_string())?;
+    let format = image::guess_format(&data).map_err(|err| err.to_string())?;
+
+    let quarter_turns = if clockwise { 1 } else { 3 };
+
+    // Without applying the orientation, changing it wouldn't show up in the gallery itself
+    if format == ImageFormat::Jpeg && settings.apply_exif_orientation {
+        if let Some(rotated) = rotate_losslessly(&data, quarter_turns) {
+            write_file(file, &rotated)?;
+            info!("Rotated {:?} by changing its orientation", file);
+            return Ok(true);
+        }
+    }
+
+    let image =
+        image::load_from_memory_with_format(&data, format).map_err(|err| err.to_string())?;
+
+    // The orientation that was shown is baked into the pixels
+    let image = if settings.apply_exif_orientation {
+        ImageEntry::apply_exif_orientation(image, file)
+    } else {
+        image
+    };
+
+    let image = if clockwise {
+        image.rotate90()
+    } else {
+        image.rotate270()
+    };
+
+    let mut encoded = Vec::new();
+
+    match format {
+        ImageFormat::Jpeg => {
+            JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY)
+                .encode_image(&image.to_rgb8())
+                .map_err(|err| err.to_string())?;
+
+            encoded = copy_jpeg_metadata(&data, &encoded);
+        }
+        format => {
+            image
+                .write_to(&mut Cursor::new(&mut encoded), format)
+                .map_err(|err| format!("Could not encode {:?}: {}", file, err))?;
+        }
+    }
+
+    write_file(file, &encoded)?;
+
+    info!("Rotated {:?} by encoding it again", file);
+
+    Ok(false)
+}
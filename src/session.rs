@@ -0,0 +1,54 @@
+use log::{error, info, warn};
+use std::{env, path::PathBuf};
+
+fn session_path() -> PathBuf {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(env::temp_dir);
+
+    base.join("gallery").join("session.json")
+}
+
+// Roots that were removed since then are left out, so a session with nothing left isn't offered
+pub fn load() -> Vec<PathBuf> {
+    let session_path = session_path();
+
+    let contents = match std::fs::read_to_string(&session_path) {
+        Ok(contents) => contents,
+        Err(_) => return Vec::new(),
+    };
+
+    match serde_json::from_str::<Vec<PathBuf>>(&contents) {
+        Ok(roots) => roots.into_iter().filter(|root| root.exists()).collect(),
+        Err(err) => {
+            warn!("Failed to read last session {:?}: {}", session_path, err);
+            Vec::new()
+        }
+    }
+}
+
+pub fn save(roots: &[PathBuf]) {
+    let session_path = session_path();
+
+    if let Some(folder) = session_path.parent() {
+        if let Err(err) = std::fs::create_dir_all(folder) {
+            error!("Failed to create config folder: {}", err);
+            return;
+        }
+    }
+
+    let contents = match serde_json::to_string_pretty(roots) {
+        Ok(contents) => contents,
+        Err(err) => {
+            error!("Failed to serialize session: {}", err);
+            return;
+        }
+    };
+
+    match std::fs::write(&session_path, contents) {
+        Ok(_) => info!("Saved {} roots to {:?}", roots.len(), session_path),
+        Err(err) => error!("Failed to write session {:?}: {}", session_path, err),
+    }
+}
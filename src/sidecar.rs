@@ -0,0 +1,74 @@
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct Sidecar {
+    #[serde(default)]
+    pub rating: u8,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Sidecar {
+    // Sidecars sit next to the file they describe, "photo.jpg" gets "photo.jpg.json"
+    pub fn path_for(file: &PathBuf) -> PathBuf {
+        let mut file_name = file.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".json");
+
+        file.with_file_name(file_name)
+    }
+
+    pub fn load(file: &PathBuf) -> Sidecar {
+        let sidecar_path = Sidecar::path_for(file);
+
+        if !sidecar_path.exists() {
+            return Sidecar::default();
+        }
+
+        let contents = match std::fs::read_to_string(&sidecar_path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                error!("Failed to read sidecar {:?}: {}", sidecar_path, err);
+                return Sidecar::default();
+            }
+        };
+
+        match serde_json::from_str(&contents) {
+            Ok(sidecar) => sidecar,
+            Err(err) => {
+                error!("Failed to parse sidecar {:?}: {}", sidecar_path, err);
+                Sidecar::default()
+            }
+        }
+    }
+
+    // An empty sidecar is removed instead of being written, so unrated files stay clean
+    pub fn save(&self, file: &PathBuf) -> Result<(), String> {
+        let sidecar_path = Sidecar::path_for(file);
+
+        if *self == Sidecar::default() {
+            if sidecar_path.exists() {
+                std::fs::remove_file(&sidecar_path).map_err(|err| err.to_string())?;
+            }
+
+            return Ok(());
+        }
+
+        let contents = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+
+        std::fs::write(&sidecar_path, contents).map_err(|err| err.to_string())
+    }
+
+    pub fn move_with(old_file: &PathBuf, new_file: &PathBuf) {
+        let old_sidecar_path = Sidecar::path_for(old_file);
+
+        if !old_sidecar_path.exists() {
+            return;
+        }
+
+        if let Err(err) = std::fs::rename(&old_sidecar_path, Sidecar::path_for(new_file)) {
+            error!("Failed to move sidecar {:?}: {}", old_sidecar_path, err);
+        }
+    }
+}
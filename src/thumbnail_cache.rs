@@ -0,0 +1,332 @@
+use crate::Settings;
+use eframe::egui::ColorImage;
+use image::{
+    codecs::{jpeg::JpegEncoder, webp::WebPEncoder},
+    ExtendedColorType, ImageEncoder,
+};
+use log::{error, info, warn};
+use std::{
+    collections::hash_map::DefaultHasher,
+    env,
+    fs::File,
+    hash::{Hash, Hasher},
+    io::BufWriter,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+// Walking the cache folder isn't free, so the size shown in the settings is refreshed lazily
+static CACHE_SIZE: Mutex<Option<(Instant, u64)>> = Mutex::new(None);
+const CACHE_SIZE_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum CacheEncoding {
+    // Fastest to read back, but by far the largest on disk
+    Raw,
+    Png,
+    #[default]
+    Jpeg,
+    WebP,
+}
+
+impl CacheEncoding {
+    pub fn label(&self) -> &str {
+        match self {
+            CacheEncoding::Raw => "Raw RGBA",
+            CacheEncoding::Png => "PNG",
+            CacheEncoding::Jpeg => "JPEG",
+            CacheEncoding::WebP => "WebP (lossless)",
+        }
+    }
+
+    fn extension(&self) -> &str {
+        match self {
+            CacheEncoding::Raw => "rgba",
+            CacheEncoding::Png => "png",
+            CacheEncoding::Jpeg => "jpg",
+            CacheEncoding::WebP => "webp",
+        }
+    }
+}
+
+const ALL_ENCODINGS: [CacheEncoding; 4] = [
+    CacheEncoding::Raw,
+    CacheEncoding::Png,
+    CacheEncoding::Jpeg,
+    CacheEncoding::WebP,
+];
+
+pub fn cache_dir() -> PathBuf {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(env::temp_dir);
+
+    base.join("gallery").join("thumbnails")
+}
+
+// The key changes whenever the file is modified or a setting that changes the decoded or the
+// stored pixels is toggled, so stale thumbnails are never picked up, they just stay around until
+// they get evicted. The encoding only decides the extension
+fn cache_path(
+    file: &PathBuf,
+    size: f32,
+    encoding: CacheEncoding,
+    settings: &Settings,
+) -> Option<PathBuf> {
+    let metadata = std::fs::metadata(file).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()?;
+
+    let mut hasher = DefaultHasher::new();
+    file.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    modified.hash(&mut hasher);
+    (size as u32).hash(&mut hasher);
+    settings.apply_exif_orientation.hash(&mut hasher);
+    settings.tone_map_hdr.hash(&mut hasher);
+    settings.color_management.hash(&mut hasher);
+    settings.raw_exposure.to_bits().hash(&mut hasher);
+    settings.raw_color_space.hash(&mut hasher);
+    settings.thumbnail_cache_quality.hash(&mut hasher);
+
+    Some(cache_dir().join(format!("{:016x}.{}", hasher.finish(), encoding.extension())))
+}
+
+// Raw thumbnails are the width and the height as little endian u32, followed by the pixels
+fn read_raw(cache_path: &PathBuf) -> Result<ColorImage, String> {
+    let bytes = std::fs::read(cache_path).map_err(|err| err.to_string())?;
+
+    if bytes.len() < 8 {
+        return Err("File is too short".to_string());
+    }
+
+    let width = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let height = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    let pixels = &bytes[8..];
+
+    if pixels.len() != width * height * 4 {
+        return Err("Pixel data doesn't match the dimensions".to_string());
+    }
+
+    Ok(ColorImage::from_rgba_unmultiplied([width, height], pixels))
+}
+
+fn read(cache_path: &PathBuf, encoding: CacheEncoding) -> Result<ColorImage, String> {
+    if encoding == CacheEncoding::Raw {
+        return read_raw(cache_path);
+    }
+
+    let image = image::open(cache_path).map_err(|err| err.to_string())?;
+    let image_size = [image.width() as usize, image.height() as usize];
+    let pixels = image.into_rgba8();
+
+    Ok(ColorImage::from_rgba_unmultiplied(
+        image_size,
+        pixels.as_flat_samples().as_slice(),
+    ))
+}
+
+// JPEG can't store transparency, so thumbnails that have some are kept as PNG instead
+fn stored_encoding(color_image: &ColorImage, encoding: CacheEncoding) -> CacheEncoding {
+    let is_opaque = color_image.pixels.iter().all(|pixel| pixel.a() == 255);
+
+    match encoding {
+        CacheEncoding::Jpeg if !is_opaque => CacheEncoding::Png,
+        encoding => encoding,
+    }
+}
+
+pub fn load(file: &PathBuf, size: f32, settings: &Settings) -> Option<ColorImage> {
+    let encoding = settings.thumbnail_cache_encoding;
+    let fallback = match encoding {
+        CacheEncoding::Jpeg => Some(CacheEncoding::Png),
+        _ => None,
+    };
+
+    for encoding in std::iter::once(encoding).chain(fallback) {
+        let cache_path = cache_path(file, size, encoding, settings)?;
+
+        if !cache_path.exists() {
+            continue;
+        }
+
+        match read(&cache_path, encoding) {
+            Ok(color_image) => return Some(color_image),
+            Err(err) => {
+                warn!("Failed to read cached thumbnail {:?}: {}", cache_path, err);
+                return None;
+            }
+        }
+    }
+
+    None
+}
+
+fn write(
+    cache_path: &PathBuf,
+    color_image: &ColorImage,
+    encoding: CacheEncoding,
+    jpeg_quality: u8,
+) -> Result<(), String> {
+    let width = color_image.size[0] as u32;
+    let height = color_image.size[1] as u32;
+
+    let pixels = color_image
+        .pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_srgba_unmultiplied())
+        .collect::<Vec<u8>>();
+
+    if encoding == CacheEncoding::Png {
+        return image::save_buffer(cache_path, &pixels, width, height, image::ColorType::Rgba8)
+            .map_err(|err| err.to_string());
+    }
+
+    if encoding == CacheEncoding::Raw {
+        let mut bytes = Vec::with_capacity(8 + pixels.len());
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+        bytes.extend_from_slice(&pixels);
+
+        return std::fs::write(cache_path, bytes).map_err(|err| err.to_string());
+    }
+
+    let writer = BufWriter::new(File::create(cache_path).map_err(|err| err.to_string())?);
+
+    match encoding {
+        CacheEncoding::Jpeg => {
+            let rgb = pixels
+                .chunks_exact(4)
+                .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                .collect::<Vec<u8>>();
+
+            JpegEncoder::new_with_quality(writer, jpeg_quality.clamp(1, 100)).write_image(
+                &rgb,
+                width,
+                height,
+                ExtendedColorType::Rgb8,
+            )
+        }
+        _ => WebPEncoder::new_lossless(writer).write_image(
+            &pixels,
+            width,
+            height,
+            ExtendedColorType::Rgba8,
+        ),
+    }
+    .map_err(|err| err.to_string())
+}
+
+pub fn store(file: &PathBuf, size: f32, color_image: &ColorImage, settings: &Settings) {
+    let encoding = stored_encoding(color_image, settings.thumbnail_cache_encoding);
+
+    let cache_path = match cache_path(file, size, encoding, settings) {
+        Some(cache_path) => cache_path,
+        None => return,
+    };
+
+    if let Err(err) = std::fs::create_dir_all(cache_dir()) {
+        error!("Failed to create thumbnail cache folder: {}", err);
+        return;
+    }
+
+    if let Err(err) = write(
+        &cache_path,
+        color_image,
+        encoding,
+        settings.thumbnail_cache_quality,
+    ) {
+        error!("Failed to write cached thumbnail {:?}: {}", cache_path, err);
+    }
+}
+
+// Thumbnails written before the encoding was changed are removed as well
+pub fn remove(file: &PathBuf, size: f32, settings: &Settings) {
+    for encoding in ALL_ENCODINGS {
+        if let Some(cache_path) = cache_path(file, size, encoding, settings) {
+            if cache_path.exists() {
+                if let Err(err) = std::fs::remove_file(&cache_path) {
+                    error!(
+                        "Failed to remove cached thumbnail {:?}: {}",
+                        cache_path, err
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn cache_files() -> Vec<(PathBuf, u64, SystemTime)> {
+    let entries = match std::fs::read_dir(cache_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+
+            Some((entry.path(), metadata.len(), modified))
+        })
+        .collect()
+}
+
+pub fn cache_size() -> u64 {
+    let mut cache_size = CACHE_SIZE.lock().unwrap();
+
+    match *cache_size {
+        Some((updated_at, size)) if updated_at.elapsed() < CACHE_SIZE_REFRESH_INTERVAL => size,
+        _ => {
+            let size = cache_files().iter().map(|(_, size, _)| size).sum();
+            *cache_size = Some((Instant::now(), size));
+
+            size
+        }
+    }
+}
+
+pub fn clear() {
+    let cache_dir = cache_dir();
+
+    if cache_dir.exists() {
+        match std::fs::remove_dir_all(&cache_dir) {
+            Ok(_) => info!("Cleared thumbnail cache"),
+            Err(err) => error!("Failed to clear thumbnail cache: {}", err),
+        }
+    }
+
+    *CACHE_SIZE.lock().unwrap() = None;
+}
+
+// Removes the least recently written thumbnails until the cache fits into max_size
+pub fn evict(max_size: u64) {
+    let mut files = cache_files();
+    let mut total_size: u64 = files.iter().map(|(_, size, _)| size).sum();
+
+    if total_size <= max_size {
+        return;
+    }
+
+    files.sort_by_key(|(_, _, modified)| *modified);
+
+    for (path, size, _) in files {
+        if total_size <= max_size {
+            break;
+        }
+
+        match std::fs::remove_file(&path) {
+            Ok(_) => total_size -= size,
+            Err(err) => error!("Failed to evict cached thumbnail {:?}: {}", path, err),
+        }
+    }
+
+    *CACHE_SIZE.lock().unwrap() = None;
+}
@@ -0,0 +1,113 @@
+use log::info;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+// Frames of the UI that take longer than this make the loaders back off, if they are allowed to
+pub const SLOW_FRAME_TIME: Duration = Duration::from_millis(50);
+
+#[derive(Clone)]
+pub struct Timing {
+    pub label: String,
+    pub duration: Duration,
+}
+
+// Set once the App is created, measurements made before that are only logged
+static SENDER: OnceLock<Sender<Timing>> = OnceLock::new();
+
+// The App keeps the recent measurements it receives
+pub fn connect() -> Receiver<Timing> {
+    let (sender, receiver) = mpsc::channel();
+    let _ = SENDER.set(sender);
+
+    receiver
+}
+
+pub fn record(label: &str, duration: Duration) {
+    if let Some(sender) = SENDER.get() {
+        let _ = sender.send(Timing {
+            label: label.to_string(),
+            duration,
+        });
+    }
+}
+
+// How long the UI took for its recent frames, smoothed and in microseconds
+static FRAME_TIME: AtomicU64 = AtomicU64::new(0);
+
+pub fn record_frame_time(duration: Duration) {
+    let previous = FRAME_TIME.load(Ordering::SeqCst);
+    let micros = duration.as_micros() as u64;
+
+    FRAME_TIME.store((previous * 7 + micros) / 8, Ordering::SeqCst);
+}
+
+pub fn frame_time() -> Duration {
+    Duration::from_micros(FRAME_TIME.load(Ordering::SeqCst))
+}
+
+#[derive(Default)]
+struct DecoderThroughput {
+    count: usize,
+    busy: Duration,
+}
+
+// Thumbnails of a single load, counted by the decoder that produced them
+pub struct Throughput {
+    started_at: Instant,
+    decoders: Mutex<BTreeMap<String, DecoderThroughput>>,
+}
+
+impl Throughput {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            decoders: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn record(&self, decoder: &str, duration: Duration) {
+        let mut decoders = self.decoders.lock().unwrap();
+        let throughput = decoders.entry(decoder.to_string()).or_default();
+
+        throughput.count += 1;
+        throughput.busy += duration;
+    }
+
+    // The rate per decoder is over the whole load, the time per thumbnail only counts decoding
+    pub fn log(&self) {
+        let elapsed = self.started_at.elapsed().as_secs_f64().max(0.001);
+        let decoders = self.decoders.lock().unwrap();
+        let total = decoders
+            .values()
+            .map(|throughput| throughput.count)
+            .sum::<usize>();
+
+        if total == 0 {
+            return;
+        }
+
+        info!(
+            "Decoded {} thumbnails in {:.1} s, {:.1} per second",
+            total,
+            elapsed,
+            total as f64 / elapsed
+        );
+
+        for (decoder, throughput) in decoders.iter() {
+            info!(
+                "  {}: {} thumbnails, {:.1} per second, {:.1} ms each",
+                decoder,
+                throughput.count,
+                throughput.count as f64 / elapsed,
+                throughput.busy.as_secs_f64() * 1000.0 / throughput.count as f64
+            );
+        }
+    }
+}
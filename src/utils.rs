@@ -1,16 +1,195 @@
+use crate::{
+    image_entry::{ImageEntry, ImageFormat},
+    MediaType, Settings,
+};
 use eframe::{
-    egui::{self, mutex::RwLock, ColorImage, TextureHandle},
+    egui::{self, mutex::RwLock, Color32, ColorImage, ImageData, TextureHandle, TextureOptions},
     epaint::TextureManager,
 };
-use std::{path::PathBuf, sync::Arc};
+use image::{imageops::FilterType, RgbaImage};
+use log::{debug, error, warn};
+use std::{
+    cell::Cell,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::Read,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
 
 pub type SharedTextureManager = Arc<RwLock<TextureManager>>;
 
-pub fn filter_media_files(files: Vec<PathBuf>) -> Vec<PathBuf> {
+// Background threads are detached, so they check this to stop working once the app exits
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+// Every window has its own epoch, bumped whenever its entries are cleared. Loaders remember the
+// epoch they started in and drop whatever they produce once it moved on, so a superseded load
+// can't write into the new entries. Called with the entries locked, so both change together
+pub fn start_load_epoch(load_epoch: &AtomicUsize) -> usize {
+    load_epoch.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+pub fn is_superseded(load_epoch: &AtomicUsize, epoch: usize) -> bool {
+    load_epoch.load(Ordering::SeqCst) != epoch
+}
+
+// The decode size is in points like the rest of the grid, on HiDPI screens thumbnails need
+// proportionally more pixels to stay sharp
+pub fn thumbnail_decode_size(settings: &Settings) -> f32 {
+    (settings.thumbnail_decode_size as f32 * settings.screen_scale.max(1.0)).round()
+}
+
+// The largest side a texture can have, larger images are scaled down before the upload instead
+// of failing to show up. 0 until the backend reported its limit
+static MAX_TEXTURE_SIDE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_max_texture_side(max_side: usize) {
+    MAX_TEXTURE_SIDE.store(max_side, Ordering::SeqCst);
+}
+
+pub fn request_shutdown() {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+pub fn is_shutting_down() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+type WatchedJob = Box<dyn FnOnce() + Send>;
+
+// Threads that run the work of run_with_timeout. They go back to this list once they're done,
+// so a load reuses a few of them instead of starting one for every file
+static IDLE_WATCHED_THREADS: Mutex<Vec<mpsc::Sender<WatchedJob>>> = Mutex::new(Vec::new());
+// Every hung thread keeps its memory, past this many of one load the rest of it runs in place
+const MAX_HUNG_THREADS: usize = 8;
+
+const JOB_RUNNING: u8 = 0;
+const JOB_DONE: u8 = 1;
+const JOB_ABANDONED: u8 = 2;
+
+fn run_on_watched_thread(job: WatchedJob) {
+    let idle_thread = IDLE_WATCHED_THREADS.lock().unwrap().pop();
+
+    // The thread is gone when sending fails, the job gets a new one then
+    let job = match idle_thread {
+        Some(sender) => match sender.send(job) {
+            Ok(()) => return,
+            Err(mpsc::SendError(job)) => job,
+        },
+        None => job,
+    };
+
+    let (sender, receiver) = mpsc::channel::<WatchedJob>();
+    let _ = sender.send(job);
+
+    thread::spawn(move || {
+        while let Ok(job) = receiver.recv() {
+            job();
+            IDLE_WATCHED_THREADS.lock().unwrap().push(sender.clone());
+        }
+    });
+}
+
+// A thread can't be stopped from the outside, so when the work takes too long it's left to
+// finish in the background and the result is thrown away. Without a timeout it runs in place.
+// Threads still stuck are counted in `hung_threads`, which belongs to a single load, so one
+// folder of bad files doesn't stop the timeouts of the next loads. Once that load has too many
+// of them the work runs in place as well instead of leaving even more threads behind
+pub fn run_with_timeout<T: Send + 'static>(
+    timeout: Option<Duration>,
+    hung_threads: &Arc<AtomicUsize>,
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, String> {
+    let timeout = match timeout {
+        Some(timeout) if hung_threads.load(Ordering::SeqCst) < MAX_HUNG_THREADS => timeout,
+        _ => return Ok(work()),
+    };
+
+    let job_hung_threads = Arc::clone(hung_threads);
+
+    let (sender, receiver) = mpsc::channel();
+    let state = Arc::new(AtomicU8::new(JOB_RUNNING));
+    let job_state = Arc::clone(&state);
+
+    run_on_watched_thread(Box::new(move || {
+        let result = work();
+
+        if job_state.swap(JOB_DONE, Ordering::SeqCst) == JOB_ABANDONED {
+            job_hung_threads.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        let _ = sender.send(result);
+    }));
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => Ok(result),
+        Err(RecvTimeoutError::Timeout) => {
+            // Counted first, so finishing right now can't take the count below zero
+            hung_threads.fetch_add(1, Ordering::SeqCst);
+
+            if state.swap(JOB_ABANDONED, Ordering::SeqCst) == JOB_DONE {
+                hung_threads.fetch_sub(1, Ordering::SeqCst);
+            }
+
+            Err(format!(
+                "Decoding took longer than {} seconds",
+                timeout.as_secs()
+            ))
+        }
+        Err(RecvTimeoutError::Disconnected) => Err("The decoder crashed".to_string()),
+    }
+}
+
+// Decoders panic on files they don't expect, this turns that into an error so only the file
+// fails instead of the whole app. Aborts inside of the C libraries can't be caught like this,
+// those need the decoder to run in a process of its own
+pub fn catch_decoder_panic<T>(
+    decoder: &str,
+    decode: impl FnOnce() -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    // A decoder that panicked is thrown away along with whatever state it had,
+    // nothing it borrowed is used in a half-updated state afterwards
+    match panic::catch_unwind(AssertUnwindSafe(decode)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown cause".to_string());
+
+            error!("The {} decoder panicked: {}", decoder, message);
+
+            Err(format!("The {} decoder panicked: {}", decoder, message).into())
+        }
+    }
+}
+
+pub fn filter_media_files(files: Vec<PathBuf>, settings: &Settings) -> Vec<PathBuf> {
     let mut filtered_files = Vec::new();
 
     for file in files {
-        if is_image(&file) || is_video(&file) || is_no_extension(&file) {
+        let extension = get_extension(&file).unwrap_or_default();
+
+        // Known extensions follow their setting, a disabled one is not sniffed either
+        if let Some(enabled) = settings.extension_enabled(&extension) {
+            if enabled {
+                filtered_files.push(file);
+            }
+
+            continue;
+        }
+
+        // Files without an extension are still kept, some formats like JBIG have no magic bytes
+        if sniff_media_type(&file).is_some() || is_no_extension(&file) {
             filtered_files.push(file);
         }
     }
@@ -18,12 +197,12 @@ pub fn filter_media_files(files: Vec<PathBuf>) -> Vec<PathBuf> {
     filtered_files
 }
 
-pub fn process_entries(entries: Vec<PathBuf>) -> Vec<PathBuf> {
+pub fn process_entries(entries: Vec<PathBuf>, follow_symlinks: bool) -> Vec<PathBuf> {
     let mut files = Vec::new();
 
     for entry in entries {
         if entry.is_dir() {
-            let dir_files = get_files_recursive(&entry);
+            let dir_files = get_files_recursive(&entry, follow_symlinks);
             files.extend(dir_files);
         } else {
             files.push(entry);
@@ -38,58 +217,182 @@ pub fn filter_valid_paths(paths: Vec<String>) -> Vec<PathBuf> {
 
     for path in paths {
         let path = PathBuf::from(path);
-        if path.exists() {
-            valid_paths.push(path);
+        if !path.exists() {
+            continue;
+        }
+
+        match check_readable(&path) {
+            Ok(()) => valid_paths.push(path),
+            Err(err) => warn!("Skipping {:?}: {}", path, err),
         }
     }
 
     valid_paths
 }
 
-pub fn is_image(file: &PathBuf) -> bool {
-    let extensions = [
-        "3fr", "arw", "avif", "bmp", "cr2", "crw", "cur", "dcm", "dds", "dng", "erf", "gif", "hdr",
-        "heic", "heif", "j2c", "jfif", "jls", "jp2", "jpeg", "jpf", "jpg", "jpm", "kdc", "mdc",
-        "mef", "mj2", "mos", "mrw", "nef", "nrw", "orf", "pef", "pgm", "png", "ppm", "raf", "raw",
-        "rw2", "sr2", "srf", "srw", "tif", "tiff", "webp", "x3f", "png_", "rpgmvp", "jbg", "jb2",
-    ];
+// Opening the file up front lets us tell "can't read" apart from "can't decode"
+pub fn check_readable(path: &PathBuf) -> Result<(), String> {
+    let result = if path.is_dir() {
+        std::fs::read_dir(path).map(|_| ())
+    } else {
+        std::fs::File::open(path).map(|_| ())
+    };
+
+    result.map_err(|err| describe_io_error(&err))
+}
+
+pub fn describe_io_error(err: &std::io::Error) -> String {
+    match err.kind() {
+        std::io::ErrorKind::PermissionDenied => "Permission denied".to_string(),
+        std::io::ErrorKind::NotFound => "File not found".to_string(),
+        _ => format!("Could not read file: {}", err),
+    }
+}
+
+// Windows drops trailing dots and spaces from file names, so "photo.JPG. " is treated as
+// "photo.jpg". Only the last extension counts, "photo.png.jpg" is a jpg, and dotfiles
+// like ".png" have no extension at all
+pub fn get_extension(file: &PathBuf) -> Option<String> {
+    let file_name = file.file_name()?.to_string_lossy();
+    let file_name = file_name.trim_end_matches(|c: char| c == '.' || c.is_whitespace());
+
+    let extension = std::path::Path::new(file_name)
+        .extension()?
+        .to_string_lossy();
+    let extension = extension.trim().to_lowercase();
+
+    if extension.is_empty() {
+        None
+    } else {
+        Some(extension)
+    }
+}
+
+pub const IMAGE_EXTENSIONS: [&str; 62] = [
+    "3fr", "arw", "avif", "bmp", "cr2", "crw", "cur", "dcm", "dds", "dng", "erf", "gif", "hdr",
+    "heic", "heif", "ico", "j2c", "jfif", "jls", "jp2", "jpeg", "jpf", "jpg", "jpm", "kdc", "mdc",
+    "mef", "mj2", "mos", "mrw", "nef", "nrw", "orf", "pef", "pgm", "png", "ppm", "raf", "raw",
+    "rw2", "sr2", "srf", "srw", "tif", "tiff", "webp", "x3f", "png_", "rpgmvp", "jbg", "jb2",
+    "exr", "ff", "pam", "pbm", "qoi", "tga", "dpx", "pcx", "pfm", "sgi", "xwd",
+];
+
+pub const VIDEO_EXTENSIONS: [&str; 20] = [
+    "3g2", "3gp", "asf", "avi", "flv", "m2ts", "m4v", "mjpeg", "mkv", "mov", "mp4", "mts", "mxf",
+    "rm", "rmvb", "swf", "ts", "vob", "webm", "wmv",
+];
 
-    let extension = file
-        .extension()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default()
-        .to_lowercase();
+// Image formats that only ffmpeg can decode
+const FFMPEG_ONLY_EXTENSIONS: [&str; 5] = ["dpx", "pcx", "pfm", "sgi", "xwd"];
 
-    extensions.contains(&extension.as_str())
+pub fn is_ffmpeg_only_image(file: &PathBuf) -> bool {
+    let extension = get_extension(file).unwrap_or_default();
+
+    FFMPEG_ONLY_EXTENSIONS.contains(&extension.as_str())
+}
+
+pub fn is_image(file: &PathBuf) -> bool {
+    let extension = get_extension(file).unwrap_or_default();
+
+    IMAGE_EXTENSIONS.contains(&extension.as_str())
 }
 
 pub fn is_video(file: &PathBuf) -> bool {
-    let video = [
-        "3g2", "3gp", "asf", "avi", "flv", "m2ts", "m4v", "mjpeg", "mkv", "mov", "mp4", "mts",
-        "mxf", "rm", "rmvb", "swf", "ts", "vob", "webm", "wmv",
-    ];
+    let extension = get_extension(file).unwrap_or_default();
 
-    let extension = file
-        .extension()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default()
-        .to_lowercase();
+    VIDEO_EXTENSIONS.contains(&extension.as_str())
+}
+
+// Image brands of the ISO base media container, everything else in there is treated as video
+const IMAGE_FTYP_BRANDS: [&[u8; 4]; 8] = [
+    b"avif", b"avis", b"heic", b"heix", b"heim", b"heis", b"mif1", b"msf1",
+];
+
+pub fn sniff_media_type(file: &PathBuf) -> Option<MediaType> {
+    let mut buffer = [0; 256];
+    let length = std::fs::File::open(file).ok()?.read(&mut buffer).ok()?;
+    let bytes = &buffer[..length];
+
+    if bytes.len() < 12 {
+        return None;
+    }
+
+    if &bytes[4..8] == b"ftyp" {
+        let brand = &bytes[8..12];
 
-    video.contains(&extension.as_str())
+        return if IMAGE_FTYP_BRANDS
+            .iter()
+            .any(|image_brand| &image_brand[..] == brand)
+        {
+            Some(MediaType::ImageStill)
+        } else {
+            Some(MediaType::Video)
+        };
+    }
+
+    let is_video = bytes.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) // Matroska, WebM
+        || (bytes.starts_with(b"RIFF") && &bytes[8..12] == b"AVI ")
+        || bytes.starts_with(b"FLV")
+        || bytes.starts_with(&[0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11]) // ASF, WMV
+        || bytes.starts_with(&[0x00, 0x00, 0x01, 0xBA]) // MPEG program stream, VOB
+        || bytes.starts_with(b".RMF")
+        || (bytes[0] == 0x47 && bytes.len() > 188 && bytes[188] == 0x47); // MPEG transport stream
+
+    if is_video {
+        return Some(MediaType::Video);
+    }
+
+    if image::guess_format(bytes).is_ok() {
+        return Some(MediaType::ImageStill);
+    }
+
+    // Still formats image doesn't know about, but ffmpeg can decode
+    let is_ffmpeg_image = bytes.starts_with(b"SDPX")
+        || bytes.starts_with(b"XPDS") // DPX, either byte order
+        || bytes.starts_with(&[0x01, 0xDA]) // SGI
+        || bytes.starts_with(b"PF\n")
+        || bytes.starts_with(b"Pf\n"); // PFM
+
+    if is_ffmpeg_image {
+        return Some(MediaType::ImageStill);
+    }
+
+    match ImageEntry::try_guess_format(file) {
+        Ok(ImageFormat::Dicom | ImageFormat::Rpgmv | ImageFormat::JpegLs) => {
+            Some(MediaType::ImageStill)
+        }
+        _ => None,
+    }
+}
+
+// The content wins over the extension, so a jpg saved as .mp4 still goes to the image decoders
+pub fn is_image_content(file: &PathBuf) -> bool {
+    match sniff_media_type(file) {
+        Some(MediaType::Video) => false,
+        Some(_) => true,
+        None => is_image(file) || is_no_extension(file),
+    }
 }
 
 pub fn is_no_extension(file: &PathBuf) -> bool {
-    file.extension().is_none()
+    get_extension(file).is_none()
+}
+
+pub fn is_symlink(path: &PathBuf) -> bool {
+    std::fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_type().is_symlink())
+        .unwrap_or(false)
 }
 
-pub fn get_files_recursive(path: &PathBuf) -> Vec<PathBuf> {
+// Paths passed in directly are always walked, the setting only applies to what is found inside
+pub fn get_files_recursive(path: &PathBuf, follow_symlinks: bool) -> Vec<PathBuf> {
     let mut queue = vec![path.clone()];
 
     let mut files = Vec::new();
 
     while let Some(current_path) = queue.pop() {
+        if is_shutting_down() {
+            break;
+        }
         if current_path.is_dir() {
             match std::fs::read_dir(&current_path) {
                 Ok(entries) => {
@@ -97,6 +400,10 @@ pub fn get_files_recursive(path: &PathBuf) -> Vec<PathBuf> {
                         if let Ok(entry) = entry {
                             let entry_path = entry.path();
 
+                            if !follow_symlinks && is_symlink(&entry_path) {
+                                continue;
+                            }
+
                             if entry_path.is_dir() {
                                 queue.push(entry_path);
                             } else {
@@ -106,7 +413,11 @@ pub fn get_files_recursive(path: &PathBuf) -> Vec<PathBuf> {
                     }
                 }
                 Err(err) => {
-                    println!("Error reading directory: {:?}", err);
+                    error!(
+                        "Error reading directory {:?}: {}",
+                        current_path,
+                        describe_io_error(&err)
+                    );
                 }
             }
         } else {
@@ -117,6 +428,14 @@ pub fn get_files_recursive(path: &PathBuf) -> Vec<PathBuf> {
     files
 }
 
+// Every RandomState starts from different keys, which is random enough for picking an entry
+pub fn random_index(len: usize) -> usize {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(len);
+
+    (hasher.finish() % len.max(1) as u64) as usize
+}
+
 pub fn calculate_thumbnail_layout(
     available_width: f32,
     min_thumbnail_width: f32,
@@ -135,6 +454,39 @@ pub fn calculate_thumbnail_layout(
     (1, available_width)
 }
 
+// Thumbnails are put into a row until it is wider than the available space at the target
+// height, then the row is scaled down to fit exactly. The last row is never stretched
+pub fn calculate_justified_rows(
+    aspect_ratios: &[f32],
+    available_width: f32,
+    target_height: f32,
+    gap: f32,
+) -> Vec<(Range<usize>, f32)> {
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut aspect_ratio_sum = 0.0;
+
+    for (index, aspect_ratio) in aspect_ratios.iter().enumerate() {
+        aspect_ratio_sum += aspect_ratio;
+
+        let total_gaps = (index - start) as f32 * gap;
+
+        if aspect_ratio_sum * target_height + total_gaps >= available_width {
+            let height = ((available_width - total_gaps) / aspect_ratio_sum).max(1.0);
+
+            rows.push((start..index + 1, height));
+            start = index + 1;
+            aspect_ratio_sum = 0.0;
+        }
+    }
+
+    if start < aspect_ratios.len() {
+        rows.push((start..aspect_ratios.len(), target_height));
+    }
+
+    rows
+}
+
 pub fn calculate_cover_size(
     container_width: f32,
     container_height: f32,
@@ -182,6 +534,93 @@ pub fn get_window_size(ctx: &egui::Context) -> egui::Vec2 {
     }
 }
 
+// Shortcuts shouldn't fire while the user is typing into a text field
+pub fn key_pressed(ctx: &egui::Context, key: egui::Key) -> bool {
+    !ctx.wants_keyboard_input() && ctx.input(|i| i.key_pressed(key))
+}
+
+pub fn validate_file_name(name: &str) -> Result<(), String> {
+    let illegal_characters = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+    let reserved_names = [
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+
+    if name.trim().is_empty() {
+        return Err("File name can't be empty".to_string());
+    }
+
+    if name == "." || name == ".." {
+        return Err(format!("\"{}\" is not a valid file name", name));
+    }
+
+    if let Some(c) = name
+        .chars()
+        .find(|c| illegal_characters.contains(c) || c.is_control())
+    {
+        return Err(format!(
+            "File name can't contain \"{}\"",
+            c.escape_default()
+        ));
+    }
+
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Err("File name can't end with a dot or a space".to_string());
+    }
+
+    let stem = name.split('.').next().unwrap_or_default().to_uppercase();
+
+    if reserved_names.contains(&stem.as_str()) {
+        return Err(format!("\"{}\" is a reserved file name", stem));
+    }
+
+    Ok(())
+}
+
+// Replaces every run of '#' in the pattern with the number, zero padded to the length of the run,
+// or appends the number if the pattern has no placeholder
+pub fn apply_rename_pattern(pattern: &str, number: usize) -> String {
+    if !pattern.contains('#') {
+        return format!("{}{}", pattern, number);
+    }
+
+    let mut name = String::new();
+    let mut rest = pattern;
+
+    while let Some(start) = rest.find('#') {
+        let width = rest[start..]
+            .find(|c| c != '#')
+            .unwrap_or(rest.len() - start);
+
+        name.push_str(&rest[..start]);
+        name.push_str(&format!("{:0width$}", number, width = width));
+        rest = &rest[start + width..];
+    }
+
+    name.push_str(rest);
+
+    name
+}
+
+pub fn get_texture_memory(texture: &TextureHandle) -> usize {
+    let [width, height] = texture.size();
+
+    width * height * 4
+}
+
+pub fn format_bytes(bytes: usize) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, units[unit])
+}
+
 pub fn print_time_elapsed(start: std::time::Instant) {
     let duration = start.elapsed();
     let secs = duration.as_secs();
@@ -189,7 +628,7 @@ pub fn print_time_elapsed(start: std::time::Instant) {
     let micros = duration.subsec_micros() - millis * 1_000;
     let nanos = duration.subsec_nanos() - millis * 1_000_000 - micros * 1_000;
 
-    println!(
+    debug!(
         "Time elapsed: {}s {}ms {}µs {}ns",
         secs, millis, micros, nanos
     );
@@ -203,11 +642,405 @@ pub fn format_time(ms: u64) -> String {
     format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
 }
 
-pub fn load_texture(texture_manager: SharedTextureManager, image: ColorImage) -> TextureHandle {
-    let name = "Texture".to_string();
-    let texture_id = texture_manager
-        .write()
-        .alloc(name, image.into(), Default::default());
+// Difference hash: compares the brightness of neighbouring cells on a 9x8 grid,
+// so it survives resizing and recompression of the same picture
+pub fn calculate_perceptual_hash(image: &ColorImage) -> u64 {
+    let [width, height] = image.size;
+
+    if width == 0 || height == 0 {
+        return 0;
+    }
+
+    let mut cells = [[0.0f32; 9]; 8];
+
+    for (y, row) in cells.iter_mut().enumerate() {
+        for (x, cell) in row.iter_mut().enumerate() {
+            let x_start = x * width / 9;
+            let x_end = ((x + 1) * width / 9).max(x_start + 1);
+            let y_start = y * height / 8;
+            let y_end = ((y + 1) * height / 8).max(y_start + 1);
+
+            // Sampling a few points per cell is enough and keeps this cheap for full-size images
+            let step_x = ((x_end - x_start) / 8).max(1);
+            let step_y = ((y_end - y_start) / 8).max(1);
+
+            let mut sum = 0.0;
+            let mut count = 0;
+
+            for pixel_y in (y_start..y_end).step_by(step_y) {
+                for pixel_x in (x_start..x_end).step_by(step_x) {
+                    let pixel = image.pixels[pixel_y * width + pixel_x];
+
+                    sum += 0.299 * pixel.r() as f32
+                        + 0.587 * pixel.g() as f32
+                        + 0.114 * pixel.b() as f32;
+                    count += 1;
+                }
+            }
+
+            *cell = sum / count as f32;
+        }
+    }
+
+    let mut hash = 0u64;
+
+    for row in cells.iter() {
+        for x in 0..8 {
+            hash <<= 1;
+
+            if row[x] > row[x + 1] {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash
+}
+
+// Sampled on a small grid, it only has to stand in for the thumbnail in the grid minimap
+pub fn calculate_average_color(image: &ColorImage) -> Color32 {
+    let [width, height] = image.size;
+
+    if width == 0 || height == 0 {
+        return Color32::TRANSPARENT;
+    }
+
+    let step_x = (width / 16).max(1);
+    let step_y = (height / 16).max(1);
+
+    let mut sum = [0u32; 3];
+    let mut count = 0;
+
+    for y in (0..height).step_by(step_y) {
+        for x in (0..width).step_by(step_x) {
+            let pixel = image.pixels[y * width + x];
+
+            sum[0] += pixel.r() as u32;
+            sum[1] += pixel.g() as u32;
+            sum[2] += pixel.b() as u32;
+            count += 1;
+        }
+    }
+
+    Color32::from_rgb(
+        (sum[0] / count) as u8,
+        (sum[1] / count) as u8,
+        (sum[2] / count) as u8,
+    )
+}
+
+// The sRGB transfer curve, from a linear value in 0..=1 to an 8 bit one
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+
+    let encoded = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+
+    (encoded * 255.0).round() as u8
+}
+
+thread_local! {
+    static TEXTURE_OPTIONS: Cell<TextureOptions> = Cell::new(TextureOptions::default());
+    // 0 when the preview isn't capped, or nothing being loaded is a preview
+    static MAX_PREVIEW_SIDE: Cell<usize> = Cell::new(0);
+    // 0 unless the whole image is loaded to be shown in the grid
+    static MAX_THUMBNAIL_SIDE: Cell<usize> = Cell::new(0);
+}
+
+// Textures are created deep inside the decoders, so instead of passing the options through
+// all of them they are set for everything loaded on this thread while `load` runs
+pub fn with_texture_options<R>(options: TextureOptions, load: impl FnOnce() -> R) -> R {
+    let previous = TEXTURE_OPTIONS.with(|cell| cell.replace(options));
+    let result = load();
+
+    TEXTURE_OPTIONS.with(|cell| cell.set(previous));
+
+    result
+}
+
+// Same as the texture options, the cap only applies to still images loaded while `load` runs
+pub fn with_preview_resolution<R>(settings: &Settings, load: impl FnOnce() -> R) -> R {
+    let max_side = if settings.cap_preview_resolution {
+        settings.max_preview_resolution as usize
+    } else {
+        0
+    };
 
-    TextureHandle::new(texture_manager, texture_id)
+    let previous = MAX_PREVIEW_SIDE.with(|cell| cell.replace(max_side));
+    let result = load();
+
+    MAX_PREVIEW_SIDE.with(|cell| cell.set(previous));
+
+    result
+}
+
+// Unlike the preview cap this one covers every texture, the frames of animations as well
+pub fn with_thumbnail_resolution<R>(max_side: usize, load: impl FnOnce() -> R) -> R {
+    let previous = MAX_THUMBNAIL_SIDE.with(|cell| cell.replace(max_side));
+    let result = load();
+
+    MAX_THUMBNAIL_SIDE.with(|cell| cell.set(previous));
+
+    result
+}
+
+// The smaller of two limits, 0 means there is none
+fn min_side(first: usize, second: usize) -> usize {
+    match (first, second) {
+        (0, max_side) | (max_side, 0) => max_side,
+        (first, second) => first.min(second),
+    }
+}
+
+// The pixels are premultiplied, which is what filtering them needs anyway
+fn scale_texture(image: &ColorImage, max_side: usize) -> Option<ColorImage> {
+    let [width, height] = image.size;
+    let scale = max_side as f32 / width.max(height) as f32;
+    let scaled_width = ((width as f32 * scale).round() as u32).clamp(1, max_side as u32);
+    let scaled_height = ((height as f32 * scale).round() as u32).clamp(1, max_side as u32);
+
+    let buffer = RgbaImage::from_raw(width as u32, height as u32, image.as_raw().to_vec())?;
+    let scaled =
+        image::imageops::resize(&buffer, scaled_width, scaled_height, FilterType::Triangle);
+
+    debug!(
+        "Scaled a {}x{} texture down to {}x{}",
+        width, height, scaled_width, scaled_height
+    );
+
+    Some(ColorImage::from_rgba_premultiplied(
+        [scaled_width as usize, scaled_height as usize],
+        scaled.as_raw(),
+    ))
+}
+
+fn limit_texture_side(image: ColorImage, max_side: usize) -> Result<ColorImage, String> {
+    let [width, height] = image.size;
+
+    if max_side == 0 || width.max(height) <= max_side {
+        return Ok(image);
+    }
+
+    scale_texture(&image, max_side).ok_or(format!(
+        "Failed to scale a {}x{} texture down to {} pixels",
+        width, height, max_side
+    ))
+}
+
+// The manager only queues the upload, the painter does it later on. So nothing here can tell if
+// it worked, an image larger than the backend supports is scaled down to fit beforehand instead
+pub fn load_texture(
+    texture_manager: SharedTextureManager,
+    image: ColorImage,
+) -> Result<TextureHandle, String> {
+    let backend_max_side = MAX_TEXTURE_SIDE.load(Ordering::SeqCst);
+    let thumbnail_max_side = MAX_THUMBNAIL_SIDE.with(|cell| cell.get());
+
+    upload_texture(
+        texture_manager,
+        image,
+        min_side(backend_max_side, thumbnail_max_side),
+    )
+}
+
+// Video frames, animations and thumbnails are left alone, only still previews get the user cap.
+// Whole images shown in the grid get the thumbnail cap either way
+pub fn load_still_texture(
+    texture_manager: SharedTextureManager,
+    image: ColorImage,
+) -> Result<TextureHandle, String> {
+    let backend_max_side = MAX_TEXTURE_SIDE.load(Ordering::SeqCst);
+    let preview_max_side = MAX_PREVIEW_SIDE.with(|cell| cell.get());
+    let thumbnail_max_side = MAX_THUMBNAIL_SIDE.with(|cell| cell.get());
+
+    let max_side = min_side(
+        min_side(backend_max_side, preview_max_side),
+        thumbnail_max_side,
+    );
+
+    upload_texture(texture_manager, image, max_side)
+}
+
+fn upload_texture(
+    texture_manager: SharedTextureManager,
+    image: ColorImage,
+    max_side: usize,
+) -> Result<TextureHandle, String> {
+    let [width, height] = image.size;
+
+    if width == 0 || height == 0 || image.pixels.len() != width * height {
+        return Err(format!(
+            "Texture of {}x{} has {} pixels",
+            width,
+            height,
+            image.pixels.len()
+        ));
+    }
+
+    let image = limit_texture_side(image, max_side)?;
+    let options = TEXTURE_OPTIONS.with(|cell| cell.get());
+    let texture_id = texture_manager.write().alloc(
+        "Texture".to_string(),
+        ImageData::Color(Arc::new(image)),
+        options,
+    );
+
+    Ok(TextureHandle::new(texture_manager, texture_id))
+}
+
+// Opens the url in the default browser
+pub fn open_url(url: &str) -> Result<(), String> {
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = std::process::Command::new("explorer");
+        command.arg(url);
+        command
+    };
+
+    #[cfg(target_os = "macos")]
+    let mut command = {
+        let mut command = std::process::Command::new("open");
+        command.arg(url);
+        command
+    };
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    let mut command = {
+        let mut command = std::process::Command::new("xdg-open");
+        command.arg(url);
+        command
+    };
+
+    // The opener returns right away, explorer even reports failure when it worked
+    command
+        .spawn()
+        .map_err(|err| format!("Could not open {}: {}", url, err))?;
+
+    Ok(())
+}
+
+// Opens the print dialog of the platform for the file. On Windows that's the one of the default
+// image viewer and on macOS the one of Preview. Linux has no dialog of its own, so a CUPS print
+// dialog is used when one is installed, otherwise the file opens in the default viewer to be
+// printed from there. Nothing is ever sent to a printer without a dialog
+pub fn print_file(path: &PathBuf) -> Result<(), String> {
+    // The path is handed over as data, never as a part of the script itself
+    #[cfg(windows)]
+    let commands = {
+        let mut command = std::process::Command::new("powershell");
+        command
+            .env("GALLERY_PRINT_PATH", path)
+            .arg("-NoProfile")
+            .arg("-Command")
+            .arg("Start-Process -FilePath $env:GALLERY_PRINT_PATH -Verb Print");
+        vec![command]
+    };
+
+    #[cfg(target_os = "macos")]
+    let commands = {
+        let mut command = std::process::Command::new("osascript");
+        command
+            .arg("-e")
+            .arg("on run argv")
+            .arg("-e")
+            .arg("tell application \"Preview\" to print (POSIX file (item 1 of argv)) with print dialog")
+            .arg("-e")
+            .arg("end run")
+            .arg(path);
+        vec![command]
+    };
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    let commands = ["gtklp", "xpp", "xdg-open"]
+        .into_iter()
+        .map(|program| {
+            let mut command = std::process::Command::new(program);
+            command.arg(path);
+            command
+        })
+        .collect::<Vec<std::process::Command>>();
+
+    let mut last_error = None;
+
+    // The dialogs stay open until the user is done with them, so nothing is waited for
+    for mut command in commands {
+        match command.spawn() {
+            Ok(_) => return Ok(()),
+            Err(err) => last_error = Some(err),
+        }
+    }
+
+    Err(match last_error {
+        Some(err) => format!("Could not open the print dialog: {}", err),
+        None => "Could not open the print dialog".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_is_lowercased() {
+        assert_eq!(
+            get_extension(&PathBuf::from("photo.JPG")),
+            Some("jpg".to_string())
+        );
+        assert_eq!(
+            get_extension(&PathBuf::from("photo.PNG_")),
+            Some("png_".to_string())
+        );
+        assert!(is_image(&PathBuf::from("photo.JPG")));
+        assert!(is_image(&PathBuf::from("photo.PNG_")));
+    }
+
+    #[test]
+    fn trailing_dots_and_spaces_are_ignored() {
+        assert_eq!(
+            get_extension(&PathBuf::from("photo.JPG. ")),
+            Some("jpg".to_string())
+        );
+        assert!(is_image(&PathBuf::from("photo.Jpg.")));
+    }
+
+    #[test]
+    fn ffmpeg_only_formats() {
+        assert!(is_ffmpeg_only_image(&PathBuf::from("scan.DPX")));
+        assert!(is_image(&PathBuf::from("scan.dpx")));
+        assert!(!is_ffmpeg_only_image(&PathBuf::from("photo.jpg")));
+    }
+
+    #[test]
+    fn files_without_extension() {
+        assert_eq!(get_extension(&PathBuf::from("photo")), None);
+        assert_eq!(get_extension(&PathBuf::from(".png")), None);
+        assert_eq!(get_extension(&PathBuf::from("photo.")), None);
+        assert!(!is_image(&PathBuf::from("photo")));
+        assert!(!is_image(&PathBuf::from(".png")));
+    }
+
+    #[test]
+    fn only_the_last_extension_counts() {
+        assert_eq!(
+            get_extension(&PathBuf::from("photo.png.jpg")),
+            Some("jpg".to_string())
+        );
+        assert_eq!(
+            get_extension(&PathBuf::from("photo.jpg.txt")),
+            Some("txt".to_string())
+        );
+        assert!(is_image(&PathBuf::from("photo.txt.PNG")));
+        assert!(!is_image(&PathBuf::from("photo.jpg.txt")));
+        assert!(is_video(&PathBuf::from("clip.jpg.MP4")));
+    }
+
+    #[test]
+    fn every_run_of_the_placeholder_is_replaced() {
+        assert_eq!(apply_rename_pattern("photo ###", 7), "photo 007");
+        assert_eq!(apply_rename_pattern("#-trip-##", 7), "7-trip-07");
+        assert_eq!(apply_rename_pattern("photo ", 12), "photo 12");
+    }
 }
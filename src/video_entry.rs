@@ -1,17 +1,24 @@
-use crate::{measure_time, utils::load_texture};
+use crate::{
+    measure_time,
+    utils::{calculate_contain_size, get_texture_memory, load_texture},
+};
 use eframe::{
     egui::{self, mutex::RwLock, Color32, ColorImage, TextureHandle},
     epaint::TextureManager,
 };
 use ffmpeg_next::{
     codec::context::Context as CodecContext,
-    decoder::{Audio as AudioDecoder, Video as VideoDecoder},
+    codec::subtitle::{Rect as SubtitleRect, Subtitle as SubtitleFrame},
+    decoder::{Audio as AudioDecoder, Subtitle as SubtitleDecoder, Video as VideoDecoder},
     format::{self, context::Input as InputContext, Pixel},
     frame::Audio as AudioFrame,
     frame::Video as VideoFrame,
-    media::Type::{Audio as AudioType, Video as VideoType},
+    media::Type as StreamType,
+    media::Type::{Audio as AudioType, Subtitle as SubtitleType, Video as VideoType},
     software::scaling::{context::Context as ScalingContext, flag::Flags},
+    Rational,
 };
+use log::{error, log_enabled, trace, warn, Level};
 use std::{
     collections::VecDeque,
     path::PathBuf,
@@ -52,23 +59,54 @@ impl FramesBuffer {
         self.frames.is_empty()
     }
 
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     pub fn should_fill_buffer(&self) -> bool {
         self.frames.len() < self.size
     }
 }
 
+pub struct StreamInfo {
+    pub index: usize,
+    pub label: String,
+}
+
+pub struct SubtitleCue {
+    pub text: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+pub struct FilmstripFrame {
+    pub time: u64,
+    pub texture: TextureHandle,
+}
+
 pub struct VideoEntry {
     pub path: PathBuf,
     pub video_decoder: VideoDecoder,
-    pub audio_decoder: AudioDecoder,
+    // Both are None when audio is disabled in the settings
+    pub audio_decoder: Option<AudioDecoder>,
     pub scaler: ScalingContext,
     pub video_input_ctx: InputContext,
-    pub audio_input_ctx: InputContext,
+    pub audio_input_ctx: Option<InputContext>,
     pub video_stream_index: usize,
     pub audio_stream_index: usize,
-    pub audio_sink: rodio::Sink,
-    pub audio_playback_stream: rodio::OutputStream,
-    pub audio_stream_handle: rodio::OutputStreamHandle,
+    pub audio_streams: Vec<StreamInfo>,
+    pub subtitle_streams: Vec<StreamInfo>,
+    pub subtitle_stream_index: Option<usize>,
+    pub current_subtitle: Option<SubtitleCue>,
+    subtitle_decoder: Option<SubtitleDecoder>,
+    pub audio_sink: Option<rodio::Sink>,
+    pub audio_playback_stream: Option<rodio::OutputStream>,
+    pub audio_stream_handle: Option<rodio::OutputStreamHandle>,
+    audio_initialized: bool,
     pub frames: Arc<Mutex<Vec<egui::TextureHandle>>>,
     pub frame_rate: f64,
     pub last_frame_time: Instant,
@@ -76,19 +114,49 @@ pub struct VideoEntry {
     pub current_time: u64,
     pub video_duration: u64,
     pub is_playing: bool,
+    pub pace_repaints: bool,
+    pub loop_start: Option<u64>,
+    pub loop_end: Option<u64>,
+    pub filmstrip: Vec<FilmstripFrame>,
     cached_frame: Option<egui::TextureHandle>,
+    // Textures can't be read back, so the frame behind the cached texture is kept as well
+    shown_frame: Option<VideoFrame>,
     eof_reached: bool,
     frames_buffer: FramesBuffer,
 }
 
-#[inline]
-fn video_frame_to_image(frame: VideoFrame) -> ColorImage {
+// The rows are read as RGBA, which is what the scalers are set up to produce. Anything else
+// would come out with garbled colors, so it's refused instead
+pub fn video_frame_to_color_image(frame: &VideoFrame) -> Result<ColorImage, String> {
+    if frame.format() != Pixel::RGBA {
+        return Err(format!(
+            "Video frame has {:?} pixels instead of RGBA",
+            frame.format()
+        ));
+    }
+
     let size = [frame.width() as usize, frame.height() as usize];
     let data = frame.data(0);
     let stride = frame.stride(0);
     let pixel_size_bytes = 4;
     let width: usize = pixel_size_bytes * frame.width() as usize;
     let height: usize = frame.height() as usize;
+
+    // Rows are read with the stride, so a frame that doesn't hold all of them
+    // would panic on slicing instead of just looking wrong
+    let is_valid =
+        width > 0 && height > 0 && stride >= width && data.len() >= stride * (height - 1) + width;
+
+    if !is_valid {
+        return Err(format!(
+            "Unexpected video frame layout: {}x{}, stride {}, {} bytes",
+            frame.width(),
+            frame.height(),
+            stride,
+            data.len()
+        ));
+    }
+
     let mut pixels = Vec::new();
 
     for line in 0..height {
@@ -102,91 +170,284 @@ fn video_frame_to_image(frame: VideoFrame) -> ColorImage {
         )
     }
 
-    ColorImage { size, pixels }
+    Ok(ColorImage { size, pixels })
 }
 
-pub fn video_frame_to_texture(frame: VideoFrame, ctx: &egui::Context) -> Option<TextureHandle> {
+// Playback goes on with a black pixel instead of a frame that can't be shown
+#[inline]
+fn video_frame_to_image(frame: &VideoFrame) -> ColorImage {
+    video_frame_to_color_image(frame).unwrap_or_else(|err| {
+        error!("{}", err);
+        ColorImage::new([1, 1], Color32::BLACK)
+    })
+}
+
+pub fn video_frame_to_texture(frame: &VideoFrame, ctx: &egui::Context) -> Option<TextureHandle> {
     let texture_manager = ctx.tex_manager();
     let color_image = video_frame_to_image(frame);
 
-    Some(load_texture(texture_manager, color_image))
+    match load_texture(texture_manager, color_image) {
+        Ok(texture) => Some(texture),
+        Err(err) => {
+            error!("Failed to create the video frame texture: {}", err);
+            None
+        }
+    }
 }
-impl VideoEntry {
-    pub fn new(video_path: &PathBuf) -> Option<Self> {
-        let video_input_ctx = match format::input(&video_path) {
-            Ok(ictx) => ictx,
-            Err(err) => {
-                println!("Error opening video file: {:?}", err);
 
-                return None;
+fn collect_streams(input_ctx: &InputContext, stream_type: StreamType) -> Vec<StreamInfo> {
+    input_ctx
+        .streams()
+        .filter(|stream| stream.parameters().medium() == stream_type)
+        .enumerate()
+        .map(|(i, stream)| {
+            let metadata = stream.metadata();
+            let mut label = format!("Track {}", i + 1);
+
+            if let Some(language) = metadata.get("language") {
+                label.push_str(&format!(" [{}]", language));
             }
-        };
 
-        let audio_input_ctx = match format::input(&video_path) {
-            Ok(ictx) => ictx,
-            Err(err) => {
-                println!("Error opening video file: {:?}", err);
+            if let Some(title) = metadata.get("title") {
+                label.push_str(&format!(" {}", title));
+            }
 
-                return None;
+            StreamInfo {
+                index: stream.index(),
+                label,
             }
-        };
+        })
+        .collect()
+}
 
-        let streams = video_input_ctx.streams();
+// ASS events look like "ReadOrder,Layer,Style,Name,MarginL,MarginR,MarginV,Effect,Text",
+// where the text itself can contain style overrides in braces and "\N" line breaks
+fn ass_to_plain_text(ass: &str) -> String {
+    let text = ass.splitn(9, ',').last().unwrap_or_default();
 
-        let video_stream = match streams.best(VideoType) {
+    let mut plain_text = String::new();
+    let mut in_override = false;
+
+    for c in text.chars() {
+        match c {
+            '{' => in_override = true,
+            '}' => in_override = false,
+            _ if !in_override => plain_text.push(c),
+            _ => {}
+        }
+    }
+
+    plain_text.replace("\\N", "\n").replace("\\n", "\n")
+}
+
+const FILMSTRIP_FRAMES_COUNT: usize = 10;
+const FILMSTRIP_FRAME_SIZE: f32 = 160.0;
+
+// Grabs evenly spaced frames from a separate input context, so the playback
+// contexts stay at the start of the file
+fn load_filmstrip(
+    video_path: &PathBuf,
+    video_duration: u64,
+    ctx: &egui::Context,
+) -> Result<Vec<FilmstripFrame>, Box<dyn std::error::Error>> {
+    let mut ictx = format::input(video_path)?;
+    let input = ictx
+        .streams()
+        .best(VideoType)
+        .ok_or("No video stream found")?;
+
+    let video_stream_index = input.index();
+    let context = CodecContext::from_parameters(input.parameters())?;
+    let mut decoder = context.decoder().video()?;
+
+    let (width, height) = calculate_contain_size(
+        FILMSTRIP_FRAME_SIZE,
+        FILMSTRIP_FRAME_SIZE,
+        decoder.width() as f32,
+        decoder.height() as f32,
+    );
+
+    let mut scaler = ScalingContext::get(
+        decoder.format(),
+        decoder.width(),
+        decoder.height(),
+        Pixel::RGBA,
+        width.trunc() as u32,
+        height.trunc() as u32,
+        Flags::BILINEAR,
+    )?;
+
+    let mut filmstrip = Vec::new();
+
+    if video_duration == 0 {
+        return Ok(filmstrip);
+    }
+
+    for i in 0..FILMSTRIP_FRAMES_COUNT {
+        let time = video_duration * (2 * i as u64 + 1) / (2 * FILMSTRIP_FRAMES_COUNT as u64);
+
+        // Seeking without a stream index expects the timestamp in AV_TIME_BASE units
+        let timestamp = time as i64 * 1000;
+
+        if let Err(err) = ictx.seek(timestamp, ..timestamp) {
+            error!("Error seeking video for filmstrip: {:?}", err);
+
+            continue;
+        }
+
+        decoder.flush();
+
+        for (stream, packet) in ictx.packets() {
+            if stream.index() != video_stream_index {
+                continue;
+            }
+
+            if decoder.send_packet(&packet).is_err() {
+                continue;
+            }
+
+            let mut decoded = VideoFrame::empty();
+
+            if decoder.receive_frame(&mut decoded).is_ok() {
+                let mut frame = VideoFrame::empty();
+                scaler.run(&decoded, &mut frame)?;
+
+                if let Some(texture) = video_frame_to_texture(&frame, ctx) {
+                    filmstrip.push(FilmstripFrame { time, texture });
+                }
+
+                break;
+            }
+        }
+    }
+
+    Ok(filmstrip)
+}
+
+// Some containers store 0/0 or absurd rates. The playback waits 1 / rate between frames,
+// so anything outside of this range would stall or race
+fn valid_frame_rate(rate: Rational) -> Option<f64> {
+    let rate = f64::from(rate);
+
+    (rate.is_finite() && rate > 0.0 && rate <= 1000.0).then_some(rate)
+}
+
+// The audio track is read through its own input context, so it can be decoded ahead of the video
+fn open_audio_decoder(
+    video_path: &PathBuf,
+    stream_index: Option<usize>,
+) -> Option<(InputContext, AudioDecoder, usize)> {
+    let audio_input_ctx = match format::input(&video_path) {
+        Ok(ictx) => ictx,
+        Err(err) => {
+            error!("Error opening video file: {:?}", err);
+
+            return None;
+        }
+    };
+
+    let audio_stream = match stream_index {
+        Some(stream_index) => match audio_input_ctx.stream(stream_index) {
             Some(stream) => stream,
             None => {
-                println!("No video stream found in file: {:?}", video_path);
+                error!("No audio stream with index {} found", stream_index);
 
                 return None;
             }
-        };
-
-        let audio_stream = match streams.best(AudioType) {
+        },
+        None => match audio_input_ctx.streams().best(AudioType) {
             Some(stream) => stream,
             None => {
-                println!("No audio stream found in file: {:?}", video_path);
+                error!("No audio stream found in file: {:?}", video_path);
 
                 return None;
             }
-        };
+        },
+    };
 
-        let video_decoder_ctx = match CodecContext::from_parameters(video_stream.parameters()) {
-            Ok(context) => context,
+    let audio_stream_index = audio_stream.index();
+
+    let audio_decoder_ctx = match CodecContext::from_parameters(audio_stream.parameters()) {
+        Ok(context) => context,
+        Err(err) => {
+            error!("Error creating audio codec context: {:?}", err);
+
+            return None;
+        }
+    };
+
+    let audio_decoder = match audio_decoder_ctx.decoder().audio() {
+        Ok(decoder) => decoder,
+        Err(err) => {
+            error!("Error creating audio decoder: {:?}", err);
+
+            return None;
+        }
+    };
+
+    Some((audio_input_ctx, audio_decoder, audio_stream_index))
+}
+
+impl VideoEntry {
+    pub fn new(
+        video_path: &PathBuf,
+        audio_enabled: bool,
+        fallback_frame_rate: f64,
+        ctx: &egui::Context,
+    ) -> Option<Self> {
+        let video_input_ctx = match format::input(&video_path) {
+            Ok(ictx) => ictx,
             Err(err) => {
-                println!("Error creating codec context: {:?}", err);
+                error!("Error opening video file: {:?}", err);
 
                 return None;
             }
         };
 
-        let audio_decoder_ctx = match CodecContext::from_parameters(audio_stream.parameters()) {
-            Ok(context) => context,
-            Err(err) => {
-                println!("Error creating audio codec context: {:?}", err);
+        let streams = video_input_ctx.streams();
+
+        let video_stream = match streams.best(VideoType) {
+            Some(stream) => stream,
+            None => {
+                error!("No video stream found in file: {:?}", video_path);
 
                 return None;
             }
         };
 
-        let video_decoder = match video_decoder_ctx.decoder().video() {
-            Ok(decoder) => decoder,
+        let video_decoder_ctx = match CodecContext::from_parameters(video_stream.parameters()) {
+            Ok(context) => context,
             Err(err) => {
-                println!("Error creating video decoder: {:?}", err);
+                error!("Error creating codec context: {:?}", err);
 
                 return None;
             }
         };
 
-        let audio_decoder = match audio_decoder_ctx.decoder().audio() {
+        let video_decoder = match video_decoder_ctx.decoder().video() {
             Ok(decoder) => decoder,
             Err(err) => {
-                println!("Error creating audio decoder: {:?}", err);
+                error!("Error creating video decoder: {:?}", err);
 
                 return None;
             }
         };
 
+        // With audio disabled the audio track isn't opened at all, so the video plays
+        // silently without going anywhere near the audio decoder or the output device
+        let (audio_input_ctx, audio_decoder, audio_stream_index) = if audio_enabled {
+            let (audio_input_ctx, audio_decoder, audio_stream_index) =
+                open_audio_decoder(video_path, None)?;
+
+            (
+                Some(audio_input_ctx),
+                Some(audio_decoder),
+                audio_stream_index,
+            )
+        } else {
+            (None, None, 0)
+        };
+
         let scaler = match ScalingContext::get(
             video_decoder.format(),
             video_decoder.width(),
@@ -198,17 +459,28 @@ impl VideoEntry {
         ) {
             Ok(scaler) => scaler,
             Err(err) => {
-                println!("Error creating scaler context: {:?}", err);
+                error!("Error creating scaler context: {:?}", err);
 
                 return None;
             }
         };
 
-        let (stream, stream_handle) = rodio::OutputStream::try_default().unwrap();
-        let sink = rodio::Sink::try_new(&stream_handle).unwrap();
+        let audio_streams = match audio_enabled {
+            true => collect_streams(&video_input_ctx, AudioType),
+            false => Vec::new(),
+        };
+        let subtitle_streams = collect_streams(&video_input_ctx, SubtitleType);
+
+        let frame_rate = valid_frame_rate(video_stream.avg_frame_rate())
+            .or_else(|| valid_frame_rate(video_stream.rate()))
+            .unwrap_or_else(|| {
+                warn!(
+                    "No usable frame rate in {:?}, playing at {} fps",
+                    video_path, fallback_frame_rate
+                );
 
-        let frame_rate =
-            video_stream.avg_frame_rate().0 as f64 / video_stream.avg_frame_rate().1 as f64;
+                fallback_frame_rate
+            });
 
         let video_duration = match video_stream.duration() {
             duration if duration >= 0 => {
@@ -218,14 +490,26 @@ impl VideoEntry {
             _ => match video_input_ctx.duration() {
                 duration if duration >= 0 => (duration as f64 / 1000.0).round() as u64,
                 _ => {
-                    println!("Warning: Could not determine video duration");
+                    warn!("Could not determine video duration");
 
                     0
                 }
             },
         };
 
-        let mut entry = VideoEntry {
+        let filmstrip = match measure_time!(
+            "Filmstrip decode",
+            load_filmstrip(video_path, video_duration, ctx)
+        ) {
+            Ok(filmstrip) => filmstrip,
+            Err(err) => {
+                error!("Error loading filmstrip: {:?}", err);
+
+                Vec::new()
+            }
+        };
+
+        let entry = VideoEntry {
             current_time: 0,
             video_duration,
             path: video_path.clone(),
@@ -233,12 +517,18 @@ impl VideoEntry {
             audio_decoder,
             scaler,
             video_stream_index: video_stream.index(),
-            audio_stream_index: audio_stream.index(),
+            audio_stream_index,
+            audio_streams,
+            subtitle_streams,
+            subtitle_stream_index: None,
+            current_subtitle: None,
+            subtitle_decoder: None,
             video_input_ctx,
             audio_input_ctx,
-            audio_sink: sink,
-            audio_playback_stream: stream,
-            audio_stream_handle: stream_handle,
+            audio_sink: None,
+            audio_playback_stream: None,
+            audio_stream_handle: None,
+            audio_initialized: false,
             frames: Arc::new(Mutex::new(Vec::new())),
             frame_rate,
             last_frame_time: Instant::now(),
@@ -246,15 +536,72 @@ impl VideoEntry {
             eof_reached: false,
             frames_buffer: FramesBuffer::new(),
             is_playing: false,
+            pace_repaints: true,
+            loop_start: None,
+            loop_end: None,
+            filmstrip,
             cached_frame: None,
+            shown_frame: None,
         };
 
-        entry.decode_next_audio_packet();
-
         Some(entry)
     }
 
+    // Decoding the audio track takes a while, so it waits until the video is played
+    // for the first time instead of delaying the first frame
+    fn init_audio(&mut self) {
+        if self.audio_initialized || self.audio_decoder.is_none() {
+            return;
+        }
+
+        self.audio_initialized = true;
+
+        // Machines without an audio device still get the visual track, just without sound
+        match rodio::OutputStream::try_default() {
+            Ok((stream, stream_handle)) => match rodio::Sink::try_new(&stream_handle) {
+                Ok(sink) => {
+                    self.audio_sink = Some(sink);
+                    self.audio_playback_stream = Some(stream);
+                    self.audio_stream_handle = Some(stream_handle);
+                }
+                Err(err) => {
+                    warn!(
+                        "Error creating audio sink, playing without sound: {:?}",
+                        err
+                    );
+
+                    return;
+                }
+            },
+            Err(err) => {
+                warn!(
+                    "Error opening audio output stream, playing without sound: {:?}",
+                    err
+                );
+
+                return;
+            }
+        };
+
+        self.decode_next_audio_packet();
+
+        if let Some(sink) = &self.audio_sink {
+            if let Err(err) = sink.try_seek(Duration::from_millis(self.current_time)) {
+                error!("Error seeking audio: {:?}", err);
+            }
+        }
+    }
+
     pub fn decode_next_audio_packet(&mut self) {
+        let (audio_sink, audio_decoder, audio_input_ctx) = match (
+            &self.audio_sink,
+            &mut self.audio_decoder,
+            &mut self.audio_input_ctx,
+        ) {
+            (Some(sink), Some(decoder), Some(input_ctx)) => (sink, decoder, input_ctx),
+            _ => return,
+        };
+
         let receive_and_process_decoded_audio = |decoder: &mut AudioDecoder| {
             let mut decoded = AudioFrame::empty();
 
@@ -266,26 +613,26 @@ impl VideoEntry {
             None
         };
 
-        let sample_rate = self.audio_decoder.rate();
-        let channel_count = self.audio_decoder.channel_layout().channels();
+        let sample_rate = audio_decoder.rate();
+        let channel_count = audio_decoder.channel_layout().channels();
 
-        for (stream, packet) in self.audio_input_ctx.packets() {
+        for (stream, packet) in audio_input_ctx.packets() {
             if stream.index() == self.audio_stream_index {
-                match self.audio_decoder.send_packet(&packet) {
+                match audio_decoder.send_packet(&packet) {
                     Ok(_) => {}
                     Err(err) => {
-                        println!("Error sending audio packet to decoder: {:?}", err);
+                        error!("Error sending audio packet to decoder: {:?}", err);
                     }
                 };
 
-                if let Some(samples) = receive_and_process_decoded_audio(&mut self.audio_decoder) {
+                if let Some(samples) = receive_and_process_decoded_audio(audio_decoder) {
                     let source = rodio::buffer::SamplesBuffer::new(
                         channel_count as u16,
                         sample_rate / channel_count as u32,
                         samples,
                     );
 
-                    self.audio_sink.append(source);
+                    audio_sink.append(source);
                 }
             }
         }
@@ -298,10 +645,36 @@ impl VideoEntry {
             while decoder.receive_frame(&mut decoded).is_ok() {
                 let mut frame = VideoFrame::empty();
 
+                // Streams can change their size or pixel format midway, the scaler would refuse
+                // those frames, so it's set up again for them and the frames stay RGBA
+                let input = self.scaler.input();
+                let input_changed = input.format != decoded.format()
+                    || input.width != decoded.width()
+                    || input.height != decoded.height();
+
+                if input_changed {
+                    warn!(
+                        "Video frames changed to {}x{} {:?}",
+                        decoded.width(),
+                        decoded.height(),
+                        decoded.format()
+                    );
+
+                    self.scaler.cached(
+                        decoded.format(),
+                        decoded.width(),
+                        decoded.height(),
+                        Pixel::RGBA,
+                        decoded.width(),
+                        decoded.height(),
+                        Flags::BILINEAR,
+                    );
+                }
+
                 match self.scaler.run(&decoded, &mut frame) {
                     Ok(_) => {}
                     Err(err) => {
-                        println!("Error scaling frame: {:?}", err);
+                        error!("Error scaling frame: {:?}", err);
 
                         return None;
                     }
@@ -314,13 +687,55 @@ impl VideoEntry {
         };
 
         for (stream, packet) in self.video_input_ctx.packets() {
+            if Some(stream.index()) == self.subtitle_stream_index {
+                let decoder = match self.subtitle_decoder.as_mut() {
+                    Some(decoder) => decoder,
+                    None => continue,
+                };
+
+                let mut subtitle = SubtitleFrame::new();
+
+                match decoder.decode(&packet, &mut subtitle) {
+                    Ok(true) => {
+                        let packet_time = (packet.pts().unwrap_or(0) as f64
+                            * f64::from(stream.time_base())
+                            * 1000.0)
+                            .round() as u64;
+
+                        let text = subtitle
+                            .rects()
+                            .filter_map(|rect| match rect {
+                                SubtitleRect::Text(text) => Some(text.get().to_string()),
+                                SubtitleRect::Ass(ass) => Some(ass_to_plain_text(ass.get())),
+                                _ => None,
+                            })
+                            .collect::<Vec<String>>()
+                            .join("\n");
+
+                        let start = packet_time + subtitle.start() as u64;
+                        let end = match subtitle.end() {
+                            0 => start + 3000,
+                            end => packet_time + end as u64,
+                        };
+
+                        self.current_subtitle = Some(SubtitleCue { text, start, end });
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        error!("Error decoding subtitle packet: {:?}", err);
+                    }
+                }
+
+                continue;
+            }
+
             if stream.index() == self.video_stream_index {
                 let current_pts = packet.pts().unwrap_or(0);
 
                 match self.video_decoder.send_packet(&packet) {
                     Ok(_) => {}
                     Err(err) => {
-                        println!("Error sending packet to decoder: {:?}", err);
+                        error!("Error sending packet to decoder: {:?}", err);
 
                         return None;
                     }
@@ -364,13 +779,16 @@ impl VideoEntry {
             texture_handle = match self.cached_frame {
                 Some(ref tex) => Some(tex.clone()),
                 None => match self.frames_buffer.front() {
-                    Some(frame) => video_frame_to_texture(frame, ctx),
+                    Some(frame) => self.show_frame(frame, ctx),
                     None => None,
                 },
             };
 
             self.cached_frame = texture_handle.clone();
-            self.audio_sink.pause();
+
+            if let Some(sink) = &self.audio_sink {
+                sink.pause();
+            }
 
             return texture_handle;
         }
@@ -390,47 +808,90 @@ impl VideoEntry {
             }
 
             texture_handle = match self.frames_buffer.pop() {
-                Some(frame) => video_frame_to_texture(frame, ctx),
+                Some(frame) => self.show_frame(frame, ctx),
                 None => None,
             };
         } else {
             texture_handle = match self.cached_frame {
                 Some(ref tex) => Some(tex.clone()),
                 None => match self.frames_buffer.front() {
-                    Some(frame) => video_frame_to_texture(frame, ctx),
+                    Some(frame) => self.show_frame(frame, ctx),
                     None => None,
                 },
             };
         }
 
-        if self.frames_buffer.is_empty() {
-            self.audio_sink.pause();
-        } else {
-            self.audio_sink.play();
+        if let (Some(loop_start), Some(loop_end)) = (self.loop_start, self.loop_end) {
+            if self.current_time >= loop_end {
+                self.seek(loop_start);
+            }
         }
 
-        let audio_pos = self.audio_sink.get_pos().as_millis() as u64;
-        let video_pos = self.current_time;
+        if let Some(sink) = &self.audio_sink {
+            if self.frames_buffer.is_empty() {
+                sink.pause();
+            } else {
+                sink.play();
+            }
+        }
 
-        println!("Video pos: {}, Audio pos: {}", video_pos, audio_pos);
+        // A/V sync diagnostics run every frame, so they are opt in with RUST_LOG=av_sync=trace
+        if log_enabled!(target: "av_sync", Level::Trace) {
+            let audio_pos = match &self.audio_sink {
+                Some(sink) => sink.get_pos().as_millis() as u64,
+                None => 0,
+            };
+            let video_pos = self.current_time;
+
+            trace!(
+                target: "av_sync",
+                "Video pos: {}, Audio pos: {}, drift: {}ms",
+                video_pos,
+                audio_pos,
+                video_pos as i64 - audio_pos as i64
+            );
+        }
 
         /*   if (audio_pos as i64 - video_pos as i64).abs() > 60 {
             match self.audio_sink.try_seek(Duration::from_millis(video_pos)) {
                 Ok(_) => {}
                 Err(err) => {
-                    println!("Error seeking audio: {:?}", err);
+                    error!("Error seeking audio: {:?}", err);
                 }
             }
         } */
 
         self.cached_frame = texture_handle.clone();
 
-        ctx.request_repaint();
+        // Waking up right when the next frame is due keeps the frame times even,
+        // instead of polling as fast as the display allows
+        let frame_duration = 1.0 / self.frame_rate;
+
+        if self.pace_repaints && frame_duration.is_finite() {
+            let until_next_frame =
+                (frame_duration - self.last_frame_time.elapsed().as_secs_f64()).max(0.0);
+
+            ctx.request_repaint_after(Duration::from_secs_f64(until_next_frame));
+        } else {
+            ctx.request_repaint();
+        }
 
         texture_handle
     }
 
+    fn show_frame(&mut self, frame: VideoFrame, ctx: &egui::Context) -> Option<TextureHandle> {
+        let texture_handle = video_frame_to_texture(&frame, ctx);
+        self.shown_frame = Some(frame);
+
+        texture_handle
+    }
+
+    pub fn current_frame_image(&self) -> Option<ColorImage> {
+        self.shown_frame.as_ref().map(video_frame_to_image)
+    }
+
     pub fn play(&mut self) {
+        self.init_audio();
         self.is_playing = true;
     }
 
@@ -457,18 +918,21 @@ impl VideoEntry {
         match self.video_input_ctx.seek(pts, 0..i64::MAX) {
             Ok(_) => {}
             Err(err) => {
-                println!("Error seeking video: {:?}", err);
+                error!("Error seeking video: {:?}", err);
             }
         }
 
-        match self.audio_sink.try_seek(Duration::from_millis(time)) {
-            Ok(_) => {}
-            Err(err) => {
-                println!("Error seeking audio: {:?}", err);
+        if let Some(sink) = &self.audio_sink {
+            match sink.try_seek(Duration::from_millis(time)) {
+                Ok(_) => {}
+                Err(err) => {
+                    error!("Error seeking audio: {:?}", err);
+                }
             }
         }
 
         self.frames_buffer.clear();
+        self.current_subtitle = None;
         self.current_time = time;
     }
 
@@ -477,4 +941,143 @@ impl VideoEntry {
 
         self.seek(new_time as u64);
     }
+
+    pub fn set_loop_start(&mut self) {
+        self.loop_start = Some(self.current_time);
+
+        if let Some(loop_end) = self.loop_end {
+            if loop_end <= self.current_time {
+                self.loop_end = None;
+            }
+        }
+    }
+
+    pub fn set_loop_end(&mut self) {
+        let loop_start = self.loop_start.unwrap_or(0);
+
+        if self.current_time <= loop_start {
+            return;
+        }
+
+        self.loop_start = Some(loop_start);
+        self.loop_end = Some(self.current_time);
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.loop_start = None;
+        self.loop_end = None;
+    }
+
+    pub fn select_audio_stream(&mut self, stream_index: usize) {
+        if stream_index == self.audio_stream_index || self.audio_decoder.is_none() {
+            return;
+        }
+
+        let (audio_input_ctx, audio_decoder, stream_index) =
+            match open_audio_decoder(&self.path, Some(stream_index)) {
+                Some(audio) => audio,
+                None => return,
+            };
+
+        self.audio_input_ctx = Some(audio_input_ctx);
+        self.audio_decoder = Some(audio_decoder);
+        self.audio_stream_index = stream_index;
+
+        // The sink holds the whole decoded track, so rebuild it and jump back to where we were
+        if let Some(sink) = &self.audio_sink {
+            sink.clear();
+        }
+
+        self.decode_next_audio_packet();
+
+        if let Some(sink) = &self.audio_sink {
+            match sink.try_seek(Duration::from_millis(self.current_time)) {
+                Ok(_) => {}
+                Err(err) => {
+                    error!("Error seeking audio: {:?}", err);
+                }
+            }
+        }
+    }
+
+    pub fn select_subtitle_stream(&mut self, stream_index: Option<usize>) {
+        if stream_index == self.subtitle_stream_index {
+            return;
+        }
+
+        self.current_subtitle = None;
+        self.subtitle_decoder = None;
+        self.subtitle_stream_index = None;
+
+        let stream_index = match stream_index {
+            Some(index) => index,
+            None => return,
+        };
+
+        let subtitle_stream = match self.video_input_ctx.stream(stream_index) {
+            Some(stream) => stream,
+            None => {
+                error!("No subtitle stream with index {} found", stream_index);
+
+                return;
+            }
+        };
+
+        let subtitle_decoder_ctx = match CodecContext::from_parameters(subtitle_stream.parameters())
+        {
+            Ok(context) => context,
+            Err(err) => {
+                error!("Error creating subtitle codec context: {:?}", err);
+
+                return;
+            }
+        };
+
+        match subtitle_decoder_ctx.decoder().subtitle() {
+            Ok(decoder) => {
+                self.subtitle_decoder = Some(decoder);
+                self.subtitle_stream_index = Some(stream_index);
+            }
+            Err(err) => {
+                error!("Error creating subtitle decoder: {:?}", err);
+            }
+        }
+    }
+
+    pub fn get_buffer_fill(&self) -> (usize, usize) {
+        (self.frames_buffer.len(), self.frames_buffer.size())
+    }
+
+    pub fn get_texture_memory(&self) -> usize {
+        let frames_memory: usize = self
+            .frames
+            .lock()
+            .unwrap()
+            .iter()
+            .map(get_texture_memory)
+            .sum();
+
+        let filmstrip_memory: usize = self
+            .filmstrip
+            .iter()
+            .map(|frame| get_texture_memory(&frame.texture))
+            .sum();
+
+        let cached_frame_memory = self
+            .cached_frame
+            .as_ref()
+            .map(get_texture_memory)
+            .unwrap_or(0);
+
+        frames_memory + filmstrip_memory + cached_frame_memory
+    }
+
+    pub fn get_current_subtitle(&self) -> Option<&str> {
+        match &self.current_subtitle {
+            Some(cue) if self.current_time >= cue.start && self.current_time <= cue.end => {
+                Some(cue.text.as_str())
+            }
+            _ => None,
+        }
+    }
 }
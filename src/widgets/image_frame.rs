@@ -1,17 +1,106 @@
 use std::path::PathBuf;
 
-use eframe::egui::{FontId, Pos2, Stroke};
+use eframe::egui::{Color32, FontId, Pos2, Rect, Stroke};
 
 use crate::egui::epaint::TextureHandle;
 use crate::egui::{self, Response, Sense, Ui, Widget};
-use crate::utils::calculate_contain_size;
+use crate::utils::{calculate_contain_size, calculate_cover_size};
+
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum ThumbnailFit {
+    #[default]
+    Contain,
+    Cover,
+    Square,
+}
+
+impl ThumbnailFit {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ThumbnailFit::Contain => "Contain",
+            ThumbnailFit::Cover => "Cover",
+            ThumbnailFit::Square => "Square crop",
+        }
+    }
+}
+
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum SelectionStyle {
+    #[default]
+    Border,
+    Dim,
+    Checkmark,
+}
+
+impl SelectionStyle {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SelectionStyle::Border => "Border",
+            SelectionStyle::Dim => "Dim",
+            SelectionStyle::Checkmark => "Checkmark only",
+        }
+    }
+}
+
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum LoadingStyle {
+    Spinner,
+    #[default]
+    Skeleton,
+}
+
+impl LoadingStyle {
+    pub fn label(&self) -> &'static str {
+        match self {
+            LoadingStyle::Spinner => "Spinner",
+            LoadingStyle::Skeleton => "Skeleton",
+        }
+    }
+}
+
+// What the label in the bottom left corner of a cell shows
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum EntryLabel {
+    #[default]
+    Extension,
+    Resolution,
+    FileSize,
+    Hidden,
+}
+
+impl EntryLabel {
+    pub fn label(&self) -> &'static str {
+        match self {
+            EntryLabel::Extension => "Extension",
+            EntryLabel::Resolution => "Resolution",
+            EntryLabel::FileSize => "File size",
+            EntryLabel::Hidden => "Nothing",
+        }
+    }
+}
 
 pub struct ImageFrame {
     texture: TextureHandle,
     width: f32,
     height: f32,
-    path: PathBuf,
+    label: Option<String>,
     draw_border: bool,
+    badge: Option<String>,
+    rating: u8,
+    fit: ThumbnailFit,
+    corner_radius: f32,
+    selection_style: SelectionStyle,
+    selection_color: Color32,
+    selection_thickness: f32,
+    checkmark: bool,
+    selection_number: Option<usize>,
+    loading: bool,
+    loading_style: LoadingStyle,
+    draggable: bool,
+    highlight: bool,
+    font_size: f32,
+    label_color: Color32,
+    text_color: Color32,
 }
 
 impl ImageFrame {
@@ -21,50 +110,294 @@ impl ImageFrame {
             width,
             height,
             draw_border,
-            path: path.clone(),
+            label: path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_string()),
+            badge: None,
+            rating: 0,
+            fit: ThumbnailFit::default(),
+            corner_radius: 0.0,
+            selection_style: SelectionStyle::default(),
+            selection_color: Color32::from_rgb(180, 123, 182),
+            selection_thickness: 3.0,
+            checkmark: false,
+            selection_number: None,
+            loading: false,
+            loading_style: LoadingStyle::default(),
+            draggable: false,
+            highlight: false,
+            font_size: 14.0,
+            label_color: Color32::LIGHT_RED,
+            text_color: Color32::WHITE,
         }
     }
+
+    // Replaces the extension that is shown by default
+    pub fn label(mut self, label: Option<String>) -> Self {
+        self.label = label;
+        self
+    }
+
+    pub fn badge(mut self, badge: Option<String>) -> Self {
+        self.badge = badge;
+        self
+    }
+
+    pub fn rating(mut self, rating: u8) -> Self {
+        self.rating = rating;
+        self
+    }
+
+    pub fn fit(mut self, fit: ThumbnailFit) -> Self {
+        self.fit = fit;
+        self
+    }
+
+    pub fn corner_radius(mut self, corner_radius: f32) -> Self {
+        self.corner_radius = corner_radius;
+        self
+    }
+
+    pub fn selection(mut self, style: SelectionStyle, color: Color32, thickness: f32) -> Self {
+        self.selection_style = style;
+        self.selection_color = color;
+        self.selection_thickness = thickness;
+        self
+    }
+
+    pub fn checkmark(mut self, checkmark: bool) -> Self {
+        self.checkmark = checkmark;
+        self
+    }
+
+    // Shown where the checkmark would be, in place of it
+    pub fn selection_number(mut self, selection_number: Option<usize>) -> Self {
+        self.selection_number = selection_number;
+        self
+    }
+
+    pub fn overlay(mut self, font_size: f32, label_color: Color32, text_color: Color32) -> Self {
+        self.font_size = font_size;
+        self.label_color = label_color;
+        self.text_color = text_color;
+        self
+    }
+
+    pub fn highlight(mut self, highlight: bool) -> Self {
+        self.highlight = highlight;
+        self
+    }
+
+    pub fn loading(mut self, loading: bool) -> Self {
+        self.loading = loading;
+        self
+    }
+
+    pub fn loading_style(mut self, loading_style: LoadingStyle) -> Self {
+        self.loading_style = loading_style;
+        self
+    }
+
+    pub fn draggable(mut self, draggable: bool) -> Self {
+        self.draggable = draggable;
+        self
+    }
 }
 
 impl Widget for ImageFrame {
     fn ui(self, ui: &mut Ui) -> Response {
         let desired_size = egui::vec2(self.width, self.height);
 
-        let (rect, response) = ui.allocate_exact_size(desired_size, Sense::click());
+        let sense = if self.draggable {
+            Sense::click_and_drag()
+        } else {
+            Sense::click()
+        };
+
+        let (rect, response) = ui.allocate_exact_size(desired_size, sense);
 
         if ui.is_rect_visible(rect) {
-            let texture_size = calculate_contain_size(
-                self.width,
-                self.height,
-                self.texture.size()[0] as f32,
-                self.texture.size()[1] as f32,
-            );
-            
-            let sized_texture =
-                egui::load::SizedTexture::new(self.texture.id(), texture_size);
-
-            let image = egui::Image::new(sized_texture).sense(egui::Sense::click());
+            let texture_width = self.texture.size()[0] as f32;
+            let texture_height = self.texture.size()[1] as f32;
+
+            let rounding = self.corner_radius;
 
             ui.painter()
-                .rect_filled(rect, 0.0, egui::Color32::from_black_alpha(200));
+                .rect_filled(rect, rounding, egui::Color32::from_black_alpha(200));
+
+            let response = match self.fit {
+                ThumbnailFit::Contain => {
+                    let texture_size = calculate_contain_size(
+                        self.width,
+                        self.height,
+                        texture_width,
+                        texture_height,
+                    );
 
-            let response = ui.put(rect, image);
+                    let sized_texture =
+                        egui::load::SizedTexture::new(self.texture.id(), texture_size);
 
-            let extension = self.path.extension().unwrap_or_default().to_str().unwrap_or_default();
-            
-            ui.painter().text(
-                Pos2::from([rect.left() + 5.0, rect.bottom() - 5.0]),
-                egui::Align2::LEFT_BOTTOM,
-                &extension,
-                FontId::monospace(14.0),
-                egui::Color32::LIGHT_RED
-            );
+                    let image = egui::Image::new(sized_texture)
+                        .rounding(rounding)
+                        .sense(sense);
 
-            if self.draw_border {
+                    ui.put(rect, image)
+                }
+                ThumbnailFit::Cover => {
+                    // Scaled until the whole cell is filled, the overflow is cut off through the UVs
+                    // so the rounded corners end up on the cell and not on the scaled image
+                    let (width, height) = calculate_cover_size(
+                        self.width,
+                        self.height,
+                        texture_width,
+                        texture_height,
+                    );
+
+                    let visible = egui::vec2(self.width / width, self.height / height);
+                    let uv = Rect::from_center_size(Pos2::new(0.5, 0.5), visible);
+
+                    egui::Image::new(&self.texture)
+                        .uv(uv)
+                        .rounding(rounding)
+                        .paint_at(ui, rect);
+
+                    response
+                }
+                ThumbnailFit::Square => {
+                    // Only the centered square of the texture is drawn, picked through the UVs
+                    let side = texture_width.min(texture_height);
+                    let uv_min = Pos2::new(
+                        (texture_width - side) / 2.0 / texture_width,
+                        (texture_height - side) / 2.0 / texture_height,
+                    );
+                    let uv = Rect::from_min_max(uv_min, Pos2::new(1.0 - uv_min.x, 1.0 - uv_min.y));
+
+                    let image_rect = Rect::from_center_size(
+                        rect.center(),
+                        egui::Vec2::splat(self.width.min(self.height)),
+                    );
+
+                    egui::Image::new(&self.texture)
+                        .uv(uv)
+                        .rounding(rounding)
+                        .paint_at(ui, image_rect);
+
+                    response
+                }
+            };
+
+            if self.loading {
+                match self.loading_style {
+                    LoadingStyle::Spinner => {
+                        let spinner_size = (self.width.min(self.height) / 4.0).clamp(16.0, 48.0);
+                        let spinner_rect =
+                            Rect::from_center_size(rect.center(), egui::Vec2::splat(spinner_size));
+
+                        egui::Spinner::new().paint_at(ui, spinner_rect);
+                    }
+                    LoadingStyle::Skeleton => paint_skeleton(ui, rect, rounding),
+                }
+            }
+
+            if self.draw_border && self.selection_style == SelectionStyle::Dim {
+                ui.painter()
+                    .rect_filled(rect, rounding, egui::Color32::from_black_alpha(160));
+            }
+
+            // Hovering only lightens the cell, so it can't be confused with the selection
+            if response.hovered() {
+                ui.painter()
+                    .rect_filled(rect, rounding, egui::Color32::from_white_alpha(15));
+                ui.painter().rect_stroke(
+                    rect,
+                    rounding,
+                    Stroke::new(1.0, egui::Color32::from_white_alpha(120)),
+                );
+            }
+
+            if let Some(label) = &self.label {
+                ui.painter().text(
+                    Pos2::from([rect.left() + 5.0, rect.bottom() - 5.0]),
+                    egui::Align2::LEFT_BOTTOM,
+                    label,
+                    FontId::monospace(self.font_size),
+                    self.label_color,
+                );
+            }
+
+            if let Some(badge) = &self.badge {
+                let badge_galley = ui.painter().layout_no_wrap(
+                    badge.to_string(),
+                    FontId::monospace(self.font_size),
+                    self.text_color,
+                );
+
+                let badge_rect = egui::Align2::RIGHT_TOP.anchor_size(
+                    Pos2::from([rect.right() - 5.0, rect.top() + 5.0]),
+                    badge_galley.size(),
+                );
+
+                ui.painter().rect_filled(
+                    badge_rect.expand(3.0),
+                    3.0,
+                    egui::Color32::from_black_alpha(200),
+                );
+
+                ui.painter()
+                    .galley(badge_rect.min, badge_galley, self.text_color);
+            }
+
+            if self.rating > 0 {
+                ui.painter().text(
+                    Pos2::from([rect.right() - 5.0, rect.bottom() - 5.0]),
+                    egui::Align2::RIGHT_BOTTOM,
+                    "★".repeat(self.rating as usize),
+                    FontId::proportional(self.font_size),
+                    egui::Color32::GOLD,
+                );
+            }
+
+            if self.draw_border && self.selection_style == SelectionStyle::Border {
                 ui.painter().rect_stroke(
                     rect,
-                    0.0,
-                    Stroke::new(3.0, egui::Color32::from_rgb(180, 123, 182)),
+                    rounding,
+                    Stroke::new(self.selection_thickness, self.selection_color),
+                );
+            }
+
+            if self.highlight {
+                ui.painter().rect_stroke(
+                    rect.shrink(1.0),
+                    rounding,
+                    Stroke::new(2.0, egui::Color32::WHITE),
+                );
+            }
+
+            let show_checkmark = self.checkmark
+                || self.selection_style == SelectionStyle::Checkmark
+                || self.selection_number.is_some();
+
+            if self.draw_border && show_checkmark {
+                let center = Pos2::from([rect.left() + 15.0, rect.top() + 15.0]);
+
+                ui.painter().circle(
+                    center,
+                    10.0,
+                    self.selection_color,
+                    Stroke::new(1.5, egui::Color32::WHITE),
+                );
+
+                let text = match self.selection_number {
+                    Some(number) => number.to_string(),
+                    None => "✔".to_string(),
+                };
+
+                ui.painter().text(
+                    center,
+                    egui::Align2::CENTER_CENTER,
+                    text,
+                    FontId::proportional(13.0),
+                    egui::Color32::WHITE,
                 );
             }
 
@@ -74,3 +407,40 @@ impl Widget for ImageFrame {
         }
     }
 }
+
+// A pulsing fill with a highlight sweeping across it, driven by the frame time so every
+// loading cell moves in step with the others
+fn paint_skeleton(ui: &Ui, rect: Rect, rounding: f32) {
+    let time = ui.input(|i| i.time);
+
+    let pulse = ((time * std::f64::consts::TAU / 1.6).sin() * 0.5 + 0.5) as f32;
+    let fill = egui::Color32::from_white_alpha(10 + (pulse * 14.0) as u8);
+
+    ui.painter().rect_filled(rect, rounding, fill);
+
+    // The band starts and ends outside of the cell, so it slides in and out instead of popping
+    let band_width = (rect.width() * 0.4).max(24.0);
+    let progress = (time % 1.2 / 1.2) as f32;
+    let center = rect.left() - band_width + progress * (rect.width() + band_width * 2.0);
+
+    let highlight = egui::Color32::from_white_alpha(28);
+    let mut mesh = egui::Mesh::default();
+
+    for (x, color) in [
+        (center - band_width / 2.0, Color32::TRANSPARENT),
+        (center, highlight),
+        (center + band_width / 2.0, Color32::TRANSPARENT),
+    ] {
+        mesh.colored_vertex(Pos2::new(x, rect.top()), color);
+        mesh.colored_vertex(Pos2::new(x, rect.bottom()), color);
+    }
+
+    mesh.add_triangle(0, 1, 2);
+    mesh.add_triangle(1, 2, 3);
+    mesh.add_triangle(2, 3, 4);
+    mesh.add_triangle(3, 4, 5);
+
+    ui.painter_at(rect).add(mesh);
+
+    ui.ctx().request_repaint();
+}
@@ -0,0 +1,219 @@
+use eframe::egui::{Pos2, Rect, Stroke, Vec2};
+
+use crate::egui::epaint::TextureHandle;
+use crate::egui::{self, Response, Sense, Ui, Widget};
+use crate::utils::calculate_contain_size;
+
+const MIN_ZOOM: f32 = 1.0;
+const MAX_ZOOM: f32 = 50.0;
+const MINIMAP_SIZE: f32 = 150.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct ViewTransform {
+    pub zoom: f32,
+    pub offset: Vec2,
+}
+
+impl Default for ViewTransform {
+    fn default() -> Self {
+        Self {
+            zoom: 1.0,
+            offset: Vec2::ZERO,
+        }
+    }
+}
+
+impl ViewTransform {
+    // Zoom is relative to the size that fits the whole image into the viewport
+    pub fn image_rect(&self, viewport: Rect, image_size: Vec2) -> Rect {
+        let (width, height) = calculate_contain_size(
+            viewport.width(),
+            viewport.height(),
+            image_size.x,
+            image_size.y,
+        );
+
+        Rect::from_center_size(
+            viewport.center() + self.offset,
+            egui::vec2(width, height) * self.zoom,
+        )
+    }
+
+    // Positions on the image go from 0 to 1 on both axes, so they stay put while zooming
+    pub fn screen_to_image(&self, viewport: Rect, image_size: Vec2, pointer: Pos2) -> Vec2 {
+        let image_rect = self.image_rect(viewport, image_size);
+
+        (pointer - image_rect.min) / image_rect.size()
+    }
+
+    pub fn image_to_screen(&self, viewport: Rect, image_size: Vec2, position: Vec2) -> Pos2 {
+        let image_rect = self.image_rect(viewport, image_size);
+
+        image_rect.min + position * image_rect.size()
+    }
+
+    // Same as `screen_to_image`, but only while the pointer is over the image
+    pub fn image_position(&self, viewport: Rect, image_size: Vec2, pointer: Pos2) -> Option<Vec2> {
+        let position = self.screen_to_image(viewport, image_size, pointer);
+        let is_inside = (0.0..=1.0).contains(&position.x) && (0.0..=1.0).contains(&position.y);
+
+        is_inside.then_some(position)
+    }
+
+    pub fn is_zoomed(&self) -> bool {
+        self.zoom > MIN_ZOOM
+    }
+
+    fn zoom_around(&mut self, viewport: Rect, image_size: Vec2, pointer: Pos2, factor: f32) {
+        let new_zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+
+        if new_zoom <= MIN_ZOOM {
+            *self = ViewTransform::default();
+            return;
+        }
+
+        // Keep the point under the cursor in place while the image grows around it
+        let image_rect = self.image_rect(viewport, image_size);
+        let center = pointer - (pointer - image_rect.center()) * (new_zoom / self.zoom);
+
+        self.zoom = new_zoom;
+        self.offset = center - viewport.center();
+    }
+}
+
+pub struct ImageViewer<'a> {
+    texture: &'a TextureHandle,
+    view: &'a mut ViewTransform,
+    pan: bool,
+    scroll_zoom: bool,
+}
+
+impl<'a> ImageViewer<'a> {
+    pub fn new(texture: &'a TextureHandle, view: &'a mut ViewTransform) -> Self {
+        Self {
+            texture,
+            view,
+            pan: true,
+            scroll_zoom: true,
+        }
+    }
+
+    // Without it only Ctrl+wheel and pinching zoom, so the wheel is free for other things
+    pub fn scroll_zoom(mut self, scroll_zoom: bool) -> Self {
+        self.scroll_zoom = scroll_zoom;
+        self
+    }
+
+    // Turned off while a tool in the preview needs the drags for itself
+    pub fn pan(mut self, pan: bool) -> Self {
+        self.pan = pan;
+        self
+    }
+
+    fn minimap_rect(viewport: Rect, image_rect: Rect) -> Rect {
+        let (width, height) = calculate_contain_size(
+            MINIMAP_SIZE,
+            MINIMAP_SIZE,
+            image_rect.width(),
+            image_rect.height(),
+        );
+
+        Rect::from_min_size(
+            viewport.right_bottom() - egui::vec2(width + 10.0, height + 10.0),
+            egui::vec2(width, height),
+        )
+    }
+
+    fn paint_minimap(&self, ui: &mut Ui, viewport: Rect, image_rect: Rect, minimap_rect: Rect) {
+        let painter = ui.painter_at(viewport);
+        let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+
+        painter.rect_filled(
+            minimap_rect.expand(3.0),
+            3.0,
+            egui::Color32::from_black_alpha(160),
+        );
+        painter.image(self.texture.id(), minimap_rect, uv, egui::Color32::WHITE);
+
+        let visible_rect = viewport.intersect(image_rect);
+        let scale = minimap_rect.size() / image_rect.size();
+
+        let visible_minimap_rect = Rect::from_min_max(
+            minimap_rect.min + (visible_rect.min - image_rect.min) * scale,
+            minimap_rect.min + (visible_rect.max - image_rect.min) * scale,
+        );
+
+        painter.rect_stroke(
+            visible_minimap_rect,
+            0.0,
+            Stroke::new(1.5, egui::Color32::WHITE),
+        );
+    }
+}
+
+impl<'a> Widget for ImageViewer<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        let viewport = ui.available_rect_before_wrap();
+        let image_size = self.texture.size_vec2();
+
+        let response = ui.allocate_rect(viewport, Sense::click_and_drag());
+
+        if response.double_clicked() {
+            *self.view = ViewTransform::default();
+        }
+
+        if self.pan && response.dragged_by(egui::PointerButton::Primary) && self.view.is_zoomed() {
+            self.view.offset += response.drag_delta();
+        }
+
+        if response.hovered() {
+            let scroll = match self.scroll_zoom {
+                true => ui.input(|i| i.smooth_scroll_delta.y),
+                false => 0.0,
+            };
+            let factor = (scroll / 200.0).exp() * ui.input(|i| i.zoom_delta());
+            let pointer = ui.input(|i| i.pointer.hover_pos());
+
+            if let (true, Some(pointer)) = (factor != 1.0, pointer) {
+                self.view.zoom_around(viewport, image_size, pointer, factor);
+            }
+        }
+
+        let image_rect = self.view.image_rect(viewport, image_size);
+        let is_overflowing =
+            image_rect.width() > viewport.width() || image_rect.height() > viewport.height();
+
+        let minimap_rect = ImageViewer::minimap_rect(viewport, image_rect);
+
+        if is_overflowing {
+            let minimap_response = ui.interact(
+                minimap_rect,
+                ui.id().with("image_minimap"),
+                Sense::click_and_drag(),
+            );
+
+            // Clicking or dragging on the minimap centers the view on that point
+            if let Some(pointer) = minimap_response.interact_pointer_pos() {
+                let relative = ((pointer - minimap_rect.min) / minimap_rect.size())
+                    .clamp(Vec2::ZERO, Vec2::splat(1.0));
+
+                self.view.offset = (Vec2::splat(0.5) - relative) * image_rect.size();
+            }
+        }
+
+        let image_rect = self.view.image_rect(viewport, image_size);
+
+        if ui.is_rect_visible(viewport) {
+            let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+
+            ui.painter_at(viewport)
+                .image(self.texture.id(), image_rect, uv, egui::Color32::WHITE);
+
+            if is_overflowing {
+                self.paint_minimap(ui, viewport, image_rect, minimap_rect);
+            }
+        }
+
+        response
+    }
+}
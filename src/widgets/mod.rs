@@ -1,2 +1,3 @@
 pub mod image_frame;
+pub mod image_viewer;
 pub mod video_player;
\ No newline at end of file
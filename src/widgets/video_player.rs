@@ -1,10 +1,14 @@
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use eframe::egui::{ColorImage, FontId, Pos2, Stroke};
+use log::warn;
 
 use crate::egui::epaint::TextureHandle;
 use crate::egui::{self, Response, Sense, Ui, Widget};
-use crate::utils::{calculate_contain_size, format_time};
+use crate::utils::{calculate_contain_size, calculate_cover_size, format_time};
 use crate::video_entry::VideoEntry;
 
 pub struct Icon {
@@ -91,37 +95,198 @@ impl Widget for VideoVolumeWidget {
     }
 }
 
-pub struct VideoPlayer {
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum VideoFit {
+    #[default]
+    Contain,
+    // Fills the whole surface, cutting off whatever doesn't fit
+    Cover,
+}
+
+impl VideoFit {
+    pub fn label(&self) -> &'static str {
+        match self {
+            VideoFit::Contain => "Contain",
+            VideoFit::Cover => "Cover",
+        }
+    }
+}
+
+pub struct VideoPlayer<'a> {
+    video: &'a mut VideoEntry,
     texture: Option<TextureHandle>,
     current_time: u64,
     duration: u64,
+    overlay_font_size: f32,
+    overlay_color: egui::Color32,
+    backdrop: egui::Color32,
+    fit: VideoFit,
+    autohide_controls: Option<Duration>,
 }
 
-impl VideoPlayer {
-    pub fn new(video: &mut VideoEntry, ctx: &egui::Context) -> Self {
-        let texture_handle = &video.get_current_frame(ctx);
+impl<'a> VideoPlayer<'a> {
+    pub fn new(video: &'a mut VideoEntry, ctx: &egui::Context) -> Self {
+        let texture_handle = video.get_current_frame(ctx);
+        let current_time = video.current_time;
+        let duration = video.video_duration;
 
         Self {
-            texture: texture_handle.clone(),
-            current_time: video.current_time,
-            duration: video.video_duration,
+            video,
+            texture: texture_handle,
+            current_time,
+            duration,
+            overlay_font_size: 14.0,
+            overlay_color: egui::Color32::WHITE,
+            backdrop: egui::Color32::BLACK,
+            fit: VideoFit::Contain,
+            autohide_controls: None,
         }
     }
+
+    // The controls are only hidden while the video plays and come back as soon as the mouse moves
+    pub fn autohide_controls(mut self, delay: Option<Duration>) -> Self {
+        self.autohide_controls = delay;
+        self
+    }
+
+    pub fn fit(mut self, fit: VideoFit) -> Self {
+        self.fit = fit;
+        self
+    }
+
+    pub fn backdrop(mut self, backdrop: egui::Color32) -> Self {
+        self.backdrop = backdrop;
+        self
+    }
+
+    pub fn overlay(mut self, font_size: f32, color: egui::Color32) -> Self {
+        self.overlay_font_size = font_size;
+        self.overlay_color = color;
+        self
+    }
+
+    fn are_controls_hidden(&self, ui: &mut Ui) -> bool {
+        let delay = match self.autohide_controls {
+            Some(delay) => delay,
+            None => return false,
+        };
+
+        // The player is built again every frame, so the time of the last activity lives in egui
+        let id = ui.id().with("video_controls_activity");
+        let now = Instant::now();
+        // A paused video counts as activity, so the delay starts over once it plays again
+        let is_active = !self.video.is_playing
+            || ui.input(|i| i.pointer.delta() != egui::Vec2::ZERO || i.pointer.any_down());
+
+        let last_activity = ui.ctx().memory_mut(|memory| {
+            if is_active {
+                memory.data.insert_temp(id, now);
+            }
+
+            *memory.data.get_temp_mut_or_insert_with(id, || now)
+        });
+
+        match delay.checked_sub(last_activity.elapsed()) {
+            Some(remaining) => {
+                ui.ctx().request_repaint_after(remaining);
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn track_selector(&mut self, ui: &mut Ui) {
+        if self.video.audio_streams.len() < 2 && self.video.subtitle_streams.is_empty() {
+            return;
+        }
+
+        let mut selected_audio_stream = self.video.audio_stream_index;
+        let mut selected_subtitle_stream = self.video.subtitle_stream_index;
+
+        egui::Area::new(egui::Id::new("video_track_selector"))
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+            .show(ui.ctx(), |ui| {
+                ui.horizontal(|ui| {
+                    if self.video.audio_streams.len() > 1 {
+                        let selected_text = self
+                            .video
+                            .audio_streams
+                            .iter()
+                            .find(|stream| stream.index == selected_audio_stream)
+                            .map(|stream| stream.label.clone())
+                            .unwrap_or_default();
+
+                        egui::ComboBox::from_id_source("video_audio_track")
+                            .selected_text(selected_text)
+                            .show_ui(ui, |ui| {
+                                for stream in self.video.audio_streams.iter() {
+                                    ui.selectable_value(
+                                        &mut selected_audio_stream,
+                                        stream.index,
+                                        &stream.label,
+                                    );
+                                }
+                            });
+                    }
+
+                    if !self.video.subtitle_streams.is_empty() {
+                        let selected_text = self
+                            .video
+                            .subtitle_streams
+                            .iter()
+                            .find(|stream| Some(stream.index) == selected_subtitle_stream)
+                            .map(|stream| stream.label.clone())
+                            .unwrap_or("No subtitles".to_string());
+
+                        egui::ComboBox::from_id_source("video_subtitle_track")
+                            .selected_text(selected_text)
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(
+                                    &mut selected_subtitle_stream,
+                                    None,
+                                    "No subtitles",
+                                );
+
+                                for stream in self.video.subtitle_streams.iter() {
+                                    ui.selectable_value(
+                                        &mut selected_subtitle_stream,
+                                        Some(stream.index),
+                                        &stream.label,
+                                    );
+                                }
+                            });
+                    }
+                });
+            });
+
+        self.video.select_audio_stream(selected_audio_stream);
+        self.video.select_subtitle_stream(selected_subtitle_stream);
+    }
 }
 
-impl Widget for VideoPlayer {
-    fn ui(self, ui: &mut Ui) -> Response {
+impl<'a> Widget for VideoPlayer<'a> {
+    fn ui(mut self, ui: &mut Ui) -> Response {
         let bottom_bar_height = 30.0;
         let progress_bar_height = bottom_bar_height / 2.0;
         let gap = 10.0;
+        let filmstrip_height = if self.video.filmstrip.is_empty() {
+            0.0
+        } else {
+            60.0
+        };
+
+        // Hidden controls give their space to the video
+        let controls_hidden = self.are_controls_hidden(ui);
+        let controls_height = if controls_hidden {
+            0.0
+        } else {
+            bottom_bar_height + filmstrip_height
+        };
 
         let screen_rect = ui.input(|i: &egui::InputState| i.screen_rect());
         let video_surface_rect = egui::Rect::from_min_max(
             Pos2::new(screen_rect.left(), screen_rect.top()),
-            Pos2::new(
-                screen_rect.right(),
-                screen_rect.bottom() - bottom_bar_height,
-            ),
+            Pos2::new(screen_rect.right(), screen_rect.bottom() - controls_height),
         );
 
         let response = ui.allocate_rect(video_surface_rect, Sense::hover());
@@ -129,7 +294,7 @@ impl Widget for VideoPlayer {
         let texture = match &self.texture {
             Some(texture) => texture,
             None => {
-                println!("No texture found for video player");
+                warn!("No texture found for video player");
 
                 return response;
             }
@@ -137,20 +302,148 @@ impl Widget for VideoPlayer {
 
         if ui.is_rect_visible(video_surface_rect) {
             ui.painter()
-                .rect_filled(video_surface_rect, 0.0, egui::Color32::RED);
-
-            let texture_size = calculate_contain_size(
-                video_surface_rect.width(),
-                video_surface_rect.height(),
-                texture.size()[0] as f32,
-                texture.size()[1] as f32,
-            );
+                .rect_filled(video_surface_rect, 0.0, self.backdrop);
+
+            let surface_width = video_surface_rect.width();
+            let surface_height = video_surface_rect.height();
+            let texture_width = texture.size()[0] as f32;
+            let texture_height = texture.size()[1] as f32;
+
+            // The covering size overflows the surface, so only the part of the frame
+            // that fits is drawn by cropping it through the UVs
+            let (texture_size, uv) = match self.fit {
+                VideoFit::Contain => (
+                    calculate_contain_size(
+                        surface_width,
+                        surface_height,
+                        texture_width,
+                        texture_height,
+                    ),
+                    egui::Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+                ),
+                VideoFit::Cover => {
+                    let (width, height) = calculate_cover_size(
+                        surface_width,
+                        surface_height,
+                        texture_width,
+                        texture_height,
+                    );
+                    let visible = egui::vec2(
+                        (surface_width / width).min(1.0),
+                        (surface_height / height).min(1.0),
+                    );
+
+                    (
+                        (width.min(surface_width), height.min(surface_height)),
+                        egui::Rect::from_center_size(Pos2::new(0.5, 0.5), visible),
+                    )
+                }
+            };
 
             let sized_texture = egui::load::SizedTexture::new(texture.id(), texture_size);
 
-            let video = egui::Image::new(sized_texture).sense(egui::Sense::click());
+            let video = egui::Image::new(sized_texture)
+                .uv(uv)
+                .sense(egui::Sense::click());
             let response = ui.put(video_surface_rect, video);
 
+            if let Some(subtitle) = self.video.get_current_subtitle() {
+                let subtitle_galley = ui.painter().layout(
+                    subtitle.to_string(),
+                    FontId::proportional(22.0),
+                    egui::Color32::WHITE,
+                    video_surface_rect.width() * 0.8,
+                );
+
+                let subtitle_rect = egui::Align2::CENTER_BOTTOM.anchor_size(
+                    Pos2::new(
+                        video_surface_rect.center().x,
+                        video_surface_rect.bottom() - gap * 3.0,
+                    ),
+                    subtitle_galley.size(),
+                );
+
+                ui.painter().rect_filled(
+                    subtitle_rect.expand(4.0),
+                    4.0,
+                    egui::Color32::from_black_alpha(160),
+                );
+
+                ui.painter()
+                    .galley(subtitle_rect.min, subtitle_galley, egui::Color32::WHITE);
+            }
+
+            self.track_selector(ui);
+
+            if controls_hidden {
+                return response;
+            }
+
+            if !self.video.filmstrip.is_empty() {
+                let filmstrip_rect = egui::Rect::from_min_max(
+                    Pos2::new(screen_rect.left(), video_surface_rect.bottom()),
+                    Pos2::new(
+                        screen_rect.right(),
+                        video_surface_rect.bottom() + filmstrip_height,
+                    ),
+                );
+
+                ui.painter()
+                    .rect_filled(filmstrip_rect, 0.0, egui::Color32::from_black_alpha(200));
+
+                let cell_width = filmstrip_rect.width() / self.video.filmstrip.len() as f32;
+                let mut seek_time = None;
+
+                for (i, frame) in self.video.filmstrip.iter().enumerate() {
+                    let cell_rect = egui::Rect::from_min_size(
+                        Pos2::new(
+                            filmstrip_rect.left() + cell_width * i as f32,
+                            filmstrip_rect.top(),
+                        ),
+                        egui::vec2(cell_width, filmstrip_height),
+                    );
+
+                    let frame_size = calculate_contain_size(
+                        cell_rect.width() - 2.0,
+                        cell_rect.height() - 2.0,
+                        frame.texture.size()[0] as f32,
+                        frame.texture.size()[1] as f32,
+                    );
+
+                    let sized_texture =
+                        egui::load::SizedTexture::new(frame.texture.id(), frame_size);
+
+                    let frame_res = ui.put(
+                        cell_rect,
+                        egui::Image::new(sized_texture).sense(egui::Sense::click()),
+                    );
+
+                    let next_frame_time = match self.video.filmstrip.get(i + 1) {
+                        Some(next_frame) => next_frame.time,
+                        None => self.duration,
+                    };
+
+                    let is_current =
+                        self.current_time >= frame.time && self.current_time < next_frame_time;
+
+                    if is_current || frame_res.hovered() {
+                        ui.painter().rect_stroke(
+                            cell_rect.shrink(1.0),
+                            0.0,
+                            Stroke::new(2.0, egui::Color32::WHITE),
+                        );
+                    }
+
+                    if frame_res.clicked() {
+                        seek_time = Some(frame.time);
+                    }
+                }
+
+                if let Some(time) = seek_time {
+                    self.video.seek(time);
+                }
+            }
+
             let bottom_bar_rect = egui::Rect::from_min_max(
                 Pos2::new(screen_rect.left(), screen_rect.bottom() - bottom_bar_height),
                 Pos2::new(screen_rect.right(), screen_rect.bottom()),
@@ -190,8 +483,8 @@ impl Widget for VideoPlayer {
                 .painter()
                 .layout(
                     time_text.to_string(),
-                    FontId::monospace(12.0),
-                    egui::Color32::WHITE,
+                    FontId::monospace(self.overlay_font_size),
+                    self.overlay_color,
                     10000.0,
                 )
                 .rect;
@@ -205,8 +498,8 @@ impl Widget for VideoPlayer {
                 text_pos,
                 egui::Align2::LEFT_CENTER,
                 time_text,
-                FontId::monospace(14.0),
-                egui::Color32::WHITE,
+                FontId::monospace(self.overlay_font_size),
+                self.overlay_color,
             );
 
             let progress_bar_background_rect = egui::Rect::from_min_max(
@@ -245,6 +538,44 @@ impl Widget for VideoPlayer {
                 egui::Color32::from_rgba_unmultiplied(255, 255, 255, 255),
             );
 
+            let time_to_x = |time: u64| {
+                progress_bar_background_rect.left()
+                    + progress_bar_background_rect.width()
+                        * (time as f32 / self.duration.max(1) as f32).min(1.0)
+            };
+
+            let loop_marker_color = egui::Color32::from_rgb(180, 123, 182);
+
+            if let Some(loop_start) = self.video.loop_start {
+                let loop_end = self.video.loop_end.unwrap_or(loop_start);
+
+                let loop_region_rect = egui::Rect::from_min_max(
+                    Pos2::new(time_to_x(loop_start), progress_bar_background_rect.top()),
+                    Pos2::new(time_to_x(loop_end), progress_bar_background_rect.bottom()),
+                );
+
+                ui.painter().rect_filled(
+                    loop_region_rect,
+                    0.0,
+                    loop_marker_color.gamma_multiply(0.6),
+                );
+
+                for time in [Some(loop_start), self.video.loop_end]
+                    .into_iter()
+                    .flatten()
+                {
+                    let x = time_to_x(time);
+
+                    ui.painter().line_segment(
+                        [
+                            Pos2::new(x, bottom_bar_rect.top()),
+                            Pos2::new(x, bottom_bar_rect.bottom()),
+                        ],
+                        Stroke::new(2.0, loop_marker_color),
+                    );
+                }
+            }
+
             let full_screen_icon_rect = egui::Rect::from_min_max(
                 Pos2::new(
                     bottom_bar_rect.right() - bottom_bar_height,